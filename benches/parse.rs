@@ -0,0 +1,51 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+// Times parsing a large generated program as it's read, which is always sequential,
+// against parsing it whole, which is parallel when the `parallel` feature is on.
+//
+//     cargo bench --bench parse
+
+use std::time::{Duration, Instant};
+
+use membrane::parser::{self, ParseOptions};
+
+const SIZE: usize = 64 << 20;
+const ROUNDS: u32 = 5;
+
+fn time(name: &str, mut parse: impl FnMut()) -> Duration {
+    parse();
+
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        parse();
+    }
+
+    let elapsed = start.elapsed() / ROUNDS;
+    println!("{:<12} {:>10.2?}", name, elapsed);
+    elapsed
+}
+
+fn main() {
+    let piece = include_str!("../examples/mandelbrot.bf");
+    let source = piece.repeat(SIZE / piece.len() + 1);
+    let options = ParseOptions::default();
+
+    println!("parsing {} MiB of source", source.len() >> 20);
+
+    let sequential = time("sequential", || {
+        parser::parse_reader_with(source.as_bytes(), &options).unwrap();
+    });
+
+    let parallel = time("parallel", || {
+        parser::parse_bytes_with(source.as_bytes(), &options).unwrap();
+    });
+
+    println!(
+        "speedup      {:>9.2}x",
+        sequential.as_secs_f64() / parallel.as_secs_f64()
+    );
+}
@@ -0,0 +1,116 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+// Measures how fast representative programs parse, optimize, and run, for checking changes
+// to how the interpreter dispatches, grows the tape, or scans for zero, and for catching
+// regressions:
+//
+//     cargo bench --bench programs
+//     cargo bench --bench programs -- interpret/hanoi
+//
+// Programs are run optimized, as `membrane run -O` runs them, with their output thrown
+// away. Mandelbrot takes seconds a run, so it's sampled as few times as criterion allows.
+
+use std::io::{self, Cursor};
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use membrane::interpreter::{EofMode, InputSource, Interpreter, OutputSource};
+use membrane::optimizer::{self, OptimizeOptions};
+use membrane::parser;
+use membrane::program::Program;
+
+const PROGRAMS: &[(&str, &str)] = &[
+    ("mandelbrot", include_str!("../examples/mandelbrot.bf")),
+    ("hanoi", include_str!("programs/hanoi.bf")),
+    ("factor", include_str!("programs/factor.bf")),
+    ("scan", include_str!("programs/scan.bf")),
+    ("cat", include_str!("programs/cat.bf")),
+];
+
+// What every program reads, which only cat does, all 4 MiB of it.
+fn input() -> Vec<u8> {
+    include_str!("../examples/life.bf")
+        .bytes()
+        .cycle()
+        .take(4 << 20)
+        .collect()
+}
+
+fn optimized(source: &str) -> Program {
+    let mut program = parser::parse_string(source).unwrap();
+    optimizer::optimize_program(&mut program, &OptimizeOptions::default()).unwrap();
+    program
+}
+
+fn interpreter(input: &[u8]) -> Interpreter {
+    Interpreter::builder()
+        .eof(EofMode::Zero)
+        .input(InputSource::File(Cursor::new(input.to_vec())))
+        .output(OutputSource::Sink(io::sink()))
+        .build()
+}
+
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+
+    for &(name, source) in PROGRAMS {
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), source, |b, source| {
+            b.iter(|| parser::parse_string(source).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+fn optimize(c: &mut Criterion) {
+    let mut group = c.benchmark_group("optimize");
+    let options = OptimizeOptions::default();
+
+    for &(name, source) in PROGRAMS {
+        let program = parser::parse_string(source).unwrap();
+
+        group.throughput(Throughput::Elements(program.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &program, |b, program| {
+            b.iter_batched(
+                || program.clone(),
+                |mut program| optimizer::optimize_program(&mut program, &options).unwrap(),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+// Throughput is in instructions executed, so that programs can be compared with each other.
+fn interpret(c: &mut Criterion) {
+    let mut group = c.benchmark_group("interpret");
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(10));
+
+    let input = input();
+
+    for &(name, source) in PROGRAMS {
+        let program = optimized(source);
+        let state = interpreter(&input).run(&program.instructions).unwrap();
+
+        group.throughput(Throughput::Elements(state.instructions_executed as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &program, |b, program| {
+            b.iter_batched(
+                || interpreter(&input),
+                |interpreter| interpreter.run(&program.instructions).unwrap(),
+                BatchSize::PerIteration,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, parse, optimize, interpret);
+criterion_main!(benches);
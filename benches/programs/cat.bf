@@ -0,0 +1,4 @@
+[cat.bf -- Copies its input to its output a byte at a time, until it reads a
+zero, which is what reads past the end of the input store when benchmarked.]
+
+,[.,]
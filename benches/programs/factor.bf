@@ -0,0 +1,37 @@
+[factor.bf -- Factors every number from 2 to 255 by trial division, printing
+each one followed by its prime factors, such as 12: 2 2 3]
+
+++>+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
++++++++++++++++++[<[->>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>+>+<<<<<<<<<<<<<<<<
+<<<<<<<<<<<<<<<<<<<<<<<<<]>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>[-<<<<<<<<<<<
+<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<+>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>]>++++++
+++++<<[->+>-[>+>>]>[+[-<+>]>+>>]<<<<<<]>[-]>[-]>[->>>>>>+<<<<<<]>[-<<<<+>>>>]<<+
++++++++++<<[->+>-[>+>>]>[+[-<+>]>+>>]<<<<<<]>[-]>[-]>[->>>>>+<<<<<]>[->>>+<<<]>>
+>[->>>>+>+<<<<<]>>>>>[-<<<<<+>>>>>]<[<<<<+++++++++++++++++++++++++++++++++++++++
++++++++++.------------------------------------------------>>>>[-]]<<<<[->>>+>+<<
+<<]>>>>[-<<<<+>>>>]<<<[->>+>+<<<]>>>[-<<<+>>>]<[->+>+<<]>>[-<<+>>]<[<<<+++++++++
++++++++++++++++++++++++++++++++++++++++.----------------------------------------
+-------->>>[-]]<<++++++++++++++++++++++++++++++++++++++++++++++++.--------------
+----------------------------------<<[-]>[-]>[-]>[-]<<<<<<<<<<<<<<<<<<<<<<<<<<<<<
+<<<<<<<<<<<<<<[-]++++++++++++++++++++++++++++++++++++++++++++++++++++++++++.[-]<
+<<<<<<[->>+>>>>>+<<<<<<<]>>>>>>>[-<<<<<<<+>>>>>>>]<<<<++<[->>>>+>+<<<<<]>>>>>[-<
+<<<<+>>>>>]<-[<<<<[->>>>>>>>>>>>>>>>>>+<<<<<<<<<<<<<+<<<<<]>>>>>[-<<<<<+>>>>>]<<
+<<[->>>>>>>>>>>>>>>>>>>+<<<<<<<<<<<<<<<+<<<<]>>>>[-<<<<+>>>>]>>>>>>>>>>>>>[->+>-
+[>+>>]>[+[-<+>]>+>>]<<<<<<]>[-]>[-]>[-<<<<<<<<<<<<<<<<<<<+>>>>>>>>>>>>>>>>>>>]>[
+-<<<<<<<<<<<<<<<<<<<+>>>>>>>>>>>>>>>>>>>]<<<<<<<<<<<<<<<+<<<<<[->>>+>+<<<<]>>>>[
+-<<<<+>>>>]<[<<<<+>>>>[-]>>-<<]>>[<<[-]++++++++++++++++++++++++++++++++.[-]<<<<[
+->>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>+>+<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<]
+>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>[-<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<+>
+>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>]>++++++++++<<[->+>-[>+>>]>[+[-<+>]>+>>]<<<
+<<<]>[-]>[-]>[->>>>>>+<<<<<<]>[-<<<<+>>>>]<<++++++++++<<[->+>-[>+>>]>[+[-<+>]>+>
+>]<<<<<<]>[-]>[-]>[->>>>>+<<<<<]>[->>>+<<<]>>>[->>>>+>+<<<<<]>>>>>[-<<<<<+>>>>>]
+<[<<<<++++++++++++++++++++++++++++++++++++++++++++++++.-------------------------
+----------------------->>>>[-]]<<<<[->>>+>+<<<<]>>>>[-<<<<+>>>>]<<<[->>+>+<<<]>>
+>[-<<<+>>>]<[->+>+<<]>>[-<<+>>]<[<<<++++++++++++++++++++++++++++++++++++++++++++
+++++.------------------------------------------------>>>[-]]<<++++++++++++++++++
+++++++++++++++++++++++++++++++.------------------------------------------------<
+<[-]>[-]>[-]>[-]<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<[-]>>>[-<<<+>>>]
+>>>>-]<<<<<[-]>[-]>[-]<<<<[->>>>+>+<<<<<]>>>>>[-<<<<<+>>>>>]<-]<<<<[-]>[-]>>>>[-
+]++++++++++.[-]<<<<<<<+>-]
@@ -0,0 +1,41 @@
+[hanoi.bf -- Solves the Towers of Hanoi for 16 disks, printing each of the
+65535 moves as the disk, from a to p, and the pegs it moves from and to, such
+as a A B. Each disk is a record on the tape, and the records are walked as a
+binary counter, so the bit that flips on is the disk to move.]
+
+>>>+>>>>>>>>>+>+>>++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
++++++++++++++++++++++++++++++++++++>+>>>+>>>>>+>+>>+++++++++++++++++++++++++++++
++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++>+>>>>+>>>>
++>+>>+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
+++++++++++++++++++++++++>+>>>+>>>>>+>+>>++++++++++++++++++++++++++++++++++++++++
+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++>+>>>>+>>>>+>+>>++++
+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
++++++++++++++++++>+>>>+>>>>>+>+>>+++++++++++++++++++++++++++++++++++++++++++++++
++++++++++++++++++++++++++++++++++++++++++++++++++++++++>+>>>>+>>>>+>+>>+++++++++
+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
+++++++++++++++>+>>>+>>>>>+>+>>++++++++++++++++++++++++++++++++++++++++++++++++++
+++++++++++++++++++++++++++++++++++++++++++++++++++++++>+>>>>+>>>>+>+>>++++++++++
+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
++++++++++++++++>+>>>+>>>>>+>+>>+++++++++++++++++++++++++++++++++++++++++++++++++
++++++++++++++++++++++++++++++++++++++++++++++++++++++++++>+>>>>+>>>>+>+>>+++++++
+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
+++++++++++++++++++++>+>>>+>>>>>+>+>>++++++++++++++++++++++++++++++++++++++++++++
+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++>+>>>>+>>>>+>+>>
+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
++++++++++++++++++++++++++++++>+>>>+>>>>>+>+>>+++++++++++++++++++++++++++++++++++
++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++>+>>>
+>+>>>>+>+>>+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
+++++++++++++++++++++++++++++++++++++++++++>+>>>+>>>>>+>+>>++++++++++++++++++++++
+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
+++++++++++>+>>>>+<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<
+<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<
+<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<[>>>>>>>>>>>[->>>>>>>>>>>>
+]<<[>>+>.>>>>>>[-]++++++++++++++++++++++++++++++++.[-]++++++++++++++++++++++++++
++++++++++++++++++++++++++++++++++++++++<<<<[->>>>+>+<<<<<]>>>>>[-<<<<<+>>>>>]<<<
+<[->>>+>+<<<<]>>>>[-<<<<+>>>>]<<<<[->>>+>+<<<<]>>>>[-<<<<+>>>>]<.[-]<<[->>+>+<<<
+]>>>[-<<<+>>>]<[<<<[->>>>>+<<<<<]<[->+<]<[->+<]>>>>>>>[-<<<<<<<+>>>>>>>]<<[-]]<[
+->+>+<<]>>[-<<+>>]<[<<<<<[->>>>>>>+<<<<<<<]>[-<+>]>[-<+>]>>>>>[-<<<<<+>>>>>]<<[-
+]][-]++++++++++++++++++++++++++++++++.[-]+++++++++++++++++++++++++++++++++++++++
+++++++++++++++++++++++++++<<<<[->>>>+>+<<<<<]>>>>>[-<<<<<+>>>>>]<<<<[->>>+>+<<<<
+]>>>>[-<<<<+>>>>]<<<<[->>>+>+<<<<]>>>>[-<<<<+>>>>]<.[-][-]++++++++++.[-]<<<<<<<<
+[<<<<<<<<<<<<]<]>>>]
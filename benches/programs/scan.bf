@@ -0,0 +1,6 @@
+[scan.bf -- Grows a run of 5000 nonzero cells one at a time, scanning to its
+end and back each time, so nearly all of the time goes to scanning for zero.]
+
+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
+++++++++++++++++++++[>++++++++++++++++++++++++++++++++++++++++++++++++++[>>[>]+[
+<]<-]<-]
@@ -0,0 +1,83 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+// Checks that optimizing never changes what a program does. Each case is a program, made
+// from the fuzzer's bytes by testgen, and its input. Whenever the program ends within the
+// step budget, the optimized program has to write the same output and leave the same tape.
+//
+//     cargo +nightly fuzz run optimize
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use membrane::interpreter::{EofMode, FinalState, Interpreter, RuntimeError};
+use membrane::optimizer::{self, OptimizeOptions};
+use membrane::program::Program;
+use membrane::testgen::{self, GenerateOptions};
+
+const MAX_STEPS: usize = 100_000;
+
+fn run(program: &Program, input: &[u8]) -> Result<(Vec<u8>, FinalState), RuntimeError> {
+    let mut input = input.iter().copied();
+    let mut output = Vec::new();
+
+    let state = Interpreter::builder()
+        .eof(EofMode::Zero)
+        .max_steps(MAX_STEPS)
+        .build()
+        .run_with(
+            &program.instructions,
+            || input.next(),
+            |byte| output.push(byte),
+        )?;
+
+    Ok((output, state))
+}
+
+// The tape up to its last nonzero cell, since the two programs needn't have touched the
+// same cells past it.
+fn cells(state: &FinalState) -> &[u32] {
+    let length = state
+        .tape
+        .iter()
+        .rposition(|&cell| cell != 0)
+        .map_or(0, |index| index + 1);
+    &state.tape[..length]
+}
+
+fuzz_target!(|case: (&[u8], &[u8])| {
+    let (code, input) = case;
+    let options = GenerateOptions {
+        size: 1024,
+        ..GenerateOptions::default()
+    };
+    let program = testgen::program_from_bytes(code, &options);
+
+    // Programs that run too long or move left of the tape aren't compared, since the
+    // optimizer is free to change where and when they stop.
+    let (expected_output, expected) = match run(&program, input) {
+        Ok(result) => result,
+        Err(_) => return,
+    };
+
+    let mut optimized = program.clone();
+    let options = OptimizeOptions {
+        parallel: false,
+        ..OptimizeOptions::default()
+    };
+
+    if let Err(err) = optimizer::optimize_program(&mut optimized, &options) {
+        assert!(!err.is_fatal(), "{}", err);
+    }
+
+    let (output, state) = match run(&optimized, input) {
+        Ok(result) => result,
+        Err(err) => panic!("the optimized program stopped early: {}", err),
+    };
+
+    assert_eq!(expected_output, output, "the output differs");
+    assert_eq!(expected.head, state.head, "the head ended up elsewhere");
+    assert_eq!(cells(&expected), cells(&state), "the tape differs");
+});
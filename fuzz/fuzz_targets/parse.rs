@@ -0,0 +1,39 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+// Parses any bytes at all, which should either make a program or fail with errors, but
+// never panic. The first byte picks which of the parser's options are on, and the source
+// is parsed both whole and as it's read, which have to agree.
+//
+//     cargo +nightly fuzz run parse
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use membrane::parser::{self, ParseOptions};
+
+fuzz_target!(|data: &[u8]| {
+    let (flags, source) = match data.split_first() {
+        Some((&flags, source)) => (flags, source),
+        None => return,
+    };
+
+    let options = ParseOptions {
+        inline_input: flags & 1 != 0,
+        procedures: flags & 2 != 0,
+        trivia: flags & 4 != 0,
+        max_depth: (flags & 8 != 0).then_some(usize::from(flags >> 4)),
+        ..ParseOptions::default()
+    };
+
+    let whole = parser::parse_bytes_with(source, &options);
+    let read = parser::parse_reader_with(source, &options);
+
+    match (whole, read) {
+        (Ok(whole), Ok(read)) => assert_eq!(whole, read),
+        (Err(whole), Err(read)) => assert_eq!(whole.len(), read.len()),
+        (whole, read) => panic!("parsed whole: {:?}\nparsed as read: {:?}", whole, read),
+    }
+});
@@ -0,0 +1,63 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * The C interface to membrane, from the library built with:
+ *
+ *     cargo build --release --lib --features ffi
+ */
+
+#ifndef MEMBRANE_H
+#define MEMBRANE_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define MEMBRANE_OK 0
+#define MEMBRANE_NULL_PROGRAM (-1)
+#define MEMBRANE_OPTIMIZE_FAILED (-2)
+#define MEMBRANE_MOVED_OFF_TAPE 1
+#define MEMBRANE_END_OF_INPUT 2
+#define MEMBRANE_UNDEFINED_PROCEDURE 3
+/* The program stopped early for any other reason. */
+#define MEMBRANE_RUNTIME_FAILED 4
+
+typedef struct MembraneProgram MembraneProgram;
+
+/* Returns the next byte of input, or any negative number once there's no more. */
+typedef int (*MembraneRead)(void *context);
+typedef void (*MembraneWrite)(void *context, uint8_t byte);
+
+/*
+ * Parses `length` bytes of source, which must be readable unless `length` is zero.
+ * Returns NULL if the brackets don't match. The program is freed with membrane_free.
+ */
+MembraneProgram *membrane_parse(const uint8_t *source, size_t length);
+
+/* Optimizes the program in place. */
+int membrane_optimize(MembraneProgram *program);
+
+/*
+ * Runs the program on a tape of `tape_size` cells, or an infinite one if it's zero. Each
+ * callback is passed `context`. Without `read`, the program has no input, and without
+ * `write`, its output is thrown away. Returns MEMBRANE_OK once the program ends, or why
+ * it stopped before then.
+ */
+int membrane_run(const MembraneProgram *program, size_t tape_size, MembraneRead read,
+                 MembraneWrite write, void *context);
+
+/* Frees a program from membrane_parse. Freeing NULL does nothing. */
+void membrane_free(MembraneProgram *program);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
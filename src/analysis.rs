@@ -0,0 +1,215 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{HashMap, HashSet};
+use std::io::{Result as IOResult, Write};
+use std::ops::RangeInclusive;
+
+use crate::instruction::Instruction;
+use crate::program::{Program, ProgramStats};
+
+// A sequence of instructions (by kind, ignoring their operands) that shows up often enough
+// to be worth fusing into a single instruction.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Candidate {
+    pub names: Vec<&'static str>,
+    pub count: usize,
+    pub programs: usize,
+}
+
+impl Candidate {
+    // Every occurrence of a fused instruction replaces `n` dispatches with one.
+    #[inline]
+    pub fn dispatches_saved(&self) -> usize {
+        self.count * (self.names.len() - 1)
+    }
+}
+
+#[derive(Default)]
+struct Occurrences {
+    count: usize,
+    programs: usize,
+}
+
+pub struct NGramMiner {
+    lengths: RangeInclusive<usize>,
+    occurrences: HashMap<Vec<&'static str>, Occurrences>,
+    programs: usize,
+    instructions: usize,
+}
+
+impl NGramMiner {
+    pub fn new(lengths: RangeInclusive<usize>) -> Self {
+        Self {
+            lengths,
+            occurrences: HashMap::new(),
+            programs: 0,
+            instructions: 0,
+        }
+    }
+
+    pub fn programs(&self) -> usize {
+        self.programs
+    }
+
+    pub fn instructions(&self) -> usize {
+        self.instructions
+    }
+
+    pub fn add_program(&mut self, instructions: &[Instruction]) {
+        let names = instructions
+            .iter()
+            .map(Instruction::name)
+            .collect::<Vec<_>>();
+        let mut seen = HashSet::new();
+
+        for length in self.lengths.clone().filter(|length| *length > 0) {
+            for window in names.windows(length) {
+                let occurrences = self.occurrences.entry(window.to_vec()).or_default();
+                occurrences.count += 1;
+
+                if seen.insert(window) {
+                    occurrences.programs += 1;
+                }
+            }
+        }
+
+        self.programs += 1;
+        self.instructions += instructions.len();
+    }
+
+    // Single instructions are never candidates, since there's nothing to fuse.
+    pub fn candidates(&self) -> Vec<Candidate> {
+        let mut candidates = self
+            .occurrences
+            .iter()
+            .filter(|(names, _)| names.len() > 1)
+            .map(|(names, occurrences)| Candidate {
+                names: names.clone(),
+                count: occurrences.count,
+                programs: occurrences.programs,
+            })
+            .collect::<Vec<_>>();
+
+        candidates.sort_by(|a, b| {
+            b.dispatches_saved()
+                .cmp(&a.dispatches_saved())
+                .then(b.programs.cmp(&a.programs))
+                .then_with(|| a.names.cmp(&b.names))
+        });
+
+        candidates
+    }
+}
+
+// Metrics of a program that can be told without running it.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Metrics {
+    pub stats: ProgramStats,
+    // How many of each kind of instruction there are, most common first.
+    pub histogram: Vec<(&'static str, usize)>,
+    // An estimate of the cells the program uses, following its moves as if every loop and
+    // procedure ran once, and every scan stopped where it started.
+    pub tape_cells: usize,
+    // Whether the estimate has the program move left of the cell it starts on, which it
+    // can't do on a right-infinite tape.
+    pub moves_left: bool,
+    pub reads: bool,
+    pub writes: bool,
+}
+
+impl Metrics {
+    pub fn of(program: &Program) -> Self {
+        let mut counts = HashMap::new();
+        let (mut head, mut lowest, mut highest) = (0isize, 0isize, 0isize);
+        let (mut reads, mut writes) = (false, false);
+
+        for instruction in &program.instructions {
+            *counts.entry(instruction.name()).or_insert(0) += 1;
+
+            let touched = match *instruction {
+                Instruction::Move(amount) => {
+                    head += amount;
+                    head..=head
+                }
+                Instruction::AddRelative { offset, .. } | Instruction::MulAdd { offset, .. } => {
+                    head + offset..=head + offset
+                }
+                // Lanes that add nothing leave their cells alone.
+                Instruction::AddVector { vector } => {
+                    let last = vector.iter().rposition(|&amount| amount != 0).unwrap_or(0);
+                    head..=head + last as isize
+                }
+                Instruction::Read(_) => {
+                    reads = true;
+                    continue;
+                }
+                Instruction::Write(_) => {
+                    writes = true;
+                    continue;
+                }
+                _ => continue,
+            };
+
+            lowest = lowest.min(*touched.start());
+            highest = highest.max(*touched.end());
+        }
+
+        let mut histogram = counts.into_iter().collect::<Vec<_>>();
+        histogram.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+        Self {
+            stats: program.stats(),
+            histogram,
+            tape_cells: (highest - lowest) as usize + 1,
+            moves_left: lowest < 0,
+            reads,
+            writes,
+        }
+    }
+
+    // Writes the metrics as a JSON object, with each line after the first indented by the
+    // given amount so it can be nested in another object.
+    pub fn write_json<W: Write>(&self, writer: &mut W, indent: &str) -> IOResult<()> {
+        writeln!(writer, "{{")?;
+        writeln!(
+            writer,
+            "{}  \"instructions\": {},",
+            indent, self.stats.instructions
+        )?;
+        writeln!(writer, "{}  \"loops\": {},", indent, self.stats.loops)?;
+        writeln!(
+            writer,
+            "{}  \"procedures\": {},",
+            indent, self.stats.procedures
+        )?;
+        writeln!(
+            writer,
+            "{}  \"max_depth\": {},",
+            indent, self.stats.max_depth
+        )?;
+        writeln!(writer, "{}  \"tape_cells\": {},", indent, self.tape_cells)?;
+        writeln!(writer, "{}  \"moves_left\": {},", indent, self.moves_left)?;
+        writeln!(writer, "{}  \"reads\": {},", indent, self.reads)?;
+        writeln!(writer, "{}  \"writes\": {},", indent, self.writes)?;
+        write!(writer, "{}  \"histogram\": {{", indent)?;
+
+        for (index, (name, count)) in self.histogram.iter().enumerate() {
+            if index > 0 {
+                write!(writer, ",")?;
+            }
+
+            write!(writer, "\n{}    \"{}\": {}", indent, name, count)?;
+        }
+
+        if !self.histogram.is_empty() {
+            write!(writer, "\n{}  ", indent)?;
+        }
+
+        writeln!(writer, "}}")?;
+        write!(writer, "{}}}", indent)
+    }
+}
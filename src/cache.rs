@@ -0,0 +1,196 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::env;
+use std::fs::{self, File};
+use std::hash::Hasher;
+use std::io::{self, BufReader, BufWriter, Read, Result as IOResult, Write};
+use std::path::{Path, PathBuf};
+
+use crate::compilers::bytecode::{self, Header};
+use crate::instruction::Instruction;
+use crate::interpreter::TapeSize;
+use crate::optimizer::{self, Fnv, OptimizeOptions};
+use crate::span::Span;
+
+const MAGIC: &[u8; 4] = b"MBOC";
+const FORMAT_VERSION: u8 = 4;
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct CacheKey(u64);
+
+impl CacheKey {
+    // Keys cover the source, every option that affects the optimizer's output, and the
+    // versions of membrane and its optimizer that produced it.
+    pub fn new<R: Read>(source: R, options: &OptimizeOptions) -> IOResult<Self> {
+        let mut hasher = Fnv::default();
+        let mut reader = BufReader::new(source);
+        let mut buffer = [0; 8192];
+
+        loop {
+            match reader.read(&mut buffer)? {
+                0 => break,
+                length => hasher.write(&buffer[..length]),
+            }
+        }
+
+        hasher.write(env!("CARGO_PKG_VERSION").as_bytes());
+        hasher.write(&optimizer::VERSION.to_le_bytes());
+
+        match options.tape_size {
+            TapeSize::Finite(tape_size) => {
+                hasher.write(&[0]);
+                hasher.write(&(tape_size as u64).to_le_bytes());
+            }
+            TapeSize::Infinite => hasher.write(&[1]),
+        }
+
+        match options.fuel {
+            Some(fuel) => {
+                hasher.write(&[0]);
+                hasher.write(&(fuel as u64).to_le_bytes());
+            }
+            None => hasher.write(&[1]),
+        }
+
+        hasher.write(&(options.max_passes as u64).to_le_bytes());
+
+        Ok(Self(hasher.finish()))
+    }
+
+    pub fn for_file<P: AsRef<Path>>(path: P, options: &OptimizeOptions) -> IOResult<Self> {
+        Self::new(File::open(path)?, options)
+    }
+}
+
+pub struct Cache {
+    directory: PathBuf,
+}
+
+impl Cache {
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Self {
+        Self {
+            directory: directory.into(),
+        }
+    }
+
+    // `$XDG_CACHE_HOME/membrane`, falling back to `~/.cache/membrane`.
+    pub fn default_directory() -> Option<PathBuf> {
+        let base = match env::var_os("XDG_CACHE_HOME") {
+            Some(directory) if !directory.is_empty() => PathBuf::from(directory),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+        };
+
+        Some(base.join("membrane"))
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    // Stale, corrupted, or otherwise unreadable entries are treated as misses.
+    pub fn load(&self, key: CacheKey) -> Option<(Vec<Instruction>, Vec<Span>)> {
+        let file = File::open(self.entry_path(key)).ok()?;
+        read_entry(&mut BufReader::new(file), key).ok()
+    }
+
+    pub fn store(
+        &self,
+        key: CacheKey,
+        instructions: &[Instruction],
+        spans: &[Span],
+    ) -> IOResult<()> {
+        fs::create_dir_all(&self.directory)?;
+
+        // Entries are written to a temporary file first, so that concurrent runs never see a
+        // partially written entry.
+        let path = self.entry_path(key);
+        let temporary_path = path.with_extension(format!("tmp{}", std::process::id()));
+
+        {
+            let mut writer = BufWriter::new(File::create(&temporary_path)?);
+            write_entry(&mut writer, key, instructions, spans)?;
+            writer.flush()?;
+        }
+
+        fs::rename(temporary_path, path)
+    }
+
+    fn entry_path(&self, key: CacheKey) -> PathBuf {
+        self.directory.join(format!("{:016x}.bin", key.0))
+    }
+}
+
+// Entries are the key, followed by the instructions as bytecode and then a span for each
+// one, since bytecode has no room for them.
+fn write_entry<W: Write>(
+    writer: &mut W,
+    key: CacheKey,
+    instructions: &[Instruction],
+    spans: &[Span],
+) -> IOResult<()> {
+    let header = Header {
+        optimized: true,
+        ..Header::new(TapeSize::Infinite)
+    };
+
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[FORMAT_VERSION])?;
+    writer.write_all(&key.0.to_le_bytes())?;
+    bytecode::encode(instructions, &header, writer)?;
+
+    for span in spans {
+        writer.write_all(&(span.start as u64).to_le_bytes())?;
+        writer.write_all(&(span.end as u64).to_le_bytes())?;
+        writer.write_all(&(span.line as u64).to_le_bytes())?;
+        writer.write_all(&(span.column as u64).to_le_bytes())?;
+    }
+
+    Ok(())
+}
+
+fn read_entry<R: Read>(reader: &mut R, key: CacheKey) -> IOResult<(Vec<Instruction>, Vec<Span>)> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+
+    if &magic != MAGIC || read_u8(reader)? != FORMAT_VERSION || read_u64(reader)? != key.0 {
+        return Err(invalid_entry());
+    }
+
+    let (program, _) = bytecode::decode(reader).map_err(|_| invalid_entry())?;
+    let mut spans = Vec::with_capacity(program.len());
+
+    for _ in 0..program.len() {
+        let start = read_u64(reader)? as usize;
+        let end = read_u64(reader)? as usize;
+
+        spans.push(Span {
+            line: read_u64(reader)? as usize,
+            column: read_u64(reader)? as usize,
+            ..Span::new(start, end)
+        });
+    }
+
+    Ok((program.instructions, spans))
+}
+
+#[inline]
+fn read_u8<R: Read>(reader: &mut R) -> IOResult<u8> {
+    let mut bytes = [0; 1];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+#[inline]
+fn read_u64<R: Read>(reader: &mut R) -> IOResult<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn invalid_entry() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "invalid cache entry")
+}
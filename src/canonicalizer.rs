@@ -0,0 +1,316 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::mem;
+
+use crate::instruction::Instruction;
+use crate::interpreter::TapeSize;
+use crate::lowering;
+use crate::optimizer::BalancedLoop;
+
+// Produces a normal form of a program: straight-line code becomes its net effect (sorted
+// relative adds followed by a single move), balanced loops become multiplications followed
+// by `SetValue`, and loops that can never be entered are dropped. Two programs with the
+// same normal form are equivalent, though the converse doesn't necessarily hold.
+pub fn canonicalize(instructions: &[Instruction]) -> Vec<Instruction> {
+    let lowered = lowering::lower(instructions);
+    let mut canonical = Vec::new();
+    let mut index = 0;
+
+    canonicalize_body(
+        &lowered,
+        &mut index,
+        &mut canonical,
+        true,
+        &mut Some(Prologue::default()),
+    );
+
+    canonical
+}
+
+#[derive(Default)]
+struct Block {
+    deltas: BTreeMap<isize, i8>,
+    position: isize,
+}
+
+impl Block {
+    #[inline]
+    fn add(&mut self, amount: i8) {
+        self.add_at(self.position, amount);
+    }
+
+    #[inline]
+    fn add_at(&mut self, offset: isize, amount: i8) {
+        let delta = self.deltas.entry(offset).or_default();
+        *delta = delta.wrapping_add(amount);
+    }
+
+    #[inline]
+    fn shift(&mut self, amount: isize) {
+        self.position += amount;
+    }
+
+    // Returns whether the current cell is known to be zero after the block executes.
+    fn flush(&mut self, canonical: &mut Vec<Instruction>, cell_is_zero: bool) -> bool {
+        let mut deltas = mem::take(&mut self.deltas);
+        let position = mem::take(&mut self.position);
+
+        if let Some(Instruction::SetValue(value)) = canonical.last_mut() {
+            if let Some(delta) = deltas.remove(&0) {
+                *value = value.wrapping_add(delta);
+            }
+        }
+
+        let mut cell_is_zero = match canonical.last() {
+            Some(Instruction::SetValue(value)) => *value == 0,
+            _ => cell_is_zero,
+        };
+
+        for (offset, amount) in deltas {
+            if amount == 0 {
+                continue;
+            }
+
+            if offset == 0 {
+                canonical.push(Instruction::Add(amount));
+                cell_is_zero = false;
+            } else {
+                canonical.push(Instruction::AddRelative { offset, amount });
+            }
+        }
+
+        if position != 0 {
+            canonical.push(Instruction::Move(position));
+            cell_is_zero = false;
+        }
+
+        cell_is_zero
+    }
+}
+
+// Until the first loop that may run, the tape head's position is known and every cell
+// that hasn't been touched yet is still zero.
+#[derive(Default)]
+struct Prologue {
+    head: isize,
+    touched: BTreeSet<isize>,
+}
+
+impl Prologue {
+    fn is_zero(&self, block: &Block) -> bool {
+        block
+            .deltas
+            .get(&block.position)
+            .copied()
+            .unwrap_or_default()
+            == 0
+            && !self.touched.contains(&(self.head + block.position))
+    }
+
+    fn apply(&mut self, block: &Block) {
+        for (offset, amount) in &block.deltas {
+            if *amount != 0 {
+                self.touched.insert(self.head + offset);
+            }
+        }
+
+        self.head += block.position;
+    }
+}
+
+fn canonicalize_body(
+    lowered: &[Instruction],
+    index: &mut usize,
+    canonical: &mut Vec<Instruction>,
+    mut cell_is_zero: bool,
+    prologue: &mut Option<Prologue>,
+) {
+    let mut block = Block::default();
+
+    while let Some(instruction) = lowered.get(*index) {
+        *index += 1;
+
+        // A balanced loop over a cell whose value is known is just straight-line code.
+        if let Instruction::JumpIfZero { location } = *instruction {
+            if fold_known_balanced_loop(&lowered[*index..location], &mut block, canonical, prologue)
+            {
+                *index = location + 1;
+                continue;
+            }
+        }
+
+        if let Some(prologue) = prologue {
+            match instruction {
+                Instruction::JumpIfZero { location } if prologue.is_zero(&block) => {
+                    *index = location + 1;
+                    continue;
+                }
+                Instruction::Write(_)
+                | Instruction::Read(_)
+                | Instruction::JumpIfZero { .. }
+                | Instruction::JumpIfNotZero { .. }
+                | Instruction::DefineProc { .. }
+                | Instruction::CallProc => {
+                    prologue.apply(&block);
+                }
+                _ => {}
+            }
+
+            if let Instruction::Read(_) = instruction {
+                prologue.touched.insert(prologue.head);
+            }
+        }
+
+        match *instruction {
+            Instruction::Add(amount) => block.add(amount),
+            Instruction::Move(amount) => block.shift(amount),
+            Instruction::Write(amount) => {
+                cell_is_zero = block.flush(canonical, cell_is_zero);
+
+                if let Some(Instruction::Write(total)) = canonical.last_mut() {
+                    *total += amount;
+                } else {
+                    canonical.push(Instruction::Write(amount));
+                }
+            }
+            Instruction::Read(amount) => {
+                block.flush(canonical, cell_is_zero);
+                cell_is_zero = false;
+
+                if let Some(Instruction::Read(total)) = canonical.last_mut() {
+                    *total += amount;
+                } else {
+                    canonical.push(Instruction::Read(amount));
+                }
+            }
+            Instruction::JumpIfZero { location } => {
+                let balanced =
+                    BalancedLoop::analyze(&lowered[*index..location], TapeSize::Infinite);
+                let multiplications = balanced
+                    .as_ref()
+                    .map(|balanced| balanced.multiplications().collect::<Vec<_>>());
+
+                // Anything added to the current cell right before it gets cleared is dead.
+                if let Some(multiplications) = &multiplications {
+                    if multiplications.is_empty() {
+                        block.deltas.remove(&block.position);
+                    }
+                }
+
+                cell_is_zero = block.flush(canonical, cell_is_zero);
+
+                if cell_is_zero {
+                    *index = location + 1;
+                } else if let Some(multiplications) = multiplications {
+                    *index = location + 1;
+
+                    if let Some(prologue) = prologue {
+                        for multiplication in &multiplications {
+                            if let Instruction::MulAdd { offset, .. } = multiplication {
+                                prologue.touched.insert(prologue.head + offset);
+                            }
+                        }
+                    }
+
+                    let is_clearing = multiplications.is_empty();
+                    canonical.extend(multiplications);
+
+                    match canonical.last_mut() {
+                        Some(Instruction::SetValue(value)) if is_clearing => *value = 0,
+                        _ => canonical.push(Instruction::SetValue(0)),
+                    }
+                } else {
+                    *prologue = None;
+
+                    let loop_start = canonical.len();
+                    canonical.push(Instruction::JumpIfZero { location: 0 });
+
+                    canonicalize_body(lowered, index, canonical, false, &mut None);
+
+                    let loop_end = canonical.len();
+                    canonical[loop_start] = Instruction::JumpIfZero { location: loop_end };
+                    canonical.push(Instruction::JumpIfNotZero {
+                        location: loop_start,
+                    });
+                }
+
+                cell_is_zero = true;
+            }
+            Instruction::JumpIfNotZero { .. } | Instruction::EndProc => {
+                block.flush(canonical, false);
+                return;
+            }
+            // Defining a procedure leaves the tape alone, but its body can run anywhere.
+            Instruction::DefineProc { .. } => {
+                cell_is_zero = block.flush(canonical, cell_is_zero);
+
+                let start = canonical.len();
+                canonical.push(Instruction::DefineProc { location: 0 });
+
+                canonicalize_body(lowered, index, canonical, false, &mut None);
+
+                let end = canonical.len();
+                canonical[start] = Instruction::DefineProc { location: end };
+                canonical.push(Instruction::EndProc);
+            }
+            Instruction::CallProc => {
+                block.flush(canonical, cell_is_zero);
+                *prologue = None;
+                cell_is_zero = false;
+                canonical.push(Instruction::CallProc);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    block.flush(canonical, cell_is_zero);
+}
+
+fn fold_known_balanced_loop(
+    body: &[Instruction],
+    block: &mut Block,
+    canonical: &mut [Instruction],
+    prologue: &Option<Prologue>,
+) -> bool {
+    let delta = block
+        .deltas
+        .get(&block.position)
+        .copied()
+        .unwrap_or_default();
+
+    // The current cell is either still untouched, or was last stored to at the start of the
+    // block.
+    let (value, from_store) = match (prologue, canonical.last()) {
+        (Some(prologue), _) if !prologue.touched.contains(&(prologue.head + block.position)) => {
+            (delta, false)
+        }
+        (_, Some(Instruction::SetValue(value))) if block.position == 0 => {
+            (value.wrapping_add(delta), true)
+        }
+        _ => return false,
+    };
+
+    let balanced = match BalancedLoop::analyze(body, TapeSize::Infinite) {
+        Some(balanced) => balanced,
+        None => return false,
+    };
+
+    for multiplication in balanced.multiplications() {
+        if let Instruction::MulAdd { offset, factor } = multiplication {
+            block.add_at(block.position + offset, value.wrapping_mul(factor));
+        }
+    }
+
+    block.deltas.remove(&block.position);
+
+    if let (true, Some(Instruction::SetValue(value))) = (from_store, canonical.last_mut()) {
+        *value = 0;
+    }
+
+    true
+}
@@ -0,0 +1,159 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use thiserror::Error;
+
+use crate::instruction::Instruction;
+
+// A program as a tree of loops, for backends that write structured code. Every node keeps
+// the index of the instruction it came from, which stays unique, so it can be used for
+// labels and for mapping generated code back to the program.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum Node {
+    // Any instruction other than a jump.
+    Instruction {
+        index: usize,
+        instruction: Instruction,
+    },
+    // The instructions between a pair of jumps, where `index` is that of the opening jump.
+    Loop {
+        index: usize,
+        body: Vec<Node>,
+    },
+    // The body of a pbrain procedure, where `index` is that of its DefineProc. Only built
+    // for backends that support procedures.
+    Procedure {
+        index: usize,
+        body: Vec<Node>,
+    },
+}
+
+impl Node {
+    // The index of the instruction the node starts with.
+    pub fn index(&self) -> usize {
+        match self {
+            Self::Instruction { index, .. }
+            | Self::Loop { index, .. }
+            | Self::Procedure { index, .. } => *index,
+        }
+    }
+
+    // The index of the instruction the node ends with, which is the closing jump of loops
+    // and the EndProc of procedures.
+    pub fn last_index(&self) -> usize {
+        self.index() + self.instruction_count() - 1
+    }
+
+    // The number of instructions the node covers, including the jumps of loops and the
+    // DefineProc and EndProc of procedures.
+    pub fn instruction_count(&self) -> usize {
+        match self {
+            Self::Instruction { .. } => 1,
+            Self::Loop { body, .. } | Self::Procedure { body, .. } => {
+                body.iter().map(Self::instruction_count).sum::<usize>() + 2
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Error)]
+pub enum AstError {
+    #[error("JumpIfZero at instruction {index} has no matching JumpIfNotZero")]
+    UnmatchedJumpIfZero { index: usize },
+    #[error("JumpIfNotZero at instruction {index} has no matching JumpIfZero")]
+    UnmatchedJumpIfNotZero { index: usize },
+    #[error("DefineProc at instruction {index} has no matching EndProc")]
+    UnmatchedDefineProc { index: usize },
+    #[error("EndProc at instruction {index} has no matching DefineProc")]
+    UnmatchedEndProc { index: usize },
+    #[error("instruction {index} uses a pbrain procedure, which this format doesn't support")]
+    UnsupportedProcedure { index: usize },
+}
+
+// Pairs up jumps by how they nest, rather than trusting their locations, so a backend
+// can't be handed a loop that ends before it starts. Procedures are refused, since most
+// backends don't support them.
+pub fn build(instructions: &[Instruction]) -> Result<Vec<Node>, AstError> {
+    build_nodes(instructions, false)
+}
+
+// Builds the tree like `build`, with procedures as nodes of their own.
+pub fn build_with_procedures(instructions: &[Instruction]) -> Result<Vec<Node>, AstError> {
+    build_nodes(instructions, true)
+}
+
+fn build_nodes(instructions: &[Instruction], procedures: bool) -> Result<Vec<Node>, AstError> {
+    // Loops and procedures share the stack, so neither can end inside the other.
+    let mut open: Vec<(usize, Instruction, Vec<Node>)> = Vec::new();
+    let mut nodes = Vec::new();
+
+    for (index, instruction) in instructions.iter().enumerate() {
+        match instruction {
+            Instruction::DefineProc { .. } | Instruction::EndProc | Instruction::CallProc
+                if !procedures =>
+            {
+                return Err(AstError::UnsupportedProcedure { index });
+            }
+            Instruction::JumpIfZero { .. } | Instruction::DefineProc { .. } => {
+                open.push((index, *instruction, nodes));
+                nodes = Vec::new();
+            }
+            Instruction::JumpIfNotZero { .. } => match open.pop() {
+                Some((start, Instruction::JumpIfZero { .. }, mut outer)) => {
+                    outer.push(Node::Loop {
+                        index: start,
+                        body: nodes,
+                    });
+                    nodes = outer;
+                }
+                _ => return Err(AstError::UnmatchedJumpIfNotZero { index }),
+            },
+            Instruction::EndProc => match open.pop() {
+                Some((start, Instruction::DefineProc { .. }, mut outer)) => {
+                    outer.push(Node::Procedure {
+                        index: start,
+                        body: nodes,
+                    });
+                    nodes = outer;
+                }
+                _ => return Err(AstError::UnmatchedEndProc { index }),
+            },
+            instruction => nodes.push(Node::Instruction {
+                index,
+                instruction: *instruction,
+            }),
+        }
+    }
+
+    match open.pop() {
+        Some((index, Instruction::DefineProc { .. }, _)) => {
+            Err(AstError::UnmatchedDefineProc { index })
+        }
+        Some((index, ..)) => Err(AstError::UnmatchedJumpIfZero { index }),
+        None => Ok(nodes),
+    }
+}
+
+// The index of every loop, in the order they start. Loops are identified by their index
+// wherever they're reported, so counts from instrumented code line up with the program.
+pub fn loop_ids(nodes: &[Node]) -> Vec<usize> {
+    let mut ids = Vec::new();
+    collect_loop_ids(nodes, &mut ids);
+    ids
+}
+
+fn collect_loop_ids(nodes: &[Node], ids: &mut Vec<usize>) {
+    for node in nodes {
+        match node {
+            Node::Loop { index, body } => {
+                ids.push(*index);
+                collect_loop_ids(body, ids);
+            }
+            Node::Procedure { body, .. } => collect_loop_ids(body, ids),
+            Node::Instruction { .. } => {}
+        }
+    }
+}
@@ -0,0 +1,117 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::io::{Result as IOResult, Write};
+use std::iter;
+
+use crate::instruction::Instruction;
+use crate::lowering;
+use crate::program::Program;
+
+use super::{Backend, CompileError, CompileOptions, ProgramInfo};
+
+const LINE_LENGTH: usize = 80;
+
+pub struct BrainfuckBackend;
+
+impl Backend for BrainfuckBackend {
+    fn name(&self) -> &'static str {
+        "brainfuck"
+    }
+
+    fn file_extension(&self) -> &'static str {
+        "bf"
+    }
+
+    fn compile(
+        &self,
+        program: &Program,
+        _options: &CompileOptions,
+        _info: &ProgramInfo,
+        mut writer: &mut dyn Write,
+    ) -> Result<(), CompileError> {
+        Ok(compile(&program.instructions, &mut writer)?)
+    }
+}
+
+// Only the eight commands are written, so there's nothing else for another tool to mistake
+// for code. Lines are wrapped to keep the output readable in an editor.
+pub fn compile<W: Write>(instructions: &[Instruction], writer: &mut W) -> IOResult<()> {
+    let code = minify(lowering::to_brainfuck(instructions).as_bytes());
+
+    for line in code.chunks(LINE_LENGTH) {
+        writer.write_all(line)?;
+        writeln!(writer)?;
+    }
+
+    Ok(())
+}
+
+// Lowering expands every instruction on its own, so neighbouring instructions leave behind
+// moves and adds that cancel out, and stores that clear cells already known to be zero.
+pub fn minify(code: &[u8]) -> Vec<u8> {
+    let mut minified = Vec::with_capacity(code.len());
+    let mut index = 0;
+
+    while index < code.len() {
+        let command = code[index];
+
+        let (up, down) = match command {
+            b'+' | b'-' => (b'+', b'-'),
+            b'>' | b'<' => (b'>', b'<'),
+            // Every cell starts out as zero, and a loop only exits on a zero cell, so a loop
+            // at the start of the program or right after another one never runs.
+            b'[' if matches!(minified.last(), None | Some(b']')) => {
+                index = loop_end(code, index) + 1;
+                continue;
+            }
+            _ => {
+                minified.push(command);
+                index += 1;
+                continue;
+            }
+        };
+
+        let mut net = 0isize;
+
+        while let Some(&next) = code.get(index) {
+            if next == up {
+                net += 1;
+            } else if next == down {
+                net -= 1;
+            } else {
+                break;
+            }
+
+            index += 1;
+        }
+
+        // Cells wrap, so adds take whichever direction is shorter.
+        if up == b'+' {
+            net = (net as i8) as isize;
+        }
+
+        let command = if net < 0 { down } else { up };
+        minified.extend(iter::repeat_n(command, net.unsigned_abs()));
+    }
+
+    minified
+}
+
+fn loop_end(code: &[u8], start: usize) -> usize {
+    let mut depth = 0;
+
+    for (index, command) in code.iter().enumerate().skip(start) {
+        match command {
+            b'[' => depth += 1,
+            b']' if depth == 1 => return index,
+            b']' => depth -= 1,
+            _ => {}
+        }
+    }
+
+    code.len()
+}
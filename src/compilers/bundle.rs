@@ -0,0 +1,305 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::io::Write;
+
+use crate::instruction::Instruction;
+use crate::program::Program;
+
+use super::ast::AstError;
+use super::bytecode::{self, opcode};
+use super::{rust, write_header, Backend, CompileError, CompileOptions, ProgramInfo};
+
+// The bytecode is decoded once when the program starts, then run by a loop over the
+// instructions that mirrors the interpreter's, on the same tape as the Rust backend. Jumps
+// go straight past their matching jump, since it would only check the cell again. Cell
+// amounts are stored as bytes and sign-extended, the same way compiled adds are.
+const INTERPRETER: &str = r#"
+#[derive(Clone, Copy)]
+enum Instruction {
+    Add(Cell),
+    Move(isize),
+    Write(usize),
+    Read(usize),
+    JumpIfZero(usize),
+    JumpIfNotZero(usize),
+    SetValue(Cell),
+    AddRelative(isize, Cell),
+    AddVector([Cell; 4]),
+    MulAdd(isize, Cell),
+    MoveRightToZero(Cell, usize),
+    MoveLeftToZero(Cell, usize),
+}
+
+struct Decoder {
+    position: usize,
+}
+
+impl Decoder {
+    fn byte(&mut self) -> u8 {
+        let byte = BYTECODE[self.position];
+        self.position += 1;
+        byte
+    }
+
+    fn amount(&mut self) -> Cell {
+        self.byte() as i8 as Cell
+    }
+
+    fn unsigned(&mut self) -> usize {
+        let mut value = 0;
+        let mut shift = 0;
+
+        loop {
+            let byte = self.byte();
+            value |= ((byte & 0x7f) as usize) << shift;
+            shift += 7;
+
+            if byte & 0x80 == 0 {
+                return value;
+            }
+        }
+    }
+
+    fn signed(&mut self) -> isize {
+        let mut value = 0;
+        let mut shift = 0;
+
+        loop {
+            let byte = self.byte();
+            value |= ((byte & 0x7f) as isize) << shift;
+            shift += 7;
+
+            if byte & 0x80 == 0 {
+                if shift < isize::BITS && byte & 0x40 != 0 {
+                    value |= -1 << shift;
+                }
+
+                return value;
+            }
+        }
+    }
+
+    fn instruction(&mut self) -> Instruction {
+        match self.byte() {
+            ADD => Instruction::Add(self.amount()),
+            MOVE => Instruction::Move(self.signed()),
+            WRITE => Instruction::Write(self.unsigned()),
+            READ => Instruction::Read(self.unsigned()),
+            JUMP_IF_ZERO => Instruction::JumpIfZero(self.unsigned()),
+            JUMP_IF_NOT_ZERO => Instruction::JumpIfNotZero(self.unsigned()),
+            SET_VALUE => Instruction::SetValue(self.amount()),
+            ADD_RELATIVE => Instruction::AddRelative(self.signed(), self.amount()),
+            ADD_VECTOR => Instruction::AddVector([
+                self.amount(),
+                self.amount(),
+                self.amount(),
+                self.amount(),
+            ]),
+            MUL_ADD => Instruction::MulAdd(self.signed(), self.amount()),
+            MOVE_RIGHT_TO_ZERO => Instruction::MoveRightToZero(self.amount(), self.unsigned()),
+            MOVE_LEFT_TO_ZERO => Instruction::MoveLeftToZero(self.amount(), self.unsigned()),
+            opcode => unreachable!("invalid opcode {:#04x}", opcode),
+        }
+    }
+}
+
+fn decode() -> Vec<Instruction> {
+    let mut decoder = Decoder { position: 0 };
+    let count = decoder.unsigned();
+
+    (0..count).map(|_| decoder.instruction()).collect()
+}
+
+fn at(tape: &mut Tape, offset: isize) -> usize {
+    if offset >= 0 {
+        tape.at_right(offset as usize)
+    } else {
+        tape.at_left(offset.unsigned_abs())
+    }
+}
+
+fn main() {
+    let instructions = decode();
+    let mut tape = Tape::new();
+    let mut counter = 0;
+
+    while let Some(instruction) = instructions.get(counter) {
+        counter += 1;
+
+        match *instruction {
+            Instruction::Add(amount) => tape.add(tape.head, amount),
+            Instruction::Move(amount) => {
+                if amount >= 0 {
+                    tape.move_right(amount as usize);
+                } else {
+                    tape.move_left(amount.unsigned_abs());
+                }
+            }
+            Instruction::Write(count) => tape.output(count),
+            Instruction::Read(count) => tape.input(count),
+            Instruction::JumpIfZero(location) => {
+                if tape.cells[tape.head] == 0 {
+                    counter = location + 1;
+                }
+            }
+            Instruction::JumpIfNotZero(location) => {
+                if tape.cells[tape.head] != 0 {
+                    counter = location + 1;
+                }
+            }
+            Instruction::SetValue(value) => {
+                let head = tape.head;
+                tape.cells[head] = value;
+            }
+            Instruction::AddRelative(offset, amount) => {
+                let index = at(&mut tape, offset);
+                tape.add(index, amount);
+            }
+            Instruction::AddVector(vector) => {
+                for (lane, amount) in vector.iter().copied().enumerate() {
+                    if amount != 0 {
+                        let index = tape.at_right(lane);
+                        tape.add(index, amount);
+                    }
+                }
+            }
+            Instruction::MulAdd(offset, factor) => {
+                if tape.cells[tape.head] != 0 {
+                    let index = at(&mut tape, offset);
+                    tape.add(index, tape.cells[tape.head].wrapping_mul(factor));
+                }
+            }
+            Instruction::MoveRightToZero(increment, stride) => {
+                while tape.cells[tape.head] != 0 {
+                    tape.add(tape.head, increment);
+                    tape.move_right(stride);
+                }
+            }
+            Instruction::MoveLeftToZero(increment, stride) => {
+                while tape.cells[tape.head] != 0 {
+                    tape.add(tape.head, increment);
+                    tape.move_left(stride);
+                }
+            }
+        }
+    }
+
+    tape.flush();
+}
+"#;
+
+pub struct BundleBackend;
+
+impl Backend for BundleBackend {
+    fn name(&self) -> &'static str {
+        "bundle"
+    }
+
+    fn file_extension(&self) -> &'static str {
+        "rs"
+    }
+
+    fn compile(
+        &self,
+        program: &Program,
+        options: &CompileOptions,
+        info: &ProgramInfo,
+        mut writer: &mut dyn Write,
+    ) -> Result<(), CompileError> {
+        compile(&program.instructions, options, info, &mut writer)
+    }
+}
+
+// A Rust program that embeds the instructions as bytecode, along with an interpreter for
+// them, so its size grows with the bytecode rather than with the code each instruction
+// would compile to. It behaves the same as the output of the Rust backend.
+pub fn compile<W: Write>(
+    instructions: &[Instruction],
+    options: &CompileOptions,
+    info: &ProgramInfo,
+    writer: &mut W,
+) -> Result<(), CompileError> {
+    write_header(writer, "bundle", options, info, "//")?;
+
+    writeln!(writer)?;
+    // Bundles aren't instrumented, so they don't report before failing.
+    let options = CompileOptions {
+        instrument: false,
+        ..*options
+    };
+
+    rust::write_runtime(writer, &options)?;
+    writeln!(writer)?;
+
+    let opcodes = [
+        ("ADD", opcode::ADD),
+        ("MOVE", opcode::MOVE),
+        ("WRITE", opcode::WRITE),
+        ("READ", opcode::READ),
+        ("JUMP_IF_ZERO", opcode::JUMP_IF_ZERO),
+        ("JUMP_IF_NOT_ZERO", opcode::JUMP_IF_NOT_ZERO),
+        ("SET_VALUE", opcode::SET_VALUE),
+        ("ADD_RELATIVE", opcode::ADD_RELATIVE),
+        ("ADD_VECTOR", opcode::ADD_VECTOR),
+        ("MUL_ADD", opcode::MUL_ADD),
+        ("MOVE_RIGHT_TO_ZERO", opcode::MOVE_RIGHT_TO_ZERO),
+        ("MOVE_LEFT_TO_ZERO", opcode::MOVE_LEFT_TO_ZERO),
+    ];
+
+    for (name, opcode) in opcodes {
+        writeln!(writer, "const {}: u8 = {:#04x};", name, opcode)?;
+    }
+
+    let mut bytecode = Vec::new();
+    bytecode::encode_instructions(&pair_jumps(instructions)?, &mut bytecode)?;
+
+    writeln!(writer)?;
+    writeln!(writer, "static BYTECODE: &[u8] = &[")?;
+
+    for line in bytecode.chunks(16) {
+        let bytes = line
+            .iter()
+            .map(|byte| format!("{:#04x}", byte))
+            .collect::<Vec<_>>();
+
+        writeln!(writer, "    {},", bytes.join(", "))?;
+    }
+
+    writeln!(writer, "];")?;
+    writer.write_all(INTERPRETER.as_bytes())?;
+    Ok(())
+}
+
+// Points every jump at the one it pairs with by nesting, the way the other backends pair
+// them, since the interpreter loop follows their locations.
+fn pair_jumps(instructions: &[Instruction]) -> Result<Vec<Instruction>, AstError> {
+    let mut paired = instructions.to_vec();
+    let mut open = Vec::new();
+
+    for (index, instruction) in instructions.iter().enumerate() {
+        match instruction {
+            Instruction::JumpIfZero { .. } => open.push(index),
+            Instruction::JumpIfNotZero { .. } => {
+                let start = open
+                    .pop()
+                    .ok_or(AstError::UnmatchedJumpIfNotZero { index })?;
+
+                paired[start] = Instruction::JumpIfZero { location: index };
+                paired[index] = Instruction::JumpIfNotZero { location: start };
+            }
+            Instruction::DefineProc { .. } | Instruction::EndProc | Instruction::CallProc => {
+                return Err(AstError::UnsupportedProcedure { index });
+            }
+            _ => {}
+        }
+    }
+
+    match open.pop() {
+        Some(index) => Err(AstError::UnmatchedJumpIfZero { index }),
+        None => Ok(paired),
+    }
+}
@@ -0,0 +1,660 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+use std::io::{self, Read, Result as IOResult, Write};
+
+use thiserror::Error;
+
+use crate::instruction::Instruction;
+use crate::interpreter::{CellWidth, EofMode, TapeSize};
+use crate::program::Program;
+
+use super::{Backend, CompileError, CompileOptions, ProgramInfo};
+
+// Layout:
+//
+//   magic        "BFC"
+//   version      u8
+//   flags        u8; bit 0 is set for optimized programs, and bits 1-2 hold the EOF mode
+//   tape size    unsigned, where zero means a right-infinite tape
+//   cell width   u8, in bytes
+//   metadata     unsigned count, followed by that many key/value pairs of strings
+//   length       unsigned, the size of the payload in bytes
+//   checksum     u32 little-endian, the CRC32 of the payload
+//   payload      unsigned count, followed by that many instructions
+//
+// Each instruction is a one-byte opcode followed by its operands, and jump targets are
+// instruction indices. Unsigned and signed integers are LEB128 varints, and strings are
+// an unsigned length followed by UTF-8.
+//
+// Version 2 had no flags, metadata, length, or checksum. Version 1 additionally stored
+// integers as 64-bit little-endian. Both are still read.
+pub const MAGIC: &[u8; 3] = b"BFC";
+pub const VERSION: u8 = 3;
+
+// The metadata key that records the path of the source file.
+pub const SOURCE_KEY: &str = "source";
+
+const FLAG_OPTIMIZED: u8 = 1 << 0;
+const FLAG_EOF_MODE_SHIFT: u8 = 1;
+const FLAG_EOF_MODE_MASK: u8 = 0b11 << FLAG_EOF_MODE_SHIFT;
+
+pub mod opcode {
+    pub const ADD: u8 = 0x00;
+    pub const MOVE: u8 = 0x01;
+    pub const WRITE: u8 = 0x02;
+    pub const READ: u8 = 0x03;
+    pub const JUMP_IF_ZERO: u8 = 0x04;
+    pub const JUMP_IF_NOT_ZERO: u8 = 0x05;
+
+    pub const SET_VALUE: u8 = 0x10;
+    pub const ADD_RELATIVE: u8 = 0x11;
+    pub const ADD_VECTOR: u8 = 0x12;
+    pub const MUL_ADD: u8 = 0x13;
+    pub const MOVE_RIGHT_TO_ZERO: u8 = 0x14;
+    pub const MOVE_LEFT_TO_ZERO: u8 = 0x15;
+
+    pub const DEFINE_PROC: u8 = 0x20;
+    pub const END_PROC: u8 = 0x21;
+    pub const CALL_PROC: u8 = 0x22;
+}
+
+#[derive(Debug, Error)]
+pub enum BytecodeError {
+    #[error("failed to read bytecode: {0}")]
+    Io(#[from] io::Error),
+    #[error("not a membrane bytecode file")]
+    InvalidMagic,
+    #[error("unsupported bytecode version {version}")]
+    UnsupportedVersion { version: u8 },
+    #[error("unsupported cell width of {cell_width} byte(s)")]
+    UnsupportedCellWidth { cell_width: u8 },
+    #[error("invalid opcode {opcode:#04x} at instruction {index}")]
+    InvalidOpcode { index: usize, opcode: u8 },
+    #[error("jump at instruction {index} targets {location}, which isn't its matching jump")]
+    InvalidJumpTarget { index: usize, location: usize },
+    #[error("instruction {index} does I/O {amount} times, more than any program can")]
+    InvalidAmount { index: usize, amount: u64 },
+    #[error("unsupported flags {flags:#010b}")]
+    UnsupportedFlags { flags: u8 },
+    #[error("metadata isn't valid UTF-8")]
+    InvalidMetadata,
+    #[error(
+        "checksum mismatch (expected {expected:08x}, found {actual:08x}); the file is corrupted"
+    )]
+    ChecksumMismatch { expected: u32, actual: u32 },
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Header {
+    pub version: u8,
+    pub optimized: bool,
+    pub eof_mode: EofMode,
+    pub tape_size: TapeSize,
+    pub cell_width: u8,
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl Header {
+    pub fn new(tape_size: TapeSize) -> Self {
+        Self {
+            version: VERSION,
+            optimized: false,
+            eof_mode: EofMode::default(),
+            tape_size,
+            cell_width: 1,
+            metadata: BTreeMap::new(),
+        }
+    }
+
+    fn flags(&self) -> u8 {
+        let eof_mode = match self.eof_mode {
+            EofMode::Unchanged => 0,
+            EofMode::Zero => 1,
+            EofMode::NegativeOne => 2,
+        };
+
+        (self.optimized as u8 * FLAG_OPTIMIZED) | (eof_mode << FLAG_EOF_MODE_SHIFT)
+    }
+
+    fn set_flags(&mut self, flags: u8) -> Result<(), BytecodeError> {
+        self.eof_mode = match (flags & FLAG_EOF_MODE_MASK) >> FLAG_EOF_MODE_SHIFT {
+            0 => EofMode::Unchanged,
+            1 => EofMode::Zero,
+            2 => EofMode::NegativeOne,
+            _ => return Err(BytecodeError::UnsupportedFlags { flags }),
+        };
+
+        if flags & !(FLAG_OPTIMIZED | FLAG_EOF_MODE_MASK) != 0 {
+            return Err(BytecodeError::UnsupportedFlags { flags });
+        }
+
+        self.optimized = flags & FLAG_OPTIMIZED != 0;
+        Ok(())
+    }
+}
+
+pub struct BytecodeBackend;
+
+impl Backend for BytecodeBackend {
+    fn name(&self) -> &'static str {
+        "bytecode"
+    }
+
+    fn file_extension(&self) -> &'static str {
+        "bfc"
+    }
+
+    fn compile(
+        &self,
+        program: &Program,
+        options: &CompileOptions,
+        info: &ProgramInfo,
+        mut writer: &mut dyn Write,
+    ) -> Result<(), CompileError> {
+        let mut header = Header::new(options.tape_size);
+        header.optimized = info.optimized;
+        header.eof_mode = options.eof_mode;
+        header.cell_width = options.cell_width.bytes();
+
+        if let Some(source_path) = &info.source_path {
+            header
+                .metadata
+                .insert(SOURCE_KEY.to_owned(), source_path.clone());
+        }
+
+        Ok(encode(&program.instructions, &header, &mut writer)?)
+    }
+}
+
+pub fn encode<W: Write>(
+    instructions: &[Instruction],
+    header: &Header,
+    writer: &mut W,
+) -> IOResult<()> {
+    let encoding = Encoding::for_version(header.version).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("can't encode bytecode version {}", header.version),
+        )
+    })?;
+
+    let tape_size = match header.tape_size {
+        TapeSize::Finite(tape_size) => tape_size as u64,
+        TapeSize::Infinite => 0,
+    };
+
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[header.version])?;
+
+    if header.version < 3 {
+        encoding.write_unsigned(writer, tape_size)?;
+        writer.write_all(&[header.cell_width])?;
+
+        return encode_payload(instructions, encoding, writer);
+    }
+
+    writer.write_all(&[header.flags()])?;
+    encoding.write_unsigned(writer, tape_size)?;
+    writer.write_all(&[header.cell_width])?;
+    encoding.write_unsigned(writer, header.metadata.len() as u64)?;
+
+    for (key, value) in &header.metadata {
+        encoding.write_string(writer, key)?;
+        encoding.write_string(writer, value)?;
+    }
+
+    let mut payload = Vec::new();
+    encode_payload(instructions, encoding, &mut payload)?;
+
+    encoding.write_unsigned(writer, payload.len() as u64)?;
+    writer.write_all(&crc32fast::hash(&payload).to_le_bytes())?;
+    writer.write_all(&payload)
+}
+
+// Writes just the payload of the current version, the instruction count followed by the
+// instructions, for formats that embed the instructions without a header.
+pub fn encode_instructions<W: Write>(instructions: &[Instruction], writer: &mut W) -> IOResult<()> {
+    encode_payload(instructions, Encoding::Varint, writer)
+}
+
+fn encode_payload<W: Write>(
+    instructions: &[Instruction],
+    encoding: Encoding,
+    writer: &mut W,
+) -> IOResult<()> {
+    encoding.write_unsigned(writer, instructions.len() as u64)?;
+
+    for instruction in instructions {
+        encode_instruction(instruction, encoding, writer)?;
+    }
+
+    Ok(())
+}
+
+fn encode_instruction<W: Write>(
+    instruction: &Instruction,
+    encoding: Encoding,
+    writer: &mut W,
+) -> IOResult<()> {
+    match *instruction {
+        Instruction::Add(amount) => writer.write_all(&[opcode::ADD, amount as u8]),
+        Instruction::Move(amount) => {
+            writer.write_all(&[opcode::MOVE])?;
+            encoding.write_signed(writer, amount as i64)
+        }
+        Instruction::Write(amount) => {
+            writer.write_all(&[opcode::WRITE])?;
+            encoding.write_unsigned(writer, amount as u64)
+        }
+        Instruction::Read(amount) => {
+            writer.write_all(&[opcode::READ])?;
+            encoding.write_unsigned(writer, amount as u64)
+        }
+        Instruction::JumpIfZero { location } => {
+            writer.write_all(&[opcode::JUMP_IF_ZERO])?;
+            encoding.write_unsigned(writer, location as u64)
+        }
+        Instruction::JumpIfNotZero { location } => {
+            writer.write_all(&[opcode::JUMP_IF_NOT_ZERO])?;
+            encoding.write_unsigned(writer, location as u64)
+        }
+
+        Instruction::SetValue(value) => writer.write_all(&[opcode::SET_VALUE, value as u8]),
+        Instruction::AddRelative { offset, amount } => {
+            writer.write_all(&[opcode::ADD_RELATIVE])?;
+            encoding.write_signed(writer, offset as i64)?;
+            writer.write_all(&[amount as u8])
+        }
+        Instruction::AddVector { vector } => {
+            writer.write_all(&[opcode::ADD_VECTOR])?;
+            writer.write_all(&vector.map(|amount| amount as u8))
+        }
+        Instruction::MulAdd { offset, factor } => {
+            writer.write_all(&[opcode::MUL_ADD])?;
+            encoding.write_signed(writer, offset as i64)?;
+            writer.write_all(&[factor as u8])
+        }
+        Instruction::MoveRightToZero { increment, stride } => {
+            writer.write_all(&[opcode::MOVE_RIGHT_TO_ZERO, increment as u8])?;
+            encoding.write_unsigned(writer, stride as u64)
+        }
+        Instruction::MoveLeftToZero { increment, stride } => {
+            writer.write_all(&[opcode::MOVE_LEFT_TO_ZERO, increment as u8])?;
+            encoding.write_unsigned(writer, stride as u64)
+        }
+
+        Instruction::DefineProc { location } => {
+            writer.write_all(&[opcode::DEFINE_PROC])?;
+            encoding.write_unsigned(writer, location as u64)
+        }
+        Instruction::EndProc => writer.write_all(&[opcode::END_PROC]),
+        Instruction::CallProc => writer.write_all(&[opcode::CALL_PROC]),
+    }
+}
+
+pub fn decode<R: Read>(reader: &mut R) -> Result<(Program, Header), BytecodeError> {
+    let mut magic = [0; 3];
+    reader.read_exact(&mut magic)?;
+
+    if &magic != MAGIC {
+        return Err(BytecodeError::InvalidMagic);
+    }
+
+    let version = read_u8(reader)?;
+    let encoding =
+        Encoding::for_version(version).ok_or(BytecodeError::UnsupportedVersion { version })?;
+
+    let mut header = Header {
+        version,
+        ..Header::new(TapeSize::Infinite)
+    };
+
+    if version >= 3 {
+        header.set_flags(read_u8(reader)?)?;
+    }
+
+    header.tape_size = match encoding.read_unsigned(reader)? {
+        0 => TapeSize::Infinite,
+        tape_size => TapeSize::Finite(tape_size as usize),
+    };
+
+    header.cell_width = read_u8(reader)?;
+
+    if !CellWidth::ALL
+        .iter()
+        .any(|width| width.bytes() == header.cell_width)
+    {
+        return Err(BytecodeError::UnsupportedCellWidth {
+            cell_width: header.cell_width,
+        });
+    }
+
+    if version < 3 {
+        let instructions = decode_payload(reader, encoding)?;
+        return Ok((Program::without_spans(instructions), header));
+    }
+
+    for _ in 0..encoding.read_unsigned(reader)? {
+        let key = encoding.read_string(reader)?;
+        let value = encoding.read_string(reader)?;
+        header.metadata.insert(key, value);
+    }
+
+    let length = encoding.read_unsigned(reader)?;
+    let expected = read_u32(reader)?;
+
+    // The length is read from the file, so the payload is only allowed to grow as it's read.
+    let mut payload = Vec::new();
+    reader.take(length).read_to_end(&mut payload)?;
+
+    if (payload.len() as u64) < length {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+
+    let actual = crc32fast::hash(&payload);
+
+    if actual != expected {
+        return Err(BytecodeError::ChecksumMismatch { expected, actual });
+    }
+
+    let instructions = decode_payload(&mut &payload[..], encoding)?;
+    Ok((Program::without_spans(instructions), header))
+}
+
+fn decode_payload<R: Read>(
+    reader: &mut R,
+    encoding: Encoding,
+) -> Result<Vec<Instruction>, BytecodeError> {
+    // The count can't be trusted to size the buffer up front, since it's read from the file.
+    let count = encoding.read_unsigned(reader)? as usize;
+    let mut instructions = Vec::with_capacity(count.min(1 << 20));
+
+    for index in 0..count {
+        instructions.push(decode_instruction(reader, encoding, index)?);
+    }
+
+    validate_jumps(&instructions)?;
+    Ok(instructions)
+}
+
+fn decode_instruction<R: Read>(
+    reader: &mut R,
+    encoding: Encoding,
+    index: usize,
+) -> Result<Instruction, BytecodeError> {
+    let instruction = match read_u8(reader)? {
+        opcode::ADD => Instruction::Add(read_u8(reader)? as i8),
+        opcode::MOVE => Instruction::Move(encoding.read_signed(reader)? as isize),
+        opcode::WRITE => Instruction::Write(read_amount(reader, encoding, index)?),
+        opcode::READ => Instruction::Read(read_amount(reader, encoding, index)?),
+        opcode::JUMP_IF_ZERO => Instruction::JumpIfZero {
+            location: encoding.read_unsigned(reader)? as usize,
+        },
+        opcode::JUMP_IF_NOT_ZERO => Instruction::JumpIfNotZero {
+            location: encoding.read_unsigned(reader)? as usize,
+        },
+
+        opcode::SET_VALUE => Instruction::SetValue(read_u8(reader)? as i8),
+        opcode::ADD_RELATIVE => Instruction::AddRelative {
+            offset: encoding.read_signed(reader)? as isize,
+            amount: read_u8(reader)? as i8,
+        },
+        opcode::ADD_VECTOR => {
+            let mut vector = [0; 4];
+            reader.read_exact(&mut vector)?;
+
+            Instruction::AddVector {
+                vector: vector.map(|amount| amount as i8),
+            }
+        }
+        opcode::MUL_ADD => Instruction::MulAdd {
+            offset: encoding.read_signed(reader)? as isize,
+            factor: read_u8(reader)? as i8,
+        },
+        opcode::MOVE_RIGHT_TO_ZERO => Instruction::MoveRightToZero {
+            increment: read_u8(reader)? as i8,
+            stride: encoding.read_unsigned(reader)? as usize,
+        },
+        opcode::MOVE_LEFT_TO_ZERO => Instruction::MoveLeftToZero {
+            increment: read_u8(reader)? as i8,
+            stride: encoding.read_unsigned(reader)? as usize,
+        },
+
+        opcode::DEFINE_PROC => Instruction::DefineProc {
+            location: encoding.read_unsigned(reader)? as usize,
+        },
+        opcode::END_PROC => Instruction::EndProc,
+        opcode::CALL_PROC => Instruction::CallProc,
+        opcode => return Err(BytecodeError::InvalidOpcode { index, opcode }),
+    };
+
+    Ok(instruction)
+}
+
+// No source can hold more than `isize::MAX` commands, so I/O amounts above that can only come
+// from a corrupted or hand-crafted file.
+fn read_amount<R: Read>(
+    reader: &mut R,
+    encoding: Encoding,
+    index: usize,
+) -> Result<usize, BytecodeError> {
+    let amount = encoding.read_unsigned(reader)?;
+
+    match usize::try_from(amount) {
+        Ok(length) if length <= isize::MAX as usize => Ok(length),
+        _ => Err(BytecodeError::InvalidAmount { index, amount }),
+    }
+}
+
+// Every jump has to target its partner, since the interpreter trusts jump locations blindly.
+// Procedures nest with loops, and their definitions target their EndProc the same way.
+fn validate_jumps(instructions: &[Instruction]) -> Result<(), BytecodeError> {
+    let mut jump_stack = Vec::new();
+
+    for (index, instruction) in instructions.iter().enumerate() {
+        match *instruction {
+            Instruction::JumpIfZero { location } | Instruction::DefineProc { location } => {
+                jump_stack.push((index, location))
+            }
+            Instruction::JumpIfNotZero { location } => {
+                let (loop_start, loop_end) = jump_stack
+                    .pop()
+                    .ok_or(BytecodeError::InvalidJumpTarget { index, location })?;
+
+                if location != loop_start
+                    || !matches!(instructions[loop_start], Instruction::JumpIfZero { .. })
+                {
+                    return Err(BytecodeError::InvalidJumpTarget { index, location });
+                }
+
+                if loop_end != index {
+                    return Err(BytecodeError::InvalidJumpTarget {
+                        index: loop_start,
+                        location: loop_end,
+                    });
+                }
+            }
+            Instruction::EndProc => {
+                let (start, end) = jump_stack.pop().ok_or(BytecodeError::InvalidJumpTarget {
+                    index,
+                    location: index,
+                })?;
+
+                if !matches!(instructions[start], Instruction::DefineProc { .. }) || end != index {
+                    return Err(BytecodeError::InvalidJumpTarget {
+                        index: start,
+                        location: end,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    match jump_stack.pop() {
+        Some((index, location)) => Err(BytecodeError::InvalidJumpTarget { index, location }),
+        None => Ok(()),
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Encoding {
+    FixedWidth,
+    Varint,
+}
+
+impl Encoding {
+    fn for_version(version: u8) -> Option<Self> {
+        match version {
+            1 => Some(Self::FixedWidth),
+            2 | 3 => Some(Self::Varint),
+            _ => None,
+        }
+    }
+
+    fn write_unsigned<W: Write>(self, writer: &mut W, mut value: u64) -> IOResult<()> {
+        match self {
+            Self::FixedWidth => writer.write_all(&value.to_le_bytes()),
+            Self::Varint => {
+                let mut bytes = [0; 10];
+                let mut length = 0;
+
+                loop {
+                    let byte = (value & 0x7f) as u8;
+                    value >>= 7;
+
+                    if value == 0 {
+                        bytes[length] = byte;
+                        length += 1;
+                        break;
+                    }
+
+                    bytes[length] = byte | 0x80;
+                    length += 1;
+                }
+
+                writer.write_all(&bytes[..length])
+            }
+        }
+    }
+
+    fn write_signed<W: Write>(self, writer: &mut W, mut value: i64) -> IOResult<()> {
+        match self {
+            Self::FixedWidth => writer.write_all(&value.to_le_bytes()),
+            Self::Varint => {
+                let mut bytes = [0; 10];
+                let mut length = 0;
+
+                loop {
+                    let byte = (value & 0x7f) as u8;
+                    value >>= 7;
+
+                    // Done once the rest is pure sign extension of the byte's top bit.
+                    let sign_bit = byte & 0x40 != 0;
+
+                    if (value == 0 && !sign_bit) || (value == -1 && sign_bit) {
+                        bytes[length] = byte;
+                        length += 1;
+                        break;
+                    }
+
+                    bytes[length] = byte | 0x80;
+                    length += 1;
+                }
+
+                writer.write_all(&bytes[..length])
+            }
+        }
+    }
+
+    fn read_unsigned<R: Read>(self, reader: &mut R) -> IOResult<u64> {
+        match self {
+            Self::FixedWidth => read_u64(reader),
+            Self::Varint => {
+                let mut value = 0u64;
+
+                for shift in (0..64).step_by(7) {
+                    let byte = read_u8(reader)?;
+                    value |= ((byte & 0x7f) as u64) << shift;
+
+                    if byte & 0x80 == 0 {
+                        return Ok(value);
+                    }
+                }
+
+                Err(overlong_varint())
+            }
+        }
+    }
+
+    fn read_signed<R: Read>(self, reader: &mut R) -> IOResult<i64> {
+        match self {
+            Self::FixedWidth => Ok(read_u64(reader)? as i64),
+            Self::Varint => {
+                let mut value = 0i64;
+
+                for shift in (0..64).step_by(7) {
+                    let byte = read_u8(reader)?;
+                    value |= ((byte & 0x7f) as i64) << shift;
+
+                    if byte & 0x80 == 0 {
+                        if shift + 7 < 64 && byte & 0x40 != 0 {
+                            value |= -1 << (shift + 7);
+                        }
+
+                        return Ok(value);
+                    }
+                }
+
+                Err(overlong_varint())
+            }
+        }
+    }
+
+    fn write_string<W: Write>(self, writer: &mut W, string: &str) -> IOResult<()> {
+        self.write_unsigned(writer, string.len() as u64)?;
+        writer.write_all(string.as_bytes())
+    }
+
+    fn read_string<R: Read>(self, reader: &mut R) -> Result<String, BytecodeError> {
+        let length = self.read_unsigned(reader)?;
+        let mut bytes = Vec::new();
+        reader.take(length).read_to_end(&mut bytes)?;
+
+        if (bytes.len() as u64) < length {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+
+        String::from_utf8(bytes).map_err(|_| BytecodeError::InvalidMetadata)
+    }
+}
+
+fn overlong_varint() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "varint operand is too long")
+}
+
+#[inline]
+fn read_u8<R: Read>(reader: &mut R) -> IOResult<u8> {
+    let mut bytes = [0; 1];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+#[inline]
+fn read_u32<R: Read>(reader: &mut R) -> IOResult<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+#[inline]
+fn read_u64<R: Read>(reader: &mut R) -> IOResult<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
@@ -0,0 +1,590 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::io::{Result as IOResult, Write};
+
+use crate::instruction::Instruction;
+use crate::interpreter::{CellWidth, EofMode, TapeSize};
+use crate::program::Program;
+
+use super::ast::{self, Node};
+use super::source_map::{self, LineTracker, SourceMap};
+use super::{
+    cell_literal, header_lines, write_annotation, Backend, CodegenChecks, CompileError,
+    CompileOptions, ProgramInfo,
+};
+
+const INITIAL_TAPE_LENGTH: usize = 30_000;
+
+// Cells are unsigned, so every add wraps by the usual unsigned conversion rules. The head
+// only ever moves through move_right and move_left, and cells away from it are reached
+// through at_right and at_left; each kind of tape gets its own definitions of them. Their
+// checks are guarded by RUNTIME_CHECKS, so they compile away when it's 0, and fail through
+// check_failed, which only says why when CHECK_MESSAGES is 1.
+const PRELUDE: &str = r#"#include <stddef.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+static void fail(const char *message) {
+    fflush(stdout);
+    fprintf(stderr, "error: %s\n", message);
+    exit(1);
+}
+"#;
+
+const CHECK_FAILED: &str = r#"
+static inline void check_failed(const char *message) {
+    if (CHECK_MESSAGES) {
+        fail(message);
+    }
+
+    exit(1);
+}
+"#;
+
+const FINITE_TAPE: &str = r#"
+static cell tape[TAPE_LENGTH];
+static size_t head;
+
+static inline void setup(void) {
+}
+
+static inline cell *at_right(size_t offset) {
+    return &tape[(head + offset % TAPE_LENGTH) % TAPE_LENGTH];
+}
+
+static inline cell *at_left(size_t offset) {
+    return &tape[(head + TAPE_LENGTH - offset % TAPE_LENGTH) % TAPE_LENGTH];
+}
+
+static inline void move_right(size_t amount) {
+    head = (head + amount % TAPE_LENGTH) % TAPE_LENGTH;
+}
+
+static inline void move_left(size_t amount) {
+    head = (head + TAPE_LENGTH - amount % TAPE_LENGTH) % TAPE_LENGTH;
+}
+"#;
+
+const BOUNDED_TAPE: &str = r#"
+static cell tape[TAPE_LENGTH];
+static size_t head;
+
+static inline void setup(void) {
+}
+
+static inline cell *at_right(size_t offset) {
+    if (RUNTIME_CHECKS && offset >= TAPE_LENGTH - head) {
+        check_failed("tried to access a cell past the end of the tape");
+    }
+
+    return &tape[head + offset];
+}
+
+static inline cell *at_left(size_t offset) {
+    if (RUNTIME_CHECKS && offset > head) {
+        check_failed("tried to access a cell left of the start of the tape");
+    }
+
+    return &tape[head - offset];
+}
+
+static inline void move_right(size_t amount) {
+    if (RUNTIME_CHECKS && amount >= TAPE_LENGTH - head) {
+        check_failed("the tape head moved past the end of the tape");
+    }
+
+    head += amount;
+}
+
+static inline void move_left(size_t amount) {
+    if (RUNTIME_CHECKS && amount > head) {
+        check_failed("the tape head moved left of the start of the tape");
+    }
+
+    head -= amount;
+}
+"#;
+
+const INFINITE_TAPE: &str = r#"
+static cell *tape;
+static size_t tape_length;
+static size_t head;
+
+static inline void setup(void) {
+    tape = calloc(INITIAL_TAPE_LENGTH, sizeof(cell));
+
+    if (tape == NULL) {
+        fail("out of memory");
+    }
+
+    tape_length = INITIAL_TAPE_LENGTH;
+}
+
+static inline void reserve(size_t index) {
+    size_t length = tape_length;
+
+    if (index < length) {
+        return;
+    }
+
+    while (length <= index) {
+        if (length > SIZE_MAX / 2 / sizeof(cell)) {
+            fail("the tape grew too large");
+        }
+
+        length *= 2;
+    }
+
+    cell *grown = realloc(tape, length * sizeof(cell));
+
+    if (grown == NULL) {
+        fail("out of memory");
+    }
+
+    memset(grown + tape_length, 0, (length - tape_length) * sizeof(cell));
+    tape = grown;
+    tape_length = length;
+}
+
+static inline cell *at_right(size_t offset) {
+    if (RUNTIME_CHECKS && offset > SIZE_MAX - head) {
+        check_failed("the tape grew too large");
+    }
+
+    reserve(head + offset);
+    return &tape[head + offset];
+}
+
+static inline cell *at_left(size_t offset) {
+    if (RUNTIME_CHECKS && offset > head) {
+        check_failed("tried to access a cell left of the start of the tape");
+    }
+
+    return &tape[head - offset];
+}
+
+static inline void move_right(size_t amount) {
+    if (RUNTIME_CHECKS && amount > SIZE_MAX - head) {
+        check_failed("the tape grew too large");
+    }
+
+    head += amount;
+    reserve(head);
+}
+
+static inline void move_left(size_t amount) {
+    if (RUNTIME_CHECKS && amount > head) {
+        check_failed("the tape head moved left of the start of the tape");
+    }
+
+    head -= amount;
+}
+"#;
+
+// Once input runs out, reads store EOF_VALUE if it's defined, and leave the cell unchanged
+// otherwise. Repeated writes and reads go through a buffer a chunk at a time, instead of
+// a byte at a time; only the last byte of a repeated read ends up in the cell.
+const IO: &str = r#"
+#define IO_CHUNK_LENGTH ((size_t) 256u)
+
+static inline void output(size_t count) {
+    if (count == 1) {
+        if (putchar((unsigned char) tape[head]) == EOF) {
+            fail("failed to write output");
+        }
+
+        return;
+    }
+
+    unsigned char buffer[IO_CHUNK_LENGTH];
+    memset(buffer, (unsigned char) tape[head], sizeof buffer);
+
+    while (count > 0) {
+        size_t length = count < IO_CHUNK_LENGTH ? count : IO_CHUNK_LENGTH;
+
+        if (fwrite(buffer, 1, length, stdout) != length) {
+            fail("failed to write output");
+        }
+
+        count -= length;
+    }
+}
+
+static inline void input(size_t count) {
+    fflush(stdout);
+
+    unsigned char buffer[IO_CHUNK_LENGTH];
+
+    while (count > 0) {
+        size_t length = count < IO_CHUNK_LENGTH ? count : IO_CHUNK_LENGTH;
+        size_t read = fread(buffer, 1, length, stdin);
+
+        if (read > 0) {
+            tape[head] = (cell) buffer[read - 1];
+        }
+
+        if (read < length) {
+#ifdef EOF_VALUE
+            tape[head] = EOF_VALUE;
+#endif
+            return;
+        }
+
+        count -= read;
+    }
+}
+"#;
+
+// Loops are reported by id, which is the index of the instruction they start at.
+const REPORT_HEADER: &str = r#"
+static void report(void) {
+    fprintf(stderr, "%12s %20s %20s\n", "loop", "entries", "iterations");
+}
+"#;
+
+const REPORT: &str = r#"
+static void report(void) {
+    fprintf(stderr, "%12s %20s %20s\n", "loop", "entries", "iterations");
+
+    for (size_t i = 0; i < LOOP_COUNT; i++) {
+        fprintf(stderr, "%12zu %20llu %20llu\n", loop_ids[i], loop_entries[i], loop_iterations[i]);
+    }
+}
+"#;
+
+pub struct CBackend;
+
+impl Backend for CBackend {
+    fn name(&self) -> &'static str {
+        "c"
+    }
+
+    fn file_extension(&self) -> &'static str {
+        "c"
+    }
+
+    fn compile(
+        &self,
+        program: &Program,
+        options: &CompileOptions,
+        info: &ProgramInfo,
+        mut writer: &mut dyn Write,
+    ) -> Result<(), CompileError> {
+        compile(&program.instructions, options, info, &mut writer)
+    }
+
+    fn compile_with_source_map(
+        &self,
+        program: &Program,
+        options: &CompileOptions,
+        info: &ProgramInfo,
+        writer: &mut dyn Write,
+    ) -> Result<Option<SourceMap>, CompileError> {
+        source_map::track_lines(program, info, writer, |writer| {
+            write_program(&program.instructions, options, info, writer)
+        })
+    }
+}
+
+pub fn compile<W: Write>(
+    instructions: &[Instruction],
+    options: &CompileOptions,
+    info: &ProgramInfo,
+    writer: &mut W,
+) -> Result<(), CompileError> {
+    write_program(instructions, options, info, &mut LineTracker::new(writer))
+}
+
+fn write_program<W: Write>(
+    instructions: &[Instruction],
+    options: &CompileOptions,
+    info: &ProgramInfo,
+    writer: &mut LineTracker<W>,
+) -> Result<(), CompileError> {
+    let width = options.cell_width;
+
+    for line in header_lines("c", options, info) {
+        writeln!(writer, "/* {} */", line.replace("*/", "*\\/"))?;
+    }
+
+    writeln!(writer)?;
+    writer.write_all(PRELUDE.as_bytes())?;
+    writeln!(writer)?;
+
+    writeln!(writer, "typedef {} cell;", cell_type(width))?;
+    writeln!(writer)?;
+    writeln!(
+        writer,
+        "#define RUNTIME_CHECKS {}",
+        options.runtime_checks() as u8
+    )?;
+    writeln!(
+        writer,
+        "#define CHECK_MESSAGES {}",
+        (options.checks == CodegenChecks::Full) as u8
+    )?;
+
+    match options.eof_mode {
+        EofMode::Unchanged => {}
+        EofMode::Zero => writeln!(writer, "#define EOF_VALUE 0u")?,
+        EofMode::NegativeOne => writeln!(writer, "#define EOF_VALUE {}u", width.max_value())?,
+    }
+
+    writer.write_all(CHECK_FAILED.as_bytes())?;
+    writeln!(writer)?;
+
+    match options.tape_size {
+        TapeSize::Finite(length) => {
+            writeln!(writer, "#define TAPE_LENGTH ((size_t) {}u)", length)?;
+
+            if options.wraps() {
+                writer.write_all(FINITE_TAPE.as_bytes())?;
+            } else {
+                writer.write_all(BOUNDED_TAPE.as_bytes())?;
+            }
+        }
+        TapeSize::Infinite => {
+            writeln!(
+                writer,
+                "#define INITIAL_TAPE_LENGTH ((size_t) {}u)",
+                INITIAL_TAPE_LENGTH
+            )?;
+            writer.write_all(INFINITE_TAPE.as_bytes())?;
+        }
+    }
+
+    writer.write_all(IO.as_bytes())?;
+    writeln!(writer)?;
+
+    let nodes = ast::build(instructions)?;
+
+    let loops = if options.instrument {
+        let loops = ast::loop_ids(&nodes);
+        write_report(writer, &loops)?;
+        loops
+    } else {
+        Vec::new()
+    };
+
+    writeln!(writer, "int main(void) {{")?;
+    writeln!(writer, "    setup();")?;
+
+    if options.instrument {
+        writeln!(writer, "    atexit(report);")?;
+    }
+
+    writeln!(writer)?;
+
+    write_block(writer, info, &loops, width, &nodes, 1)?;
+    writer.end();
+
+    writeln!(writer)?;
+    writeln!(writer, "    if (fflush(stdout) == EOF) {{")?;
+    writeln!(writer, "        fail(\"failed to write output\");")?;
+    writeln!(writer, "    }}")?;
+    writeln!(writer)?;
+    writeln!(writer, "    return 0;")?;
+    writeln!(writer, "}}")?;
+
+    Ok(())
+}
+
+fn write_block<W: Write>(
+    writer: &mut LineTracker<W>,
+    info: &ProgramInfo,
+    loops: &[usize],
+    width: CellWidth,
+    nodes: &[Node],
+    depth: usize,
+) -> IOResult<()> {
+    let indent = "    ".repeat(depth);
+
+    for node in nodes {
+        writer.begin(node.index());
+        write_annotation(writer, info, node.index(), &indent, "//")?;
+
+        let instruction = match node {
+            Node::Instruction { instruction, .. } => instruction,
+            Node::Procedure { .. } => unreachable!(),
+            Node::Loop { index, body } => {
+                let counter = loops.binary_search(index).ok();
+
+                if let Some(counter) = counter {
+                    writeln!(writer, "{}loop_entries[{}]++;", indent, counter)?;
+                }
+
+                writeln!(writer, "{}while (tape[head] != 0) {{", indent)?;
+
+                if let Some(counter) = counter {
+                    writeln!(writer, "{}    loop_iterations[{}]++;", indent, counter)?;
+                }
+
+                write_block(writer, info, loops, width, body, depth + 1)?;
+                writer.begin(node.last_index());
+                write_annotation(writer, info, node.last_index(), &indent, "//")?;
+                writeln!(writer, "{}}}", indent)?;
+                continue;
+            }
+        };
+
+        match instruction {
+            Instruction::Add(amount) => {
+                writeln!(
+                    writer,
+                    "{}tape[head] += {}u;",
+                    indent,
+                    cell_literal(width, *amount)
+                )?;
+            }
+            Instruction::Move(amount) => {
+                writeln!(writer, "{}{};", indent, move_by(*amount))?;
+            }
+            Instruction::Write(count) => {
+                writeln!(writer, "{}output({}u);", indent, count)?;
+            }
+            Instruction::Read(count) => {
+                writeln!(writer, "{}input({}u);", indent, count)?;
+            }
+            Instruction::JumpIfZero { .. }
+            | Instruction::JumpIfNotZero { .. }
+            | Instruction::DefineProc { .. }
+            | Instruction::EndProc
+            | Instruction::CallProc => unreachable!(),
+
+            Instruction::SetValue(value) => {
+                writeln!(
+                    writer,
+                    "{}tape[head] = {}u;",
+                    indent,
+                    cell_literal(width, *value)
+                )?;
+            }
+            Instruction::AddRelative { offset, amount } => {
+                writeln!(
+                    writer,
+                    "{}{} += {}u;",
+                    indent,
+                    cell_at(*offset),
+                    cell_literal(width, *amount)
+                )?;
+            }
+            Instruction::AddVector { vector } => {
+                for (lane, amount) in vector.iter().enumerate() {
+                    if *amount != 0 {
+                        writeln!(
+                            writer,
+                            "{}{} += {}u;",
+                            indent,
+                            cell_at(lane as isize),
+                            cell_literal(width, *amount)
+                        )?;
+                    }
+                }
+            }
+            Instruction::MulAdd { offset, factor } => {
+                writeln!(writer, "{}if (tape[head] != 0) {{", indent)?;
+                writeln!(
+                    writer,
+                    "{}    {} += (cell) (tape[head] * {}u);",
+                    indent,
+                    cell_at(*offset),
+                    cell_literal(width, *factor)
+                )?;
+                writeln!(writer, "{}}}", indent)?;
+            }
+            Instruction::MoveRightToZero { increment, stride } => {
+                write_scan(writer, &indent, width, *increment, "move_right", *stride)?;
+            }
+            Instruction::MoveLeftToZero { increment, stride } => {
+                write_scan(writer, &indent, width, *increment, "move_left", *stride)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Writes the counters of every loop, and a report of them to run when the program exits.
+fn write_report<W: Write>(writer: &mut W, loops: &[usize]) -> IOResult<()> {
+    // C has no empty arrays, so programs without loops get an empty report.
+    if loops.is_empty() {
+        writer.write_all(REPORT_HEADER.as_bytes())?;
+        return writeln!(writer);
+    }
+
+    let ids = loops
+        .iter()
+        .map(|id| format!("{}u", id))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    writeln!(writer, "#define LOOP_COUNT {}", loops.len())?;
+    writeln!(
+        writer,
+        "static const size_t loop_ids[LOOP_COUNT] = {{ {} }};",
+        ids
+    )?;
+    writeln!(
+        writer,
+        "static unsigned long long loop_entries[LOOP_COUNT];"
+    )?;
+    writeln!(
+        writer,
+        "static unsigned long long loop_iterations[LOOP_COUNT];"
+    )?;
+    writer.write_all(REPORT.as_bytes())?;
+    writeln!(writer)
+}
+
+fn write_scan<W: Write>(
+    writer: &mut W,
+    indent: &str,
+    width: CellWidth,
+    increment: i8,
+    direction: &str,
+    stride: usize,
+) -> IOResult<()> {
+    writeln!(writer, "{}while (tape[head] != 0) {{", indent)?;
+
+    if increment != 0 {
+        writeln!(
+            writer,
+            "{}    tape[head] += {}u;",
+            indent,
+            cell_literal(width, increment)
+        )?;
+    }
+
+    writeln!(writer, "{}    {}({}u);", indent, direction, stride)?;
+    writeln!(writer, "{}}}", indent)
+}
+
+fn cell_type(width: CellWidth) -> &'static str {
+    match width {
+        CellWidth::U8 => "uint8_t",
+        CellWidth::U16 => "uint16_t",
+        CellWidth::U32 => "uint32_t",
+    }
+}
+
+fn move_by(amount: isize) -> String {
+    if amount >= 0 {
+        format!("move_right({}u)", amount)
+    } else {
+        format!("move_left({}u)", amount.unsigned_abs())
+    }
+}
+
+fn cell_at(offset: isize) -> String {
+    match offset {
+        0 => "tape[head]".to_owned(),
+        offset if offset > 0 => format!("*at_right({}u)", offset),
+        offset => format!("*at_left({}u)", offset.unsigned_abs()),
+    }
+}
@@ -0,0 +1,698 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::io::{self, Result as IOResult};
+
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{
+    types, AbiParam, Block, InstBuilder, MemFlags, Signature, Type, Value,
+};
+use cranelift_codegen::isa::OwnedTargetIsa;
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_module::{DataDescription, DataId, FuncId, Linkage, Module};
+
+use crate::instruction::Instruction;
+use crate::interpreter::{CellWidth, EofMode, TapeSize};
+
+use super::ast::{self, Node};
+use super::{CompileError, CompileOptions};
+
+// The C-callable function that runs the program: `void membrane_run(void)`.
+pub const ENTRY_SYMBOL: &str = "membrane_run";
+
+// A right-infinite tape can't grow as static data, so it gets a large fixed region instead,
+// and running off either end of it is reported as an error, like the x86-64 backend.
+const INFINITE_TAPE_LENGTH: usize = 1 << 26;
+
+const OUT_OF_BOUNDS_MESSAGE: &[u8] = b"error: the tape head moved out of bounds\n";
+const WRITE_ERROR_MESSAGE: &[u8] = b"error: failed to write output\n";
+
+// The ISA of the machine membrane is running on. Object files need position-independent
+// code to link into the executables compilers build by default; a JIT doesn't.
+pub fn host_isa(position_independent: bool) -> IOResult<OwnedTargetIsa> {
+    let mut flags = settings::builder();
+    flags.set("opt_level", "speed").map_err(io::Error::other)?;
+    flags
+        .set(
+            "is_pic",
+            if position_independent {
+                "true"
+            } else {
+                "false"
+            },
+        )
+        .map_err(io::Error::other)?;
+
+    cranelift_native::builder()
+        .map_err(io::Error::other)?
+        .finish(settings::Flags::new(flags))
+        .map_err(io::Error::other)
+}
+
+// Declares and defines everything the program needs in the module: the tape, the I/O
+// helpers, and the entry function, which gets the given linkage. Standard I/O goes through
+// the C library, so the module has to be linked or loaded against it.
+pub fn define_program<M: Module>(
+    module: &mut M,
+    instructions: &[Instruction],
+    options: &CompileOptions,
+    linkage: Linkage,
+) -> Result<FuncId, CompileError> {
+    options.require_cell_width(CellWidth::U8, "Cranelift")?;
+    let nodes = ast::build(instructions)?;
+
+    let runtime = Runtime::declare(module, options)?;
+    runtime.define_fail(module)?;
+    runtime.define_output(module)?;
+    runtime.define_input(module, options.eof_mode)?;
+
+    let run = module
+        .declare_function(ENTRY_SYMBOL, linkage, &module.make_signature())
+        .map_err(io::Error::other)?;
+
+    let mut context = module.make_context();
+    let mut builder_context = FunctionBuilderContext::new();
+
+    {
+        let mut builder = FunctionBuilder::new(&mut context.func, &mut builder_context);
+        let entry = builder.create_block();
+        builder.switch_to_block(entry);
+
+        let pointer = module.target_config().pointer_type();
+        let tape = module.declare_data_in_func(runtime.tape, builder.func);
+        let head = Variable::from_u32(0);
+        builder.declare_var(head, pointer);
+
+        let zero = builder.ins().iconst(pointer, 0);
+        builder.def_var(head, zero);
+
+        let mut lowering = Lowering {
+            module,
+            runtime: &runtime,
+            builder,
+            pointer,
+            tape: Tape::new(options),
+            base: zero,
+            head,
+            out_of_bounds: None,
+        };
+
+        lowering.base = lowering.builder.ins().global_value(pointer, tape);
+        lowering.block(&nodes)?;
+
+        lowering.flush();
+        lowering.finish_out_of_bounds();
+
+        lowering.builder.seal_all_blocks();
+        lowering.builder.finalize();
+    }
+
+    module
+        .define_function(run, &mut context)
+        .map_err(io::Error::other)?;
+    module.clear_context(&mut context);
+
+    Ok(run)
+}
+
+struct Tape {
+    length: usize,
+    wraps: bool,
+    checks: bool,
+}
+
+impl Tape {
+    fn new(options: &CompileOptions) -> Self {
+        let length = match options.tape_size {
+            TapeSize::Finite(length) => length,
+            TapeSize::Infinite => INFINITE_TAPE_LENGTH,
+        };
+
+        Self {
+            length,
+            wraps: options.wraps(),
+            checks: options.runtime_checks(),
+        }
+    }
+}
+
+// The C library functions, helpers, and data every program uses.
+struct Runtime {
+    putchar: FuncId,
+    getchar: FuncId,
+    fflush: FuncId,
+    write: FuncId,
+    exit: FuncId,
+
+    fail: FuncId,
+    output: FuncId,
+    input: FuncId,
+
+    tape: DataId,
+    out_of_bounds_message: DataId,
+    write_error_message: DataId,
+}
+
+impl Runtime {
+    fn declare<M: Module>(module: &mut M, options: &CompileOptions) -> IOResult<Self> {
+        let pointer = module.target_config().pointer_type();
+
+        let putchar = import(module, "putchar", &[types::I32], Some(types::I32))?;
+        let getchar = import(module, "getchar", &[], Some(types::I32))?;
+        let fflush = import(module, "fflush", &[pointer], Some(types::I32))?;
+        let write = import(
+            module,
+            "write",
+            &[types::I32, pointer, pointer],
+            Some(pointer),
+        )?;
+        let exit = import(module, "exit", &[types::I32], None)?;
+
+        let fail = local(module, "membrane_fail", &[pointer, pointer])?;
+        let output = local(module, "membrane_output", &[types::I32, pointer])?;
+        let input = local(module, "membrane_input", &[pointer, pointer])?;
+
+        let mut tape = DataDescription::new();
+        tape.define_zeroinit(Tape::new(options).length);
+        tape.set_align(16);
+
+        let tape = data(module, "membrane_tape", true, &tape)?;
+        let out_of_bounds_message =
+            message(module, "membrane_out_of_bounds", OUT_OF_BOUNDS_MESSAGE)?;
+        let write_error_message = message(module, "membrane_write_error", WRITE_ERROR_MESSAGE)?;
+
+        Ok(Self {
+            putchar,
+            getchar,
+            fflush,
+            write,
+            exit,
+            fail,
+            output,
+            input,
+            tape,
+            out_of_bounds_message,
+            write_error_message,
+        })
+    }
+
+    // fail(message, length): flushes output, writes the message to standard error, and
+    // exits with a status of 1.
+    fn define_fail<M: Module>(&self, module: &mut M) -> IOResult<()> {
+        let pointer = module.target_config().pointer_type();
+
+        define(module, self.fail, |module, builder| {
+            let entry = builder.create_block();
+            builder.append_block_params_for_function_params(entry);
+            builder.switch_to_block(entry);
+
+            let (message, length) = (
+                builder.block_params(entry)[0],
+                builder.block_params(entry)[1],
+            );
+
+            let fflush = module.declare_func_in_func(self.fflush, builder.func);
+            let write = module.declare_func_in_func(self.write, builder.func);
+            let exit = module.declare_func_in_func(self.exit, builder.func);
+
+            let null = builder.ins().iconst(pointer, 0);
+            builder.ins().call(fflush, &[null]);
+
+            let stderr = builder.ins().iconst(types::I32, 2);
+            builder.ins().call(write, &[stderr, message, length]);
+
+            let status = builder.ins().iconst(types::I32, 1);
+            builder.ins().call(exit, &[status]);
+            builder.ins().return_(&[]);
+        })
+    }
+
+    // output(value, count): writes the byte `count` times.
+    fn define_output<M: Module>(&self, module: &mut M) -> IOResult<()> {
+        let pointer = module.target_config().pointer_type();
+
+        define(module, self.output, |module, builder| {
+            let entry = builder.create_block();
+            let next = builder.create_block();
+            let body = builder.create_block();
+            let failed = builder.create_block();
+            let done = builder.create_block();
+
+            builder.append_block_params_for_function_params(entry);
+            builder.switch_to_block(entry);
+
+            let value = builder.block_params(entry)[0];
+            let count = builder.block_params(entry)[1];
+            builder.ins().jump(next, &[count]);
+
+            builder.switch_to_block(next);
+            let remaining = builder.append_block_param(next, pointer);
+            builder.ins().brif(remaining, body, &[], done, &[]);
+
+            builder.switch_to_block(body);
+            let putchar = module.declare_func_in_func(self.putchar, builder.func);
+            let call = builder.ins().call(putchar, &[value]);
+            let result = builder.inst_results(call)[0];
+            let remaining = builder.ins().iadd_imm(remaining, -1);
+            let error = builder.ins().icmp_imm(IntCC::SignedLessThan, result, 0);
+            builder.ins().brif(error, failed, &[], next, &[remaining]);
+
+            builder.switch_to_block(failed);
+            self.call_fail(
+                module,
+                builder,
+                self.write_error_message,
+                WRITE_ERROR_MESSAGE,
+            );
+            builder.ins().return_(&[]);
+
+            builder.switch_to_block(done);
+            builder.ins().return_(&[]);
+        })
+    }
+
+    // input(cell, count): flushes output, then reads `count` bytes into the cell. Once
+    // input runs out, the cell is set according to the EOF mode.
+    fn define_input<M: Module>(&self, module: &mut M, eof_mode: EofMode) -> IOResult<()> {
+        let pointer = module.target_config().pointer_type();
+
+        define(module, self.input, |module, builder| {
+            let entry = builder.create_block();
+            let next = builder.create_block();
+            let body = builder.create_block();
+            let store = builder.create_block();
+            let end_of_input = builder.create_block();
+            let done = builder.create_block();
+
+            builder.append_block_params_for_function_params(entry);
+            builder.switch_to_block(entry);
+
+            let cell = builder.block_params(entry)[0];
+            let count = builder.block_params(entry)[1];
+
+            let fflush = module.declare_func_in_func(self.fflush, builder.func);
+            let null = builder.ins().iconst(pointer, 0);
+            builder.ins().call(fflush, &[null]);
+            builder.ins().jump(next, &[count]);
+
+            builder.switch_to_block(next);
+            let remaining = builder.append_block_param(next, pointer);
+            builder.ins().brif(remaining, body, &[], done, &[]);
+
+            builder.switch_to_block(body);
+            let getchar = module.declare_func_in_func(self.getchar, builder.func);
+            let call = builder.ins().call(getchar, &[]);
+            let byte = builder.inst_results(call)[0];
+            let ended = builder.ins().icmp_imm(IntCC::SignedLessThan, byte, 0);
+            builder.ins().brif(ended, end_of_input, &[], store, &[]);
+
+            builder.switch_to_block(store);
+            let value = builder.ins().ireduce(types::I8, byte);
+            builder.ins().store(MemFlags::trusted(), value, cell, 0);
+            let remaining = builder.ins().iadd_imm(remaining, -1);
+            builder.ins().jump(next, &[remaining]);
+
+            builder.switch_to_block(end_of_input);
+
+            let eof_value = match eof_mode {
+                EofMode::Unchanged => None,
+                EofMode::Zero => Some(0),
+                EofMode::NegativeOne => Some(-1),
+            };
+
+            if let Some(eof_value) = eof_value {
+                let value = builder.ins().iconst(types::I8, eof_value);
+                builder.ins().store(MemFlags::trusted(), value, cell, 0);
+            }
+
+            builder.ins().jump(done, &[]);
+
+            builder.switch_to_block(done);
+            builder.ins().return_(&[]);
+        })
+    }
+
+    fn call_fail<M: Module>(
+        &self,
+        module: &mut M,
+        builder: &mut FunctionBuilder,
+        message: DataId,
+        text: &[u8],
+    ) {
+        let pointer = module.target_config().pointer_type();
+        let fail = module.declare_func_in_func(self.fail, builder.func);
+        let message = module.declare_data_in_func(message, builder.func);
+
+        let address = builder.ins().global_value(pointer, message);
+        let length = builder.ins().iconst(pointer, text.len() as i64);
+        builder.ins().call(fail, &[address, length]);
+    }
+}
+
+struct Lowering<'a, M: Module> {
+    module: &'a mut M,
+    runtime: &'a Runtime,
+    builder: FunctionBuilder<'a>,
+    pointer: Type,
+    tape: Tape,
+    // The address of the first cell, and the variable holding the head's index.
+    base: Value,
+    head: Variable,
+    // The block that reports the head leaving the tape, created the first time it's needed
+    // and filled in once the program has been lowered.
+    out_of_bounds: Option<Block>,
+}
+
+impl<M: Module> Lowering<'_, M> {
+    fn block(&mut self, nodes: &[Node]) -> IOResult<()> {
+        for node in nodes {
+            let instruction = match node {
+                Node::Instruction { instruction, .. } => instruction,
+                Node::Procedure { .. } => unreachable!(),
+                Node::Loop { body, .. } => {
+                    self.while_nonzero(|lowering| lowering.block(body))?;
+                    continue;
+                }
+            };
+
+            match instruction {
+                Instruction::Add(amount) => {
+                    let head = self.current_index();
+                    self.add_to(head, *amount);
+                }
+                Instruction::Move(amount) => {
+                    let head = self.index(*amount as i64);
+                    self.builder.def_var(self.head, head);
+                }
+                Instruction::Write(count) => {
+                    let output = self.call_function(self.runtime.output);
+                    let head = self.current_index();
+                    let value = self.load(head);
+                    let value = self.builder.ins().uextend(types::I32, value);
+                    let count = self.builder.ins().iconst(self.pointer, *count as i64);
+                    self.builder.ins().call(output, &[value, count]);
+                }
+                Instruction::Read(count) => {
+                    let input = self.call_function(self.runtime.input);
+                    let head = self.current_index();
+                    let cell = self.address(head);
+                    let count = self.builder.ins().iconst(self.pointer, *count as i64);
+                    self.builder.ins().call(input, &[cell, count]);
+                }
+                Instruction::JumpIfZero { .. }
+                | Instruction::JumpIfNotZero { .. }
+                | Instruction::DefineProc { .. }
+                | Instruction::EndProc
+                | Instruction::CallProc => unreachable!(),
+
+                Instruction::SetValue(value) => {
+                    let head = self.current_index();
+                    let cell = self.address(head);
+                    let value = self.builder.ins().iconst(types::I8, *value as i64);
+                    self.builder
+                        .ins()
+                        .store(MemFlags::trusted(), value, cell, 0);
+                }
+                Instruction::AddRelative { offset, amount } => {
+                    let cell = self.index(*offset as i64);
+                    self.add_to(cell, *amount);
+                }
+                Instruction::AddVector { vector } => {
+                    for (lane, amount) in vector.iter().enumerate() {
+                        if *amount != 0 {
+                            let cell = self.index(lane as i64);
+                            self.add_to(cell, *amount);
+                        }
+                    }
+                }
+                Instruction::MulAdd { offset, factor } => {
+                    let head = self.current_index();
+                    let value = self.load(head);
+                    let multiply = self.builder.create_block();
+                    let done = self.builder.create_block();
+                    self.builder.ins().brif(value, multiply, &[], done, &[]);
+
+                    self.builder.switch_to_block(multiply);
+                    let cell = self.index(*offset as i64);
+                    let product = self.builder.ins().imul_imm(value, *factor as i64);
+                    let current = self.load(cell);
+                    let sum = self.builder.ins().iadd(current, product);
+                    self.store(cell, sum);
+                    self.builder.ins().jump(done, &[]);
+
+                    self.builder.switch_to_block(done);
+                }
+                Instruction::MoveRightToZero { increment, stride } => {
+                    self.scan(*increment, *stride as i64)?;
+                }
+                Instruction::MoveLeftToZero { increment, stride } => {
+                    self.scan(*increment, -(*stride as i64))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn scan(&mut self, increment: i8, stride: i64) -> IOResult<()> {
+        self.while_nonzero(|lowering| {
+            if increment != 0 {
+                let head = lowering.current_index();
+                lowering.add_to(head, increment);
+            }
+
+            let head = lowering.index(stride);
+            lowering.builder.def_var(lowering.head, head);
+            Ok(())
+        })
+    }
+
+    // Lowers a loop that runs its body while the current cell isn't zero.
+    fn while_nonzero<F>(&mut self, body: F) -> IOResult<()>
+    where
+        F: FnOnce(&mut Self) -> IOResult<()>,
+    {
+        let header = self.builder.create_block();
+        let start = self.builder.create_block();
+        let exit = self.builder.create_block();
+
+        self.builder.ins().jump(header, &[]);
+
+        self.builder.switch_to_block(header);
+        let head = self.current_index();
+        let value = self.load(head);
+        self.builder.ins().brif(value, start, &[], exit, &[]);
+
+        self.builder.switch_to_block(start);
+        body(self)?;
+        self.builder.ins().jump(header, &[]);
+
+        self.builder.switch_to_block(exit);
+        Ok(())
+    }
+
+    // Returns the index of the cell `offset` away from the head, either wrapping around
+    // the tape or bailing out at its ends, unless runtime checks are off.
+    fn index(&mut self, offset: i64) -> Value {
+        let head = self.current_index();
+        let length = self.tape.length as i64;
+
+        if self.tape.wraps {
+            let distance = offset.rem_euclid(length);
+
+            if distance == 0 {
+                return head;
+            }
+
+            let index = self.builder.ins().iadd_imm(head, distance);
+            let wrapped = self.builder.ins().iadd_imm(index, -length);
+            let past_end =
+                self.builder
+                    .ins()
+                    .icmp_imm(IntCC::UnsignedGreaterThanOrEqual, index, length);
+
+            return self.builder.ins().select(past_end, wrapped, index);
+        }
+
+        if offset == 0 {
+            return head;
+        }
+
+        // Moving left of the first cell wraps around to a huge index, so one unsigned
+        // comparison catches both ends.
+        let index = self.builder.ins().iadd_imm(head, offset);
+
+        if self.tape.checks {
+            let out_of_bounds = self.out_of_bounds_block();
+            let in_bounds = self.builder.create_block();
+            let past_end =
+                self.builder
+                    .ins()
+                    .icmp_imm(IntCC::UnsignedGreaterThanOrEqual, index, length);
+
+            self.builder
+                .ins()
+                .brif(past_end, out_of_bounds, &[], in_bounds, &[]);
+            self.builder.switch_to_block(in_bounds);
+        }
+
+        index
+    }
+
+    fn out_of_bounds_block(&mut self) -> Block {
+        *self
+            .out_of_bounds
+            .get_or_insert_with(|| self.builder.create_block())
+    }
+
+    // Fills in the block that reports the head leaving the tape, if anything jumps to it.
+    fn finish_out_of_bounds(&mut self) {
+        if let Some(block) = self.out_of_bounds {
+            self.builder.switch_to_block(block);
+            self.runtime.call_fail(
+                self.module,
+                &mut self.builder,
+                self.runtime.out_of_bounds_message,
+                OUT_OF_BOUNDS_MESSAGE,
+            );
+            self.builder.ins().return_(&[]);
+        }
+    }
+
+    // Flushes output at the end of the program, failing if it couldn't be written, and
+    // returns.
+    fn flush(&mut self) {
+        let fflush = self.call_function(self.runtime.fflush);
+        let null = self.builder.ins().iconst(self.pointer, 0);
+        let call = self.builder.ins().call(fflush, &[null]);
+        let result = self.builder.inst_results(call)[0];
+
+        let failed = self.builder.create_block();
+        let done = self.builder.create_block();
+        self.builder.ins().brif(result, failed, &[], done, &[]);
+
+        self.builder.switch_to_block(failed);
+        self.runtime.call_fail(
+            self.module,
+            &mut self.builder,
+            self.runtime.write_error_message,
+            WRITE_ERROR_MESSAGE,
+        );
+        self.builder.ins().jump(done, &[]);
+
+        self.builder.switch_to_block(done);
+        self.builder.ins().return_(&[]);
+    }
+
+    fn current_index(&mut self) -> Value {
+        self.builder.use_var(self.head)
+    }
+
+    fn call_function(&mut self, function: FuncId) -> cranelift_codegen::ir::FuncRef {
+        self.module
+            .declare_func_in_func(function, self.builder.func)
+    }
+
+    fn address(&mut self, index: Value) -> Value {
+        self.builder.ins().iadd(self.base, index)
+    }
+
+    fn load(&mut self, index: Value) -> Value {
+        let address = self.address(index);
+        self.builder
+            .ins()
+            .load(types::I8, MemFlags::trusted(), address, 0)
+    }
+
+    fn store(&mut self, index: Value, value: Value) {
+        let address = self.address(index);
+        self.builder
+            .ins()
+            .store(MemFlags::trusted(), value, address, 0);
+    }
+
+    fn add_to(&mut self, index: Value, amount: i8) {
+        let value = self.load(index);
+        let sum = self.builder.ins().iadd_imm(value, amount as i64);
+        self.store(index, sum);
+    }
+}
+
+fn import<M: Module>(
+    module: &mut M,
+    name: &str,
+    parameters: &[Type],
+    result: Option<Type>,
+) -> IOResult<FuncId> {
+    let signature = signature(module, parameters, result);
+    module
+        .declare_function(name, Linkage::Import, &signature)
+        .map_err(io::Error::other)
+}
+
+fn local<M: Module>(module: &mut M, name: &str, parameters: &[Type]) -> IOResult<FuncId> {
+    let signature = signature(module, parameters, None);
+    module
+        .declare_function(name, Linkage::Local, &signature)
+        .map_err(io::Error::other)
+}
+
+fn signature<M: Module>(module: &M, parameters: &[Type], result: Option<Type>) -> Signature {
+    let mut signature = module.make_signature();
+    signature
+        .params
+        .extend(parameters.iter().map(|ty| AbiParam::new(*ty)));
+    signature.returns.extend(result.map(AbiParam::new));
+    signature
+}
+
+fn data<M: Module>(
+    module: &mut M,
+    name: &str,
+    writable: bool,
+    description: &DataDescription,
+) -> IOResult<DataId> {
+    let id = module
+        .declare_data(name, Linkage::Local, writable, false)
+        .map_err(io::Error::other)?;
+    module
+        .define_data(id, description)
+        .map_err(io::Error::other)?;
+    Ok(id)
+}
+
+fn message<M: Module>(module: &mut M, name: &str, text: &[u8]) -> IOResult<DataId> {
+    let mut description = DataDescription::new();
+    description.define(text.into());
+    data(module, name, false, &description)
+}
+
+// Builds the body of a declared function with `build`, and defines it in the module.
+fn define<M, F>(module: &mut M, function: FuncId, build: F) -> IOResult<()>
+where
+    M: Module,
+    F: FnOnce(&mut M, &mut FunctionBuilder),
+{
+    let mut context = module.make_context();
+    context.func.signature = module
+        .declarations()
+        .get_function_decl(function)
+        .signature
+        .clone();
+
+    let mut builder_context = FunctionBuilderContext::new();
+    let mut builder = FunctionBuilder::new(&mut context.func, &mut builder_context);
+    build(module, &mut builder);
+    builder.seal_all_blocks();
+    builder.finalize();
+
+    module
+        .define_function(function, &mut context)
+        .map_err(io::Error::other)?;
+    module.clear_context(&mut context);
+    Ok(())
+}
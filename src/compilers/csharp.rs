@@ -0,0 +1,452 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::io::Write;
+
+use crate::instruction::Instruction;
+use crate::interpreter::{CellWidth, EofMode, TapeSize};
+use crate::program::Program;
+
+use super::ast::{self, Node};
+use super::source_map::{self, LineTracker, SourceMap};
+use super::{
+    cell_literal, write_annotation, write_header, Backend, CompileError, CompileOptions,
+    ProgramInfo,
+};
+
+const INITIAL_TAPE_LENGTH: usize = 30_000;
+
+// Arrays are indexed by int, so cells away from the head are reached through Right and
+// Left, which also wrap a finite tape and grow an infinite one. Their index is stored in
+// a local before the tape is touched, since growing the tape replaces the array. Cell is
+// an alias for the unsigned type of the cell width.
+const PRELUDE: &str = r#"using System;
+using System.IO;
+"#;
+
+const CLASS: &str = r#"
+public static class Program
+{
+    static readonly Stream input = Console.OpenStandardInput();
+    static readonly Stream output = new BufferedStream(Console.OpenStandardOutput());
+"#;
+
+const FINITE_TAPE: &str = r#"
+    static readonly Cell[] tape = new Cell[TapeLength];
+    static int head;
+
+    static int Right(int offset)
+    {
+        return (int)(((long)head + offset % TapeLength) % TapeLength);
+    }
+
+    static int Left(int offset)
+    {
+        return (int)(((long)head + TapeLength - offset % TapeLength) % TapeLength);
+    }
+"#;
+
+const BOUNDED_TAPE: &str = r#"
+    static readonly Cell[] tape = new Cell[TapeLength];
+    static int head;
+
+    static int Right(int offset)
+    {
+        if (RuntimeChecks && offset >= TapeLength - head)
+        {
+            Fail("tried to access a cell past the end of the tape");
+        }
+
+        return head + offset;
+    }
+
+    static int Left(int offset)
+    {
+        if (RuntimeChecks && offset > head)
+        {
+            Fail("tried to access a cell left of the start of the tape");
+        }
+
+        return head - offset;
+    }
+"#;
+
+const INFINITE_TAPE: &str = r#"
+    static Cell[] tape = new Cell[InitialTapeLength];
+    static int head;
+
+    static int Right(int offset)
+    {
+        long index = (long)head + offset;
+
+        if (index >= tape.Length)
+        {
+            if (index >= Array.MaxLength)
+            {
+                Fail("the tape grew too large");
+            }
+
+            long length = tape.Length;
+
+            while (length <= index)
+            {
+                length = Math.Min(length * 2, Array.MaxLength);
+            }
+
+            Array.Resize(ref tape, (int)length);
+        }
+
+        return (int)index;
+    }
+
+    static int Left(int offset)
+    {
+        if (RuntimeChecks && offset > head)
+        {
+            Fail("tried to access a cell left of the start of the tape");
+        }
+
+        return head - offset;
+    }
+"#;
+
+// Once input runs out, reads store EofValue if it has one, and leave the cell unchanged
+// otherwise.
+const HELPERS: &str = r#"
+    static void Output(int count)
+    {
+        for (; count > 0; count--)
+        {
+            output.WriteByte((byte)tape[head]);
+        }
+    }
+
+    static void Input(int count)
+    {
+        output.Flush();
+
+        for (; count > 0; count--)
+        {
+            int value = input.ReadByte();
+
+            if (value < 0)
+            {
+                if (EofValue.HasValue)
+                {
+                    tape[head] = EofValue.Value;
+                }
+
+                return;
+            }
+
+            tape[head] = (Cell)value;
+        }
+    }
+
+    static void Fail(string message)
+    {
+        output.Flush();
+        Console.Error.WriteLine("error: " + message);
+        Environment.Exit(1);
+    }
+"#;
+
+pub struct CSharpBackend;
+
+impl Backend for CSharpBackend {
+    fn name(&self) -> &'static str {
+        "csharp"
+    }
+
+    fn file_extension(&self) -> &'static str {
+        "cs"
+    }
+
+    fn compile(
+        &self,
+        program: &Program,
+        options: &CompileOptions,
+        info: &ProgramInfo,
+        mut writer: &mut dyn Write,
+    ) -> Result<(), CompileError> {
+        compile(&program.instructions, options, info, &mut writer)
+    }
+
+    fn compile_with_source_map(
+        &self,
+        program: &Program,
+        options: &CompileOptions,
+        info: &ProgramInfo,
+        writer: &mut dyn Write,
+    ) -> Result<Option<SourceMap>, CompileError> {
+        source_map::track_lines(program, info, writer, |writer| {
+            write_program(&program.instructions, options, info, writer)
+        })
+    }
+}
+
+pub fn compile<W: Write>(
+    instructions: &[Instruction],
+    options: &CompileOptions,
+    info: &ProgramInfo,
+    writer: &mut W,
+) -> Result<(), CompileError> {
+    write_program(instructions, options, info, &mut LineTracker::new(writer))
+}
+
+fn write_program<W: Write>(
+    instructions: &[Instruction],
+    options: &CompileOptions,
+    info: &ProgramInfo,
+    writer: &mut LineTracker<W>,
+) -> Result<(), CompileError> {
+    let width = options.cell_width;
+
+    write_header(writer, "csharp", options, info, "//")?;
+
+    writeln!(writer)?;
+    writer.write_all(PRELUDE.as_bytes())?;
+    writeln!(writer, "using Cell = System.{};", cell_type(width))?;
+    writer.write_all(CLASS.as_bytes())?;
+    writeln!(writer)?;
+
+    // This is readonly rather than const, which keeps the compiler from warning about
+    // unreachable checks, while the JIT still removes them.
+    writeln!(
+        writer,
+        "    static readonly bool RuntimeChecks = {};",
+        options.runtime_checks()
+    )?;
+
+    match options.eof_mode {
+        EofMode::Unchanged => writeln!(writer, "    static readonly Cell? EofValue = null;")?,
+        EofMode::Zero => writeln!(writer, "    static readonly Cell? EofValue = 0;")?,
+        EofMode::NegativeOne => writeln!(
+            writer,
+            "    static readonly Cell? EofValue = Cell.MaxValue;"
+        )?,
+    }
+
+    match options.tape_size {
+        TapeSize::Finite(length) => {
+            writeln!(writer, "    const int TapeLength = {};", constant(length)?)?;
+
+            if options.wraps() {
+                writer.write_all(FINITE_TAPE.as_bytes())?;
+            } else {
+                writer.write_all(BOUNDED_TAPE.as_bytes())?;
+            }
+        }
+        TapeSize::Infinite => {
+            writeln!(
+                writer,
+                "    const int InitialTapeLength = {};",
+                INITIAL_TAPE_LENGTH
+            )?;
+            writer.write_all(INFINITE_TAPE.as_bytes())?;
+        }
+    }
+
+    writer.write_all(HELPERS.as_bytes())?;
+    writeln!(writer)?;
+
+    writeln!(writer, "    public static void Main()")?;
+    writeln!(writer, "    {{")?;
+    writeln!(writer, "        int cell;")?;
+    writeln!(writer)?;
+    writeln!(writer, "        unchecked")?;
+    writeln!(writer, "        {{")?;
+
+    write_block(writer, info, width, &ast::build(instructions)?, 3)?;
+    writer.end();
+
+    writeln!(writer, "        }}")?;
+    writeln!(writer)?;
+    writeln!(writer, "        output.Flush();")?;
+    writeln!(writer, "    }}")?;
+    writeln!(writer, "}}")?;
+
+    Ok(())
+}
+
+fn write_block<W: Write>(
+    writer: &mut LineTracker<W>,
+    info: &ProgramInfo,
+    width: CellWidth,
+    nodes: &[Node],
+    depth: usize,
+) -> Result<(), CompileError> {
+    let indent = "    ".repeat(depth);
+
+    for node in nodes {
+        writer.begin(node.index());
+        write_annotation(writer, info, node.index(), &indent, "//")?;
+
+        let instruction = match node {
+            Node::Instruction { instruction, .. } => instruction,
+            Node::Procedure { .. } => unreachable!(),
+            Node::Loop { body, .. } => {
+                writeln!(writer, "{}while (tape[head] != 0)", indent)?;
+                writeln!(writer, "{}{{", indent)?;
+                write_block(writer, info, width, body, depth + 1)?;
+                writer.begin(node.last_index());
+                write_annotation(writer, info, node.last_index(), &indent, "//")?;
+                writeln!(writer, "{}}}", indent)?;
+                continue;
+            }
+        };
+
+        match instruction {
+            Instruction::Add(amount) => {
+                writeln!(
+                    writer,
+                    "{}tape[head] += {};",
+                    indent,
+                    cell_literal(width, *amount)
+                )?;
+            }
+            Instruction::Move(amount) => {
+                writeln!(writer, "{}head = {};", indent, index(*amount)?)?;
+            }
+            Instruction::Write(count) => {
+                writeln!(writer, "{}Output({});", indent, constant(*count)?)?;
+            }
+            Instruction::Read(count) => {
+                writeln!(writer, "{}Input({});", indent, constant(*count)?)?;
+            }
+            Instruction::JumpIfZero { .. }
+            | Instruction::JumpIfNotZero { .. }
+            | Instruction::DefineProc { .. }
+            | Instruction::EndProc
+            | Instruction::CallProc => unreachable!(),
+
+            Instruction::SetValue(value) => {
+                writeln!(
+                    writer,
+                    "{}tape[head] = {};",
+                    indent,
+                    cell_literal(width, *value)
+                )?;
+            }
+            Instruction::AddRelative { offset, amount } => {
+                let cell = cell_at(writer, &indent, *offset)?;
+                writeln!(
+                    writer,
+                    "{}tape[{}] += {};",
+                    indent,
+                    cell,
+                    cell_literal(width, *amount)
+                )?;
+            }
+            Instruction::AddVector { vector } => {
+                for (lane, amount) in vector.iter().enumerate() {
+                    if *amount != 0 {
+                        let cell = cell_at(writer, &indent, lane as isize)?;
+                        writeln!(
+                            writer,
+                            "{}tape[{}] += {};",
+                            indent,
+                            cell,
+                            cell_literal(width, *amount)
+                        )?;
+                    }
+                }
+            }
+            Instruction::MulAdd { offset, factor } => {
+                writeln!(writer, "{}if (tape[head] != 0)", indent)?;
+                writeln!(writer, "{}{{", indent)?;
+
+                let inner = format!("{}    ", indent);
+                let cell = cell_at(writer, &inner, *offset)?;
+                writeln!(
+                    writer,
+                    "{}tape[{}] += (Cell)(tape[head] * {});",
+                    inner,
+                    cell,
+                    cell_literal(width, *factor)
+                )?;
+                writeln!(writer, "{}}}", indent)?;
+            }
+            Instruction::MoveRightToZero { increment, stride } => {
+                write_scan(writer, &indent, width, *increment, *stride as isize)?;
+            }
+            Instruction::MoveLeftToZero { increment, stride } => {
+                write_scan(writer, &indent, width, *increment, -(*stride as isize))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn write_scan<W: Write>(
+    writer: &mut W,
+    indent: &str,
+    width: CellWidth,
+    increment: i8,
+    stride: isize,
+) -> Result<(), CompileError> {
+    writeln!(writer, "{}while (tape[head] != 0)", indent)?;
+    writeln!(writer, "{}{{", indent)?;
+
+    if increment != 0 {
+        writeln!(
+            writer,
+            "{}    tape[head] += {};",
+            indent,
+            cell_literal(width, increment)
+        )?;
+    }
+
+    writeln!(writer, "{}    head = {};", indent, index(stride)?)?;
+    writeln!(writer, "{}}}", indent)?;
+    Ok(())
+}
+
+// Emits code that leaves the index of the cell `offset` away from the head in a local,
+// and returns an expression for that index.
+fn cell_at<W: Write>(
+    writer: &mut W,
+    indent: &str,
+    offset: isize,
+) -> Result<&'static str, CompileError> {
+    if offset == 0 {
+        return Ok("head");
+    }
+
+    writeln!(writer, "{}cell = {};", indent, index(offset)?)?;
+    Ok("cell")
+}
+
+fn cell_type(width: CellWidth) -> &'static str {
+    match width {
+        CellWidth::U8 => "Byte",
+        CellWidth::U16 => "UInt16",
+        CellWidth::U32 => "UInt32",
+    }
+}
+
+fn index(offset: isize) -> Result<String, CompileError> {
+    let distance = constant(offset.unsigned_abs())?;
+
+    if offset >= 0 {
+        Ok(format!("Right({})", distance))
+    } else {
+        Ok(format!("Left({})", distance))
+    }
+}
+
+// Arrays are indexed by int, so anything larger can't be compiled.
+fn constant(value: usize) -> Result<usize, CompileError> {
+    if value <= i32::MAX as usize {
+        Ok(value)
+    } else {
+        Err(CompileError::Unsupported(format!(
+            "{} doesn't fit in a C# array index",
+            value
+        )))
+    }
+}
@@ -0,0 +1,463 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::io::{Result as IOResult, Write};
+
+use crate::instruction::Instruction;
+use crate::interpreter::{CellWidth, EofMode, TapeSize};
+use crate::program::Program;
+
+use super::ast::{self, Node};
+use super::{write_annotation, write_header, Backend, CompileError, CompileOptions, ProgramInfo};
+
+const INITIAL_TAPE_LENGTH: usize = 30_000;
+
+// Java methods are limited to 64 KiB of bytecode, so any run of more instructions than
+// this is split into methods of its own.
+const METHOD_LENGTH: usize = 1_000;
+
+// Cells are signed, and compound assignments narrow back to the cell type, so every add
+// wraps, and adding a sign-extended amount is the same as adding it unsigned. As with C#,
+// cells away from the head are reached through right and left, and their index is stored
+// in a local before the tape is touched, since growing the tape replaces the array.
+//
+// Java has no type aliases, so CELL in the templates is replaced by the cell type.
+const PRELUDE: &str = r#"import java.io.BufferedOutputStream;
+import java.io.FileDescriptor;
+import java.io.FileOutputStream;
+import java.io.IOException;
+import java.io.InputStream;
+import java.io.OutputStream;
+import java.util.Arrays;
+
+public class Main {
+    static final InputStream in = System.in;
+    static final OutputStream out = new BufferedOutputStream(new FileOutputStream(FileDescriptor.out), 1 << 16);
+"#;
+
+const FINITE_TAPE: &str = r#"
+    static final CELL[] tape = new CELL[TAPE_LENGTH];
+    static int head;
+
+    static int right(int offset) {
+        return (int) (((long) head + offset % TAPE_LENGTH) % TAPE_LENGTH);
+    }
+
+    static int left(int offset) {
+        return (int) (((long) head + TAPE_LENGTH - offset % TAPE_LENGTH) % TAPE_LENGTH);
+    }
+"#;
+
+const BOUNDED_TAPE: &str = r#"
+    static final CELL[] tape = new CELL[TAPE_LENGTH];
+    static int head;
+
+    static int right(int offset) throws IOException {
+        if (RUNTIME_CHECKS && offset >= TAPE_LENGTH - head) {
+            fail("tried to access a cell past the end of the tape");
+        }
+
+        return head + offset;
+    }
+
+    static int left(int offset) throws IOException {
+        if (RUNTIME_CHECKS && offset > head) {
+            fail("tried to access a cell left of the start of the tape");
+        }
+
+        return head - offset;
+    }
+"#;
+
+const INFINITE_TAPE: &str = r#"
+    static final int MAX_TAPE_LENGTH = Integer.MAX_VALUE - 8;
+
+    static CELL[] tape = new CELL[INITIAL_TAPE_LENGTH];
+    static int head;
+
+    static int right(int offset) throws IOException {
+        long index = (long) head + offset;
+
+        if (index >= tape.length) {
+            if (index >= MAX_TAPE_LENGTH) {
+                fail("the tape grew too large");
+            }
+
+            long length = tape.length;
+
+            while (length <= index) {
+                length = Math.min(length * 2, MAX_TAPE_LENGTH);
+            }
+
+            tape = Arrays.copyOf(tape, (int) length);
+        }
+
+        return (int) index;
+    }
+
+    static int left(int offset) throws IOException {
+        if (RUNTIME_CHECKS && offset > head) {
+            fail("tried to access a cell left of the start of the tape");
+        }
+
+        return head - offset;
+    }
+"#;
+
+// Once input runs out, reads store EOF_VALUE if it isn't null, and leave the cell
+// unchanged otherwise.
+const HELPERS: &str = r#"
+    static void output(int count) throws IOException {
+        for (; count > 0; count--) {
+            out.write(tape[head]);
+        }
+    }
+
+    static void input(int count) throws IOException {
+        out.flush();
+
+        for (; count > 0; count--) {
+            int value = in.read();
+
+            if (value < 0) {
+                if (EOF_VALUE != null) {
+                    tape[head] = (CELL) (int) EOF_VALUE;
+                }
+
+                return;
+            }
+
+            tape[head] = (CELL) value;
+        }
+    }
+
+    static void fail(String message) throws IOException {
+        out.flush();
+        System.err.println("error: " + message);
+        System.exit(1);
+    }
+"#;
+
+pub struct JavaBackend;
+
+impl Backend for JavaBackend {
+    fn name(&self) -> &'static str {
+        "java"
+    }
+
+    fn file_extension(&self) -> &'static str {
+        "java"
+    }
+
+    fn compile(
+        &self,
+        program: &Program,
+        options: &CompileOptions,
+        info: &ProgramInfo,
+        mut writer: &mut dyn Write,
+    ) -> Result<(), CompileError> {
+        compile(&program.instructions, options, info, &mut writer)
+    }
+}
+
+pub fn compile<W: Write>(
+    instructions: &[Instruction],
+    options: &CompileOptions,
+    info: &ProgramInfo,
+    writer: &mut W,
+) -> Result<(), CompileError> {
+    let cell_type = cell_type(options.cell_width);
+
+    write_header(writer, "java", options, info, "//")?;
+
+    writeln!(
+        writer,
+        "// The class is public, so this file must be named Main.java."
+    )?;
+    writeln!(writer)?;
+    writer.write_all(PRELUDE.as_bytes())?;
+    writeln!(writer)?;
+
+    writeln!(
+        writer,
+        "    static final boolean RUNTIME_CHECKS = {};",
+        options.runtime_checks()
+    )?;
+
+    match options.eof_mode {
+        EofMode::Unchanged => writeln!(writer, "    static final Integer EOF_VALUE = null;")?,
+        EofMode::Zero => writeln!(writer, "    static final Integer EOF_VALUE = 0;")?,
+        EofMode::NegativeOne => writeln!(writer, "    static final Integer EOF_VALUE = -1;")?,
+    }
+
+    let tape = match options.tape_size {
+        TapeSize::Finite(length) => {
+            writeln!(
+                writer,
+                "    static final int TAPE_LENGTH = {};",
+                constant(length)?
+            )?;
+
+            if options.wraps() {
+                FINITE_TAPE
+            } else {
+                BOUNDED_TAPE
+            }
+        }
+        TapeSize::Infinite => {
+            writeln!(
+                writer,
+                "    static final int INITIAL_TAPE_LENGTH = {};",
+                INITIAL_TAPE_LENGTH
+            )?;
+            INFINITE_TAPE
+        }
+    };
+
+    writer.write_all(tape.replace("CELL", cell_type).as_bytes())?;
+    writer.write_all(HELPERS.replace("CELL", cell_type).as_bytes())?;
+
+    let mut emitter = Emitter {
+        info,
+        methods: Vec::new(),
+    };
+
+    let mut body = emitter.block(&ast::build(instructions)?, 2)?;
+    writeln!(body)?;
+    writeln!(body, "        out.flush();")?;
+
+    for (index, method) in emitter.methods.iter().enumerate() {
+        writeln!(writer)?;
+        write_method(writer, &format!("static void part{}()", index), method)?;
+    }
+
+    writeln!(writer)?;
+    write_method(writer, "public static void main(String[] args)", &body)?;
+    writeln!(writer, "}}")?;
+    Ok(())
+}
+
+fn write_method<W: Write>(writer: &mut W, signature: &str, body: &[u8]) -> IOResult<()> {
+    writeln!(writer, "    {} throws IOException {{", signature)?;
+    writeln!(writer, "        int cell;")?;
+    writeln!(writer)?;
+    writer.write_all(body)?;
+    writeln!(writer, "    }}")
+}
+
+struct Emitter<'a> {
+    info: &'a ProgramInfo,
+    methods: Vec<Vec<u8>>,
+}
+
+impl Emitter<'_> {
+    // Returns the statements for a run of nodes, moving them into methods of their own if
+    // they cover too many instructions for one method.
+    fn block(&mut self, nodes: &[Node], depth: usize) -> Result<Vec<u8>, CompileError> {
+        if nodes.iter().map(Node::instruction_count).sum::<usize>() <= METHOD_LENGTH {
+            return statements(self.info, nodes, depth);
+        }
+
+        let mut code = Vec::new();
+        let mut chunk = 0..0;
+        let mut chunk_length = 0;
+
+        for (position, node) in nodes.iter().enumerate() {
+            let length = node.instruction_count();
+
+            if chunk_length + length > METHOD_LENGTH && !chunk.is_empty() {
+                self.call(&mut code, &nodes[chunk], depth)?;
+                chunk = position..position;
+                chunk_length = 0;
+            }
+
+            match node {
+                // Only a loop can be this long, and its body is split up instead.
+                Node::Loop { body, .. } if length > METHOD_LENGTH => {
+                    let indent = "    ".repeat(depth);
+                    let body = self.block(body, depth + 1)?;
+
+                    write_annotation(&mut code, self.info, node.index(), &indent, "//")?;
+                    writeln!(code, "{}while (tape[head] != 0) {{", indent)?;
+                    code.extend_from_slice(&body);
+                    write_annotation(&mut code, self.info, node.last_index(), &indent, "//")?;
+                    writeln!(code, "{}}}", indent)?;
+
+                    chunk = position + 1..position + 1;
+                }
+                _ => {
+                    chunk.end = position + 1;
+                    chunk_length += length;
+                }
+            }
+        }
+
+        if !chunk.is_empty() {
+            self.call(&mut code, &nodes[chunk], depth)?;
+        }
+
+        Ok(code)
+    }
+
+    fn call(
+        &mut self,
+        code: &mut Vec<u8>,
+        nodes: &[Node],
+        depth: usize,
+    ) -> Result<(), CompileError> {
+        let body = statements(self.info, nodes, 2)?;
+        writeln!(
+            code,
+            "{}part{}();",
+            "    ".repeat(depth),
+            self.methods.len()
+        )?;
+
+        self.methods.push(body);
+        Ok(())
+    }
+}
+
+fn statements(info: &ProgramInfo, nodes: &[Node], depth: usize) -> Result<Vec<u8>, CompileError> {
+    let mut code = Vec::new();
+    write_block(&mut code, info, nodes, depth)?;
+    Ok(code)
+}
+
+fn write_block(
+    code: &mut Vec<u8>,
+    info: &ProgramInfo,
+    nodes: &[Node],
+    depth: usize,
+) -> Result<(), CompileError> {
+    let indent = "    ".repeat(depth);
+
+    for node in nodes {
+        write_annotation(code, info, node.index(), &indent, "//")?;
+
+        let instruction = match node {
+            Node::Instruction { instruction, .. } => instruction,
+            Node::Procedure { .. } => unreachable!(),
+            Node::Loop { body, .. } => {
+                writeln!(code, "{}while (tape[head] != 0) {{", indent)?;
+                write_block(code, info, body, depth + 1)?;
+                write_annotation(code, info, node.last_index(), &indent, "//")?;
+                writeln!(code, "{}}}", indent)?;
+                continue;
+            }
+        };
+
+        match instruction {
+            Instruction::Add(amount) => {
+                writeln!(code, "{}tape[head] += {};", indent, *amount)?;
+            }
+            Instruction::Move(amount) => {
+                writeln!(code, "{}head = {};", indent, index(*amount)?)?;
+            }
+            Instruction::Write(count) => {
+                writeln!(code, "{}output({});", indent, constant(*count)?)?;
+            }
+            Instruction::Read(count) => {
+                writeln!(code, "{}input({});", indent, constant(*count)?)?;
+            }
+            Instruction::JumpIfZero { .. }
+            | Instruction::JumpIfNotZero { .. }
+            | Instruction::DefineProc { .. }
+            | Instruction::EndProc
+            | Instruction::CallProc => unreachable!(),
+
+            Instruction::SetValue(value) => {
+                writeln!(code, "{}tape[head] = {};", indent, *value)?;
+            }
+            Instruction::AddRelative { offset, amount } => {
+                let cell = cell_at(code, &indent, *offset)?;
+                writeln!(code, "{}tape[{}] += {};", indent, cell, *amount)?;
+            }
+            Instruction::AddVector { vector } => {
+                for (lane, amount) in vector.iter().enumerate() {
+                    if *amount != 0 {
+                        let cell = cell_at(code, &indent, lane as isize)?;
+                        writeln!(code, "{}tape[{}] += {};", indent, cell, *amount)?;
+                    }
+                }
+            }
+            Instruction::MulAdd { offset, factor } => {
+                writeln!(code, "{}if (tape[head] != 0) {{", indent)?;
+
+                let inner = format!("{}    ", indent);
+                let cell = cell_at(code, &inner, *offset)?;
+                writeln!(code, "{}tape[{}] += tape[head] * {};", inner, cell, *factor)?;
+                writeln!(code, "{}}}", indent)?;
+            }
+            Instruction::MoveRightToZero { increment, stride } => {
+                write_scan(code, &indent, *increment, *stride as isize)?;
+            }
+            Instruction::MoveLeftToZero { increment, stride } => {
+                write_scan(code, &indent, *increment, -(*stride as isize))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn write_scan(
+    code: &mut Vec<u8>,
+    indent: &str,
+    increment: i8,
+    stride: isize,
+) -> Result<(), CompileError> {
+    writeln!(code, "{}while (tape[head] != 0) {{", indent)?;
+
+    if increment != 0 {
+        writeln!(code, "{}    tape[head] += {};", indent, increment)?;
+    }
+
+    writeln!(code, "{}    head = {};", indent, index(stride)?)?;
+    writeln!(code, "{}}}", indent)?;
+    Ok(())
+}
+
+// Leaves the index of the cell `offset` away from the head in a local, and returns an
+// expression for that index.
+fn cell_at(code: &mut Vec<u8>, indent: &str, offset: isize) -> Result<&'static str, CompileError> {
+    if offset == 0 {
+        return Ok("head");
+    }
+
+    writeln!(code, "{}cell = {};", indent, index(offset)?)?;
+    Ok("cell")
+}
+
+fn cell_type(width: CellWidth) -> &'static str {
+    match width {
+        CellWidth::U8 => "byte",
+        CellWidth::U16 => "short",
+        CellWidth::U32 => "int",
+    }
+}
+
+fn index(offset: isize) -> Result<String, CompileError> {
+    let distance = constant(offset.unsigned_abs())?;
+
+    if offset >= 0 {
+        Ok(format!("right({})", distance))
+    } else {
+        Ok(format!("left({})", distance))
+    }
+}
+
+// Arrays are indexed by int, so anything larger can't be compiled.
+fn constant(value: usize) -> Result<usize, CompileError> {
+    if value <= i32::MAX as usize {
+        Ok(value)
+    } else {
+        Err(CompileError::Unsupported(format!(
+            "{} doesn't fit in a Java array index",
+            value
+        )))
+    }
+}
@@ -0,0 +1,298 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::io::{Result as IOResult, Write};
+
+use crate::instruction::Instruction;
+use crate::interpreter::{CellWidth, EofMode, TapeSize};
+use crate::program::Program;
+
+use super::ast::{self, Node};
+use super::source_map::{self, LineTracker, SourceMap};
+use super::{
+    cell_literal, write_annotation, write_header, Backend, CompileError, CompileOptions,
+    ProgramInfo,
+};
+
+// Lua 5.4. The tape is a table indexed from zero, with unset cells reading as zero, and
+// cells wrap through integer masks. Lua's modulo always takes the sign of the divisor, so
+// wrapping a finite tape needs no special cases, while other tapes check their ends
+// through left and right.
+const PRELUDE: &str = r#"local function fail(message)
+  io.stdout:flush()
+  io.stderr:write("error: ", message, "\n")
+  os.exit(1)
+end
+
+local tape = setmetatable({}, { __index = function() return 0 end })
+local head = 0
+
+local function left(offset)
+  local index = head - offset
+
+  if RUNTIME_CHECKS and index < 0 then
+    fail("tried to access a cell left of the start of the tape")
+  end
+
+  return index
+end
+
+local function right(offset)
+  local index = head + offset
+
+  if RUNTIME_CHECKS and index >= TAPE_LENGTH then
+    fail("tried to access a cell past the end of the tape")
+  end
+
+  return index
+end
+
+local function output(count)
+  io.write(string.rep(string.char(tape[head] & 255), count))
+end
+
+-- Once input runs out, reads store EOF_VALUE if it isn't nil, and leave the cell
+-- unchanged otherwise.
+local function input(count)
+  io.stdout:flush()
+
+  for _ = 1, count do
+    local byte = io.read(1)
+
+    if byte == nil then
+      if EOF_VALUE ~= nil then
+        tape[head] = EOF_VALUE
+      end
+
+      return
+    end
+
+    tape[head] = string.byte(byte)
+  end
+end
+"#;
+
+pub struct LuaBackend;
+
+impl Backend for LuaBackend {
+    fn name(&self) -> &'static str {
+        "lua"
+    }
+
+    fn file_extension(&self) -> &'static str {
+        "lua"
+    }
+
+    fn compile(
+        &self,
+        program: &Program,
+        options: &CompileOptions,
+        info: &ProgramInfo,
+        mut writer: &mut dyn Write,
+    ) -> Result<(), CompileError> {
+        compile(&program.instructions, options, info, &mut writer)
+    }
+
+    fn compile_with_source_map(
+        &self,
+        program: &Program,
+        options: &CompileOptions,
+        info: &ProgramInfo,
+        writer: &mut dyn Write,
+    ) -> Result<Option<SourceMap>, CompileError> {
+        source_map::track_lines(program, info, writer, |writer| {
+            write_program(&program.instructions, options, info, writer)
+        })
+    }
+}
+
+pub fn compile<W: Write>(
+    instructions: &[Instruction],
+    options: &CompileOptions,
+    info: &ProgramInfo,
+    writer: &mut W,
+) -> Result<(), CompileError> {
+    write_program(instructions, options, info, &mut LineTracker::new(writer))
+}
+
+fn write_program<W: Write>(
+    instructions: &[Instruction],
+    options: &CompileOptions,
+    info: &ProgramInfo,
+    writer: &mut LineTracker<W>,
+) -> Result<(), CompileError> {
+    let width = options.cell_width;
+
+    write_header(writer, "lua", options, info, "--")?;
+
+    writeln!(writer)?;
+
+    if let TapeSize::Finite(length) = options.tape_size {
+        writeln!(writer, "local TAPE_LENGTH = {}", length)?;
+    }
+
+    writeln!(writer, "local CELL_MASK = {}", width.max_value())?;
+    writeln!(
+        writer,
+        "local RUNTIME_CHECKS = {}",
+        options.runtime_checks()
+    )?;
+
+    match options.eof_mode {
+        EofMode::Unchanged => writeln!(writer, "local EOF_VALUE = nil")?,
+        EofMode::Zero => writeln!(writer, "local EOF_VALUE = 0")?,
+        EofMode::NegativeOne => writeln!(writer, "local EOF_VALUE = CELL_MASK")?,
+    }
+
+    writeln!(writer)?;
+
+    writer.write_all(PRELUDE.as_bytes())?;
+    writeln!(writer)?;
+
+    write_block(writer, info, options, &ast::build(instructions)?, 0)?;
+    writer.end();
+
+    writeln!(writer)?;
+    writeln!(writer, "io.stdout:flush()")?;
+    Ok(())
+}
+
+fn write_block<W: Write>(
+    writer: &mut LineTracker<W>,
+    info: &ProgramInfo,
+    options: &CompileOptions,
+    nodes: &[Node],
+    depth: usize,
+) -> IOResult<()> {
+    let width = options.cell_width;
+    let indent = "  ".repeat(depth);
+
+    for node in nodes {
+        writer.begin(node.index());
+        write_annotation(writer, info, node.index(), &indent, "--")?;
+
+        let instruction = match node {
+            Node::Instruction { instruction, .. } => instruction,
+            Node::Procedure { .. } => unreachable!(),
+            Node::Loop { body, .. } => {
+                writeln!(writer, "{}while tape[head] ~= 0 do", indent)?;
+                write_block(writer, info, options, body, depth + 1)?;
+                writer.begin(node.last_index());
+                write_annotation(writer, info, node.last_index(), &indent, "--")?;
+                writeln!(writer, "{}end", indent)?;
+                continue;
+            }
+        };
+
+        match instruction {
+            Instruction::Add(amount) => {
+                writeln!(writer, "{}{}", indent, add_to("head", width, *amount))?;
+            }
+            Instruction::Move(amount) => {
+                writeln!(writer, "{}head = {}", indent, index(options, *amount))?;
+            }
+            Instruction::Write(count) => {
+                writeln!(writer, "{}output({})", indent, count)?;
+            }
+            Instruction::Read(count) => {
+                writeln!(writer, "{}input({})", indent, count)?;
+            }
+            Instruction::JumpIfZero { .. }
+            | Instruction::JumpIfNotZero { .. }
+            | Instruction::DefineProc { .. }
+            | Instruction::EndProc
+            | Instruction::CallProc => unreachable!(),
+
+            Instruction::SetValue(value) => {
+                writeln!(
+                    writer,
+                    "{}tape[head] = {}",
+                    indent,
+                    cell_literal(width, *value)
+                )?;
+            }
+            Instruction::AddRelative { offset, amount } => {
+                let cell = index(options, *offset);
+                writeln!(writer, "{}{}", indent, add_to(&cell, width, *amount))?;
+            }
+            Instruction::AddVector { vector } => {
+                for (lane, amount) in vector.iter().enumerate() {
+                    if *amount != 0 {
+                        let cell = index(options, lane as isize);
+                        writeln!(writer, "{}{}", indent, add_to(&cell, width, *amount))?;
+                    }
+                }
+            }
+            Instruction::MulAdd { offset, factor } => {
+                let cell = index(options, *offset);
+
+                writeln!(writer, "{}if tape[head] ~= 0 then", indent)?;
+                writeln!(
+                    writer,
+                    "{}  tape[{}] = (tape[{}] + tape[head] * {}) & CELL_MASK",
+                    indent,
+                    cell,
+                    cell,
+                    cell_literal(width, *factor)
+                )?;
+                writeln!(writer, "{}end", indent)?;
+            }
+            Instruction::MoveRightToZero { increment, stride } => {
+                write_scan(writer, options, &indent, *increment, *stride as isize)?;
+            }
+            Instruction::MoveLeftToZero { increment, stride } => {
+                write_scan(writer, options, &indent, *increment, -(*stride as isize))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn write_scan<W: Write>(
+    writer: &mut W,
+    options: &CompileOptions,
+    indent: &str,
+    increment: i8,
+    stride: isize,
+) -> IOResult<()> {
+    writeln!(writer, "{}while tape[head] ~= 0 do", indent)?;
+
+    if increment != 0 {
+        writeln!(
+            writer,
+            "{}  {}",
+            indent,
+            add_to("head", options.cell_width, increment)
+        )?;
+    }
+
+    writeln!(writer, "{}  head = {}", indent, index(options, stride))?;
+    writeln!(writer, "{}end", indent)
+}
+
+fn add_to(cell: &str, width: CellWidth, amount: i8) -> String {
+    format!(
+        "tape[{}] = (tape[{}] + {}) & CELL_MASK",
+        cell,
+        cell,
+        cell_literal(width, amount)
+    )
+}
+
+// Returns an expression for the index `offset` cells away from the head.
+fn index(options: &CompileOptions, offset: isize) -> String {
+    let bounded = matches!(options.tape_size, TapeSize::Finite(_));
+
+    match (options.wraps(), offset) {
+        (_, 0) => "head".to_owned(),
+        (true, offset) if offset > 0 => format!("(head + {}) % TAPE_LENGTH", offset),
+        (true, offset) => format!("(head - {}) % TAPE_LENGTH", offset.unsigned_abs()),
+        (false, offset) if offset > 0 && bounded => format!("right({})", offset),
+        (false, offset) if offset > 0 => format!("head + {}", offset),
+        (false, offset) => format!("left({})", offset.unsigned_abs()),
+    }
+}
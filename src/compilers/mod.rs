@@ -0,0 +1,424 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::fmt::{self, Formatter};
+use std::fs::File;
+use std::io::{self, BufWriter, Result as IOResult, Write};
+use std::mem;
+use std::path::Path;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::interpreter::{CellWidth, EofMode, TapeSize, WrapSemantics};
+use crate::program::Program;
+
+use self::ast::AstError;
+use self::source_map::SourceMap;
+
+pub mod ast;
+pub mod brainfuck;
+pub mod bundle;
+pub mod bytecode;
+pub mod c;
+#[cfg(feature = "cranelift")]
+pub mod cranelift;
+pub mod csharp;
+pub mod java;
+pub mod lua;
+pub mod native;
+#[cfg(feature = "cranelift")]
+pub mod object;
+pub mod qbe;
+pub mod rust;
+pub mod source_map;
+pub mod typescript;
+pub mod wasm;
+pub mod wat;
+pub mod x86_64;
+
+// Where the instructions being compiled came from, for formats that record it. Backends
+// that write code also write the annotations as comments, if there are any. The source
+// hash is the CRC-32 of the source file.
+#[derive(Clone, Eq, PartialEq, Default, Debug)]
+pub struct ProgramInfo {
+    pub source_path: Option<String>,
+    pub source_hash: Option<u32>,
+    pub optimized: bool,
+    pub annotations: Option<Annotations>,
+}
+
+impl ProgramInfo {
+    fn annotation(&self, index: usize) -> Option<&str> {
+        self.annotations.as_ref()?.get(index)
+    }
+}
+
+// The snippet of source each instruction came from, along with its offset, such as
+// "[->+<] (offset 12)". Only commands are kept, so the snippets are safe to put in a comment
+// in any language, and long ones are shortened.
+#[derive(Clone, Eq, PartialEq, Default, Debug)]
+pub struct Annotations {
+    comments: Vec<Option<String>>,
+}
+
+impl Annotations {
+    const SNIPPET_LENGTH: usize = 40;
+
+    pub fn new(program: &Program, source: &str) -> Self {
+        let comments = program
+            .spans
+            .iter()
+            .map(|span| {
+                let snippet = source
+                    .as_bytes()
+                    .get(span.range())?
+                    .iter()
+                    .filter(|byte| b"+-<>[].,".contains(byte))
+                    .map(|byte| *byte as char)
+                    .collect::<String>();
+
+                if snippet.is_empty() {
+                    None
+                } else if snippet.len() > Self::SNIPPET_LENGTH {
+                    Some(format!(
+                        "{}... (offset {})",
+                        &snippet[..Self::SNIPPET_LENGTH - 3],
+                        span.start
+                    ))
+                } else {
+                    Some(format!("{} (offset {})", snippet, span.start))
+                }
+            })
+            .collect();
+
+        Self { comments }
+    }
+
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.comments.get(index)?.as_deref()
+    }
+}
+
+// How much checking generated code does while it runs. Full checks report moving past the
+// ends of the tape with a message, minimal checks still stop the program there but without
+// one, and no checks leave it out altogether, for programs that are known to stay on the
+// tape. The C and Rust backends tell the three apart; the others only check or don't.
+#[derive(Copy, Clone, Eq, PartialEq, Default, Debug)]
+pub enum CodegenChecks {
+    #[default]
+    Full,
+    Minimal,
+    None,
+}
+
+impl CodegenChecks {
+    pub const ALL: &'static [Self] = &[Self::Full, Self::Minimal, Self::None];
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Full => "full",
+            Self::Minimal => "minimal",
+            Self::None => "none",
+        }
+    }
+}
+
+named_option!(CodegenChecks);
+
+// How the compiled program should behave when it runs. Every backend sees the same
+// options, so a compiled program can match the configuration of the interpreter.
+// Instrumented programs count how often each loop is entered and how many times it
+// iterates, and print the counts to standard error when they exit; only the C and Rust
+// backends support it.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct CompileOptions {
+    pub tape_size: TapeSize,
+    pub cell_width: CellWidth,
+    pub eof_mode: EofMode,
+    pub wrap_semantics: WrapSemantics,
+    pub checks: CodegenChecks,
+    pub instrument: bool,
+}
+
+impl CompileOptions {
+    pub fn new(tape_size: TapeSize) -> Self {
+        Self {
+            tape_size,
+            ..Self::default()
+        }
+    }
+
+    // Whether the head wraps around the ends of the tape, instead of failing at them.
+    pub fn wraps(&self) -> bool {
+        matches!(self.tape_size, TapeSize::Finite(_)) && self.wrap_semantics == WrapSemantics::Wrap
+    }
+
+    // Whether the program checks for moving past the ends of the tape at all.
+    pub fn runtime_checks(&self) -> bool {
+        self.checks != CodegenChecks::None
+    }
+
+    // Returns an error if the cells are wider than the format supports.
+    fn require_cell_width(&self, widest: CellWidth, format: &str) -> Result<(), CompileError> {
+        if self.cell_width.bits() <= widest.bits() {
+            Ok(())
+        } else {
+            Err(CompileError::Unsupported(format!(
+                "{} doesn't support {}-bit cells",
+                format,
+                self.cell_width.bits()
+            )))
+        }
+    }
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        Self {
+            tape_size: TapeSize::Infinite,
+            cell_width: CellWidth::default(),
+            eof_mode: EofMode::default(),
+            wrap_semantics: WrapSemantics::default(),
+            checks: CodegenChecks::Full,
+            instrument: false,
+        }
+    }
+}
+
+// The lines every backend that writes code starts with, which say how the code was
+// generated. They leave out anything that changes from one run to the next, such as the
+// time, so compiling the same source the same way always writes the same bytes.
+fn header_lines(format: &str, options: &CompileOptions, info: &ProgramInfo) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    match &info.source_path {
+        Some(source_path) => lines.push(format!(
+            "Generated by membrane {} from {}.",
+            env!("CARGO_PKG_VERSION"),
+            source_path
+        )),
+        None => lines.push(format!(
+            "Generated by membrane {}.",
+            env!("CARGO_PKG_VERSION")
+        )),
+    }
+
+    lines.push(format!("Format: {}", format));
+
+    let tape_size = match options.tape_size {
+        TapeSize::Finite(length) => length,
+        TapeSize::Infinite => 0,
+    };
+
+    let mut flags = format!(
+        "--tape {} --cell-width {} --eof {} --wrap-semantics {} --codegen-checks {}",
+        tape_size, options.cell_width, options.eof_mode, options.wrap_semantics, options.checks
+    );
+
+    if info.optimized {
+        flags.push_str(" --optimize");
+    }
+
+    if options.instrument {
+        flags.push_str(" --instrument");
+    }
+
+    lines.push(format!("Options: {}", flags));
+
+    if let Some(source_hash) = info.source_hash {
+        lines.push(format!("Source CRC-32: {:08x}", source_hash));
+    }
+
+    lines
+}
+
+// Writes the header lines as comments that start with the given prefix.
+fn write_header<W: Write>(
+    writer: &mut W,
+    format: &str,
+    options: &CompileOptions,
+    info: &ProgramInfo,
+    comment: &str,
+) -> IOResult<()> {
+    for line in header_lines(format, options, info) {
+        writeln!(writer, "{} {}", comment, line)?;
+    }
+
+    Ok(())
+}
+
+// Writes the annotation of an instruction as a comment on a line of its own, if the
+// program has annotations.
+fn write_annotation<W: Write>(
+    writer: &mut W,
+    info: &ProgramInfo,
+    index: usize,
+    indent: &str,
+    comment: &str,
+) -> IOResult<()> {
+    match info.annotation(index) {
+        Some(annotation) => writeln!(writer, "{}{} {}", indent, comment, annotation),
+        None => Ok(()),
+    }
+}
+
+// Returns an amount as the cell value it adds or stores. Amounts are sign-extended, so -1
+// is the largest value a cell holds, whatever its width.
+fn cell_literal(width: CellWidth, amount: i8) -> u32 {
+    amount as i32 as u32 & width.max_value()
+}
+
+// Why a backend couldn't compile a program.
+#[derive(Debug, Error)]
+pub enum CompileError {
+    // The format can't express the program as it was asked to be compiled, such as with
+    // cells wider than it supports or a tape too long for its addresses.
+    #[error("{0}")]
+    Unsupported(String),
+    // The program's jumps or procedures don't pair up, or the format doesn't support the
+    // procedures it uses.
+    #[error("{0}")]
+    Ast(#[from] AstError),
+    #[error("{0}")]
+    Io(#[from] io::Error),
+}
+
+// A format programs can be compiled to. Every built-in format is a backend, and other
+// crates can add their own by registering them with a `Registry`.
+pub trait Backend: Send + Sync {
+    // The name the format is selected by, such as "c".
+    fn name(&self) -> &'static str;
+
+    // The extension of files in this format, without the leading dot.
+    fn file_extension(&self) -> &'static str;
+
+    fn compile(
+        &self,
+        program: &Program,
+        options: &CompileOptions,
+        info: &ProgramInfo,
+        writer: &mut dyn Write,
+    ) -> Result<(), CompileError>;
+
+    // Compiles the program like `compile`, also mapping the lines of the output back to the
+    // source. Formats that aren't written as lines of code don't have a map.
+    fn compile_with_source_map(
+        &self,
+        program: &Program,
+        options: &CompileOptions,
+        info: &ProgramInfo,
+        writer: &mut dyn Write,
+    ) -> Result<Option<SourceMap>, CompileError> {
+        self.compile(program, options, info, writer)?;
+        Ok(None)
+    }
+}
+
+// The backends that can be selected by name, in the order they were registered.
+pub struct Registry {
+    backends: Vec<Box<dyn Backend>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self {
+            backends: Vec::new(),
+        }
+    }
+
+    // A registry holding every backend that ships with membrane.
+    pub fn builtin() -> Self {
+        let mut registry = Self::new();
+
+        registry.register(Box::new(bytecode::BytecodeBackend));
+        registry.register(Box::new(c::CBackend));
+        registry.register(Box::new(x86_64::X86_64Backend));
+        registry.register(Box::new(wat::WatBackend));
+        registry.register(Box::new(wasm::WasmBackend));
+        registry.register(Box::new(csharp::CSharpBackend));
+        registry.register(Box::new(lua::LuaBackend));
+        registry.register(Box::new(java::JavaBackend));
+        registry.register(Box::new(brainfuck::BrainfuckBackend));
+        registry.register(Box::new(rust::RustBackend));
+        registry.register(Box::new(bundle::BundleBackend));
+        registry.register(Box::new(qbe::QbeBackend));
+        registry.register(Box::new(typescript::TypeScriptBackend));
+
+        #[cfg(feature = "cranelift")]
+        registry.register(Box::new(object::ObjectBackend));
+
+        registry
+    }
+
+    // Adds a backend, returning the one it replaces if another backend already had its
+    // name.
+    pub fn register(&mut self, backend: Box<dyn Backend>) -> Option<Box<dyn Backend>> {
+        match self
+            .backends
+            .iter_mut()
+            .find(|existing| existing.name() == backend.name())
+        {
+            Some(existing) => Some(mem::replace(existing, backend)),
+            None => {
+                self.backends.push(backend);
+                None
+            }
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Backend> {
+        self.iter().find(|backend| backend.name() == name)
+    }
+
+    // The backend that writes files with the extension. If several do, the first one
+    // registered wins, so .rs files are compiled by the Rust backend rather than as bundles.
+    pub fn for_extension(&self, extension: &str) -> Option<&dyn Backend> {
+        self.iter()
+            .find(|backend| backend.file_extension().eq_ignore_ascii_case(extension))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn Backend> {
+        self.backends.iter().map(|backend| backend.as_ref())
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.iter().map(|backend| backend.name()).collect()
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+// Compiles the program with the backend, writing it to a new file at the path.
+pub fn compile_file<P: AsRef<Path>>(
+    backend: &dyn Backend,
+    program: &Program,
+    options: &CompileOptions,
+    info: &ProgramInfo,
+    path: P,
+) -> Result<(), CompileError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    backend.compile(program, options, info, &mut writer)?;
+    Ok(writer.flush()?)
+}
+
+// Compiles the program with the backend to standard output, so it can be piped into a
+// compiler or a pager.
+pub fn compile_stdout(
+    backend: &dyn Backend,
+    program: &Program,
+    options: &CompileOptions,
+    info: &ProgramInfo,
+) -> Result<(), CompileError> {
+    let stdout = io::stdout();
+    let mut writer = BufWriter::new(stdout.lock());
+    backend.compile(program, options, info, &mut writer)?;
+    Ok(writer.flush()?)
+}
@@ -0,0 +1,110 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::{self, Command, ExitStatus};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use thiserror::Error;
+
+// A compiler that turns the source written by a backend into an executable.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Toolchain {
+    pub compiler: String,
+    pub arguments: Vec<String>,
+    pub source_extension: &'static str,
+}
+
+#[derive(Debug, Error)]
+pub enum NativeError {
+    #[error("can't build executables from {format} (expected one of: c, rust, bundle, object)")]
+    UnsupportedFormat { format: String },
+    #[error("couldn't run '{compiler}'; install it or pass another compiler with --compiler")]
+    CompilerNotFound { compiler: String },
+    #[error("'{compiler}' failed to compile the generated program ({status})")]
+    CompilerFailed {
+        compiler: String,
+        status: ExitStatus,
+    },
+    #[error("failed to build the executable: {0}")]
+    Io(#[from] io::Error),
+}
+
+impl Toolchain {
+    // The toolchain for a format, using the compiler named by CC or RUSTC if it's set, the
+    // same way build systems do.
+    pub fn for_format(format: &str) -> Result<Self, NativeError> {
+        match format {
+            "c" => Ok(Self {
+                compiler: env::var("CC").unwrap_or_else(|_| "cc".to_owned()),
+                arguments: vec!["-O2".to_owned()],
+                source_extension: "c",
+            }),
+            "rust" | "bundle" => Ok(Self {
+                compiler: env::var("RUSTC").unwrap_or_else(|_| "rustc".to_owned()),
+                arguments: vec!["--edition=2021".to_owned(), "-O".to_owned()],
+                source_extension: "rs",
+            }),
+            // Object files only need linking, which the C compiler does.
+            "object" => Ok(Self {
+                compiler: env::var("CC").unwrap_or_else(|_| "cc".to_owned()),
+                arguments: Vec::new(),
+                source_extension: "o",
+            }),
+            format => Err(NativeError::UnsupportedFormat {
+                format: format.to_owned(),
+            }),
+        }
+    }
+
+    // Writes the source to a scratch directory and compiles it to an executable at the
+    // output path. The compiler's own diagnostics go straight to standard error.
+    pub fn build<P: AsRef<Path>>(&self, source: &[u8], output: P) -> Result<(), NativeError> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+        let directory = env::temp_dir().join(format!(
+            "membrane-native-{}-{}",
+            process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        fs::create_dir_all(&directory)?;
+
+        let result = self.build_in(&directory, source, output.as_ref());
+        let _ = fs::remove_dir_all(&directory);
+        result
+    }
+
+    fn build_in(&self, directory: &Path, source: &[u8], output: &Path) -> Result<(), NativeError> {
+        let source_path = directory.join(format!("main.{}", self.source_extension));
+        fs::write(&source_path, source)?;
+
+        let status = Command::new(&self.compiler)
+            .args(&self.arguments)
+            .arg("-o")
+            .arg(output)
+            .arg(&source_path)
+            .status()
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::NotFound => NativeError::CompilerNotFound {
+                    compiler: self.compiler.clone(),
+                },
+                _ => NativeError::Io(err),
+            })?;
+
+        if status.success() {
+            Ok(())
+        } else {
+            Err(NativeError::CompilerFailed {
+                compiler: self.compiler.clone(),
+                status,
+            })
+        }
+    }
+}
@@ -0,0 +1,94 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::io::{self, Result as IOResult, Write};
+
+use cranelift_codegen::ir::{types, AbiParam, InstBuilder};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_module::{FuncId, Linkage, Module};
+use cranelift_object::{ObjectBuilder, ObjectModule};
+
+use crate::instruction::Instruction;
+use crate::program::Program;
+
+use super::cranelift;
+use super::{Backend, CompileError, CompileOptions, ProgramInfo};
+
+pub struct ObjectBackend;
+
+impl Backend for ObjectBackend {
+    fn name(&self) -> &'static str {
+        "object"
+    }
+
+    fn file_extension(&self) -> &'static str {
+        "o"
+    }
+
+    fn compile(
+        &self,
+        program: &Program,
+        options: &CompileOptions,
+        _info: &ProgramInfo,
+        writer: &mut dyn Write,
+    ) -> Result<(), CompileError> {
+        writer.write_all(&compile(&program.instructions, options)?)?;
+        Ok(())
+    }
+}
+
+// A relocatable object file for the host, compiled with Cranelift. It exports
+// `void membrane_run(void)` for C programs to call, and a weak `int main(void)` that calls
+// it, so linking the object on its own with `cc program.o` gives a runnable program while a
+// C program with its own main can still embed it.
+pub fn compile(
+    instructions: &[Instruction],
+    options: &CompileOptions,
+) -> Result<Vec<u8>, CompileError> {
+    let builder = ObjectBuilder::new(
+        cranelift::host_isa(true)?,
+        "membrane",
+        cranelift_module::default_libcall_names(),
+    )
+    .map_err(io::Error::other)?;
+
+    let mut module = ObjectModule::new(builder);
+    let run = cranelift::define_program(&mut module, instructions, options, Linkage::Export)?;
+    define_main(&mut module, run)?;
+
+    Ok(module.finish().emit().map_err(io::Error::other)?)
+}
+
+fn define_main(module: &mut ObjectModule, run: FuncId) -> IOResult<()> {
+    let mut signature = module.make_signature();
+    signature.returns.push(AbiParam::new(types::I32));
+
+    let main = module
+        .declare_function("main", Linkage::Preemptible, &signature)
+        .map_err(io::Error::other)?;
+
+    let mut context = module.make_context();
+    context.func.signature = signature;
+
+    let mut builder_context = FunctionBuilderContext::new();
+    let mut builder = FunctionBuilder::new(&mut context.func, &mut builder_context);
+
+    let entry = builder.create_block();
+    builder.switch_to_block(entry);
+
+    let run = module.declare_func_in_func(run, builder.func);
+    builder.ins().call(run, &[]);
+
+    let status = builder.ins().iconst(types::I32, 0);
+    builder.ins().return_(&[status]);
+
+    builder.seal_all_blocks();
+    builder.finalize();
+
+    module
+        .define_function(main, &mut context)
+        .map_err(io::Error::other)
+}
@@ -0,0 +1,649 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::io::{Result as IOResult, Write};
+
+use crate::instruction::Instruction;
+use crate::interpreter::{CellWidth, EofMode, TapeSize};
+use crate::program::Program;
+
+use super::ast::{self, Node};
+use super::source_map::{self, LineTracker, SourceMap};
+use super::{
+    cell_literal, write_annotation, write_header, Backend, CodegenChecks, CompileError,
+    CompileOptions, ProgramInfo,
+};
+
+const INITIAL_TAPE_LENGTH: usize = 30_000;
+
+// The messages programs fail with, by the name of the data holding them.
+const MESSAGES: &[(&str, &str)] = &[
+    (
+        "moved_left",
+        "the tape head moved left of the start of the tape",
+    ),
+    (
+        "moved_right",
+        "the tape head moved past the end of the tape",
+    ),
+    (
+        "accessed_left",
+        "tried to access a cell left of the start of the tape",
+    ),
+    (
+        "accessed_right",
+        "tried to access a cell past the end of the tape",
+    ),
+    ("too_large", "the tape grew too large"),
+    ("out_of_memory", "out of memory"),
+    ("write_failed", "failed to write output"),
+];
+
+// Failing flushes standard output first, so the message comes after everything the program
+// wrote. Output goes through putchar and input through getchar, so both are buffered by the
+// C library, which is flushed before every read.
+const FAIL: &str = r#"
+function $fail(l %message, l %length) {
+@start
+    %flushed =w call $fflush(l 0)
+    %written =l call $write(w 2, l %message, l %length)
+    call $exit(w 1)
+    ret
+}
+
+function $output(w %value, l %count) {
+@start
+    %byte =w and %value, 255
+    %remaining =l copy %count
+@loop
+    %more =w cnel %remaining, 0
+    jnz %more, @write, @done
+@write
+    %result =w call $putchar(w %byte)
+    %failed =w ceqw %result, -1
+    jnz %failed, @write_failed, @next
+@write_failed
+    call $fail(l $write_failed, l WRITE_FAILED_LENGTH)
+    ret
+@next
+    %remaining =l sub %remaining, 1
+    jmp @loop
+@done
+    ret
+}
+"#;
+
+// Returns the new value of the cell, which is the last byte read, or EOF_VALUE once input
+// runs out if it's set.
+const INPUT_START: &str = r#"
+function w $input(w %value, l %count) {
+@start
+    %flushed =w call $fflush(l 0)
+    %cell =w copy %value
+    %remaining =l copy %count
+@loop
+    %more =w cnel %remaining, 0
+    jnz %more, @read, @done
+@read
+    %byte =w call $getchar()
+    %eof =w ceqw %byte, -1
+    jnz %eof, @end_of_input, @store
+@store
+    %cell =w copy %byte
+    %remaining =l sub %remaining, 1
+    jmp @loop
+@end_of_input
+"#;
+
+const INPUT_END: &str = r#"@done
+    ret %cell
+}
+"#;
+
+// A right-infinite tape lives on the heap and doubles in length whenever the head moves
+// past its end, with the new cells cleared.
+const RESERVE: &str = r#"
+data $tape = { l 0 }
+data $tape_length = { l 0 }
+
+function $setup() {
+@start
+    %tape =l call $calloc(l INITIAL_TAPE_LENGTH, l CELL_SIZE)
+    %failed =w ceql %tape, 0
+    jnz %failed, @out_of_memory, @done
+@out_of_memory
+    call $fail(l $out_of_memory, l OUT_OF_MEMORY_LENGTH)
+    ret
+@done
+    storel %tape, $tape
+    storel INITIAL_TAPE_LENGTH, $tape_length
+    ret
+}
+
+function $reserve(l %index) {
+@start
+    %old =l loadl $tape_length
+    %length =l copy %old
+@grow
+    %enough =w cugtl %length, %index
+    jnz %enough, @allocate, @check
+@check
+    %limit =w cugtl %length, MAXIMUM_LENGTH
+    jnz %limit, @too_large, @double
+@too_large
+    call $fail(l $too_large, l TOO_LARGE_LENGTH)
+    ret
+@double
+    %length =l mul %length, 2
+    jmp @grow
+@allocate
+    %tape =l loadl $tape
+    %bytes =l mul %length, CELL_SIZE
+    %grown =l call $realloc(l %tape, l %bytes)
+    %failed =w ceql %grown, 0
+    jnz %failed, @out_of_memory, @clear
+@out_of_memory
+    call $fail(l $out_of_memory, l OUT_OF_MEMORY_LENGTH)
+    ret
+@clear
+    %start =l mul %old, CELL_SIZE
+    %start =l add %grown, %start
+    %cleared =l sub %length, %old
+    %cleared =l mul %cleared, CELL_SIZE
+    %ignored =l call $memset(l %start, w 0, l %cleared)
+    storel %grown, $tape
+    storel %length, $tape_length
+    ret
+}
+"#;
+
+pub struct QbeBackend;
+
+impl Backend for QbeBackend {
+    fn name(&self) -> &'static str {
+        "qbe"
+    }
+
+    fn file_extension(&self) -> &'static str {
+        "ssa"
+    }
+
+    fn compile(
+        &self,
+        program: &Program,
+        options: &CompileOptions,
+        info: &ProgramInfo,
+        mut writer: &mut dyn Write,
+    ) -> Result<(), CompileError> {
+        compile(&program.instructions, options, info, &mut writer)
+    }
+
+    fn compile_with_source_map(
+        &self,
+        program: &Program,
+        options: &CompileOptions,
+        info: &ProgramInfo,
+        writer: &mut dyn Write,
+    ) -> Result<Option<SourceMap>, CompileError> {
+        source_map::track_lines(program, info, writer, |writer| {
+            write_program(&program.instructions, options, info, writer)
+        })
+    }
+}
+
+// QBE's intermediate language, for a program linked against the C library. Build it with
+// `qbe -o program.s program.ssa && cc -o program program.s`. The main function keeps the
+// address of the tape in %tape and the head's index in %head; the temporaries are assigned
+// more than once, which QBE turns into SSA form itself.
+pub fn compile<W: Write>(
+    instructions: &[Instruction],
+    options: &CompileOptions,
+    info: &ProgramInfo,
+    writer: &mut W,
+) -> Result<(), CompileError> {
+    write_program(instructions, options, info, &mut LineTracker::new(writer))
+}
+
+fn write_program<W: Write>(
+    instructions: &[Instruction],
+    options: &CompileOptions,
+    info: &ProgramInfo,
+    writer: &mut LineTracker<W>,
+) -> Result<(), CompileError> {
+    let mut tape = Tape::new(options);
+
+    write_header(writer, "qbe", options, info, "#")?;
+    writeln!(writer)?;
+
+    for (name, message) in MESSAGES {
+        writeln!(writer, "data ${} = {{ b \"error: {}\\n\" }}", name, message)?;
+    }
+
+    let runtime = FAIL.replace("WRITE_FAILED_LENGTH", &message_length("write_failed"));
+    writer.write_all(runtime.as_bytes())?;
+
+    writer.write_all(INPUT_START.as_bytes())?;
+
+    match options.eof_mode {
+        EofMode::Unchanged => {}
+        EofMode::Zero => writeln!(writer, "    %cell =w copy 0")?,
+        EofMode::NegativeOne => writeln!(writer, "    %cell =w copy {}", tape.width.max_value())?,
+    }
+
+    writer.write_all(INPUT_END.as_bytes())?;
+
+    match options.tape_size {
+        TapeSize::Finite(length) => {
+            writeln!(writer)?;
+            writeln!(
+                writer,
+                "data $cells = align 8 {{ z {} }}",
+                length * tape.cell_size()
+            )?;
+        }
+        TapeSize::Infinite => {
+            let runtime = RESERVE
+                .replace("INITIAL_TAPE_LENGTH", &INITIAL_TAPE_LENGTH.to_string())
+                .replace("CELL_SIZE", &tape.cell_size().to_string())
+                .replace(
+                    "MAXIMUM_LENGTH",
+                    &(usize::MAX / 2 / tape.cell_size()).to_string(),
+                )
+                .replace("OUT_OF_MEMORY_LENGTH", &message_length("out_of_memory"))
+                .replace("TOO_LARGE_LENGTH", &message_length("too_large"));
+
+            writer.write_all(runtime.as_bytes())?;
+        }
+    }
+
+    writeln!(writer)?;
+    writeln!(writer, "export function w $main() {{")?;
+    writeln!(writer, "@start")?;
+
+    match options.tape_size {
+        TapeSize::Finite(_) => writeln!(writer, "    %tape =l copy $cells")?,
+        TapeSize::Infinite => {
+            writeln!(writer, "    call $setup()")?;
+            tape.reload(writer)?;
+        }
+    }
+
+    writeln!(writer, "    %head =l copy 0")?;
+
+    write_block(writer, info, &mut tape, &ast::build(instructions)?)?;
+    writer.end();
+
+    writeln!(writer, "    %flushed =w call $fflush(l 0)")?;
+    writeln!(writer, "    jnz %flushed, @write_failed, @exit")?;
+    writeln!(writer, "@exit")?;
+    writeln!(writer, "    ret 0")?;
+    writeln!(writer, "@write_failed")?;
+    write_failure(writer, "write_failed", CodegenChecks::Full)?;
+
+    // Checks jump to these blocks, and QBE drops the ones nothing jumps to.
+    for name in [
+        "moved_left",
+        "moved_right",
+        "accessed_left",
+        "accessed_right",
+        "too_large",
+    ] {
+        writeln!(writer, "@{}", name)?;
+        write_failure(writer, name, options.checks)?;
+    }
+
+    writeln!(writer, "}}")?;
+
+    Ok(())
+}
+
+// The length of a message, including its prefix and newline.
+fn message_length(name: &str) -> String {
+    let (_, message) = MESSAGES.iter().find(|(other, _)| *other == name).unwrap();
+    ("error: ".len() + message.len() + 1).to_string()
+}
+
+// Ends a block by failing with the named message. Minimal checks fail without one.
+fn write_failure<W: Write>(writer: &mut W, name: &str, checks: CodegenChecks) -> IOResult<()> {
+    if checks == CodegenChecks::Full {
+        writeln!(
+            writer,
+            "    call $fail(l ${}, l {})",
+            name,
+            message_length(name)
+        )?;
+    } else {
+        writeln!(writer, "    call $exit(w 1)")?;
+    }
+
+    writeln!(writer, "    ret 1")
+}
+
+fn write_block<W: Write>(
+    writer: &mut LineTracker<W>,
+    info: &ProgramInfo,
+    tape: &mut Tape,
+    nodes: &[Node],
+) -> IOResult<()> {
+    for node in nodes {
+        writer.begin(node.index());
+        write_annotation(writer, info, node.index(), "    ", "#")?;
+
+        let (index, instruction) = match node {
+            Node::Instruction { index, instruction } => (*index, instruction),
+            Node::Procedure { .. } => unreachable!(),
+            Node::Loop { index, body } => {
+                writeln!(writer, "@loop_{}", index)?;
+                tape.load(writer, "%head")?;
+                writeln!(writer, "    jnz %value, @body_{}, @end_{}", index, index)?;
+                writeln!(writer, "@body_{}", index)?;
+                write_block(writer, info, tape, body)?;
+                writer.begin(node.last_index());
+                write_annotation(writer, info, node.last_index(), "    ", "#")?;
+                writeln!(writer, "    jmp @loop_{}", index)?;
+                writeln!(writer, "@end_{}", index)?;
+                continue;
+            }
+        };
+
+        let width = tape.width;
+
+        match instruction {
+            Instruction::Add(amount) => {
+                tape.load(writer, "%head")?;
+                writeln!(
+                    writer,
+                    "    %value =w add %value, {}",
+                    cell_literal(width, *amount)
+                )?;
+                tape.store(writer, "%value")?;
+            }
+            Instruction::Move(amount) => {
+                tape.advance(writer, "%head", *amount, Access::Move)?;
+            }
+            Instruction::Write(count) => {
+                tape.load(writer, "%head")?;
+                writeln!(writer, "    call $output(w %value, l {})", count)?;
+            }
+            Instruction::Read(count) => {
+                tape.load(writer, "%head")?;
+                writeln!(writer, "    %value =w call $input(w %value, l {})", count)?;
+                tape.store(writer, "%value")?;
+            }
+            Instruction::JumpIfZero { .. }
+            | Instruction::JumpIfNotZero { .. }
+            | Instruction::DefineProc { .. }
+            | Instruction::EndProc
+            | Instruction::CallProc => unreachable!(),
+
+            Instruction::SetValue(value) => {
+                tape.address(writer, "%head")?;
+                tape.store(writer, &cell_literal(width, *value).to_string())?;
+            }
+            Instruction::AddRelative { offset, amount } => {
+                tape.advance(writer, "%index", *offset, Access::Cell)?;
+                tape.load(writer, "%index")?;
+                writeln!(
+                    writer,
+                    "    %value =w add %value, {}",
+                    cell_literal(width, *amount)
+                )?;
+                tape.store(writer, "%value")?;
+            }
+            Instruction::AddVector { vector } => {
+                for (lane, amount) in vector.iter().enumerate() {
+                    if *amount != 0 {
+                        tape.advance(writer, "%index", lane as isize, Access::Cell)?;
+                        tape.load(writer, "%index")?;
+                        writeln!(
+                            writer,
+                            "    %value =w add %value, {}",
+                            cell_literal(width, *amount)
+                        )?;
+                        tape.store(writer, "%value")?;
+                    }
+                }
+            }
+            Instruction::MulAdd { offset, factor } => {
+                tape.load(writer, "%head")?;
+                writeln!(
+                    writer,
+                    "    %product =w mul %value, {}",
+                    cell_literal(width, *factor)
+                )?;
+                writeln!(writer, "    jnz %value, @mul_{}, @mul_end_{}", index, index)?;
+                writeln!(writer, "@mul_{}", index)?;
+                tape.advance(writer, "%index", *offset, Access::Cell)?;
+                tape.load(writer, "%index")?;
+                writeln!(writer, "    %value =w add %value, %product")?;
+                tape.store(writer, "%value")?;
+                writeln!(writer, "@mul_end_{}", index)?;
+            }
+            Instruction::MoveRightToZero { increment, stride } => {
+                write_scan(writer, tape, index, *increment, *stride as isize)?;
+            }
+            Instruction::MoveLeftToZero { increment, stride } => {
+                write_scan(writer, tape, index, *increment, -(*stride as isize))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn write_scan<W: Write>(
+    writer: &mut W,
+    tape: &mut Tape,
+    index: usize,
+    increment: i8,
+    stride: isize,
+) -> IOResult<()> {
+    writeln!(writer, "@scan_{}", index)?;
+    tape.load(writer, "%head")?;
+    writeln!(
+        writer,
+        "    jnz %value, @scan_body_{}, @scan_end_{}",
+        index, index
+    )?;
+    writeln!(writer, "@scan_body_{}", index)?;
+
+    if increment != 0 {
+        writeln!(
+            writer,
+            "    %value =w add %value, {}",
+            cell_literal(tape.width, increment)
+        )?;
+        tape.store(writer, "%value")?;
+    }
+
+    tape.advance(writer, "%head", stride, Access::Move)?;
+    writeln!(writer, "    jmp @scan_{}", index)?;
+    writeln!(writer, "@scan_end_{}", index)
+}
+
+// Whether an index is computed to move the head, or to reach a cell away from it, which
+// only changes the message a failed check gives.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Access {
+    Move,
+    Cell,
+}
+
+struct Tape {
+    length: Option<usize>,
+    width: CellWidth,
+    wraps: bool,
+    checks: bool,
+    labels: usize,
+}
+
+impl Tape {
+    fn new(options: &CompileOptions) -> Self {
+        let length = match options.tape_size {
+            TapeSize::Finite(length) => Some(length),
+            TapeSize::Infinite => None,
+        };
+
+        Self {
+            length,
+            width: options.cell_width,
+            wraps: options.wraps(),
+            checks: options.runtime_checks(),
+            labels: 0,
+        }
+    }
+
+    fn cell_size(&self) -> usize {
+        self.width.bytes() as usize
+    }
+
+    // Returns a label no other code uses, for the blocks of a single check.
+    fn label(&mut self) -> usize {
+        self.labels += 1;
+        self.labels
+    }
+
+    // Picks up the tape again after it may have grown.
+    fn reload<W: Write>(&self, writer: &mut W) -> IOResult<()> {
+        writeln!(writer, "    %tape =l loadl $tape")?;
+        writeln!(writer, "    %length =l loadl $tape_length")
+    }
+
+    // Emits code that leaves the address of the cell at the index in %address.
+    fn address<W: Write>(&self, writer: &mut W, index: &str) -> IOResult<()> {
+        if self.cell_size() == 1 {
+            writeln!(writer, "    %address =l add %tape, {}", index)
+        } else {
+            writeln!(
+                writer,
+                "    %address =l mul {}, {}",
+                index,
+                self.cell_size()
+            )?;
+            writeln!(writer, "    %address =l add %tape, %address")
+        }
+    }
+
+    // Emits code that loads the cell at the index into %value, leaving its address in
+    // %address for a store.
+    fn load<W: Write>(&self, writer: &mut W, index: &str) -> IOResult<()> {
+        self.address(writer, index)?;
+
+        let load = match self.width {
+            CellWidth::U8 => "loadub",
+            CellWidth::U16 => "loaduh",
+            CellWidth::U32 => "loadw",
+        };
+
+        writeln!(writer, "    %value =w {} %address", load)
+    }
+
+    fn store<W: Write>(&self, writer: &mut W, value: &str) -> IOResult<()> {
+        let store = match self.width {
+            CellWidth::U8 => "storeb",
+            CellWidth::U16 => "storeh",
+            CellWidth::U32 => "storew",
+        };
+
+        writeln!(writer, "    {} {}, %address", store, value)
+    }
+
+    // Emits code that sets `target` to the index `amount` cells away from the head, either
+    // wrapping around the tape or failing at its ends, unless runtime checks are off. A
+    // right-infinite tape grows to hold the index.
+    fn advance<W: Write>(
+        &mut self,
+        writer: &mut W,
+        target: &str,
+        amount: isize,
+        access: Access,
+    ) -> IOResult<()> {
+        let (left, right) = match access {
+            Access::Move => ("moved_left", "moved_right"),
+            Access::Cell => ("accessed_left", "accessed_right"),
+        };
+
+        let distance = match self.length {
+            Some(length) if self.wraps => amount.unsigned_abs() % length,
+            _ => amount.unsigned_abs(),
+        };
+
+        if distance == 0 {
+            return writeln!(writer, "    {} =l copy %head", target);
+        }
+
+        let label = self.label();
+
+        match (amount > 0, self.length) {
+            (true, Some(length)) if self.wraps => {
+                writeln!(writer, "    {} =l add %head, {}", target, distance)?;
+                writeln!(writer, "    %outside =w cugel {}, {}", target, length)?;
+                writeln!(
+                    writer,
+                    "    jnz %outside, @wrap_{}, @wrapped_{}",
+                    label, label
+                )?;
+                writeln!(writer, "@wrap_{}", label)?;
+                writeln!(writer, "    {} =l sub {}, {}", target, target, length)?;
+                writeln!(writer, "@wrapped_{}", label)
+            }
+            (false, Some(length)) if self.wraps => {
+                writeln!(writer, "    %outside =w cultl %head, {}", distance)?;
+                writeln!(writer, "    {} =l sub %head, {}", target, distance)?;
+                writeln!(
+                    writer,
+                    "    jnz %outside, @wrap_{}, @wrapped_{}",
+                    label, label
+                )?;
+                writeln!(writer, "@wrap_{}", label)?;
+                writeln!(writer, "    {} =l add {}, {}", target, target, length)?;
+                writeln!(writer, "@wrapped_{}", label)
+            }
+            (true, Some(length)) => {
+                writeln!(writer, "    {} =l add %head, {}", target, distance)?;
+
+                if self.checks {
+                    writeln!(writer, "    %outside =w cugel {}, {}", target, length)?;
+                    writeln!(writer, "    jnz %outside, @{}, @checked_{}", right, label)?;
+                    writeln!(writer, "@checked_{}", label)?;
+                }
+
+                Ok(())
+            }
+            (true, None) => {
+                writeln!(writer, "    %next =l add %head, {}", distance)?;
+
+                if self.checks {
+                    writeln!(writer, "    %outside =w cultl %next, %head")?;
+                    writeln!(writer, "    jnz %outside, @too_large, @checked_{}", label)?;
+                    writeln!(writer, "@checked_{}", label)?;
+                }
+
+                writeln!(writer, "    {} =l copy %next", target)?;
+
+                writeln!(writer, "    %outside =w cugel {}, %length", target)?;
+                writeln!(
+                    writer,
+                    "    jnz %outside, @grow_{}, @grown_{}",
+                    label, label
+                )?;
+                writeln!(writer, "@grow_{}", label)?;
+                writeln!(writer, "    call $reserve(l {})", target)?;
+                self.reload(writer)?;
+                writeln!(writer, "@grown_{}", label)
+            }
+            (false, _) => {
+                if self.checks {
+                    writeln!(writer, "    %outside =w cultl %head, {}", distance)?;
+                    writeln!(writer, "    jnz %outside, @{}, @checked_{}", left, label)?;
+                    writeln!(writer, "@checked_{}", label)?;
+                }
+
+                writeln!(writer, "    {} =l sub %head, {}", target, distance)
+            }
+        }
+    }
+}
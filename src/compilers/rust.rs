@@ -0,0 +1,841 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::fs;
+use std::io::{Result as IOResult, Write};
+use std::path::Path;
+
+use crate::instruction::Instruction;
+use crate::interpreter::{CellWidth, EofMode, TapeSize};
+use crate::program::Program;
+
+use super::ast::{self, Node};
+use super::source_map::{self, LineTracker, SourceMap};
+use super::{
+    cell_literal, write_annotation, write_header, Backend, CodegenChecks, CompileError,
+    CompileOptions, ProgramInfo,
+};
+
+const INITIAL_TAPE_LENGTH: usize = 30_000;
+
+// Cells are unsigned and every add goes through wrapping_add, so nothing panics in debug
+// builds. The head only ever moves through move_right and move_left, and cells away from
+// it are reached through at_right and at_left, which return indices; each kind of tape
+// gets its own definitions of them, with checks that compile away when RUNTIME_CHECKS is
+// false and fail through check_failed, which only says why when CHECK_MESSAGES is true.
+// Without any checks, cells are indexed without bounds checks as well. A finite tape wraps
+// without subtracting past zero, so moving left of the first cell lands on the last one,
+// like the interpreter.
+//
+// Standard input and output are locked once, and output goes through a BufWriter as raw
+// bytes, so it's flushed before every read, before failing, and at the end of main.
+const PRELUDE: &str = r#"#![allow(dead_code)]
+
+use std::io::{self, BufWriter, Read, StdinLock, StdoutLock, Write};
+use std::process;
+
+struct Tape {
+    cells: Cells,
+    head: usize,
+    input: StdinLock<'static>,
+    output: BufWriter<StdoutLock<'static>>,
+}
+"#;
+
+const FINITE_TAPE: &str = r#"
+impl Tape {
+    fn new() -> Self {
+        Self::with_length(TAPE_LENGTH)
+    }
+
+    fn at_right(&mut self, offset: usize) -> usize {
+        (self.head + offset % TAPE_LENGTH) % TAPE_LENGTH
+    }
+
+    fn at_left(&mut self, offset: usize) -> usize {
+        (self.head + (TAPE_LENGTH - offset % TAPE_LENGTH)) % TAPE_LENGTH
+    }
+
+    fn move_right(&mut self, amount: usize) {
+        self.head = self.at_right(amount);
+    }
+
+    fn move_left(&mut self, amount: usize) {
+        self.head = self.at_left(amount);
+    }
+"#;
+
+const BOUNDED_TAPE: &str = r#"
+impl Tape {
+    fn new() -> Self {
+        Self::with_length(TAPE_LENGTH)
+    }
+
+    fn at_right(&mut self, offset: usize) -> usize {
+        if RUNTIME_CHECKS && offset >= TAPE_LENGTH - self.head {
+            self.check_failed("tried to access a cell past the end of the tape");
+        }
+
+        self.head + offset
+    }
+
+    fn at_left(&mut self, offset: usize) -> usize {
+        if RUNTIME_CHECKS && offset > self.head {
+            self.check_failed("tried to access a cell left of the start of the tape");
+        }
+
+        self.head.wrapping_sub(offset)
+    }
+
+    fn move_right(&mut self, amount: usize) {
+        if RUNTIME_CHECKS && amount >= TAPE_LENGTH - self.head {
+            self.check_failed("the tape head moved past the end of the tape");
+        }
+
+        self.head += amount;
+    }
+
+    fn move_left(&mut self, amount: usize) {
+        if RUNTIME_CHECKS && amount > self.head {
+            self.check_failed("the tape head moved left of the start of the tape");
+        }
+
+        self.head = self.head.wrapping_sub(amount);
+    }
+"#;
+
+const INFINITE_TAPE: &str = r#"
+impl Tape {
+    fn new() -> Self {
+        Self::with_length(INITIAL_TAPE_LENGTH)
+    }
+
+    fn reserve(&mut self, index: usize) {
+        if index < self.cells.len() {
+            return;
+        }
+
+        let mut length = self.cells.len();
+
+        while length <= index {
+            length = length
+                .checked_mul(2)
+                .unwrap_or_else(|| self.fail("the tape grew too large"));
+        }
+
+        self.cells.resize(length, 0);
+    }
+
+    fn at_right(&mut self, offset: usize) -> usize {
+        if RUNTIME_CHECKS && offset > usize::MAX - self.head {
+            self.check_failed("the tape grew too large");
+        }
+
+        let index = self.head.wrapping_add(offset);
+        self.reserve(index);
+        index
+    }
+
+    fn at_left(&mut self, offset: usize) -> usize {
+        if RUNTIME_CHECKS && offset > self.head {
+            self.check_failed("tried to access a cell left of the start of the tape");
+        }
+
+        self.head.wrapping_sub(offset)
+    }
+
+    fn move_right(&mut self, amount: usize) {
+        self.head = self.at_right(amount);
+    }
+
+    fn move_left(&mut self, amount: usize) {
+        if RUNTIME_CHECKS && amount > self.head {
+            self.check_failed("the tape head moved left of the start of the tape");
+        }
+
+        self.head = self.head.wrapping_sub(amount);
+    }
+"#;
+
+// Once input runs out, reads store EOF_VALUE if it's set, and leave the cell unchanged
+// otherwise. Repeated writes and reads go through a buffer a chunk at a time, instead of
+// a byte at a time; only the last byte of a repeated read ends up in the cell.
+const IO: &str = r#"
+    fn with_length(length: usize) -> Self {
+        Self {
+            cells: Cells::from(vec![0; length]),
+            head: 0,
+            input: io::stdin().lock(),
+            output: BufWriter::with_capacity(1 << 16, io::stdout().lock()),
+        }
+    }
+
+    fn add(&mut self, index: usize, amount: Cell) {
+        self.cells[index] = self.cells[index].wrapping_add(amount);
+    }
+
+    fn output(&mut self, count: usize) {
+        let buffer = [self.cells[self.head] as u8; 256];
+        let mut remaining = count;
+
+        while remaining > 0 {
+            let length = remaining.min(buffer.len());
+
+            if self.output.write_all(&buffer[..length]).is_err() {
+                self.fail("failed to write output");
+            }
+
+            remaining -= length;
+        }
+    }
+
+    fn input(&mut self, count: usize) {
+        self.flush();
+
+        let mut buffer = [0; 256];
+        let mut remaining = count;
+
+        while remaining > 0 {
+            let length = remaining.min(buffer.len());
+
+            match self.input.read(&mut buffer[..length]) {
+                Ok(read) if read > 0 => {
+                    self.cells[self.head] = Cell::from(buffer[read - 1]);
+                    remaining -= read;
+                }
+                _ => {
+                    if let Some(value) = EOF_VALUE {
+                        self.cells[self.head] = value;
+                    }
+
+                    return;
+                }
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.output.flush().is_err() {
+            self.fail("failed to write output");
+        }
+    }
+
+    fn fail(&mut self, message: &str) -> ! {
+        let _ = self.output.flush();
+        eprintln!("error: {}", message);
+        process::exit(1);
+    }
+
+    fn check_failed(&mut self, message: &str) -> ! {
+        if CHECK_MESSAGES {
+            self.fail(message);
+        }
+
+        let _ = self.output.flush();
+        process::exit(1);
+    }
+}
+"#;
+
+const UNCHECKED_CELLS: &str = r#"
+use std::ops::{Deref, DerefMut, Index, IndexMut};
+
+struct Cells(Vec<Cell>);
+
+impl From<Vec<Cell>> for Cells {
+    fn from(cells: Vec<Cell>) -> Self {
+        Self(cells)
+    }
+}
+
+impl Deref for Cells {
+    type Target = Vec<Cell>;
+
+    fn deref(&self) -> &Vec<Cell> {
+        &self.0
+    }
+}
+
+impl DerefMut for Cells {
+    fn deref_mut(&mut self) -> &mut Vec<Cell> {
+        &mut self.0
+    }
+}
+
+impl Index<usize> for Cells {
+    type Output = Cell;
+
+    fn index(&self, index: usize) -> &Cell {
+        unsafe { self.0.get_unchecked(index) }
+    }
+}
+
+impl IndexMut<usize> for Cells {
+    fn index_mut(&mut self, index: usize) -> &mut Cell {
+        unsafe { self.0.get_unchecked_mut(index) }
+    }
+}
+"#;
+
+const FAIL_EXIT: &str = "        process::exit(1);\n";
+const INSTRUMENTED_FAIL_EXIT: &str = "        report();\n        process::exit(1);\n";
+
+// Loops are reported by id, which is the index of the instruction they start at.
+const REPORT: &str = r#"
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static LOOP_ENTRIES: [AtomicU64; LOOP_COUNT] = [const { AtomicU64::new(0) }; LOOP_COUNT];
+static LOOP_ITERATIONS: [AtomicU64; LOOP_COUNT] = [const { AtomicU64::new(0) }; LOOP_COUNT];
+
+fn report() {
+    eprintln!("{:>12} {:>20} {:>20}", "loop", "entries", "iterations");
+
+    for (counter, id) in LOOP_IDS.iter().enumerate() {
+        eprintln!(
+            "{:>12} {:>20} {:>20}",
+            id,
+            LOOP_ENTRIES[counter].load(Ordering::Relaxed),
+            LOOP_ITERATIONS[counter].load(Ordering::Relaxed)
+        );
+    }
+}
+"#;
+
+// pbrain procedures are written as functions, named after the index of their DefineProc,
+// and defining one stores it in the table under the value of the current cell. Calls
+// look the current cell up and run whatever is stored there.
+const PROCEDURES: &str = r#"
+use std::collections::HashMap;
+
+struct Procedures(HashMap<Cell, fn(&mut Tape, &mut Procedures)>);
+
+impl Procedures {
+    fn new() -> Self {
+        Self(HashMap::new())
+    }
+
+    fn define(&mut self, tape: &Tape, procedure: fn(&mut Tape, &mut Procedures)) {
+        self.0.insert(tape.cells[tape.head], procedure);
+    }
+
+    fn call(&mut self, tape: &mut Tape) {
+        match self.0.get(&tape.cells[tape.head]) {
+            Some(&procedure) => procedure(tape, self),
+            None => tape.fail("called a procedure that was never defined"),
+        }
+    }
+}
+"#;
+
+pub struct RustBackend;
+
+impl Backend for RustBackend {
+    fn name(&self) -> &'static str {
+        "rust"
+    }
+
+    fn file_extension(&self) -> &'static str {
+        "rs"
+    }
+
+    fn compile(
+        &self,
+        program: &Program,
+        options: &CompileOptions,
+        info: &ProgramInfo,
+        mut writer: &mut dyn Write,
+    ) -> Result<(), CompileError> {
+        compile(&program.instructions, options, info, &mut writer)
+    }
+
+    fn compile_with_source_map(
+        &self,
+        program: &Program,
+        options: &CompileOptions,
+        info: &ProgramInfo,
+        writer: &mut dyn Write,
+    ) -> Result<Option<SourceMap>, CompileError> {
+        source_map::track_lines(program, info, writer, |writer| {
+            write_program(&program.instructions, options, info, writer)
+        })
+    }
+}
+
+pub fn compile<W: Write>(
+    instructions: &[Instruction],
+    options: &CompileOptions,
+    info: &ProgramInfo,
+    writer: &mut W,
+) -> Result<(), CompileError> {
+    write_program(instructions, options, info, &mut LineTracker::new(writer))
+}
+
+fn write_program<W: Write>(
+    instructions: &[Instruction],
+    options: &CompileOptions,
+    info: &ProgramInfo,
+    writer: &mut LineTracker<W>,
+) -> Result<(), CompileError> {
+    let width = options.cell_width;
+
+    write_header(writer, "rust", options, info, "//")?;
+
+    writeln!(writer)?;
+    write_runtime(writer, options)?;
+    writeln!(writer)?;
+
+    let nodes = ast::build_with_procedures(instructions)?;
+
+    let loops = if options.instrument {
+        let loops = ast::loop_ids(&nodes);
+        write_report(writer, &loops)?;
+        loops
+    } else {
+        Vec::new()
+    };
+
+    let has_procedures = instructions.iter().any(|instruction| {
+        matches!(
+            instruction,
+            Instruction::DefineProc { .. } | Instruction::CallProc
+        )
+    });
+
+    if has_procedures {
+        writer.write_all(PROCEDURES.as_bytes())?;
+        writeln!(writer)?;
+        write_procedures(writer, info, &loops, width, &nodes)?;
+        writer.end();
+    }
+
+    writeln!(writer, "fn main() {{")?;
+
+    // The tape is borrowed the same way procedures borrow it, so their code is the same.
+    if has_procedures {
+        writeln!(writer, "    let tape = &mut Tape::new();")?;
+        writeln!(writer, "    let procedures = &mut Procedures::new();")?;
+    } else {
+        writeln!(writer, "    let mut tape = Tape::new();")?;
+    }
+
+    writeln!(writer)?;
+
+    write_block(writer, info, &loops, width, &nodes, 1)?;
+    writer.end();
+
+    writeln!(writer)?;
+    writeln!(writer, "    tape.flush();")?;
+
+    if options.instrument {
+        writeln!(writer, "    report();")?;
+    }
+
+    writeln!(writer, "}}")?;
+
+    Ok(())
+}
+
+// Writes the definitions every program needs, from the imports to the Tape type, which
+// reaches cells through at_right and at_left and moves through move_right and move_left.
+pub(super) fn write_runtime<W: Write>(writer: &mut W, options: &CompileOptions) -> IOResult<()> {
+    writer.write_all(PRELUDE.as_bytes())?;
+    writeln!(writer)?;
+
+    writeln!(writer, "type Cell = {};", cell_type(options.cell_width))?;
+
+    if options.checks == CodegenChecks::None {
+        writer.write_all(UNCHECKED_CELLS.as_bytes())?;
+    } else {
+        writeln!(writer, "type Cells = Vec<Cell>;")?;
+    }
+
+    writeln!(writer)?;
+    writeln!(
+        writer,
+        "const RUNTIME_CHECKS: bool = {};",
+        options.runtime_checks()
+    )?;
+    writeln!(
+        writer,
+        "const CHECK_MESSAGES: bool = {};",
+        options.checks == CodegenChecks::Full
+    )?;
+
+    match options.eof_mode {
+        EofMode::Unchanged => writeln!(writer, "const EOF_VALUE: Option<Cell> = None;")?,
+        EofMode::Zero => writeln!(writer, "const EOF_VALUE: Option<Cell> = Some(0);")?,
+        EofMode::NegativeOne => {
+            writeln!(writer, "const EOF_VALUE: Option<Cell> = Some(Cell::MAX);")?
+        }
+    }
+
+    match options.tape_size {
+        TapeSize::Finite(length) => {
+            writeln!(writer, "const TAPE_LENGTH: usize = {};", length)?;
+
+            if options.wraps() {
+                writer.write_all(FINITE_TAPE.as_bytes())?;
+            } else {
+                writer.write_all(BOUNDED_TAPE.as_bytes())?;
+            }
+        }
+        TapeSize::Infinite => {
+            writeln!(
+                writer,
+                "const INITIAL_TAPE_LENGTH: usize = {};",
+                INITIAL_TAPE_LENGTH
+            )?;
+            writer.write_all(INFINITE_TAPE.as_bytes())?;
+        }
+    }
+
+    // Instrumented programs report their loops before failing, as well as at the end of
+    // main.
+    if options.instrument {
+        writer.write_all(IO.replace(FAIL_EXIT, INSTRUMENTED_FAIL_EXIT).as_bytes())
+    } else {
+        writer.write_all(IO.as_bytes())
+    }
+}
+
+// Writes the counters of every loop, and the report of them.
+fn write_report<W: Write>(writer: &mut W, loops: &[usize]) -> IOResult<()> {
+    let ids = loops
+        .iter()
+        .map(|id| id.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    writeln!(writer, "const LOOP_COUNT: usize = {};", loops.len())?;
+    writeln!(writer, "const LOOP_IDS: [usize; LOOP_COUNT] = [{}];", ids)?;
+    writer.write_all(REPORT.as_bytes())?;
+    writeln!(writer)
+}
+
+// Writes a Cargo package for the program to the directory, named after the directory the
+// way `cargo new` names packages, so it builds with `cargo build` as it is. The source of
+// the program goes in a README, if it's given.
+pub fn write_project<P: AsRef<Path>>(
+    instructions: &[Instruction],
+    options: &CompileOptions,
+    info: &ProgramInfo,
+    directory: P,
+    source: Option<&str>,
+) -> Result<(), CompileError> {
+    let directory = directory.as_ref();
+
+    let mut main = Vec::new();
+    compile(instructions, options, info, &mut main)?;
+
+    fs::create_dir_all(directory.join("src"))?;
+
+    // Resolved so that a directory like "." is still named after the real directory.
+    let name = package_name(&directory.canonicalize()?);
+
+    fs::write(directory.join("src").join("main.rs"), main)?;
+    fs::write(directory.join(".gitignore"), "/target\n")?;
+
+    let mut manifest = Vec::new();
+    writeln!(manifest, "[package]")?;
+    writeln!(manifest, "name = \"{}\"", name)?;
+    writeln!(manifest, "version = \"0.1.0\"")?;
+    writeln!(manifest, "edition = \"2021\"")?;
+    writeln!(manifest)?;
+    writeln!(manifest, "[dependencies]")?;
+    fs::write(directory.join("Cargo.toml"), manifest)?;
+
+    if let Some(source) = source {
+        let mut readme = Vec::new();
+        writeln!(readme, "# {}", name)?;
+        writeln!(readme)?;
+
+        match &info.source_path {
+            Some(source_path) => writeln!(readme, "Generated by membrane from {}.", source_path)?,
+            None => writeln!(readme, "Generated by membrane.")?,
+        }
+
+        writeln!(readme, "Build and run it with `cargo run --release`.")?;
+        writeln!(readme)?;
+        writeln!(readme, "## Source")?;
+        writeln!(readme)?;
+
+        // The fence has to be longer than any run of backticks in the source.
+        let longest = source
+            .split(|character| character != '`')
+            .map(str::len)
+            .max()
+            .unwrap_or(0);
+        let fence = "`".repeat(longest.max(2) + 1);
+
+        writeln!(readme, "{}brainfuck", fence)?;
+        write!(readme, "{}", source)?;
+
+        if !source.is_empty() && !source.ends_with('\n') {
+            writeln!(readme)?;
+        }
+
+        writeln!(readme, "{}", fence)?;
+        fs::write(directory.join("README.md"), readme)?;
+    }
+
+    Ok(())
+}
+
+// Package names are limited to letters, digits, dashes, and underscores, and can't start
+// with a digit.
+fn package_name(directory: &Path) -> String {
+    let name = directory
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default()
+        .chars()
+        .map(|character| match character {
+            'a'..='z' | '0'..='9' | '-' | '_' => character,
+            'A'..='Z' => character.to_ascii_lowercase(),
+            _ => '-',
+        })
+        .collect::<String>();
+
+    match name.chars().next() {
+        None => "program".to_owned(),
+        Some(first) if first.is_ascii_digit() => format!("bf-{}", name),
+        Some(_) => name,
+    }
+}
+
+// Writes a function for every procedure in the nodes, including those nested in loops and
+// other procedures.
+fn write_procedures<W: Write>(
+    writer: &mut LineTracker<W>,
+    info: &ProgramInfo,
+    loops: &[usize],
+    width: CellWidth,
+    nodes: &[Node],
+) -> IOResult<()> {
+    for node in nodes {
+        match node {
+            Node::Procedure { index, body } => {
+                write_procedures(writer, info, loops, width, body)?;
+
+                // Procedures that call none of their own don't need the table.
+                writer.begin(*index);
+                writeln!(writer, "#[allow(unused_variables)]")?;
+                writeln!(
+                    writer,
+                    "fn procedure_{}(tape: &mut Tape, procedures: &mut Procedures) {{",
+                    index
+                )?;
+                write_block(writer, info, loops, width, body, 1)?;
+                writer.begin(node.last_index());
+                write_annotation(writer, info, node.last_index(), "", "//")?;
+                writeln!(writer, "}}")?;
+                writeln!(writer)?;
+            }
+            Node::Loop { body, .. } => write_procedures(writer, info, loops, width, body)?,
+            Node::Instruction { .. } => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn write_block<W: Write>(
+    writer: &mut LineTracker<W>,
+    info: &ProgramInfo,
+    loops: &[usize],
+    width: CellWidth,
+    nodes: &[Node],
+    depth: usize,
+) -> IOResult<()> {
+    let indent = "    ".repeat(depth);
+
+    for node in nodes {
+        writer.begin(node.index());
+        write_annotation(writer, info, node.index(), &indent, "//")?;
+
+        let instruction = match node {
+            Node::Instruction { instruction, .. } => instruction,
+            // The body was written as a function of its own by write_procedures.
+            Node::Procedure { index, .. } => {
+                writeln!(
+                    writer,
+                    "{}procedures.define(tape, procedure_{});",
+                    indent, index
+                )?;
+                continue;
+            }
+            Node::Loop { index, body } => {
+                let counter = loops.binary_search(index).ok();
+
+                if let Some(counter) = counter {
+                    writeln!(
+                        writer,
+                        "{}LOOP_ENTRIES[{}].fetch_add(1, Ordering::Relaxed);",
+                        indent, counter
+                    )?;
+                }
+
+                writeln!(writer, "{}while tape.cells[tape.head] != 0 {{", indent)?;
+
+                if let Some(counter) = counter {
+                    writeln!(
+                        writer,
+                        "{}    LOOP_ITERATIONS[{}].fetch_add(1, Ordering::Relaxed);",
+                        indent, counter
+                    )?;
+                }
+
+                write_block(writer, info, loops, width, body, depth + 1)?;
+                writer.begin(node.last_index());
+                write_annotation(writer, info, node.last_index(), &indent, "//")?;
+                writeln!(writer, "{}}}", indent)?;
+                continue;
+            }
+        };
+
+        match instruction {
+            Instruction::Add(amount) => {
+                writeln!(
+                    writer,
+                    "{}tape.add(tape.head, {});",
+                    indent,
+                    cell_literal(width, *amount)
+                )?;
+            }
+            Instruction::Move(amount) => {
+                writeln!(writer, "{}{};", indent, move_by(*amount))?;
+            }
+            Instruction::Write(count) => {
+                writeln!(writer, "{}tape.output({});", indent, count)?;
+            }
+            Instruction::Read(count) => {
+                writeln!(writer, "{}tape.input({});", indent, count)?;
+            }
+            Instruction::JumpIfZero { .. }
+            | Instruction::JumpIfNotZero { .. }
+            | Instruction::DefineProc { .. }
+            | Instruction::EndProc => unreachable!(),
+
+            Instruction::SetValue(value) => {
+                writeln!(
+                    writer,
+                    "{}tape.cells[tape.head] = {};",
+                    indent,
+                    cell_literal(width, *value)
+                )?;
+            }
+            Instruction::AddRelative { offset, amount } => {
+                let cell = cell_at(writer, &indent, *offset)?;
+                writeln!(
+                    writer,
+                    "{}tape.add({}, {});",
+                    indent,
+                    cell,
+                    cell_literal(width, *amount)
+                )?;
+            }
+            Instruction::AddVector { vector } => {
+                for (lane, amount) in vector.iter().enumerate() {
+                    if *amount != 0 {
+                        let cell = cell_at(writer, &indent, lane as isize)?;
+                        writeln!(
+                            writer,
+                            "{}tape.add({}, {});",
+                            indent,
+                            cell,
+                            cell_literal(width, *amount)
+                        )?;
+                    }
+                }
+            }
+            Instruction::MulAdd { offset, factor } => {
+                writeln!(writer, "{}if tape.cells[tape.head] != 0 {{", indent)?;
+
+                let inner = format!("{}    ", indent);
+                let cell = cell_at(writer, &inner, *offset)?;
+                writeln!(
+                    writer,
+                    "{}tape.add({}, tape.cells[tape.head].wrapping_mul({}));",
+                    inner,
+                    cell,
+                    cell_literal(width, *factor)
+                )?;
+                writeln!(writer, "{}}}", indent)?;
+            }
+            Instruction::MoveRightToZero { increment, stride } => {
+                write_scan(writer, &indent, width, *increment, "move_right", *stride)?;
+            }
+            Instruction::MoveLeftToZero { increment, stride } => {
+                write_scan(writer, &indent, width, *increment, "move_left", *stride)?;
+            }
+
+            Instruction::CallProc => {
+                writeln!(writer, "{}procedures.call(tape);", indent)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn write_scan<W: Write>(
+    writer: &mut W,
+    indent: &str,
+    width: CellWidth,
+    increment: i8,
+    direction: &str,
+    stride: usize,
+) -> IOResult<()> {
+    writeln!(writer, "{}while tape.cells[tape.head] != 0 {{", indent)?;
+
+    if increment != 0 {
+        writeln!(
+            writer,
+            "{}    tape.add(tape.head, {});",
+            indent,
+            cell_literal(width, increment)
+        )?;
+    }
+
+    writeln!(writer, "{}    tape.{}({});", indent, direction, stride)?;
+    writeln!(writer, "{}}}", indent)
+}
+
+fn cell_type(width: CellWidth) -> &'static str {
+    match width {
+        CellWidth::U8 => "u8",
+        CellWidth::U16 => "u16",
+        CellWidth::U32 => "u32",
+    }
+}
+
+fn move_by(amount: isize) -> String {
+    if amount >= 0 {
+        format!("tape.move_right({})", amount)
+    } else {
+        format!("tape.move_left({})", amount.unsigned_abs())
+    }
+}
+
+// Emits code that leaves the index of the cell `offset` away from the head in a local,
+// and returns an expression for that index. The index is taken before the cell is
+// touched, since reaching it can grow the tape.
+fn cell_at<W: Write>(writer: &mut W, indent: &str, offset: isize) -> IOResult<&'static str> {
+    match offset {
+        0 => return Ok("tape.head"),
+        offset if offset > 0 => {
+            writeln!(writer, "{}let cell = tape.at_right({});", indent, offset)?
+        }
+        offset => writeln!(
+            writer,
+            "{}let cell = tape.at_left({});",
+            indent,
+            offset.unsigned_abs()
+        )?,
+    }
+
+    Ok("cell")
+}
@@ -0,0 +1,171 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::io::{Result as IOResult, Write};
+use std::mem;
+use std::ops::RangeInclusive;
+
+use crate::json;
+use crate::program::Program;
+use crate::span::Span;
+
+use super::{CompileError, ProgramInfo};
+
+// The lines of generated code written for one instruction, numbered from one.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct LineMapping {
+    pub lines: RangeInclusive<usize>,
+    pub instruction: usize,
+}
+
+// Counts the lines written through it, so backends can record which instruction each
+// line of their output was written for.
+pub struct LineTracker<W> {
+    writer: W,
+    line: usize,
+    current: Option<(usize, usize)>,
+    mappings: Vec<LineMapping>,
+}
+
+impl<W: Write> LineTracker<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            line: 1,
+            current: None,
+            mappings: Vec::new(),
+        }
+    }
+
+    // Attributes the lines written from now on to the instruction at the index.
+    pub fn begin(&mut self, instruction: usize) {
+        self.end();
+        self.current = Some((instruction, self.line));
+    }
+
+    // Stops attributing lines to the last instruction begun, such as before an epilogue.
+    pub fn end(&mut self) {
+        if let Some((instruction, first)) = self.current.take() {
+            // Instructions that wrote nothing, or only part of a line, are left out.
+            if self.line > first {
+                self.mappings.push(LineMapping {
+                    lines: first..=self.line - 1,
+                    instruction,
+                });
+            }
+        }
+    }
+
+    pub fn finish(&mut self) -> Vec<LineMapping> {
+        self.end();
+        mem::take(&mut self.mappings)
+    }
+}
+
+impl<W: Write> Write for LineTracker<W> {
+    fn write(&mut self, buf: &[u8]) -> IOResult<usize> {
+        let written = self.writer.write(buf)?;
+        self.line += buf[..written].iter().filter(|byte| **byte == b'\n').count();
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> IOResult<()> {
+        self.writer.flush()
+    }
+}
+
+// Runs a backend's code generation through a `LineTracker`, and maps the lines it wrote for
+// each instruction back to the program's source.
+pub fn track_lines<W: Write>(
+    program: &Program,
+    info: &ProgramInfo,
+    writer: W,
+    write_program: impl FnOnce(&mut LineTracker<W>) -> Result<(), CompileError>,
+) -> Result<Option<SourceMap>, CompileError> {
+    let mut tracker = LineTracker::new(writer);
+    write_program(&mut tracker)?;
+
+    Ok(Some(SourceMap::new(
+        program,
+        info.source_path.clone(),
+        tracker.finish(),
+    )))
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct SourceMapEntry {
+    pub lines: RangeInclusive<usize>,
+    pub instruction: usize,
+    pub span: Span,
+}
+
+// Which part of the source each range of lines in a compiled file came from. Programs
+// without spans still map lines to instructions, but every span is empty.
+#[derive(Clone, Eq, PartialEq, Default, Debug)]
+pub struct SourceMap {
+    pub file: Option<String>,
+    pub source: Option<String>,
+    pub entries: Vec<SourceMapEntry>,
+}
+
+impl SourceMap {
+    pub fn new(program: &Program, source: Option<String>, mappings: Vec<LineMapping>) -> Self {
+        let entries = mappings
+            .into_iter()
+            .map(|mapping| SourceMapEntry {
+                span: program.spans[mapping.instruction],
+                lines: mapping.lines,
+                instruction: mapping.instruction,
+            })
+            .collect();
+
+        Self {
+            file: None,
+            source,
+            entries,
+        }
+    }
+
+    // Writes the map as JSON, with one entry per line so it diffs well.
+    pub fn write_json<W: Write>(&self, writer: &mut W) -> IOResult<()> {
+        writeln!(writer, "{{")?;
+        writeln!(writer, "  \"version\": 1,")?;
+        writeln!(writer, "  \"file\": {},", json_string(self.file.as_deref()))?;
+        writeln!(
+            writer,
+            "  \"source\": {},",
+            json_string(self.source.as_deref())
+        )?;
+        write!(writer, "  \"mappings\": [")?;
+
+        for (index, entry) in self.entries.iter().enumerate() {
+            if index > 0 {
+                write!(writer, ",")?;
+            }
+
+            write!(
+                writer,
+                "\n    {{\"first_line\": {}, \"last_line\": {}, \"instruction\": {}, \"start\": {}, \"end\": {}}}",
+                entry.lines.start(),
+                entry.lines.end(),
+                entry.instruction,
+                entry.span.start,
+                entry.span.end
+            )?;
+        }
+
+        if !self.entries.is_empty() {
+            write!(writer, "\n  ")?;
+        }
+
+        writeln!(writer, "]")?;
+        writeln!(writer, "}}")
+    }
+}
+
+pub fn json_string(string: Option<&str>) -> String {
+    string.map_or_else(|| "null".to_owned(), json::quote)
+}
@@ -0,0 +1,376 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::io::{Result as IOResult, Write};
+
+use crate::instruction::Instruction;
+use crate::interpreter::{CellWidth, EofMode, TapeSize};
+use crate::program::Program;
+
+use super::ast::{self, Node};
+use super::source_map::{self, LineTracker, SourceMap};
+use super::{
+    cell_literal, write_annotation, write_header, Backend, CompileError, CompileOptions,
+    ProgramInfo,
+};
+
+const INITIAL_TAPE_LENGTH: usize = 30_000;
+
+// The module only exports `run`, which takes its I/O from the caller, so the same program
+// runs in a browser or under Node. Failures are thrown as errors rather than exiting.
+const INTERFACE: &str = r#"// Reads return the next byte of input, or -1 once it has run out.
+export interface IO {
+  read(): number;
+  write(byte: number): void;
+}
+"#;
+
+// The tape is a typed array of the cell width, so stores wrap cells on their own. Cells
+// away from the head are reached through right and left, which also wrap a finite tape
+// and grow an infinite one. Their index is computed before the tape is touched, since
+// growing the tape replaces the array.
+const FINITE_TAPE: &str = r#"  const tape = new CELLS(TAPE_LENGTH);
+  let head = 0;
+
+  function right(offset: number): number {
+    return (head + (offset % TAPE_LENGTH)) % TAPE_LENGTH;
+  }
+
+  function left(offset: number): number {
+    return (head + TAPE_LENGTH - (offset % TAPE_LENGTH)) % TAPE_LENGTH;
+  }
+"#;
+
+const BOUNDED_TAPE: &str = r#"  const tape = new CELLS(TAPE_LENGTH);
+  let head = 0;
+
+  function right(offset: number): number {
+    if (RUNTIME_CHECKS && offset >= TAPE_LENGTH - head) {
+      fail("tried to access a cell past the end of the tape");
+    }
+
+    return head + offset;
+  }
+
+  function left(offset: number): number {
+    if (RUNTIME_CHECKS && offset > head) {
+      fail("tried to access a cell left of the start of the tape");
+    }
+
+    return head - offset;
+  }
+"#;
+
+const INFINITE_TAPE: &str = r#"  let tape = new CELLS(INITIAL_TAPE_LENGTH);
+  let head = 0;
+
+  function right(offset: number): number {
+    const index = head + offset;
+
+    if (index >= tape.length) {
+      let length = tape.length;
+
+      while (length <= index) {
+        length *= 2;
+      }
+
+      const grown = new CELLS(length);
+      grown.set(tape);
+      tape = grown;
+    }
+
+    return index;
+  }
+
+  function left(offset: number): number {
+    if (RUNTIME_CHECKS && offset > head) {
+      fail("tried to access a cell left of the start of the tape");
+    }
+
+    return head - offset;
+  }
+"#;
+
+// Once input runs out, reads store EOF_VALUE if it isn't null, and leave the cell
+// unchanged otherwise.
+const HELPERS: &str = r#"
+  function add(index: number, amount: number): void {
+    tape[index] += amount;
+  }
+
+  function output(count: number): void {
+    const byte = tape[head] & 255;
+
+    for (; count > 0; count--) {
+      io.write(byte);
+    }
+  }
+
+  function input(count: number): void {
+    for (; count > 0; count--) {
+      const byte = io.read();
+
+      if (byte < 0) {
+        if (EOF_VALUE !== null) {
+          tape[head] = EOF_VALUE;
+        }
+
+        return;
+      }
+
+      tape[head] = byte;
+    }
+  }
+"#;
+
+pub struct TypeScriptBackend;
+
+impl Backend for TypeScriptBackend {
+    fn name(&self) -> &'static str {
+        "typescript"
+    }
+
+    fn file_extension(&self) -> &'static str {
+        "ts"
+    }
+
+    fn compile(
+        &self,
+        program: &Program,
+        options: &CompileOptions,
+        info: &ProgramInfo,
+        mut writer: &mut dyn Write,
+    ) -> Result<(), CompileError> {
+        compile(&program.instructions, options, info, &mut writer)
+    }
+
+    fn compile_with_source_map(
+        &self,
+        program: &Program,
+        options: &CompileOptions,
+        info: &ProgramInfo,
+        writer: &mut dyn Write,
+    ) -> Result<Option<SourceMap>, CompileError> {
+        source_map::track_lines(program, info, writer, |writer| {
+            write_program(&program.instructions, options, info, writer)
+        })
+    }
+}
+
+pub fn compile<W: Write>(
+    instructions: &[Instruction],
+    options: &CompileOptions,
+    info: &ProgramInfo,
+    writer: &mut W,
+) -> Result<(), CompileError> {
+    write_program(instructions, options, info, &mut LineTracker::new(writer))
+}
+
+fn write_program<W: Write>(
+    instructions: &[Instruction],
+    options: &CompileOptions,
+    info: &ProgramInfo,
+    writer: &mut LineTracker<W>,
+) -> Result<(), CompileError> {
+    let width = options.cell_width;
+
+    write_header(writer, "typescript", options, info, "//")?;
+
+    writeln!(writer)?;
+    writer.write_all(INTERFACE.as_bytes())?;
+    writeln!(writer)?;
+
+    match options.tape_size {
+        TapeSize::Finite(length) => writeln!(writer, "const TAPE_LENGTH = {};", length)?,
+        TapeSize::Infinite => writeln!(
+            writer,
+            "const INITIAL_TAPE_LENGTH = {};",
+            INITIAL_TAPE_LENGTH
+        )?,
+    }
+
+    writeln!(
+        writer,
+        "const RUNTIME_CHECKS = {};",
+        options.runtime_checks()
+    )?;
+
+    let eof_value = match options.eof_mode {
+        EofMode::Unchanged => "null".to_owned(),
+        EofMode::Zero => "0".to_owned(),
+        EofMode::NegativeOne => width.max_value().to_string(),
+    };
+
+    writeln!(writer, "const EOF_VALUE: number | null = {};", eof_value)?;
+    writeln!(writer)?;
+
+    writeln!(writer, "function fail(message: string): never {{")?;
+    writeln!(writer, "  throw new Error(message);")?;
+    writeln!(writer, "}}")?;
+    writeln!(writer)?;
+
+    writeln!(writer, "export function run(io: IO): void {{")?;
+
+    let tape = match options.tape_size {
+        TapeSize::Finite(_) if options.wraps() => FINITE_TAPE,
+        TapeSize::Finite(_) => BOUNDED_TAPE,
+        TapeSize::Infinite => INFINITE_TAPE,
+    };
+
+    writer.write_all(tape.replace("CELLS", array_type(width)).as_bytes())?;
+    writer.write_all(HELPERS.as_bytes())?;
+    writeln!(writer)?;
+
+    write_block(writer, info, width, &ast::build(instructions)?, 1)?;
+    writer.end();
+
+    writeln!(writer, "}}")?;
+    Ok(())
+}
+
+fn write_block<W: Write>(
+    writer: &mut LineTracker<W>,
+    info: &ProgramInfo,
+    width: CellWidth,
+    nodes: &[Node],
+    depth: usize,
+) -> IOResult<()> {
+    let indent = "  ".repeat(depth);
+
+    for node in nodes {
+        writer.begin(node.index());
+        write_annotation(writer, info, node.index(), &indent, "//")?;
+
+        let instruction = match node {
+            Node::Instruction { instruction, .. } => instruction,
+            Node::Procedure { .. } => unreachable!(),
+            Node::Loop { body, .. } => {
+                writeln!(writer, "{}while (tape[head] !== 0) {{", indent)?;
+                write_block(writer, info, width, body, depth + 1)?;
+                writer.begin(node.last_index());
+                write_annotation(writer, info, node.last_index(), &indent, "//")?;
+                writeln!(writer, "{}}}", indent)?;
+                continue;
+            }
+        };
+
+        match instruction {
+            Instruction::Add(amount) => {
+                writeln!(
+                    writer,
+                    "{}tape[head] += {};",
+                    indent,
+                    cell_literal(width, *amount)
+                )?;
+            }
+            Instruction::Move(amount) => {
+                writeln!(writer, "{}head = {};", indent, index(*amount))?;
+            }
+            Instruction::Write(count) => {
+                writeln!(writer, "{}output({});", indent, count)?;
+            }
+            Instruction::Read(count) => {
+                writeln!(writer, "{}input({});", indent, count)?;
+            }
+            Instruction::JumpIfZero { .. }
+            | Instruction::JumpIfNotZero { .. }
+            | Instruction::DefineProc { .. }
+            | Instruction::EndProc
+            | Instruction::CallProc => unreachable!(),
+
+            Instruction::SetValue(value) => {
+                writeln!(
+                    writer,
+                    "{}tape[head] = {};",
+                    indent,
+                    cell_literal(width, *value)
+                )?;
+            }
+            Instruction::AddRelative { offset, amount } => {
+                writeln!(
+                    writer,
+                    "{}add({}, {});",
+                    indent,
+                    index(*offset),
+                    cell_literal(width, *amount)
+                )?;
+            }
+            Instruction::AddVector { vector } => {
+                for (lane, amount) in vector.iter().enumerate() {
+                    if *amount != 0 {
+                        writeln!(
+                            writer,
+                            "{}add({}, {});",
+                            indent,
+                            index(lane as isize),
+                            cell_literal(width, *amount)
+                        )?;
+                    }
+                }
+            }
+            Instruction::MulAdd { offset, factor } => {
+                // Math.imul keeps the low 32 bits of the product, which is all a cell
+                // holds, where a plain multiplication would lose them to rounding.
+                writeln!(writer, "{}if (tape[head] !== 0) {{", indent)?;
+                writeln!(
+                    writer,
+                    "{}  add({}, Math.imul(tape[head], {}));",
+                    indent,
+                    index(*offset),
+                    cell_literal(width, *factor)
+                )?;
+                writeln!(writer, "{}}}", indent)?;
+            }
+            Instruction::MoveRightToZero { increment, stride } => {
+                write_scan(writer, width, &indent, *increment, *stride as isize)?;
+            }
+            Instruction::MoveLeftToZero { increment, stride } => {
+                write_scan(writer, width, &indent, *increment, -(*stride as isize))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn write_scan<W: Write>(
+    writer: &mut W,
+    width: CellWidth,
+    indent: &str,
+    increment: i8,
+    stride: isize,
+) -> IOResult<()> {
+    writeln!(writer, "{}while (tape[head] !== 0) {{", indent)?;
+
+    if increment != 0 {
+        writeln!(
+            writer,
+            "{}  tape[head] += {};",
+            indent,
+            cell_literal(width, increment)
+        )?;
+    }
+
+    writeln!(writer, "{}  head = {};", indent, index(stride))?;
+    writeln!(writer, "{}}}", indent)
+}
+
+// Returns an expression for the index `offset` cells away from the head.
+fn index(offset: isize) -> String {
+    match offset {
+        0 => "head".to_owned(),
+        offset if offset > 0 => format!("right({})", offset),
+        offset => format!("left({})", offset.unsigned_abs()),
+    }
+}
+
+fn array_type(width: CellWidth) -> &'static str {
+    match width {
+        CellWidth::U8 => "Uint8Array",
+        CellWidth::U16 => "Uint16Array",
+        CellWidth::U32 => "Uint32Array",
+    }
+}
@@ -0,0 +1,788 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::io::Write;
+
+use crate::instruction::Instruction;
+use crate::interpreter::{CellWidth, EofMode, TapeSize};
+use crate::program::Program;
+
+use super::ast::{self, Node};
+use super::{Backend, CompileError, CompileOptions, ProgramInfo};
+
+const PAGE_SIZE: usize = 1 << 16;
+
+// Memory layout. The first bytes hold the I/O vector and results passed to WASI, followed
+// by the output buffer and the tape. Tape accesses use the tape's base as their static
+// offset, so cell addresses are plain indices.
+const IOVEC_BUFFER: i32 = 0;
+const IOVEC_LENGTH: i32 = 4;
+const IO_RESULT: i32 = 8;
+const INPUT_BYTE: i32 = 12;
+const OUTPUT_BUFFER: u32 = 16;
+const OUTPUT_BUFFER_LENGTH: i32 = 4096;
+const TAPE_BASE: u32 = OUTPUT_BUFFER + OUTPUT_BUFFER_LENGTH as u32;
+
+const STDIN: i32 = 0;
+const STDOUT: i32 = 1;
+
+// Function indices; the imports come first.
+const FD_WRITE: u32 = 0;
+const FD_READ: u32 = 1;
+const PROC_EXIT: u32 = 2;
+const FLUSH: u32 = 3;
+const OUTPUT: u32 = 4;
+const INPUT: u32 = 5;
+const RESERVE: u32 = 6;
+
+// Global indices.
+const HEAD: u32 = 0;
+const BUFFERED: u32 = 1;
+
+mod op {
+    pub const UNREACHABLE: u8 = 0x00;
+    pub const BLOCK: u8 = 0x02;
+    pub const LOOP: u8 = 0x03;
+    pub const IF: u8 = 0x04;
+    pub const END: u8 = 0x0b;
+    pub const BR: u8 = 0x0c;
+    pub const BR_IF: u8 = 0x0d;
+    pub const CALL: u8 = 0x10;
+    pub const LOCAL_GET: u8 = 0x20;
+    pub const LOCAL_SET: u8 = 0x21;
+    pub const GLOBAL_GET: u8 = 0x23;
+    pub const GLOBAL_SET: u8 = 0x24;
+    pub const I32_LOAD: u8 = 0x28;
+    pub const I32_LOAD8_U: u8 = 0x2d;
+    pub const I32_STORE: u8 = 0x36;
+    pub const I32_STORE8: u8 = 0x3a;
+    pub const MEMORY_SIZE: u8 = 0x3f;
+    pub const MEMORY_GROW: u8 = 0x40;
+    pub const I32_CONST: u8 = 0x41;
+    pub const I32_EQZ: u8 = 0x45;
+    pub const I32_EQ: u8 = 0x46;
+    pub const I32_LT_S: u8 = 0x48;
+    pub const I32_GE_U: u8 = 0x4f;
+    pub const I32_ADD: u8 = 0x6a;
+    pub const I32_SUB: u8 = 0x6b;
+    pub const I32_MUL: u8 = 0x6c;
+    pub const I32_REM_U: u8 = 0x70;
+    pub const I32_SHR_U: u8 = 0x76;
+}
+
+const I32: u8 = 0x7f;
+const EMPTY_BLOCK: u8 = 0x40;
+
+pub struct WasmBackend;
+
+impl Backend for WasmBackend {
+    fn name(&self) -> &'static str {
+        "wasm"
+    }
+
+    fn file_extension(&self) -> &'static str {
+        "wasm"
+    }
+
+    fn compile(
+        &self,
+        program: &Program,
+        options: &CompileOptions,
+        _info: &ProgramInfo,
+        mut writer: &mut dyn Write,
+    ) -> Result<(), CompileError> {
+        compile(&program.instructions, options, &mut writer)
+    }
+}
+
+// A WASI command module: it exports its memory and a _start function, and uses fd_read
+// and fd_write for I/O, so runtimes like wasmtime can run it directly. Output is buffered
+// until the buffer fills, the program reads, or the program ends.
+pub fn compile<W: Write>(
+    instructions: &[Instruction],
+    options: &CompileOptions,
+    writer: &mut W,
+) -> Result<(), CompileError> {
+    options.require_cell_width(CellWidth::U8, "WebAssembly")?;
+    let tape = Tape::new(options)?;
+
+    let mut start = Function::new(0, 1);
+
+    write_block(&mut start, &tape, &ast::build(instructions)?)?;
+
+    start.call(FLUSH);
+
+    let functions = [flush(), output(), input(options.eof_mode), reserve(), start];
+    writer.write_all(&module(&functions, tape.pages()))?;
+    Ok(())
+}
+
+fn write_block(function: &mut Function, tape: &Tape, nodes: &[Node]) -> Result<(), CompileError> {
+    for node in nodes {
+        let instruction = match node {
+            Node::Instruction { instruction, .. } => instruction,
+            Node::Procedure { .. } => unreachable!(),
+            Node::Loop { body, .. } => {
+                function.block(op::BLOCK);
+                function.load_cell(None);
+                function.op(op::I32_EQZ);
+                function.branch(op::BR_IF, 0);
+                function.block(op::LOOP);
+                write_block(function, tape, body)?;
+                function.load_cell(None);
+                function.branch(op::BR_IF, 0);
+                function.op(op::END);
+                function.op(op::END);
+                continue;
+            }
+        };
+
+        match instruction {
+            Instruction::Add(amount) => {
+                function.add_to_cell(None, *amount);
+            }
+            Instruction::Move(amount) => {
+                tape.advance(function, *amount)?;
+            }
+            Instruction::Write(count) => {
+                function.i32_const(constant(*count)?);
+                function.call(OUTPUT);
+            }
+            Instruction::Read(count) => {
+                function.i32_const(constant(*count)?);
+                function.call(INPUT);
+            }
+            Instruction::JumpIfZero { .. }
+            | Instruction::JumpIfNotZero { .. }
+            | Instruction::DefineProc { .. }
+            | Instruction::EndProc
+            | Instruction::CallProc => unreachable!(),
+
+            Instruction::SetValue(value) => {
+                function.global_get(HEAD);
+                function.i32_const(*value as u8 as i32);
+                function.store_cell();
+            }
+            Instruction::AddRelative { offset, amount } => {
+                let cell = tape.cell_at(function, *offset)?;
+                function.add_to_cell(cell, *amount);
+            }
+            Instruction::AddVector { vector } => {
+                for (lane, amount) in vector.iter().enumerate() {
+                    if *amount != 0 {
+                        let cell = tape.cell_at(function, lane as isize)?;
+                        function.add_to_cell(cell, *amount);
+                    }
+                }
+            }
+            Instruction::MulAdd { offset, factor } => {
+                function.load_cell(None);
+                function.block(op::IF);
+
+                let cell = tape.cell_at(function, *offset)?;
+                function.address(cell);
+                function.load_cell(cell);
+                function.load_cell(None);
+                function.i32_const(*factor as u8 as i32);
+                function.op(op::I32_MUL);
+                function.op(op::I32_ADD);
+                function.store_cell();
+                function.op(op::END);
+            }
+            Instruction::MoveRightToZero { increment, stride } => {
+                write_scan(function, tape, *increment, *stride as isize)?;
+            }
+            Instruction::MoveLeftToZero { increment, stride } => {
+                write_scan(function, tape, *increment, -(*stride as isize))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn write_scan(
+    function: &mut Function,
+    tape: &Tape,
+    increment: i8,
+    stride: isize,
+) -> Result<(), CompileError> {
+    function.block(op::BLOCK);
+    function.block(op::LOOP);
+    function.load_cell(None);
+    function.op(op::I32_EQZ);
+    function.branch(op::BR_IF, 1);
+
+    if increment != 0 {
+        function.add_to_cell(None, increment);
+    }
+
+    tape.advance(function, stride)?;
+    function.branch(op::BR, 0);
+    function.op(op::END);
+    function.op(op::END);
+
+    Ok(())
+}
+
+// Writes everything in the output buffer to stdout, exiting if that fails.
+fn flush() -> Function {
+    let mut function = Function::new(0, 1);
+    let written = 0;
+
+    function.block(op::BLOCK);
+    function.block(op::LOOP);
+    function.local_get(written);
+    function.global_get(BUFFERED);
+    function.op(op::I32_GE_U);
+    function.branch(op::BR_IF, 1);
+
+    function.i32_const(IOVEC_BUFFER);
+    function.local_get(written);
+    function.i32_const(OUTPUT_BUFFER as i32);
+    function.op(op::I32_ADD);
+    function.memory(op::I32_STORE, 2, 0);
+
+    function.i32_const(IOVEC_LENGTH);
+    function.global_get(BUFFERED);
+    function.local_get(written);
+    function.op(op::I32_SUB);
+    function.memory(op::I32_STORE, 2, 0);
+
+    function.i32_const(STDOUT);
+    function.i32_const(IOVEC_BUFFER);
+    function.i32_const(1);
+    function.i32_const(IO_RESULT);
+    function.call(FD_WRITE);
+    function.exit_if_true();
+
+    function.i32_const(IO_RESULT);
+    function.memory(op::I32_LOAD, 2, 0);
+    function.op(op::I32_EQZ);
+    function.exit_if_true();
+
+    function.local_get(written);
+    function.i32_const(IO_RESULT);
+    function.memory(op::I32_LOAD, 2, 0);
+    function.op(op::I32_ADD);
+    function.local_set(written);
+    function.branch(op::BR, 0);
+    function.op(op::END);
+    function.op(op::END);
+
+    function.i32_const(0);
+    function.global_set(BUFFERED);
+    function
+}
+
+// Buffers the current cell as many times as its parameter says.
+fn output() -> Function {
+    let mut function = Function::new(1, 0);
+    let count = 0;
+
+    function.block(op::BLOCK);
+    function.block(op::LOOP);
+    function.local_get(count);
+    function.op(op::I32_EQZ);
+    function.branch(op::BR_IF, 1);
+
+    function.global_get(BUFFERED);
+    function.i32_const(OUTPUT_BUFFER_LENGTH);
+    function.op(op::I32_GE_U);
+    function.block(op::IF);
+    function.call(FLUSH);
+    function.op(op::END);
+
+    function.global_get(BUFFERED);
+    function.load_cell(None);
+    function.memory(op::I32_STORE8, 0, OUTPUT_BUFFER);
+
+    function.global_get(BUFFERED);
+    function.i32_const(1);
+    function.op(op::I32_ADD);
+    function.global_set(BUFFERED);
+    function.decrement(count);
+    function.branch(op::BR, 0);
+    function.op(op::END);
+    function.op(op::END);
+    function
+}
+
+// Reads as many bytes as its parameter says into the current cell. Once input runs out,
+// it branches out of the inner block, where the EOF mode's value is stored, if it has one.
+fn input(eof_mode: EofMode) -> Function {
+    let mut function = Function::new(1, 0);
+    let count = 0;
+
+    function.call(FLUSH);
+    function.block(op::BLOCK);
+    function.block(op::BLOCK);
+    function.block(op::LOOP);
+    function.local_get(count);
+    function.op(op::I32_EQZ);
+    function.branch(op::BR_IF, 2);
+
+    function.i32_const(IOVEC_BUFFER);
+    function.i32_const(INPUT_BYTE);
+    function.memory(op::I32_STORE, 2, 0);
+    function.i32_const(IOVEC_LENGTH);
+    function.i32_const(1);
+    function.memory(op::I32_STORE, 2, 0);
+
+    function.i32_const(STDIN);
+    function.i32_const(IOVEC_BUFFER);
+    function.i32_const(1);
+    function.i32_const(IO_RESULT);
+    function.call(FD_READ);
+    function.branch(op::BR_IF, 1);
+
+    function.i32_const(IO_RESULT);
+    function.memory(op::I32_LOAD, 2, 0);
+    function.op(op::I32_EQZ);
+    function.branch(op::BR_IF, 1);
+
+    function.global_get(HEAD);
+    function.i32_const(INPUT_BYTE);
+    function.memory(op::I32_LOAD8_U, 0, 0);
+    function.store_cell();
+    function.decrement(count);
+    function.branch(op::BR, 0);
+    function.op(op::END);
+    function.op(op::END);
+
+    let eof_value = match eof_mode {
+        EofMode::Unchanged => None,
+        EofMode::Zero => Some(0),
+        EofMode::NegativeOne => Some(u8::MAX as i32),
+    };
+
+    if let Some(value) = eof_value {
+        function.global_get(HEAD);
+        function.i32_const(value);
+        function.store_cell();
+    }
+
+    function.op(op::END);
+    function
+}
+
+// Grows the memory until the cell at its parameter is part of the tape.
+fn reserve() -> Function {
+    let mut function = Function::new(1, 1);
+    let index = 0;
+    let page = 1;
+
+    function.local_get(index);
+    function.i32_const(TAPE_BASE as i32);
+    function.op(op::I32_ADD);
+    function.i32_const(16);
+    function.op(op::I32_SHR_U);
+    function.local_set(page);
+
+    function.local_get(page);
+    function.op(op::MEMORY_SIZE);
+    function.bytes(&[0]);
+    function.op(op::I32_GE_U);
+    function.block(op::IF);
+    function.local_get(page);
+    function.i32_const(1);
+    function.op(op::I32_ADD);
+    function.op(op::MEMORY_SIZE);
+    function.bytes(&[0]);
+    function.op(op::I32_SUB);
+    function.op(op::MEMORY_GROW);
+    function.bytes(&[0]);
+    function.i32_const(-1);
+    function.op(op::I32_EQ);
+    function.block(op::IF);
+    function.op(op::UNREACHABLE);
+    function.op(op::END);
+    function.op(op::END);
+    function
+}
+
+fn module(functions: &[Function], pages: usize) -> Vec<u8> {
+    let mut bytes = b"\0asm".to_vec();
+    bytes.extend_from_slice(&1u32.to_le_bytes());
+
+    // fd_read and fd_write, proc_exit, functions without parameters, and functions with one.
+    let types: [(&[u8], &[u8]); 4] = [
+        (&[I32, I32, I32, I32], &[I32]),
+        (&[I32], &[]),
+        (&[], &[]),
+        (&[I32], &[]),
+    ];
+
+    let mut section = Vec::new();
+    write_unsigned(&mut section, types.len() as u64);
+
+    for (parameters, results) in types {
+        section.push(0x60);
+        write_unsigned(&mut section, parameters.len() as u64);
+        section.extend_from_slice(parameters);
+        write_unsigned(&mut section, results.len() as u64);
+        section.extend_from_slice(results);
+    }
+
+    write_section(&mut bytes, 1, &section);
+
+    let imports = [("fd_write", 0), ("fd_read", 0), ("proc_exit", 1)];
+
+    let mut section = Vec::new();
+    write_unsigned(&mut section, imports.len() as u64);
+
+    for (name, type_index) in imports {
+        write_name(&mut section, "wasi_snapshot_preview1");
+        write_name(&mut section, name);
+        section.push(0x00);
+        write_unsigned(&mut section, type_index);
+    }
+
+    write_section(&mut bytes, 2, &section);
+
+    let mut section = Vec::new();
+    write_unsigned(&mut section, functions.len() as u64);
+
+    for function in functions {
+        let type_index = if function.parameters == 0 { 2 } else { 3 };
+        write_unsigned(&mut section, type_index);
+    }
+
+    write_section(&mut bytes, 3, &section);
+
+    let mut section = vec![1, 0x00];
+    write_unsigned(&mut section, pages as u64);
+    write_section(&mut bytes, 5, &section);
+
+    let mut section = vec![2];
+
+    for _ in 0..2 {
+        section.extend_from_slice(&[I32, 0x01, op::I32_CONST, 0, op::END]);
+    }
+
+    write_section(&mut bytes, 6, &section);
+
+    let start = PROC_EXIT as u64 + functions.len() as u64;
+
+    let mut section = vec![2];
+    write_name(&mut section, "memory");
+    section.extend_from_slice(&[0x02, 0]);
+    write_name(&mut section, "_start");
+    section.push(0x00);
+    write_unsigned(&mut section, start);
+    write_section(&mut bytes, 7, &section);
+
+    let mut section = Vec::new();
+    write_unsigned(&mut section, functions.len() as u64);
+
+    for function in functions {
+        let mut body = Vec::new();
+
+        if function.locals == 0 {
+            body.push(0);
+        } else {
+            body.push(1);
+            write_unsigned(&mut body, function.locals as u64);
+            body.push(I32);
+        }
+
+        body.extend_from_slice(&function.code);
+        body.push(op::END);
+
+        write_unsigned(&mut section, body.len() as u64);
+        section.extend_from_slice(&body);
+    }
+
+    write_section(&mut bytes, 10, &section);
+    bytes
+}
+
+fn write_section(bytes: &mut Vec<u8>, id: u8, section: &[u8]) {
+    bytes.push(id);
+    write_unsigned(bytes, section.len() as u64);
+    bytes.extend_from_slice(section);
+}
+
+fn write_name(bytes: &mut Vec<u8>, name: &str) {
+    write_unsigned(bytes, name.len() as u64);
+    bytes.extend_from_slice(name.as_bytes());
+}
+
+fn write_unsigned(bytes: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+
+        if value == 0 {
+            bytes.push(byte);
+            return;
+        }
+
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn write_signed(bytes: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            bytes.push(byte);
+            return;
+        }
+
+        bytes.push(byte | 0x80);
+    }
+}
+
+// Addresses are 32 bits, so anything that doesn't fit can't be compiled.
+fn constant(value: usize) -> Result<i32, CompileError> {
+    if value <= i32::MAX as usize - TAPE_BASE as usize {
+        Ok(value as i32)
+    } else {
+        Err(CompileError::Unsupported(format!(
+            "{} doesn't fit in WebAssembly's 32-bit addresses",
+            value
+        )))
+    }
+}
+
+// Functions take at most one parameter, which comes first among their locals, and every
+// local is an i32.
+struct Function {
+    parameters: u32,
+    locals: u32,
+    code: Vec<u8>,
+}
+
+impl Function {
+    fn new(parameters: u32, locals: u32) -> Self {
+        Self {
+            parameters,
+            locals,
+            code: Vec::new(),
+        }
+    }
+
+    fn op(&mut self, op: u8) {
+        self.code.push(op);
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    fn block(&mut self, op: u8) {
+        self.code.extend_from_slice(&[op, EMPTY_BLOCK]);
+    }
+
+    fn branch(&mut self, op: u8, depth: u32) {
+        self.code.push(op);
+        write_unsigned(&mut self.code, depth as u64);
+    }
+
+    fn call(&mut self, function: u32) {
+        self.code.push(op::CALL);
+        write_unsigned(&mut self.code, function as u64);
+    }
+
+    fn i32_const(&mut self, value: i32) {
+        self.code.push(op::I32_CONST);
+        write_signed(&mut self.code, value as i64);
+    }
+
+    fn local_get(&mut self, local: u32) {
+        self.code.push(op::LOCAL_GET);
+        write_unsigned(&mut self.code, local as u64);
+    }
+
+    fn local_set(&mut self, local: u32) {
+        self.code.push(op::LOCAL_SET);
+        write_unsigned(&mut self.code, local as u64);
+    }
+
+    fn global_get(&mut self, global: u32) {
+        self.code.push(op::GLOBAL_GET);
+        write_unsigned(&mut self.code, global as u64);
+    }
+
+    fn global_set(&mut self, global: u32) {
+        self.code.push(op::GLOBAL_SET);
+        write_unsigned(&mut self.code, global as u64);
+    }
+
+    fn memory(&mut self, op: u8, align: u32, offset: u32) {
+        self.code.push(op);
+        write_unsigned(&mut self.code, align as u64);
+        write_unsigned(&mut self.code, offset as u64);
+    }
+
+    fn decrement(&mut self, local: u32) {
+        self.local_get(local);
+        self.i32_const(1);
+        self.op(op::I32_SUB);
+        self.local_set(local);
+    }
+
+    fn exit_if_true(&mut self) {
+        self.block(op::IF);
+        self.i32_const(1);
+        self.call(PROC_EXIT);
+        self.op(op::END);
+    }
+
+    // Cells are addressed by their index in a local, or by the head if there's none.
+    fn address(&mut self, cell: Option<u32>) {
+        match cell {
+            Some(local) => self.local_get(local),
+            None => self.global_get(HEAD),
+        }
+    }
+
+    fn load_cell(&mut self, cell: Option<u32>) {
+        self.address(cell);
+        self.memory(op::I32_LOAD8_U, 0, TAPE_BASE);
+    }
+
+    // Stores the value on top of the stack into the cell whose index is below it.
+    fn store_cell(&mut self) {
+        self.memory(op::I32_STORE8, 0, TAPE_BASE);
+    }
+
+    fn add_to_cell(&mut self, cell: Option<u32>, amount: i8) {
+        self.address(cell);
+        self.load_cell(cell);
+        self.i32_const(amount as u8 as i32);
+        self.op(op::I32_ADD);
+        self.store_cell();
+    }
+}
+
+struct Tape {
+    length: Option<usize>,
+    wraps: bool,
+    checks: bool,
+}
+
+impl Tape {
+    fn new(options: &CompileOptions) -> Result<Self, CompileError> {
+        let length = match options.tape_size {
+            // Wrapping adds two indices below the length, so they can't exceed 2^31 each.
+            TapeSize::Finite(length) => Some(constant(length).map(|_| length)?),
+            TapeSize::Infinite => None,
+        };
+
+        Ok(Self {
+            length,
+            wraps: options.wraps(),
+            checks: options.runtime_checks(),
+        })
+    }
+
+    fn pages(&self) -> usize {
+        (TAPE_BASE as usize + self.length.unwrap_or(0)).div_ceil(PAGE_SIZE)
+    }
+
+    // Pushes the index `offset` cells away from the head.
+    fn index(&self, function: &mut Function, offset: isize) -> Result<(), CompileError> {
+        let distance = match self.length {
+            Some(length) if self.wraps => offset.unsigned_abs() % length,
+            _ => offset.unsigned_abs(),
+        };
+
+        let distance = constant(distance)?;
+        function.global_get(HEAD);
+
+        match (offset >= 0, self.length) {
+            (true, Some(length)) if self.wraps => {
+                function.i32_const(distance);
+                function.op(op::I32_ADD);
+                function.i32_const(length as i32);
+                function.op(op::I32_REM_U);
+            }
+            (false, Some(length)) if self.wraps => {
+                function.i32_const(length as i32);
+                function.op(op::I32_ADD);
+                function.i32_const(distance);
+                function.op(op::I32_SUB);
+                function.i32_const(length as i32);
+                function.op(op::I32_REM_U);
+            }
+            (true, _) => {
+                function.i32_const(distance);
+                function.op(op::I32_ADD);
+            }
+            (false, _) => {
+                function.i32_const(distance);
+                function.op(op::I32_SUB);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Traps if a cell is past either end of a tape that doesn't wrap. Negative indices are
+    // huge once they're unsigned, so one comparison covers both.
+    fn check_bounds(&self, function: &mut Function, cell: Option<u32>) {
+        if let Some(length) = self.length {
+            if !self.wraps && self.checks {
+                function.address(cell);
+                function.i32_const(length as i32);
+                function.op(op::I32_GE_U);
+                function.block(op::IF);
+                function.op(op::UNREACHABLE);
+                function.op(op::END);
+            }
+        }
+    }
+
+    fn advance(&self, function: &mut Function, amount: isize) -> Result<(), CompileError> {
+        if amount == 0 {
+            return Ok(());
+        }
+
+        self.index(function, amount)?;
+        function.global_set(HEAD);
+
+        if self.length.is_none() {
+            if amount > 0 {
+                function.global_get(HEAD);
+                function.call(RESERVE);
+            } else if self.checks {
+                function.global_get(HEAD);
+                function.i32_const(0);
+                function.op(op::I32_LT_S);
+                function.block(op::IF);
+                function.op(op::UNREACHABLE);
+                function.op(op::END);
+            }
+        }
+
+        self.check_bounds(function, None);
+        Ok(())
+    }
+
+    // Leaves the index of the cell `offset` away from the head in the start function's
+    // local, and returns that local, or nothing for the head itself.
+    fn cell_at(&self, function: &mut Function, offset: isize) -> Result<Option<u32>, CompileError> {
+        if offset == 0 {
+            return Ok(None);
+        }
+
+        let local = 0;
+        self.index(function, offset)?;
+        function.local_set(local);
+
+        if self.length.is_none() && offset > 0 {
+            function.local_get(local);
+            function.call(RESERVE);
+        }
+
+        self.check_bounds(function, Some(local));
+        Ok(Some(local))
+    }
+}
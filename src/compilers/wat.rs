@@ -0,0 +1,456 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::io::{Result as IOResult, Write};
+
+use crate::instruction::Instruction;
+use crate::interpreter::{CellWidth, EofMode, TapeSize};
+use crate::program::Program;
+
+use super::ast::{self, Node};
+use super::source_map::{self, LineTracker, SourceMap};
+use super::{write_annotation, write_header, Backend, CompileError, CompileOptions, ProgramInfo};
+
+const PAGE_SIZE: usize = 1 << 16;
+
+pub struct WatBackend;
+
+impl Backend for WatBackend {
+    fn name(&self) -> &'static str {
+        "wat"
+    }
+
+    fn file_extension(&self) -> &'static str {
+        "wat"
+    }
+
+    fn compile(
+        &self,
+        program: &Program,
+        options: &CompileOptions,
+        info: &ProgramInfo,
+        mut writer: &mut dyn Write,
+    ) -> Result<(), CompileError> {
+        compile(&program.instructions, options, info, &mut writer)
+    }
+
+    fn compile_with_source_map(
+        &self,
+        program: &Program,
+        options: &CompileOptions,
+        info: &ProgramInfo,
+        writer: &mut dyn Write,
+    ) -> Result<Option<SourceMap>, CompileError> {
+        source_map::track_lines(program, info, writer, |writer| {
+            write_program(&program.instructions, options, info, writer)
+        })
+    }
+}
+
+// The tape starts at address zero of the module's memory, and the head is a global. A
+// finite tape wraps or traps at its ends, while a right-infinite tape grows the memory as
+// the head moves right, and traps if it moves left of the start. Cells are bytes.
+//
+// The module imports env.read_byte, which returns the next byte of input or -1 once input
+// runs out, and env.write_byte. It exports its memory and a run function.
+pub fn compile<W: Write>(
+    instructions: &[Instruction],
+    options: &CompileOptions,
+    info: &ProgramInfo,
+    writer: &mut W,
+) -> Result<(), CompileError> {
+    write_program(instructions, options, info, &mut LineTracker::new(writer))
+}
+
+fn write_program<W: Write>(
+    instructions: &[Instruction],
+    options: &CompileOptions,
+    info: &ProgramInfo,
+    writer: &mut LineTracker<W>,
+) -> Result<(), CompileError> {
+    options.require_cell_width(CellWidth::U8, "WebAssembly text")?;
+    let tape = Tape::new(options)?;
+
+    write_header(writer, "wat", options, info, ";;")?;
+
+    writeln!(writer, "(module")?;
+    writeln!(
+        writer,
+        "  (import \"env\" \"read_byte\" (func $read_byte (result i32)))"
+    )?;
+    writeln!(
+        writer,
+        "  (import \"env\" \"write_byte\" (func $write_byte (param i32)))"
+    )?;
+    writeln!(writer)?;
+    writeln!(writer, "  (memory (export \"memory\") {})", tape.pages())?;
+    writeln!(writer, "  (global $head (mut i32) (i32.const 0))")?;
+    writeln!(writer)?;
+    writer.write_all(OUTPUT.as_bytes())?;
+    write_input(writer, options.eof_mode)?;
+
+    if tape.length.is_none() {
+        writer.write_all(RESERVE.as_bytes())?;
+    }
+
+    writeln!(writer)?;
+    writeln!(writer, "  (func (export \"run\")")?;
+    writeln!(writer, "    (local $address i32)")?;
+
+    write_block(writer, info, &tape, &ast::build(instructions)?, 2)?;
+    writer.end();
+
+    writeln!(writer, "  )")?;
+    writeln!(writer, ")")?;
+    Ok(())
+}
+
+fn write_block<W: Write>(
+    writer: &mut LineTracker<W>,
+    info: &ProgramInfo,
+    tape: &Tape,
+    nodes: &[Node],
+    depth: usize,
+) -> Result<(), CompileError> {
+    let indent = "  ".repeat(depth);
+
+    for node in nodes {
+        writer.begin(node.index());
+        write_annotation(writer, info, node.index(), &indent, ";;")?;
+
+        let (index, instruction) = match node {
+            Node::Instruction { index, instruction } => (*index, instruction),
+            Node::Procedure { .. } => unreachable!(),
+            Node::Loop { index, body } => {
+                writeln!(writer, "{}(block $end_{}", indent, index)?;
+                writeln!(
+                    writer,
+                    "{}  (br_if $end_{} (i32.eqz {}))",
+                    indent, index, CURRENT_CELL
+                )?;
+                writeln!(writer, "{}  (loop $loop_{}", indent, index)?;
+                write_block(writer, info, tape, body, depth + 2)?;
+                writer.begin(node.last_index());
+                write_annotation(writer, info, node.last_index(), &indent, ";;")?;
+                writeln!(
+                    writer,
+                    "{}    (br_if $loop_{} {})))",
+                    indent, index, CURRENT_CELL
+                )?;
+                continue;
+            }
+        };
+
+        match instruction {
+            Instruction::Add(amount) => {
+                writeln!(writer, "{}{}", indent, add_to(HEAD, *amount))?;
+            }
+            Instruction::Move(amount) => {
+                tape.advance(writer, &indent, *amount)?;
+            }
+            Instruction::Write(count) => {
+                writeln!(writer, "{}(call $output {})", indent, constant(*count)?)?;
+            }
+            Instruction::Read(count) => {
+                writeln!(writer, "{}(call $input {})", indent, constant(*count)?)?;
+            }
+            Instruction::JumpIfZero { .. }
+            | Instruction::JumpIfNotZero { .. }
+            | Instruction::DefineProc { .. }
+            | Instruction::EndProc
+            | Instruction::CallProc => unreachable!(),
+
+            Instruction::SetValue(value) => {
+                writeln!(
+                    writer,
+                    "{}(i32.store8 {} (i32.const {}))",
+                    indent, HEAD, *value as u8
+                )?;
+            }
+            Instruction::AddRelative { offset, amount } => {
+                let cell = tape.cell_at(writer, &indent, *offset)?;
+                writeln!(writer, "{}{}", indent, add_to(cell, *amount))?;
+            }
+            Instruction::AddVector { vector } => {
+                for (lane, amount) in vector.iter().enumerate() {
+                    if *amount != 0 {
+                        let cell = tape.cell_at(writer, &indent, lane as isize)?;
+                        writeln!(writer, "{}{}", indent, add_to(cell, *amount))?;
+                    }
+                }
+            }
+            Instruction::MulAdd { offset, factor } => {
+                writeln!(writer, "{}(if {}", indent, CURRENT_CELL)?;
+                writeln!(writer, "{}  (then", indent)?;
+
+                let inner = format!("{}    ", indent);
+                let cell = tape.cell_at(writer, &inner, *offset)?;
+                writeln!(
+                    writer,
+                    "{}(i32.store8 {} (i32.add (i32.load8_u {}) (i32.mul {} (i32.const {}))))",
+                    inner, cell, cell, CURRENT_CELL, *factor as u8
+                )?;
+                writeln!(writer, "{}  ))", indent)?;
+            }
+            Instruction::MoveRightToZero { increment, stride } => {
+                write_scan(writer, tape, &indent, index, *increment, *stride as isize)?;
+            }
+            Instruction::MoveLeftToZero { increment, stride } => {
+                write_scan(
+                    writer,
+                    tape,
+                    &indent,
+                    index,
+                    *increment,
+                    -(*stride as isize),
+                )?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+const HEAD: &str = "(global.get $head)";
+const CURRENT_CELL: &str = "(i32.load8_u (global.get $head))";
+
+const OUTPUT: &str = r#"  (func $output (param $count i32)
+    (block $done
+      (loop $next
+        (br_if $done (i32.eqz (local.get $count)))
+        (call $write_byte (i32.load8_u (global.get $head)))
+        (local.set $count (i32.sub (local.get $count) (i32.const 1)))
+        (br $next))))
+"#;
+
+// Once input runs out, reads either leave the cell unchanged, or store the value of the
+// EOF mode before returning.
+fn write_input<W: Write>(writer: &mut W, eof_mode: EofMode) -> IOResult<()> {
+    writeln!(writer)?;
+    writeln!(writer, "  (func $input (param $count i32)")?;
+    writeln!(writer, "    (local $value i32)")?;
+    writeln!(writer, "    (block $done")?;
+    writeln!(writer, "      (loop $next")?;
+    writeln!(writer, "        (br_if $done (i32.eqz (local.get $count)))")?;
+    writeln!(writer, "        (local.set $value (call $read_byte))")?;
+
+    let eof_value = match eof_mode {
+        EofMode::Unchanged => None,
+        EofMode::Zero => Some(0),
+        EofMode::NegativeOne => Some(u8::MAX),
+    };
+
+    match eof_value {
+        Some(value) => {
+            writeln!(
+                writer,
+                "        (if (i32.lt_s (local.get $value) (i32.const 0))"
+            )?;
+            writeln!(
+                writer,
+                "          (then (i32.store8 {} (i32.const {})) (br $done)))",
+                HEAD, value
+            )?;
+        }
+        None => writeln!(
+            writer,
+            "        (br_if $done (i32.lt_s (local.get $value) (i32.const 0)))"
+        )?,
+    }
+
+    writeln!(writer, "        (i32.store8 {} (local.get $value))", HEAD)?;
+    writeln!(
+        writer,
+        "        (local.set $count (i32.sub (local.get $count) (i32.const 1)))"
+    )?;
+    writeln!(writer, "        (br $next))))")
+}
+
+const RESERVE: &str = r#"
+  ;; Grows the memory until the index is part of the tape.
+  (func $reserve (param $index i32)
+    (if (i32.ge_u (i32.shr_u (local.get $index) (i32.const 16)) (memory.size))
+      (then
+        (if (i32.eq
+              (memory.grow
+                (i32.sub
+                  (i32.add (i32.shr_u (local.get $index) (i32.const 16)) (i32.const 1))
+                  (memory.size)))
+              (i32.const -1))
+          (then unreachable)))))
+"#;
+
+fn write_scan<W: Write>(
+    writer: &mut W,
+    tape: &Tape,
+    indent: &str,
+    index: usize,
+    increment: i8,
+    stride: isize,
+) -> Result<(), CompileError> {
+    writeln!(writer, "{}(block $scan_end_{}", indent, index)?;
+    writeln!(writer, "{}  (loop $scan_{}", indent, index)?;
+    writeln!(
+        writer,
+        "{}    (br_if $scan_end_{} (i32.eqz {}))",
+        indent, index, CURRENT_CELL
+    )?;
+
+    let inner = format!("{}    ", indent);
+
+    if increment != 0 {
+        writeln!(writer, "{}{}", inner, add_to(HEAD, increment))?;
+    }
+
+    tape.advance(writer, &inner, stride)?;
+    writeln!(writer, "{}    (br $scan_{})))", indent, index)?;
+    Ok(())
+}
+
+fn add_to(address: &str, amount: i8) -> String {
+    format!(
+        "(i32.store8 {} (i32.add (i32.load8_u {}) (i32.const {})))",
+        address, address, amount as u8
+    )
+}
+
+// Addresses are 32 bits, so anything that doesn't fit can't be compiled.
+fn constant(value: usize) -> Result<String, CompileError> {
+    if value <= i32::MAX as usize {
+        Ok(format!("(i32.const {})", value))
+    } else {
+        Err(CompileError::Unsupported(format!(
+            "{} doesn't fit in WebAssembly's 32-bit addresses",
+            value
+        )))
+    }
+}
+
+struct Tape {
+    length: Option<usize>,
+    wraps: bool,
+    checks: bool,
+}
+
+impl Tape {
+    fn new(options: &CompileOptions) -> Result<Self, CompileError> {
+        let length = match options.tape_size {
+            // Wrapping adds two indices below the length, so they can't exceed 2^31 each.
+            TapeSize::Finite(length) => Some(constant(length).map(|_| length)?),
+            TapeSize::Infinite => None,
+        };
+
+        Ok(Self {
+            length,
+            wraps: options.wraps(),
+            checks: options.runtime_checks(),
+        })
+    }
+
+    fn pages(&self) -> usize {
+        match self.length {
+            Some(length) => length.div_ceil(PAGE_SIZE),
+            None => 1,
+        }
+        .max(1)
+    }
+
+    // Returns an expression for the index `offset` cells away from the head.
+    fn index(&self, offset: isize) -> Result<String, CompileError> {
+        let distance = match self.length {
+            Some(length) if self.wraps => offset.unsigned_abs() % length,
+            _ => offset.unsigned_abs(),
+        };
+
+        let distance = constant(distance)?;
+
+        Ok(match (offset >= 0, self.length) {
+            (true, Some(length)) if self.wraps => format!(
+                "(i32.rem_u (i32.add {} {}) (i32.const {}))",
+                HEAD, distance, length
+            ),
+            (false, Some(length)) if self.wraps => format!(
+                "(i32.rem_u (i32.sub (i32.add {} (i32.const {})) {}) (i32.const {}))",
+                HEAD, length, distance, length
+            ),
+            (true, _) => format!("(i32.add {} {})", HEAD, distance),
+            (false, _) => format!("(i32.sub {} {})", HEAD, distance),
+        })
+    }
+
+    // Emits a trap for when an index is past either end of a tape that doesn't wrap.
+    // Negative indices are huge once they're unsigned, so one comparison covers both.
+    fn check_bounds<W: Write>(&self, writer: &mut W, indent: &str, index: &str) -> IOResult<()> {
+        match self.length {
+            Some(length) if !self.wraps && self.checks => writeln!(
+                writer,
+                "{}(if (i32.ge_u {} (i32.const {})) (then unreachable))",
+                indent, index, length
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    fn advance<W: Write>(
+        &self,
+        writer: &mut W,
+        indent: &str,
+        amount: isize,
+    ) -> Result<(), CompileError> {
+        if amount == 0 {
+            return Ok(());
+        }
+
+        writeln!(
+            writer,
+            "{}(global.set $head {})",
+            indent,
+            self.index(amount)?
+        )?;
+
+        if self.length.is_none() {
+            if amount > 0 {
+                writeln!(writer, "{}(call $reserve {})", indent, HEAD)?;
+            } else if self.checks {
+                writeln!(
+                    writer,
+                    "{}(if (i32.lt_s {} (i32.const 0)) (then unreachable))",
+                    indent, HEAD
+                )?;
+            }
+        }
+
+        self.check_bounds(writer, indent, HEAD)?;
+        Ok(())
+    }
+
+    // Emits code that leaves the address of the cell `offset` away from the head in a
+    // local, and returns an expression for that address.
+    fn cell_at<W: Write>(
+        &self,
+        writer: &mut W,
+        indent: &str,
+        offset: isize,
+    ) -> Result<&'static str, CompileError> {
+        if offset == 0 {
+            return Ok(HEAD);
+        }
+
+        writeln!(
+            writer,
+            "{}(local.set $address {})",
+            indent,
+            self.index(offset)?
+        )?;
+
+        if self.length.is_none() && offset > 0 {
+            writeln!(writer, "{}(call $reserve (local.get $address))", indent)?;
+        }
+
+        self.check_bounds(writer, indent, "(local.get $address)")?;
+        Ok("(local.get $address)")
+    }
+}
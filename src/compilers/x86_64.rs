@@ -0,0 +1,422 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::io::{Result as IOResult, Write};
+
+use crate::instruction::Instruction;
+use crate::interpreter::{CellWidth, EofMode, TapeSize};
+use crate::program::Program;
+
+use super::ast::{self, Node};
+use super::source_map::{self, LineTracker, SourceMap};
+use super::{write_annotation, write_header, Backend, CompileError, CompileOptions, ProgramInfo};
+
+// A right-infinite tape can't grow in .bss, so it gets a large fixed region instead, and
+// running off either end of it is reported as an error.
+const INFINITE_TAPE_LENGTH: usize = 1 << 26;
+const OUTPUT_BUFFER_LENGTH: usize = 4096;
+
+// GAS, Intel syntax, for x86-64 Linux. The generated code keeps the head index in rbx,
+// the tape's address in r12, and the number of buffered output bytes in r13. The
+// subroutines below also clobber r14, rax, rcx, rdx, rsi, rdi, and r11.
+const SUBROUTINES: &str = r#"
+    .section .rodata
+out_of_bounds_message:
+    .ascii "error: the tape head moved out of bounds\n"
+    .set OUT_OF_BOUNDS_LENGTH, . - out_of_bounds_message
+write_error_message:
+    .ascii "error: failed to write output\n"
+    .set WRITE_ERROR_LENGTH, . - write_error_message
+
+    .text
+# Writes the current cell rdi times.
+output:
+    mov r14, rdi
+    movzx ecx, byte ptr [r12 + rbx]
+1:
+    test r14, r14
+    jz 2f
+    lea rax, [rip + output_buffer]
+    mov byte ptr [rax + r13], cl
+    inc r13
+    dec r14
+    cmp r13, OUTPUT_BUFFER_LENGTH
+    jb 1b
+    push rcx
+    call flush
+    pop rcx
+    jmp 1b
+2:
+    ret
+
+# Reads rdi bytes into the current cell. Once input runs out, the cell is set to
+# EOF_VALUE if EOF_STORES is set, and left unchanged otherwise.
+input:
+    mov r14, rdi
+    call flush
+1:
+    test r14, r14
+    jz 2f
+    xor eax, eax
+    xor edi, edi
+    lea rsi, [rip + input_byte]
+    mov edx, 1
+    syscall
+    test rax, rax
+    jle 3f
+    movzx eax, byte ptr [rip + input_byte]
+    mov byte ptr [r12 + rbx], al
+    dec r14
+    jmp 1b
+3:
+    .if EOF_STORES
+    mov byte ptr [r12 + rbx], EOF_VALUE
+    .endif
+2:
+    ret
+
+flush:
+    lea rsi, [rip + output_buffer]
+    mov rdx, r13
+1:
+    test rdx, rdx
+    jz 2f
+    mov eax, 1
+    mov edi, 1
+    syscall
+    test rax, rax
+    jle write_error
+    add rsi, rax
+    sub rdx, rax
+    jmp 1b
+2:
+    xor r13d, r13d
+    ret
+
+out_of_bounds:
+    call flush
+    lea rsi, [rip + out_of_bounds_message]
+    mov edx, OUT_OF_BOUNDS_LENGTH
+    jmp fail
+
+write_error:
+    lea rsi, [rip + write_error_message]
+    mov edx, WRITE_ERROR_LENGTH
+
+fail:
+    mov eax, 1
+    mov edi, 2
+    syscall
+    mov eax, 60
+    mov edi, 1
+    syscall
+
+    .globl _start
+_start:
+    lea r12, [rip + tape]
+    xor ebx, ebx
+    xor r13d, r13d
+"#;
+
+const EXIT: &str = r#"
+    call flush
+    mov eax, 60
+    xor edi, edi
+    syscall
+"#;
+
+pub struct X86_64Backend;
+
+impl Backend for X86_64Backend {
+    fn name(&self) -> &'static str {
+        "x86_64"
+    }
+
+    fn file_extension(&self) -> &'static str {
+        "s"
+    }
+
+    fn compile(
+        &self,
+        program: &Program,
+        options: &CompileOptions,
+        info: &ProgramInfo,
+        mut writer: &mut dyn Write,
+    ) -> Result<(), CompileError> {
+        compile(&program.instructions, options, info, &mut writer)
+    }
+
+    fn compile_with_source_map(
+        &self,
+        program: &Program,
+        options: &CompileOptions,
+        info: &ProgramInfo,
+        writer: &mut dyn Write,
+    ) -> Result<Option<SourceMap>, CompileError> {
+        source_map::track_lines(program, info, writer, |writer| {
+            write_program(&program.instructions, options, info, writer)
+        })
+    }
+}
+
+pub fn compile<W: Write>(
+    instructions: &[Instruction],
+    options: &CompileOptions,
+    info: &ProgramInfo,
+    writer: &mut W,
+) -> Result<(), CompileError> {
+    write_program(instructions, options, info, &mut LineTracker::new(writer))
+}
+
+fn write_program<W: Write>(
+    instructions: &[Instruction],
+    options: &CompileOptions,
+    info: &ProgramInfo,
+    writer: &mut LineTracker<W>,
+) -> Result<(), CompileError> {
+    options.require_cell_width(CellWidth::U8, "x86-64 assembly")?;
+    write_header(writer, "x86_64", options, info, "#")?;
+
+    writeln!(
+        writer,
+        "# Assemble and link with: as -o program.o program.s && ld -o program program.o"
+    )?;
+    writeln!(writer)?;
+    writeln!(writer, "    .intel_syntax noprefix")?;
+    writeln!(writer)?;
+    writeln!(
+        writer,
+        "    .set OUTPUT_BUFFER_LENGTH, {}",
+        OUTPUT_BUFFER_LENGTH
+    )?;
+
+    let eof_value = match options.eof_mode {
+        EofMode::Unchanged => None,
+        EofMode::Zero => Some(0),
+        EofMode::NegativeOne => Some(u8::MAX),
+    };
+
+    writeln!(writer, "    .set EOF_STORES, {}", eof_value.is_some() as u8)?;
+    writeln!(writer, "    .set EOF_VALUE, {}", eof_value.unwrap_or(0))?;
+
+    let tape = Tape::new(options);
+    writeln!(writer, "    .lcomm tape, {}", tape.length)?;
+    writeln!(writer, "    .lcomm output_buffer, OUTPUT_BUFFER_LENGTH")?;
+    writeln!(writer, "    .lcomm input_byte, 1")?;
+
+    writer.write_all(SUBROUTINES.as_bytes())?;
+    writeln!(writer)?;
+
+    write_block(writer, info, &tape, &ast::build(instructions)?)?;
+    writer.end();
+
+    writer.write_all(EXIT.as_bytes())?;
+    Ok(())
+}
+
+fn write_block<W: Write>(
+    writer: &mut LineTracker<W>,
+    info: &ProgramInfo,
+    tape: &Tape,
+    nodes: &[Node],
+) -> IOResult<()> {
+    for node in nodes {
+        writer.begin(node.index());
+        write_annotation(writer, info, node.index(), "    ", "#")?;
+
+        let (index, instruction) = match node {
+            Node::Instruction { index, instruction } => (*index, instruction),
+            Node::Procedure { .. } => unreachable!(),
+            Node::Loop { index, body } => {
+                writeln!(writer, "    cmp {}, 0", CURRENT_CELL)?;
+                writeln!(writer, "    je .Lend_{}", index)?;
+                writeln!(writer, ".Lloop_{}:", index)?;
+                write_block(writer, info, tape, body)?;
+                writer.begin(node.last_index());
+                write_annotation(writer, info, node.last_index(), "    ", "#")?;
+                writeln!(writer, "    cmp {}, 0", CURRENT_CELL)?;
+                writeln!(writer, "    jne .Lloop_{}", index)?;
+                writeln!(writer, ".Lend_{}:", index)?;
+                continue;
+            }
+        };
+
+        match instruction {
+            Instruction::Add(amount) => {
+                writeln!(writer, "    add {}, {}", CURRENT_CELL, *amount as u8)?;
+            }
+            Instruction::Move(amount) => {
+                tape.advance(writer, "rbx", *amount)?;
+            }
+            Instruction::Write(count) => {
+                writeln!(writer, "    mov rdi, {}", count)?;
+                writeln!(writer, "    call output")?;
+            }
+            Instruction::Read(count) => {
+                writeln!(writer, "    mov rdi, {}", count)?;
+                writeln!(writer, "    call input")?;
+            }
+            Instruction::JumpIfZero { .. }
+            | Instruction::JumpIfNotZero { .. }
+            | Instruction::DefineProc { .. }
+            | Instruction::EndProc
+            | Instruction::CallProc => unreachable!(),
+
+            Instruction::SetValue(value) => {
+                writeln!(writer, "    mov {}, {}", CURRENT_CELL, *value as u8)?;
+            }
+            Instruction::AddRelative { offset, amount } => {
+                let cell = tape.cell_at(writer, *offset)?;
+                writeln!(writer, "    add {}, {}", cell, *amount as u8)?;
+            }
+            Instruction::AddVector { vector } => {
+                for (lane, amount) in vector.iter().enumerate() {
+                    if *amount != 0 {
+                        let cell = tape.cell_at(writer, lane as isize)?;
+                        writeln!(writer, "    add {}, {}", cell, *amount as u8)?;
+                    }
+                }
+            }
+            Instruction::MulAdd { offset, factor } => {
+                writeln!(writer, "    movzx ecx, {}", CURRENT_CELL)?;
+                writeln!(writer, "    test ecx, ecx")?;
+                writeln!(writer, "    jz 1f")?;
+                writeln!(writer, "    imul ecx, ecx, {}", *factor as u8)?;
+
+                let cell = tape.cell_at(writer, *offset)?;
+                writeln!(writer, "    add {}, cl", cell)?;
+                writeln!(writer, "1:")?;
+            }
+            Instruction::MoveRightToZero { increment, stride } => {
+                write_scan(writer, tape, index, *increment, *stride as isize)?;
+            }
+            Instruction::MoveLeftToZero { increment, stride } => {
+                write_scan(writer, tape, index, *increment, -(*stride as isize))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+const CURRENT_CELL: &str = "byte ptr [r12 + rbx]";
+
+fn write_scan<W: Write>(
+    writer: &mut W,
+    tape: &Tape,
+    index: usize,
+    increment: i8,
+    stride: isize,
+) -> IOResult<()> {
+    writeln!(writer, ".Lscan_{}:", index)?;
+    writeln!(writer, "    cmp {}, 0", CURRENT_CELL)?;
+    writeln!(writer, "    je .Lscan_end_{}", index)?;
+
+    if increment != 0 {
+        writeln!(writer, "    add {}, {}", CURRENT_CELL, increment as u8)?;
+    }
+
+    tape.advance(writer, "rbx", stride)?;
+    writeln!(writer, "    jmp .Lscan_{}", index)?;
+    writeln!(writer, ".Lscan_end_{}:", index)
+}
+
+struct Tape {
+    length: usize,
+    wraps: bool,
+    checks: bool,
+}
+
+impl Tape {
+    fn new(options: &CompileOptions) -> Self {
+        let length = match options.tape_size {
+            TapeSize::Finite(length) => length,
+            TapeSize::Infinite => INFINITE_TAPE_LENGTH,
+        };
+
+        Self {
+            length,
+            wraps: options.wraps(),
+            checks: options.runtime_checks(),
+        }
+    }
+
+    // Emits code that moves the index in `register` by `amount` cells, either wrapping
+    // around the tape or bailing out at its ends, unless runtime checks are off.
+    fn advance<W: Write>(&self, writer: &mut W, register: &str, amount: isize) -> IOResult<()> {
+        let distance = if self.wraps {
+            amount.unsigned_abs() % self.length
+        } else {
+            amount.unsigned_abs()
+        };
+
+        if distance == 0 {
+            return Ok(());
+        }
+
+        let distance = immediate(writer, distance, "rdx")?;
+        let length = immediate(writer, self.length, "rsi")?;
+
+        match (amount > 0, self.wraps) {
+            (true, true) => {
+                writeln!(writer, "    add {}, {}", register, distance)?;
+                writeln!(writer, "    cmp {}, {}", register, length)?;
+                writeln!(writer, "    jb 2f")?;
+                writeln!(writer, "    sub {}, {}", register, length)?;
+                writeln!(writer, "2:")
+            }
+            (false, true) => {
+                writeln!(writer, "    sub {}, {}", register, distance)?;
+                writeln!(writer, "    jae 2f")?;
+                writeln!(writer, "    add {}, {}", register, length)?;
+                writeln!(writer, "2:")
+            }
+            (true, false) => {
+                writeln!(writer, "    add {}, {}", register, distance)?;
+
+                if self.checks {
+                    writeln!(writer, "    jc out_of_bounds")?;
+                    writeln!(writer, "    cmp {}, {}", register, length)?;
+                    writeln!(writer, "    jae out_of_bounds")?;
+                }
+
+                Ok(())
+            }
+            (false, false) => {
+                writeln!(writer, "    sub {}, {}", register, distance)?;
+
+                if self.checks {
+                    writeln!(writer, "    jb out_of_bounds")?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    // Emits code that leaves the index of the cell `offset` away from the head in rax, and
+    // returns an operand for that cell.
+    fn cell_at<W: Write>(&self, writer: &mut W, offset: isize) -> IOResult<&'static str> {
+        if offset == 0 {
+            return Ok(CURRENT_CELL);
+        }
+
+        writeln!(writer, "    mov rax, rbx")?;
+        self.advance(writer, "rax", offset)?;
+
+        Ok("byte ptr [r12 + rax]")
+    }
+}
+
+// Immediates are limited to 32 bits, so larger values go through a scratch register.
+fn immediate<W: Write>(writer: &mut W, value: usize, scratch: &str) -> IOResult<String> {
+    if value <= i32::MAX as usize {
+        Ok(value.to_string())
+    } else {
+        writeln!(writer, "    mov {}, {}", scratch, value)?;
+        Ok(scratch.to_owned())
+    }
+}
@@ -0,0 +1,232 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::interpreter::{self, CellWidth, EofMode};
+
+pub const FILE_NAME: &str = "membrane.toml";
+
+// Defaults for the options every subcommand shares, read from a `membrane.toml`. Options
+// left out of the file are None, and options given on the command line win over it. The
+// file is a flat list of TOML keys named after the command line flags:
+//
+//     # Comments start with a hash.
+//     tape = "30k"
+//     cell-width = 8
+//     optimize = true
+//     eof = "zero"
+//     buffer-read = true
+//     buffer-write = false
+#[derive(Clone, Eq, PartialEq, Default, Debug)]
+pub struct Config {
+    pub tape_size: Option<usize>,
+    pub cell_width: Option<CellWidth>,
+    pub optimize: Option<bool>,
+    pub eof_mode: Option<EofMode>,
+    pub buffer_read: Option<bool>,
+    pub buffer_write: Option<bool>,
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    // A line that isn't a comment or `key = value`, such as a table header.
+    #[error("line {line}: expected 'key = value'")]
+    Syntax { line: usize },
+    #[error("line {line}: unknown key '{key}'")]
+    UnknownKey { line: usize, key: String },
+    #[error("line {line}: '{key}' is already set")]
+    DuplicateKey { line: usize, key: String },
+    #[error("line {line}: invalid value for '{key}': {reason}")]
+    InvalidValue {
+        line: usize,
+        key: String,
+        reason: String,
+    },
+    #[error("failed to read the configuration: {0}")]
+    Io(#[from] io::Error),
+}
+
+impl Config {
+    // The configuration in the current directory, or else in the user's configuration
+    // directory, if either has one.
+    pub fn find() -> Option<PathBuf> {
+        let local = PathBuf::from(FILE_NAME);
+
+        if local.is_file() {
+            return Some(local);
+        }
+
+        let global = Self::user_directory()?.join(FILE_NAME);
+        global.is_file().then_some(global)
+    }
+
+    // `$XDG_CONFIG_HOME/membrane`, falling back to `~/.config/membrane`.
+    pub fn user_directory() -> Option<PathBuf> {
+        let base = match env::var_os("XDG_CONFIG_HOME") {
+            Some(directory) if !directory.is_empty() => PathBuf::from(directory),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+
+        Some(base.join("membrane"))
+    }
+
+    pub fn load_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        fs::read_to_string(path)?.parse()
+    }
+}
+
+impl FromStr for Config {
+    type Err = ConfigError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut config = Self::default();
+
+        for (index, line) in text.lines().enumerate() {
+            let number = index + 1;
+            let line = strip_comment(line).trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or(ConfigError::Syntax { line: number })?;
+            let (key, value) = (key.trim(), Value::parse(value.trim(), number)?);
+
+            let invalid = |reason: &dyn fmt::Display| ConfigError::InvalidValue {
+                line: number,
+                key: key.to_owned(),
+                reason: reason.to_string(),
+            };
+
+            let duplicate = match key {
+                "tape" => config
+                    .tape_size
+                    .replace(
+                        interpreter::parse_tape_size(value.text()).map_err(|err| invalid(&err))?,
+                    )
+                    .is_some(),
+                "cell-width" => config
+                    .cell_width
+                    .replace(value.text().parse().map_err(|err| invalid(&err))?)
+                    .is_some(),
+                "optimize" => config
+                    .optimize
+                    .replace(value.boolean().map_err(|err| invalid(&err))?)
+                    .is_some(),
+                "eof" => config
+                    .eof_mode
+                    .replace(value.text().parse().map_err(|err| invalid(&err))?)
+                    .is_some(),
+                "buffer-read" => config
+                    .buffer_read
+                    .replace(value.boolean().map_err(|err| invalid(&err))?)
+                    .is_some(),
+                "buffer-write" => config
+                    .buffer_write
+                    .replace(value.boolean().map_err(|err| invalid(&err))?)
+                    .is_some(),
+                _ => {
+                    return Err(ConfigError::UnknownKey {
+                        line: number,
+                        key: key.to_owned(),
+                    })
+                }
+            };
+
+            if duplicate {
+                return Err(ConfigError::DuplicateKey {
+                    line: number,
+                    key: key.to_owned(),
+                });
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+// The part of the line before a comment, which can't start inside a string.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+
+    for (index, character) in line.char_indices() {
+        match (character, quote) {
+            ('#', None) => return &line[..index],
+            ('"' | '\'', None) => quote = Some(character),
+            (character, Some(open)) if character == open => quote = None,
+            _ => {}
+        }
+    }
+
+    line
+}
+
+// The values TOML has that the configuration uses.
+enum Value {
+    Boolean(bool),
+    // Kept as written, since cell widths and tape sizes can be given as either a number or
+    // a string.
+    Integer(String),
+    String(String),
+}
+
+impl Value {
+    fn parse(value: &str, line: usize) -> Result<Self, ConfigError> {
+        match value {
+            "true" => return Ok(Self::Boolean(true)),
+            "false" => return Ok(Self::Boolean(false)),
+            _ => {}
+        }
+
+        let quoted = ['"', '\'']
+            .into_iter()
+            .find(|&quote| value.len() >= 2 && value.starts_with(quote) && value.ends_with(quote));
+
+        if let Some(quote) = quoted {
+            let text = &value[1..value.len() - 1];
+
+            // Escapes would only ever matter in the names of options, which have none.
+            if text.contains(quote) || (quote == '"' && text.contains('\\')) {
+                return Err(ConfigError::Syntax { line });
+            }
+
+            return Ok(Self::String(text.to_owned()));
+        }
+
+        let digits = value.replace('_', "");
+
+        if !digits.is_empty() && digits.bytes().all(|byte| byte.is_ascii_digit()) {
+            return Ok(Self::Integer(digits));
+        }
+
+        Err(ConfigError::Syntax { line })
+    }
+
+    fn boolean(&self) -> Result<bool, String> {
+        match self {
+            Self::Boolean(value) => Ok(*value),
+            _ => Err("expected true or false".to_owned()),
+        }
+    }
+
+    fn text(&self) -> &str {
+        match self {
+            Self::Boolean(true) => "true",
+            Self::Boolean(false) => "false",
+            Self::Integer(text) | Self::String(text) => text,
+        }
+    }
+}
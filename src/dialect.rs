@@ -0,0 +1,287 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use alloc::borrow::ToOwned;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+use core::fmt;
+use core::fmt::Formatter;
+use core::str::FromStr;
+
+use thiserror::Error;
+
+const COMMANDS: &[u8] = b"+-><.,[]";
+
+// Ook! spells each command with two of the words `Ook.`, `Ook?`, and `Ook!`.
+const OOK: &str = "
+> Ook. Ook?
+< Ook? Ook.
++ Ook. Ook.
+- Ook! Ook!
+. Ook! Ook.
+, Ook. Ook!
+[ Ook! Ook?
+] Ook? Ook!
+";
+
+// The dialects that membrane reads without a map of its own.
+#[derive(Copy, Clone, Eq, PartialEq, Default, Debug)]
+pub enum Dialect {
+    #[default]
+    Brainfuck,
+    Ook,
+    Pbrain,
+}
+
+impl Dialect {
+    pub const ALL: &'static [Self] = &[Self::Brainfuck, Self::Ook, Self::Pbrain];
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Brainfuck => "brainfuck",
+            Self::Ook => "ook",
+            Self::Pbrain => "pbrain",
+        }
+    }
+
+    pub const fn extensions(&self) -> &'static [&'static str] {
+        match self {
+            Self::Brainfuck => &["b", "bf"],
+            Self::Ook => &["ook"],
+            Self::Pbrain => &[],
+        }
+    }
+
+    pub fn for_extension(extension: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|dialect| {
+            dialect
+                .extensions()
+                .iter()
+                .any(|known| known.eq_ignore_ascii_case(extension))
+        })
+    }
+
+    // The tokens of the dialect, or None for those spelled like Brainfuck.
+    pub fn map(&self) -> Option<DialectMap> {
+        match self {
+            Self::Brainfuck | Self::Pbrain => None,
+            Self::Ook => Some(OOK.parse().unwrap()),
+        }
+    }
+
+    // Whether the dialect has pbrain's procedures.
+    pub const fn has_procedures(&self) -> bool {
+        matches!(self, Self::Pbrain)
+    }
+}
+
+named_option!(Dialect);
+
+#[derive(Clone, Eq, PartialEq, Debug, Error)]
+pub enum DialectError {
+    #[error(
+        "line {line}: '{command}' isn't one of the commands {}",
+        String::from_utf8_lossy(COMMANDS)
+    )]
+    UnknownCommand { line: usize, command: String },
+    #[error("line {line}: the command has no token")]
+    MissingToken { line: usize },
+    #[error("line {line}: '{token}' already stands for a command")]
+    DuplicateToken { line: usize, token: String },
+    // Errors from writing code in the dialect, rather than reading its map.
+    #[error("the dialect has no token for '{}'", *.command as char)]
+    NoToken { command: u8 },
+    #[error("the dialect's tokens run together into other tokens when written out")]
+    Ambiguous,
+}
+
+// What the source starts with, as far as a dialect is concerned.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Token {
+    // One of the eight commands, spelled with this many bytes.
+    Command(u8, usize),
+    // A byte that isn't part of any token, and so is a comment.
+    Comment,
+    // The bytes could be the start of a longer token, so more are needed to tell.
+    Partial,
+}
+
+// A dialect that spells the commands with tokens of its own, for the many languages that
+// are Brainfuck with the commands renamed. Maps are read from text with one command per
+// line, followed by the token that stands for it:
+//
+//     # Comments start with a hash.
+//     + inc
+//     - dec
+//
+// The token is the rest of the line with the whitespace around it trimmed, so it can be
+// several words, and the whitespace between its words matches any run of whitespace. A
+// command can have several tokens, and commands without one can't be written in the
+// dialect. Bytes that aren't part of a token are comments, as usual.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct DialectMap {
+    // Longest first, so the first token the source starts with is the longest one.
+    tokens: Vec<(Vec<u8>, u8)>,
+}
+
+impl DialectMap {
+    // Splits off the token the bytes start with, preferring the longest. Unless the bytes
+    // are the end of the source, a token that might continue past them is Partial.
+    pub fn token(&self, bytes: &[u8], end: bool) -> Token {
+        for (token, command) in &self.tokens {
+            if let Some(token) = matches(token, *command, bytes, end) {
+                return token;
+            }
+        }
+
+        Token::Comment
+    }
+
+    // The token written for the command, which is the shortest of them, or None if the
+    // dialect can't write it.
+    pub fn spelling(&self, command: u8) -> Option<&[u8]> {
+        self.tokens
+            .iter()
+            .rev()
+            .find(|(_, spelled)| *spelled == command)
+            .map(|(token, _)| token.as_slice())
+    }
+
+    // Spells out Brainfuck code in the dialect, with a space between tokens and lines
+    // wrapped before they grow wider than the width. Tokens that would be read back as
+    // other tokens once they're next to each other are an error, rather than a different
+    // program.
+    pub fn write_code(&self, code: &[u8], width: usize) -> Result<Vec<u8>, DialectError> {
+        let mut written = Vec::new();
+        let mut line_length = 0;
+
+        for &command in code {
+            let token = self
+                .spelling(command)
+                .ok_or(DialectError::NoToken { command })?;
+
+            if line_length > 0 && line_length + 1 + token.len() > width {
+                written.push(b'\n');
+                line_length = 0;
+            } else if line_length > 0 {
+                written.push(b' ');
+                line_length += 1;
+            }
+
+            written.extend_from_slice(token);
+            line_length += token.len();
+        }
+
+        written.push(b'\n');
+
+        let mut offset = 0;
+        let mut commands = code.iter();
+
+        while offset < written.len() {
+            match self.token(&written[offset..], true) {
+                Token::Command(command, length) if commands.next() == Some(&command) => {
+                    offset += length;
+                }
+                Token::Comment if written[offset].is_ascii_whitespace() => offset += 1,
+                _ => return Err(DialectError::Ambiguous),
+            }
+        }
+
+        if commands.next().is_some() {
+            return Err(DialectError::Ambiguous);
+        }
+
+        Ok(written)
+    }
+}
+
+// Matches the token against the start of the bytes, where each space in the token stands
+// for a run of whitespace.
+fn matches(token: &[u8], command: u8, bytes: &[u8], end: bool) -> Option<Token> {
+    let partial = if end { None } else { Some(Token::Partial) };
+    let mut length = 0;
+
+    for &expected in token {
+        let &byte = match bytes.get(length) {
+            Some(byte) => byte,
+            None => return partial,
+        };
+
+        if expected != b' ' {
+            if byte != expected {
+                return None;
+            }
+
+            length += 1;
+            continue;
+        }
+
+        if !byte.is_ascii_whitespace() {
+            return None;
+        }
+
+        while bytes.get(length).is_some_and(u8::is_ascii_whitespace) {
+            length += 1;
+        }
+
+        // The whitespace might carry on into bytes that haven't been read yet.
+        if length == bytes.len() {
+            return partial;
+        }
+    }
+
+    Some(Token::Command(command, length))
+}
+
+impl FromStr for DialectMap {
+    type Err = DialectError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut tokens: Vec<(Vec<u8>, u8)> = Vec::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (command, token) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let token = token.split_whitespace().collect::<Vec<_>>().join(" ");
+            let number = index + 1;
+
+            let command = match command.as_bytes() {
+                [command] if COMMANDS.contains(command) => *command,
+                _ => {
+                    return Err(DialectError::UnknownCommand {
+                        line: number,
+                        command: command.to_owned(),
+                    })
+                }
+            };
+
+            if token.is_empty() {
+                return Err(DialectError::MissingToken { line: number });
+            }
+
+            if tokens
+                .iter()
+                .any(|(existing, _)| existing == token.as_bytes())
+            {
+                return Err(DialectError::DuplicateToken {
+                    line: number,
+                    token,
+                });
+            }
+
+            tokens.push((token.as_bytes().to_vec(), command));
+        }
+
+        tokens.sort_by_key(|(token, _)| Reverse(token.len()));
+        Ok(Self { tokens })
+    }
+}
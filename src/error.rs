@@ -0,0 +1,116 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use alloc::string::String;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::io;
+
+use thiserror::Error;
+
+#[cfg(feature = "std")]
+use crate::{
+    compilers::bytecode::BytecodeError, compilers::native::NativeError, compilers::CompileError,
+    config::ConfigError, dialect::DialectError, interpreter::RuntimeError, lister::ListingError,
+    loader::LoadError, optimizer::OptimizeError, parser::ParseError, preprocessor::PreprocessError,
+    profile::ProfileError,
+};
+
+// Every way membrane can fail, for callers that go from source to a running or compiled
+// program and want one error type for all of it. Each module's own error converts into it
+// with `?`.
+#[cfg(feature = "std")]
+#[derive(Debug, Error)]
+pub enum Error {
+    // Written one to a line.
+    #[error("{}", lines(.0))]
+    Parse(Vec<ParseError>),
+    #[error("{0}")]
+    Preprocess(#[from] PreprocessError),
+    #[error("{0}")]
+    Bytecode(#[from] BytecodeError),
+    #[error("{0}")]
+    Optimize(#[from] OptimizeError),
+    #[error("{0}")]
+    Runtime(#[from] RuntimeError),
+    // A backend couldn't compile the program, such as one with unmatched jumps, or couldn't
+    // write it.
+    #[error("{0}")]
+    Compile(#[from] CompileError),
+    #[error("{0}")]
+    Native(#[from] NativeError),
+    #[error("{0}")]
+    Dialect(#[from] DialectError),
+    #[error("{0}")]
+    Config(#[from] ConfigError),
+    #[error("{0}")]
+    Listing(#[from] ListingError),
+    #[error("{0}")]
+    Profile(#[from] ProfileError),
+    #[error("{0}")]
+    Io(#[from] io::Error),
+}
+
+#[cfg(feature = "std")]
+impl Error {
+    // Whether reading or writing failed, rather than anything being wrong with the program
+    // or how it was asked to be handled.
+    pub fn is_io(&self) -> bool {
+        match self {
+            Self::Parse(errors) => errors.iter().any(|err| matches!(err, ParseError::Io(_))),
+            Self::Bytecode(BytecodeError::Io(_))
+            | Self::Native(NativeError::Io(_))
+            | Self::Config(ConfigError::Io(_))
+            | Self::Profile(ProfileError::Io(_))
+            | Self::Compile(CompileError::Io(_))
+            | Self::Io(_) => true,
+            Self::Runtime(err) => err.is_io(),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn lines(errors: &[ParseError]) -> String {
+    errors
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(feature = "std")]
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Self {
+        Self::Parse(vec![err])
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<Vec<ParseError>> for Error {
+    fn from(errors: Vec<ParseError>) -> Self {
+        Self::Parse(errors)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<LoadError> for Error {
+    fn from(err: LoadError) -> Self {
+        match err {
+            LoadError::Parse(errors) => Self::Parse(errors),
+            LoadError::Bytecode(err) => Self::Bytecode(err),
+        }
+    }
+}
+
+// A name that isn't one of an option's values, from parsing options such as `EofMode` by
+// name.
+#[derive(Clone, Eq, PartialEq, Debug, Error)]
+#[error("unknown value '{value}' (expected one of: {})", .expected.join(", "))]
+pub struct UnknownValueError {
+    pub value: String,
+    pub expected: Vec<&'static str>,
+}
@@ -0,0 +1,254 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use crate::instruction::Instruction;
+use crate::program::Program;
+use crate::span::{Location, Span};
+
+// A stretch of the program that the optimizer turned into the same instructions, such as a
+// run of one command or a whole loop it recognized, along with what it does in words.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Region {
+    pub span: Span,
+    pub instructions: Vec<Instruction>,
+    // How many loops and procedures the region is inside.
+    pub depth: usize,
+    pub explanation: String,
+}
+
+// Splits an optimized program into the regions each of its instructions came from, and
+// explains each of them. Without spans, as for bytecode, every instruction is a region.
+pub fn explain(program: &Program) -> Vec<Region> {
+    let instructions = &program.instructions;
+    let mut regions: Vec<Region> = Vec::new();
+    let mut depth = 0;
+
+    for (index, instruction) in instructions.iter().enumerate() {
+        let span = program.spans.get(index).copied().unwrap_or_default();
+
+        if matches!(
+            instruction,
+            Instruction::JumpIfNotZero { .. } | Instruction::EndProc
+        ) {
+            depth = usize::saturating_sub(depth, 1);
+        }
+
+        match regions.last_mut() {
+            Some(region) if region.span == span && program.spans.len() == instructions.len() => {
+                region.instructions.push(*instruction);
+            }
+            _ => regions.push(Region {
+                span,
+                instructions: vec![*instruction],
+                depth,
+                explanation: String::new(),
+            }),
+        }
+
+        if matches!(
+            instruction,
+            Instruction::JumpIfZero { .. } | Instruction::DefineProc { .. }
+        ) {
+            depth += 1;
+        }
+    }
+
+    for region in &mut regions {
+        region.explanation = describe(&region.instructions, |location| {
+            program.spans.get(location).and_then(Span::location)
+        });
+    }
+
+    regions
+}
+
+// What the instructions do, one clause for each of them, except that a run of
+// multiplications and the store that ends it are explained together.
+fn describe(
+    instructions: &[Instruction],
+    loop_start: impl Fn(usize) -> Option<Location>,
+) -> String {
+    let mut clauses = Vec::new();
+    let mut index = 0;
+
+    while index < instructions.len() {
+        let targets = instructions[index..]
+            .iter()
+            .map_while(|instruction| match instruction {
+                Instruction::MulAdd { offset, factor } => Some((*offset, *factor)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        if !targets.is_empty() {
+            index += targets.len();
+
+            let store = match instructions.get(index) {
+                Some(Instruction::SetValue(value)) => {
+                    index += 1;
+                    Some(*value)
+                }
+                _ => None,
+            };
+
+            clauses.push(multiply(&targets, store));
+            continue;
+        }
+
+        let clause = match instructions[index] {
+            Instruction::Add(amount) => add(amount, 0),
+            Instruction::Move(amount) => format!("moves {}", cells(amount)),
+            Instruction::Write(1) => "writes the current cell".to_owned(),
+            Instruction::Write(count) => format!("writes the current cell {} times", count),
+            Instruction::Read(1) => "reads a byte into the current cell".to_owned(),
+            Instruction::Read(count) => format!(
+                "reads {} bytes into the current cell, keeping the last",
+                count
+            ),
+            Instruction::JumpIfZero { .. } => {
+                "loops over what follows until the current cell is zero".to_owned()
+            }
+            Instruction::JumpIfNotZero { location } => match loop_start(location) {
+                Some(location) => format!("ends the loop from {}", location),
+                None => "ends the loop".to_owned(),
+            },
+
+            Instruction::SetValue(0) => "zeroes the current cell".to_owned(),
+            Instruction::SetValue(value) => format!("sets the current cell to {}", value),
+            Instruction::AddRelative { offset, amount } => add(amount, offset),
+            Instruction::AddVector { vector } => vector
+                .iter()
+                .enumerate()
+                .filter(|(_, amount)| **amount != 0)
+                .map(|(offset, amount)| add(*amount, offset as isize))
+                .collect::<Vec<_>>()
+                .join(", "),
+            Instruction::MulAdd { .. } => unreachable!(),
+
+            Instruction::MoveRightToZero { increment, stride } => scan("right", increment, stride),
+            Instruction::MoveLeftToZero { increment, stride } => scan("left", increment, stride),
+
+            Instruction::DefineProc { .. } => {
+                "defines the procedure numbered by the current cell as what follows".to_owned()
+            }
+            Instruction::EndProc => "ends the procedure".to_owned(),
+            Instruction::CallProc => "calls the procedure numbered by the current cell".to_owned(),
+        };
+
+        clauses.push(clause);
+        index += 1;
+    }
+
+    clauses.join(", then ")
+}
+
+// A cell relative to the tape head.
+fn cell(offset: isize) -> String {
+    match offset {
+        0 => "the current cell".to_owned(),
+        1 => "the next cell".to_owned(),
+        -1 => "the previous cell".to_owned(),
+        2.. => format!("the cell {} to the right", offset),
+        _ => format!("the cell {} to the left", offset.unsigned_abs()),
+    }
+}
+
+// A distance along the tape, such as "3 cells left".
+fn cells(amount: isize) -> String {
+    let direction = if amount > 0 { "right" } else { "left" };
+
+    match amount.unsigned_abs() {
+        1 => format!("1 cell {}", direction),
+        distance => format!("{} cells {}", distance, direction),
+    }
+}
+
+fn add(amount: i8, offset: isize) -> String {
+    if amount < 0 {
+        format!("subtracts {} from {}", amount.unsigned_abs(), cell(offset))
+    } else {
+        format!("adds {} to {}", amount, cell(offset))
+    }
+}
+
+fn scan(direction: &str, increment: i8, stride: usize) -> String {
+    let mut clause = match stride {
+        1 => format!("scans {} to the next zero cell", direction),
+        _ => format!(
+            "scans {} {} cells at a time to the next zero cell",
+            direction, stride
+        ),
+    };
+
+    if increment > 0 {
+        clause.push_str(&format!(", adding {} to each cell it leaves", increment));
+    } else if increment < 0 {
+        let amount = increment.unsigned_abs();
+        clause.push_str(&format!(
+            ", subtracting {} from each cell it leaves",
+            amount
+        ));
+    }
+
+    clause
+}
+
+// A loop the optimizer recognized as adding multiples of the current cell to others. Ending
+// with the current cell zeroed, which is how such loops end, makes it a move, and copying a
+// cell to several others is the usual reason for one.
+fn multiply(targets: &[(isize, i8)], store: Option<i8>) -> String {
+    let list = |targets: &[(isize, i8)]| {
+        let names = targets
+            .iter()
+            .map(|(offset, _)| cell(*offset))
+            .collect::<Vec<_>>();
+
+        match names.split_last() {
+            Some((last, [])) => last.clone(),
+            Some((last, rest)) => format!("{} and {}", rest.join(", "), last),
+            None => String::new(),
+        }
+    };
+
+    let copies = targets.iter().all(|(_, factor)| *factor == 1);
+
+    if copies && store == Some(0) {
+        return format!("moves the current cell into {}", list(targets));
+    }
+
+    let mut clause = if copies {
+        format!("adds the current cell to {}", list(targets))
+    } else if targets.iter().all(|(_, factor)| *factor == -1) {
+        format!("subtracts the current cell from {}", list(targets))
+    } else {
+        targets
+            .iter()
+            .map(|(offset, factor)| match factor {
+                1 => format!("adds the current cell to {}", cell(*offset)),
+                -1 => format!("subtracts the current cell from {}", cell(*offset)),
+                2.. => format!(
+                    "adds {} times the current cell to {}",
+                    factor,
+                    cell(*offset)
+                ),
+                _ => format!(
+                    "subtracts {} times the current cell from {}",
+                    factor.unsigned_abs(),
+                    cell(*offset)
+                ),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    match store {
+        Some(0) => clause.push_str(", then zeroes it"),
+        Some(value) => clause.push_str(&format!(", then sets it to {}", value)),
+        None => {}
+    }
+
+    clause
+}
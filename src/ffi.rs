@@ -0,0 +1,120 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+// A C interface to parsing, optimizing, and interpreting programs, declared for C in
+// include/membrane.h, which spells out what each function expects of its pointers. It's
+// built into a shared library, target/release/libmembrane.so or its platform's equivalent,
+// with:
+//
+//     cargo build --release --lib --features ffi
+//
+// Programs are handed out as opaque pointers, which are freed with `membrane_free`.
+#![allow(clippy::missing_safety_doc)]
+
+use std::ffi::c_void;
+use std::os::raw::c_int;
+use std::ptr;
+use std::slice;
+
+use crate::interpreter::{Interpreter, RuntimeError, TapeSize};
+use crate::optimizer::{self, OptimizeOptions};
+use crate::parser::{self, ParseOptions};
+use crate::program::Program;
+
+// What each function returns, other than `membrane_parse`.
+pub const MEMBRANE_OK: c_int = 0;
+pub const MEMBRANE_NULL_PROGRAM: c_int = -1;
+pub const MEMBRANE_OPTIMIZE_FAILED: c_int = -2;
+pub const MEMBRANE_MOVED_OFF_TAPE: c_int = 1;
+pub const MEMBRANE_END_OF_INPUT: c_int = 2;
+pub const MEMBRANE_UNDEFINED_PROCEDURE: c_int = 3;
+pub const MEMBRANE_RUNTIME_FAILED: c_int = 4;
+
+// Returns the next byte of input, or any negative number once there's no more.
+pub type MembraneRead = unsafe extern "C" fn(context: *mut c_void) -> c_int;
+pub type MembraneWrite = unsafe extern "C" fn(context: *mut c_void, byte: u8);
+
+// Parses `length` bytes of source, returning null if the brackets don't match.
+#[no_mangle]
+pub unsafe extern "C" fn membrane_parse(source: *const u8, length: usize) -> *mut Program {
+    let bytes = match length {
+        0 => &[],
+        _ if source.is_null() => return ptr::null_mut(),
+        _ => slice::from_raw_parts(source, length),
+    };
+
+    match parser::parse_bytes_with(bytes, &ParseOptions::default()) {
+        Ok(program) => Box::into_raw(Box::new(program)),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+// Stopping early without reaching a fixpoint still leaves a program that runs, so only
+// the optimizer breaking the program fails.
+#[no_mangle]
+pub unsafe extern "C" fn membrane_optimize(program: *mut Program) -> c_int {
+    let program = match program.as_mut() {
+        Some(program) => program,
+        None => return MEMBRANE_NULL_PROGRAM,
+    };
+
+    match optimizer::optimize_program(program, &OptimizeOptions::default()) {
+        Err(err) if err.is_fatal() => MEMBRANE_OPTIMIZE_FAILED,
+        _ => MEMBRANE_OK,
+    }
+}
+
+// Runs the program on a tape of `tape_size` cells, or an infinite one if it's zero. Each
+// callback is passed `context`. Without `read`, the program has no input, and without
+// `write`, its output is thrown away.
+#[no_mangle]
+pub unsafe extern "C" fn membrane_run(
+    program: *const Program,
+    tape_size: usize,
+    read: Option<MembraneRead>,
+    write: Option<MembraneWrite>,
+    context: *mut c_void,
+) -> c_int {
+    let program = match program.as_ref() {
+        Some(program) => program,
+        None => return MEMBRANE_NULL_PROGRAM,
+    };
+
+    let tape_size = match tape_size {
+        0 => TapeSize::Infinite,
+        size => TapeSize::Finite(size),
+    };
+
+    let result = Interpreter::builder().tape(tape_size).build().run_with(
+        &program.instructions,
+        || {
+            let byte = read.map_or(-1, |read| read(context));
+            u8::try_from(byte).ok()
+        },
+        |byte| {
+            if let Some(write) = write {
+                write(context, byte);
+            }
+        },
+    );
+
+    match result {
+        Ok(_) => MEMBRANE_OK,
+        Err(RuntimeError::MovedOffTape { .. }) => MEMBRANE_MOVED_OFF_TAPE,
+        Err(RuntimeError::EndOfInput { .. }) => MEMBRANE_END_OF_INPUT,
+        Err(RuntimeError::UndefinedProcedure { .. }) => MEMBRANE_UNDEFINED_PROCEDURE,
+        // Anything the interpreter learns to stop for later, which C callers can't tell
+        // apart yet.
+        Err(_) => MEMBRANE_RUNTIME_FAILED,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn membrane_free(program: *mut Program) {
+    if !program.is_null() {
+        drop(Box::from_raw(program));
+    }
+}
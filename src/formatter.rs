@@ -0,0 +1,283 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::ops::Range;
+
+use crate::instruction::Instruction;
+use crate::parser::{self, ParseError, ParseOptions};
+use crate::program::{Program, Trivia};
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct FormatOptions {
+    // The widest a line of code gets, in characters, unless it's indented too deeply for
+    // even one command to fit. Comments are never wrapped.
+    pub width: usize,
+    // The spaces added for each loop or procedure a line is nested in.
+    pub indent: usize,
+    pub strip_comments: bool,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self {
+            width: 80,
+            indent: 4,
+            strip_comments: false,
+        }
+    }
+}
+
+// Reflows source, filling lines with commands up to the width. Loops that fit on a line
+// stay on one, while the rest are split into a block, with their body indented between
+// the lines of their brackets. Comments keep their place in the program, either after the
+// code on their line or on lines of their own, and blank lines are kept, though runs of
+// them are collapsed into one. Formatting what the formatter wrote gives back the same.
+pub fn format(
+    source: &[u8],
+    parse_options: &ParseOptions,
+    options: &FormatOptions,
+) -> Result<Vec<u8>, Vec<ParseError>> {
+    let program = parser::parse_bytes_with(
+        source,
+        &ParseOptions {
+            trivia: true,
+            ..parse_options.clone()
+        },
+    )?;
+
+    let trivia = program
+        .trivia
+        .as_ref()
+        .expect("the parser was asked for trivia");
+    let mut formatter = Formatter {
+        source,
+        program: &program,
+        trivia,
+        options,
+        // Dialects' tokens can be words, which would run together without a space between.
+        separator: if parse_options.dialect.is_some() {
+            b" "
+        } else {
+            b""
+        },
+        output: Vec::new(),
+        line_width: 0,
+        line_empty: true,
+        line_ended: false,
+    };
+
+    formatter.block(0..program.instructions.len(), 0);
+    formatter.trivia(trivia.trailing(), 0);
+    formatter.end_line();
+
+    let mut output = formatter.output;
+    while output.ends_with(b"\n\n") {
+        output.pop();
+    }
+
+    if let Some(input) = &program.input {
+        output.push(b'!');
+        output.extend_from_slice(input);
+    }
+
+    Ok(output)
+}
+
+struct Formatter<'a> {
+    source: &'a [u8],
+    program: &'a Program,
+    trivia: &'a Trivia,
+    options: &'a FormatOptions,
+    separator: &'static [u8],
+    output: Vec<u8>,
+    line_width: usize,
+    // Whether nothing but indentation has been written to the current line.
+    line_empty: bool,
+    // Whether the next command starts a new line, such as after the bracket of a block.
+    // The line is left open until then, so a comment after the bracket stays on its line.
+    line_ended: bool,
+}
+
+impl<'a> Formatter<'a> {
+    fn block(&mut self, instructions: Range<usize>, depth: usize) {
+        let mut index = instructions.start;
+
+        while index < instructions.end {
+            self.trivia(self.trivia.leading(index), depth);
+
+            let end = match self.program.instructions[index] {
+                Instruction::JumpIfZero { location } | Instruction::DefineProc { location } => {
+                    location
+                }
+                _ => {
+                    self.instruction(index, depth);
+                    index += 1;
+                    continue;
+                }
+            };
+
+            if self.fits_inline(index, end, depth) {
+                self.wrap(self.inline_width(index, end));
+
+                for index in index..=end {
+                    self.instruction(index, depth);
+                }
+            } else {
+                self.instruction(index, depth);
+                self.line_ended = true;
+
+                self.block(index + 1..end, depth + 1);
+                self.trivia(self.trivia.leading(end), depth + 1);
+
+                self.line_ended = true;
+                self.instruction(end, depth);
+                self.line_ended = true;
+            }
+
+            index = end + 1;
+        }
+    }
+
+    // Whether the loop or procedure fits on a line of its own and has nothing between its
+    // commands that needs a line of its own.
+    fn fits_inline(&self, start: usize, end: usize, depth: usize) -> bool {
+        let notes = (start + 1..=end).any(|index| self.has_notes(self.trivia.leading(index)));
+
+        !notes && depth * self.options.indent + self.inline_width(start, end) <= self.options.width
+    }
+
+    fn inline_width(&self, start: usize, end: usize) -> usize {
+        let commands = (start..=end)
+            .map(|index| width(self.text(index)))
+            .sum::<usize>();
+
+        commands + (end - start) * width(self.separator)
+    }
+
+    // Writes the commands of the instruction, which are split across lines if need be
+    // unless they're a dialect's tokens.
+    fn instruction(&mut self, index: usize, depth: usize) {
+        let text = self.text(index);
+
+        if !self.separator.is_empty() {
+            self.unit(text, depth);
+            return;
+        }
+
+        for command in text.chunks(1) {
+            self.unit(command, depth);
+        }
+    }
+
+    fn text(&self, index: usize) -> &'a [u8] {
+        &self.source[self.program.spans[index].range()]
+    }
+
+    fn unit(&mut self, text: &[u8], depth: usize) {
+        self.wrap(width(text));
+
+        if self.line_empty {
+            self.indent(depth);
+        } else {
+            self.write(self.separator);
+        }
+
+        self.write(text);
+    }
+
+    // Moves to the next line if the current one has ended, or has no room left for
+    // something of the width.
+    fn wrap(&mut self, unit_width: usize) {
+        let width = self.line_width + width(self.separator) + unit_width;
+
+        if self.line_ended || (!self.line_empty && width > self.options.width) {
+            self.end_line();
+        }
+    }
+
+    fn write(&mut self, text: &[u8]) {
+        self.output.extend_from_slice(text);
+        self.line_width += width(text);
+        self.line_empty = false;
+    }
+
+    fn indent(&mut self, depth: usize) {
+        let indent = depth * self.options.indent;
+        self.output.resize(self.output.len() + indent, b' ');
+        self.line_width = indent;
+    }
+
+    fn end_line(&mut self) {
+        if !self.line_empty {
+            self.output.push(b'\n');
+            self.line_width = 0;
+            self.line_empty = true;
+        }
+
+        self.line_ended = false;
+    }
+
+    // Comments and blank lines in the trivia, which each line of it has at most one of.
+    // The first line goes on with the code before it, and the last line is the start of
+    // the code after it, so only the lines between can be blank.
+    fn has_notes(&self, trivia: &[u8]) -> bool {
+        let lines = trivia.split(|&byte| byte == b'\n').collect::<Vec<_>>();
+
+        lines.iter().enumerate().any(|(index, line)| {
+            let comment = line.trim_ascii();
+
+            if comment.is_empty() {
+                index > 0 && index + 1 < lines.len()
+            } else {
+                !self.options.strip_comments
+            }
+        })
+    }
+
+    fn trivia(&mut self, trivia: &[u8], depth: usize) {
+        let lines = trivia.split(|&byte| byte == b'\n').collect::<Vec<_>>();
+
+        for (index, line) in lines.iter().enumerate() {
+            let comment = line.trim_ascii();
+
+            if comment.is_empty() {
+                if index > 0 && index + 1 < lines.len() {
+                    self.blank_line();
+                }
+
+                continue;
+            }
+
+            if self.options.strip_comments {
+                continue;
+            }
+
+            // A comment that follows code on the same line stays there.
+            if index > 0 || self.line_empty {
+                self.end_line();
+                self.indent(depth);
+            } else {
+                self.write(b" ");
+            }
+
+            self.write(comment);
+            self.line_ended = true;
+        }
+    }
+
+    fn blank_line(&mut self) {
+        self.end_line();
+
+        if !self.output.is_empty() && !self.output.ends_with(b"\n\n") {
+            self.output.push(b'\n');
+        }
+    }
+}
+
+// The width of UTF-8 in characters, counting the bytes that start one.
+fn width(text: &[u8]) -> usize {
+    text.iter().filter(|&&byte| byte & 0xc0 != 0x80).count()
+}
@@ -0,0 +1,218 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::io::Read;
+use std::mem;
+
+use crate::dialect::Dialect;
+use crate::lister;
+use crate::parser::{self, ParseError, ParseOptions};
+use crate::program::Program;
+
+// Reads programs written in some language into instructions, so every dialect is selected
+// and parsed the same way.
+pub trait Frontend: Send + Sync {
+    // The name the dialect is selected by, such as "ook".
+    fn name(&self) -> &str;
+
+    // The extensions of files in this dialect, without the leading dot.
+    fn file_extensions(&self) -> &[&str] {
+        &[]
+    }
+
+    // The options of frontends that read source with membrane's own parser, which the cache
+    // needs to know that it parses programs the same way.
+    fn options(&self) -> Option<&ParseOptions> {
+        None
+    }
+
+    fn parse(&self, source: &[u8]) -> Result<Program, Vec<ParseError>>;
+
+    // Parses source as it's read, which frontends that can't parse it a piece at a time do
+    // by reading all of it first.
+    fn parse_reader(&self, reader: &mut dyn Read) -> Result<Program, Vec<ParseError>> {
+        let mut source = Vec::new();
+        reader
+            .read_to_end(&mut source)
+            .map_err(|err| vec![err.into()])?;
+
+        self.parse(&source)
+    }
+}
+
+// Membrane's parser, reading Brainfuck or one of its dialects with the options.
+#[derive(Clone, Debug)]
+pub struct ParserFrontend {
+    name: String,
+    extensions: &'static [&'static str],
+    options: ParseOptions,
+}
+
+impl ParserFrontend {
+    pub fn new(name: impl Into<String>, options: ParseOptions) -> Self {
+        Self {
+            name: name.into(),
+            extensions: &[],
+            options,
+        }
+    }
+
+    // Reads one of the dialects membrane knows, with the options for everything else.
+    pub fn for_dialect(dialect: Dialect, options: &ParseOptions) -> Self {
+        Self {
+            name: dialect.name().to_owned(),
+            extensions: dialect.extensions(),
+            options: ParseOptions {
+                dialect: dialect.map(),
+                procedures: dialect.has_procedures(),
+                ..options.clone()
+            },
+        }
+    }
+
+    fn named(&self, mut program: Program) -> Program {
+        program.dialect = Some(self.name.clone());
+        program
+    }
+}
+
+impl Frontend for ParserFrontend {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn file_extensions(&self) -> &[&str] {
+        self.extensions
+    }
+
+    fn options(&self) -> Option<&ParseOptions> {
+        Some(&self.options)
+    }
+
+    fn parse(&self, source: &[u8]) -> Result<Program, Vec<ParseError>> {
+        parser::parse_bytes_with(source, &self.options).map(|program| self.named(program))
+    }
+
+    fn parse_reader(&self, reader: &mut dyn Read) -> Result<Program, Vec<ParseError>> {
+        parser::parse_reader_with(reader, &self.options).map(|program| self.named(program))
+    }
+}
+
+// Reads text listings, so that programs can be edited as instructions and run again.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct ListingFrontend;
+
+impl Frontend for ListingFrontend {
+    fn name(&self) -> &str {
+        "listing"
+    }
+
+    fn file_extensions(&self) -> &[&str] {
+        &["lst"]
+    }
+
+    fn parse(&self, source: &[u8]) -> Result<Program, Vec<ParseError>> {
+        let text = String::from_utf8_lossy(source);
+
+        match lister::parse_listing(&text) {
+            Ok(mut program) => {
+                program.dialect = Some(self.name().to_owned());
+                Ok(program)
+            }
+            Err(err) => Err(vec![ParseError::Malformed {
+                location: err.location(),
+                message: err.message(),
+            }]),
+        }
+    }
+}
+
+// The frontends that can be selected by name, in the order they were registered.
+pub struct Registry {
+    frontends: Vec<Box<dyn Frontend>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self {
+            frontends: Vec::new(),
+        }
+    }
+
+    // A registry holding every dialect that ships with membrane, read with default options.
+    pub fn builtin() -> Self {
+        Self::builtin_with(&ParseOptions::default())
+    }
+
+    // A registry holding every dialect that ships with membrane, read with the options. The
+    // dialects' own options, such as their tokens, take the place of those given.
+    pub fn builtin_with(options: &ParseOptions) -> Self {
+        let mut registry = Self::new();
+
+        for &dialect in Dialect::ALL {
+            registry.register(Box::new(ParserFrontend::for_dialect(dialect, options)));
+        }
+
+        registry.register(Box::new(ListingFrontend));
+
+        registry
+    }
+
+    // Adds a frontend, returning the one it replaces if another frontend already had its
+    // name.
+    pub fn register(&mut self, frontend: Box<dyn Frontend>) -> Option<Box<dyn Frontend>> {
+        match self
+            .frontends
+            .iter_mut()
+            .find(|existing| existing.name() == frontend.name())
+        {
+            Some(existing) => Some(mem::replace(existing, frontend)),
+            None => {
+                self.frontends.push(frontend);
+                None
+            }
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Frontend> {
+        self.iter().find(|frontend| frontend.name() == name)
+    }
+
+    // Takes the frontend out of the registry, for callers that only need the one.
+    pub fn remove(&mut self, name: &str) -> Option<Box<dyn Frontend>> {
+        let index = self
+            .frontends
+            .iter()
+            .position(|frontend| frontend.name() == name)?;
+
+        Some(self.frontends.remove(index))
+    }
+
+    // The frontend that reads files with the extension. If several do, the first one
+    // registered wins.
+    pub fn for_extension(&self, extension: &str) -> Option<&dyn Frontend> {
+        self.iter().find(|frontend| {
+            frontend
+                .file_extensions()
+                .iter()
+                .any(|known| known.eq_ignore_ascii_case(extension))
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn Frontend> {
+        self.frontends.iter().map(|frontend| frontend.as_ref())
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.iter().map(|frontend| frontend.name()).collect()
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
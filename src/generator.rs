@@ -0,0 +1,107 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use crate::instruction::Instruction;
+use crate::lowering;
+
+// The loop counter used unless the size is optimized, which suits text in the printable
+// range of ASCII.
+const DEFAULT_COUNTER: u8 = 10;
+
+// Builds a program that writes the text. A loop first fills a few cells with multiples of
+// a counter near the bytes of the text, and each byte is then written from whichever cell
+// is cheapest to reach and adjust. With `optimize_size`, every counter and way of grouping
+// the bytes into cells is tried, and the one that lowers to the least Brainfuck is kept.
+pub fn text(text: &[u8], optimize_size: bool) -> Vec<Instruction> {
+    if !optimize_size {
+        return write_text(text, DEFAULT_COUNTER, 1);
+    }
+
+    // A counter of zero leaves out the loop, for text too short to be worth one.
+    let mut best = write_text(text, 0, 0);
+    let mut best_length = lowering::to_brainfuck(&best).len();
+
+    for counter in 2..=20 {
+        for tolerance in 0..=4 {
+            let candidate = write_text(text, counter, tolerance);
+            let length = lowering::to_brainfuck(&candidate).len();
+
+            if length < best_length {
+                best = candidate;
+                best_length = length;
+            }
+        }
+    }
+
+    best
+}
+
+// Bytes are grouped into the same cell when the multiples of the counter nearest to them are
+// within the tolerance of each other.
+fn write_text(text: &[u8], counter: u8, tolerance: u8) -> Vec<Instruction> {
+    let mut factors: Vec<u8> = Vec::new();
+
+    if counter > 0 {
+        for &byte in text {
+            let factor = ((byte as u16 + counter as u16 / 2) / counter as u16).min(127) as u8;
+
+            if factor > 0 && !factors.iter().any(|&f| f.abs_diff(factor) <= tolerance) {
+                factors.push(factor);
+            }
+        }
+    }
+
+    let mut instructions = Vec::new();
+
+    // Cell zero holds the counter, and is left at zero once the loop is done.
+    let mut cells = vec![0u8; factors.len() + 1];
+
+    if !factors.is_empty() {
+        instructions.push(Instruction::Add(counter as i8));
+        instructions.push(Instruction::JumpIfZero { location: 0 });
+
+        for (cell, &factor) in factors.iter().enumerate() {
+            instructions.push(Instruction::Move(1));
+            instructions.push(Instruction::Add(factor as i8));
+            cells[cell + 1] = factor.wrapping_mul(counter);
+        }
+
+        instructions.push(Instruction::Move(-(factors.len() as isize)));
+        instructions.push(Instruction::Add(-1));
+
+        let end = instructions.len();
+        instructions[1] = Instruction::JumpIfZero { location: end };
+        instructions.push(Instruction::JumpIfNotZero { location: 1 });
+    }
+
+    let mut head: usize = 0;
+
+    for &byte in text {
+        let cost = |cell: usize| {
+            head.abs_diff(cell) + (byte.wrapping_sub(cells[cell]) as i8).unsigned_abs() as usize
+        };
+
+        let cell = (0..cells.len()).min_by_key(|&cell| cost(cell)).unwrap();
+        let amount = byte.wrapping_sub(cells[cell]) as i8;
+
+        if cell != head {
+            instructions.push(Instruction::Move(cell as isize - head as isize));
+            head = cell;
+        }
+
+        if amount != 0 {
+            instructions.push(Instruction::Add(amount));
+            cells[cell] = byte;
+        }
+
+        match instructions.last_mut() {
+            Some(Instruction::Write(count)) => *count += 1,
+            _ => instructions.push(Instruction::Write(1)),
+        }
+    }
+
+    instructions
+}
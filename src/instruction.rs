@@ -4,10 +4,17 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use std::fmt;
-use std::fmt::Formatter;
+use alloc::borrow::ToOwned;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::fmt::Formatter;
+use core::str::FromStr;
 
-#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+use thiserror::Error;
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Instruction {
     Add(i8),
     Move(isize),
@@ -21,22 +28,74 @@ pub enum Instruction {
     AddRelative { offset: isize, amount: i8 },
     AddVector { vector: [i8; 4] },
 
+    // Adds the current cell times `factor` to the cell at `offset`. Only ever emitted as a
+    // run followed by a store to the current cell, which is what lets it be lowered back into
+    // a loop. When the current cell is zero it does nothing at all, not even touch the cell
+    // at `offset`, since the loop it came from wouldn't have run.
+    MulAdd { offset: isize, factor: i8 },
+
     MoveRightToZero { increment: i8, stride: usize },
     MoveLeftToZero { increment: i8, stride: usize },
+
+    // Procedures, from pbrain. Defining one makes the instructions up to its EndProc at
+    // `location` the procedure numbered by the current cell, and skips over them. CallProc
+    // runs the procedure numbered by the current cell, returning at its EndProc.
+    DefineProc { location: usize },
+    EndProc,
+    CallProc,
 }
 
 impl Instruction {
+    #[inline]
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Add(_) => "Add",
+            Self::Move(_) => "Move",
+            Self::Write(_) => "Write",
+            Self::Read(_) => "Read",
+            Self::JumpIfZero { .. } => "JumpIfZero",
+            Self::JumpIfNotZero { .. } => "JumpIfNotZero",
+
+            Self::SetValue(_) => "SetValue",
+            Self::AddRelative { .. } => "AddRelative",
+            Self::AddVector { .. } => "AddVector",
+            Self::MulAdd { .. } => "MulAdd",
+            Self::MoveRightToZero { .. } => "MoveRightToZero",
+            Self::MoveLeftToZero { .. } => "MoveLeftToZero",
+
+            Self::DefineProc { .. } => "DefineProc",
+            Self::EndProc => "EndProc",
+            Self::CallProc => "CallProc",
+        }
+    }
+
+    // Jumps count as moving the tape head, since the code they lead to may run with the head
+    // anywhere, and so do procedures.
     #[inline]
     pub const fn preserves_tape_head(&self) -> bool {
         !matches!(
             self,
-            Self::Move(_) | Self::MoveRightToZero { .. } | Self::MoveLeftToZero { .. }
+            Self::Move(_)
+                | Self::JumpIfZero { .. }
+                | Self::JumpIfNotZero { .. }
+                | Self::MoveRightToZero { .. }
+                | Self::MoveLeftToZero { .. }
+                | Self::DefineProc { .. }
+                | Self::EndProc
+                | Self::CallProc
         )
     }
 
+    // Whether an add to the cell at `offset` from the tape head can be moved past this
+    // instruction. I/O and stores only ever touch the current cell. Nothing is moved past a
+    // multiplication, so that its run stays next to the store that ends it.
     #[inline]
-    pub const fn is_add_friendly(&self) -> bool {
-        !matches!(self, Self::Read(_) | Self::SetValue(_))
+    pub const fn is_add_friendly(&self, offset: isize) -> bool {
+        match self {
+            Self::Write(_) | Self::Read(_) | Self::SetValue(_) => offset != 0,
+            Self::MulAdd { .. } => false,
+            _ => self.preserves_tape_head(),
+        }
     }
 }
 
@@ -63,12 +122,118 @@ impl fmt::Display for Instruction {
             Self::AddVector { vector } => {
                 write!(f, "{:16}{:?}", "AddVector", vector)
             }
+            Self::MulAdd { offset, factor } => {
+                write!(f, "{:16}{:+}*{:+}", "MulAdd", offset, factor)
+            }
             Self::MoveRightToZero { increment, stride } => {
-                write!(f, "{:16}{:+}>{}", "MoveToZero", increment, stride)
+                write!(f, "{:16}{:+}>{}", "MoveRightToZero", increment, stride)
             }
             Self::MoveLeftToZero { increment, stride } => {
-                write!(f, "{:16}{:+}<{}", "MoveToZero", increment, stride)
+                write!(f, "{:16}{:+}<{}", "MoveLeftToZero", increment, stride)
             }
+
+            Self::DefineProc { location } => write!(f, "{:16}({}", "DefineProc", location),
+            Self::EndProc => write!(f, "{:16})", "EndProc"),
+            Self::CallProc => write!(f, "{:16}:", "CallProc"),
         }
     }
 }
+
+#[derive(Clone, Eq, PartialEq, Debug, Error)]
+pub enum ParseInstructionError {
+    #[error("expected an instruction")]
+    Empty,
+    #[error("unknown instruction '{0}'")]
+    UnknownMnemonic(String),
+    #[error("invalid operand '{0}'")]
+    InvalidOperand(String),
+}
+
+// Reads an instruction the way it's displayed, its name followed by its operands, such as
+// `MulAdd +1*-2` or `JumpIfZero [12`. Any amount of whitespace can separate the two.
+impl FromStr for Instruction {
+    type Err = ParseInstructionError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let text = text.trim();
+        let (mnemonic, operand) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        let operand = operand.trim();
+
+        let invalid = || ParseInstructionError::InvalidOperand(operand.to_owned());
+
+        let instruction = match mnemonic {
+            "" => return Err(ParseInstructionError::Empty),
+            "Add" => Self::Add(operand.parse().map_err(|_| invalid())?),
+            "Move" => match (count(operand, '>'), count(operand, '<')) {
+                (Some(distance), _) => Self::Move(distance as isize),
+                (_, Some(distance)) => Self::Move(-(distance as isize)),
+                _ => return Err(invalid()),
+            },
+            "Write" => Self::Write(count(operand, '.').ok_or_else(invalid)?),
+            "Read" => Self::Read(count(operand, ',').ok_or_else(invalid)?),
+            "JumpIfZero" => Self::JumpIfZero {
+                location: count(operand, '[').ok_or_else(invalid)?,
+            },
+            "JumpIfNotZero" => Self::JumpIfNotZero {
+                location: count(operand, ']').ok_or_else(invalid)?,
+            },
+
+            "SetValue" => Self::SetValue(operand.parse().map_err(|_| invalid())?),
+            "AddRelative" => {
+                let (offset, amount) = pair(operand, '~').ok_or_else(invalid)?;
+                Self::AddRelative { offset, amount }
+            }
+            "AddVector" => {
+                let lanes = operand
+                    .strip_prefix('[')
+                    .and_then(|lanes| lanes.strip_suffix(']'))
+                    .ok_or_else(invalid)?
+                    .split(',')
+                    .map(|lane| lane.trim().parse::<i8>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|_| invalid())?;
+
+                Self::AddVector {
+                    vector: lanes.try_into().map_err(|_| invalid())?,
+                }
+            }
+            "MulAdd" => {
+                let (offset, factor) = pair(operand, '*').ok_or_else(invalid)?;
+                Self::MulAdd { offset, factor }
+            }
+            "MoveRightToZero" => {
+                let (increment, stride) = pair(operand, '>').ok_or_else(invalid)?;
+                Self::MoveRightToZero { increment, stride }
+            }
+            "MoveLeftToZero" => {
+                let (increment, stride) = pair(operand, '<').ok_or_else(invalid)?;
+                Self::MoveLeftToZero { increment, stride }
+            }
+
+            "DefineProc" => Self::DefineProc {
+                location: count(operand, '(').ok_or_else(invalid)?,
+            },
+            "EndProc" if operand == ")" => Self::EndProc,
+            "CallProc" if operand == ":" => Self::CallProc,
+            "EndProc" | "CallProc" => return Err(invalid()),
+            _ => return Err(ParseInstructionError::UnknownMnemonic(mnemonic.to_owned())),
+        };
+
+        Ok(instruction)
+    }
+}
+
+// A number after the command it's written with, such as `.3`.
+fn count(operand: &str, command: char) -> Option<usize> {
+    operand
+        .strip_prefix(command)
+        .filter(|count| count.starts_with(|digit: char| digit.is_ascii_digit()))?
+        .parse()
+        .ok()
+}
+
+// Two numbers either side of a separator, such as `+1*-2`.
+fn pair<A: FromStr, B: FromStr>(operand: &str, separator: char) -> Option<(A, B)> {
+    let (first, second) = operand.split_once(separator)?;
+    Some((first.parse().ok()?, second.parse().ok()?))
+}
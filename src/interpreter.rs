@@ -4,77 +4,302 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use std::fs::File;
-use std::io::{BufReader, BufWriter, Cursor, Read, Stdin, Stdout, Write};
-use std::iter;
+use alloc::borrow::ToOwned;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::fmt::Formatter;
+use core::str::FromStr;
+#[cfg(feature = "std")]
+use std::io::{self, ErrorKind, Read, Write};
+
+use thiserror::Error;
 
 use crate::instruction::Instruction;
+#[cfg(feature = "std")]
+pub use crate::streams::{pipe, InputSource, OutputSource, PipeReader, PipeWriter};
 
 const VECTOR_SIZE: usize = 4;
 const STANDARD_TAPE_SIZE: usize = 30_000;
 const DEFAULT_INPUT_BUFFER_SIZE: usize = 8;
+const MAX_IO_CHUNK_SIZE: usize = 4096;
 
-#[derive(Copy, Clone, Eq, PartialEq)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TapeSize {
     Finite(usize),
     Infinite,
 }
 
-pub enum InputSource {
-    Stdin(Stdin),
-    StdinBuffer(BufReader<Stdin>),
-    File(Cursor<Vec<u8>>),
-    FileBuffer(BufReader<File>),
+// The suffixes a tape size can be given in, along with how many cells each one is.
+const SIZE_SUFFIXES: &[(&str, usize)] = &[
+    ("k", 1_000),
+    ("M", 1_000_000),
+    ("G", 1_000_000_000),
+    ("Ki", 1 << 10),
+    ("Mi", 1 << 20),
+    ("Gi", 1 << 30),
+];
+
+// Reads a number of cells the way people write them, as a whole number with an optional
+// decimal or binary suffix, such as 30000, 30k, or 64Ki. A K is taken to be a k.
+pub fn parse_tape_size(text: &str) -> Result<usize, TapeSizeError> {
+    let text = text.trim();
+    let digits = text
+        .find(|character: char| !character.is_ascii_digit() && character != '_')
+        .unwrap_or(text.len());
+    let (number, suffix) = text.split_at(digits);
+    let number = number.replace('_', "");
+
+    if number.is_empty() || suffix.starts_with('.') {
+        return Err(TapeSizeError::NotANumber);
+    }
+
+    let multiplier = match suffix {
+        "" => 1,
+        "K" => 1_000,
+        _ => SIZE_SUFFIXES
+            .iter()
+            .find(|(name, _)| *name == suffix)
+            .map(|&(_, multiplier)| multiplier)
+            .ok_or_else(|| TapeSizeError::UnknownSuffix {
+                suffix: suffix.to_owned(),
+            })?,
+    };
+
+    number
+        .parse::<usize>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .ok_or(TapeSizeError::TooLarge)
 }
 
-impl Read for InputSource {
-    #[inline]
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+#[derive(Clone, Eq, PartialEq, Debug, Error)]
+pub enum TapeSizeError {
+    #[error("expected a whole number of cells, such as 30000 or 30k")]
+    NotANumber,
+    #[error("unknown suffix '{suffix}' (expected one of: {})", suffix_names())]
+    UnknownSuffix { suffix: String },
+    #[error("too large (the most cells there can be is {})", usize::MAX)]
+    TooLarge,
+}
+
+fn suffix_names() -> String {
+    SIZE_SUFFIXES
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// What a read stores in the current cell once input has run out.
+#[derive(Copy, Clone, Eq, PartialEq, Default, Debug)]
+pub enum EofMode {
+    #[default]
+    Unchanged,
+    Zero,
+    NegativeOne,
+}
+
+impl EofMode {
+    pub const ALL: &'static [Self] = &[Self::Unchanged, Self::Zero, Self::NegativeOne];
+
+    pub const fn name(&self) -> &'static str {
         match self {
-            Self::Stdin(stdin) => stdin.read(buf),
-            Self::StdinBuffer(reader) => reader.read(buf),
-            Self::File(cursor) => cursor.read(buf),
-            Self::FileBuffer(reader) => reader.read(buf),
+            Self::Unchanged => "unchanged",
+            Self::Zero => "zero",
+            Self::NegativeOne => "negative-one",
         }
     }
 }
 
-pub enum OutputSource {
-    Stdout(Stdout),
-    StdoutBuffer(BufWriter<Stdout>),
-    File(File),
-    FileBuffer(BufWriter<File>),
+// The size of every cell on the tape.
+#[derive(Copy, Clone, Eq, PartialEq, Default, Debug)]
+pub enum CellWidth {
+    #[default]
+    U8,
+    U16,
+    U32,
 }
 
-impl Write for OutputSource {
-    #[inline]
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+impl CellWidth {
+    pub const ALL: &'static [Self] = &[Self::U8, Self::U16, Self::U32];
+
+    pub const fn name(&self) -> &'static str {
         match self {
-            Self::Stdout(stdout) => stdout.write(buf),
-            Self::StdoutBuffer(writer) => writer.write(buf),
-            Self::File(file) => file.write(buf),
-            Self::FileBuffer(writer) => writer.write(buf),
+            Self::U8 => "8",
+            Self::U16 => "16",
+            Self::U32 => "32",
         }
     }
 
-    #[inline]
-    fn flush(&mut self) -> std::io::Result<()> {
+    pub const fn bits(&self) -> u32 {
         match self {
-            Self::Stdout(stdout) => stdout.flush(),
-            Self::StdoutBuffer(writer) => writer.flush(),
-            Self::File(file) => file.flush(),
-            Self::FileBuffer(writer) => writer.flush(),
+            Self::U8 => 8,
+            Self::U16 => 16,
+            Self::U32 => 32,
         }
     }
+
+    pub const fn bytes(&self) -> u8 {
+        (self.bits() / 8) as u8
+    }
+
+    // The largest value a cell holds, which is also what -1 wraps around to.
+    pub const fn max_value(&self) -> u32 {
+        u32::MAX >> (32 - self.bits())
+    }
+}
+
+// What happens when the head moves past either end of a finite tape.
+#[derive(Copy, Clone, Eq, PartialEq, Default, Debug)]
+pub enum WrapSemantics {
+    #[default]
+    Wrap,
+    Abort,
+}
+
+impl WrapSemantics {
+    pub const ALL: &'static [Self] = &[Self::Wrap, Self::Abort];
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Wrap => "wrap",
+            Self::Abort => "abort",
+        }
+    }
+}
+
+named_option!(EofMode);
+named_option!(CellWidth);
+named_option!(WrapSemantics);
+
+// What reading and writing fail with. Callbacks can't fail, so without std nothing can.
+#[cfg(feature = "std")]
+type IoError = io::Error;
+#[cfg(not(feature = "std"))]
+type IoError = core::convert::Infallible;
+
+// Why a program stopped before its end. Each error is at the index of the instruction
+// that ran into it, other than failing to flush the output once the program is done.
+#[derive(Debug, Error)]
+pub enum RuntimeError {
+    // The head moved left of the first cell of an infinite tape.
+    #[error("the head moved left of the first cell at instruction {index}")]
+    MovedOffTape { index: usize },
+    // The program read past the end of its input.
+    #[error("read past the end of the input at instruction {index}")]
+    EndOfInput { index: usize },
+    #[error("called procedure {cell} at instruction {index}, which was never defined")]
+    UndefinedProcedure { index: usize, cell: u32 },
+    // The program ran as many instructions as it was allowed to, and was about to run
+    // another.
+    #[error("stopped at instruction {index} after running {limit} instructions, the most allowed")]
+    StepLimit { index: usize, limit: usize },
+    #[error("failed to read at instruction {index}: {err}")]
+    Read { index: usize, err: IoError },
+    #[error("failed to write at instruction {index}: {err}")]
+    Write { index: usize, err: IoError },
+    #[error("failed to flush the output: {0}")]
+    Flush(IoError),
 }
 
-struct Memory {
+impl RuntimeError {
+    // Whether the program failed because reading or writing did, rather than because of
+    // something it did itself.
+    pub fn is_io(&self) -> bool {
+        matches!(
+            self,
+            Self::Read { .. } | Self::Write { .. } | Self::Flush(_)
+        )
+    }
+
+    // Whether writing failed because nothing reads the output anymore, such as when the
+    // program it's piped into has ended.
+    #[cfg(feature = "std")]
+    pub fn is_broken_pipe(&self) -> bool {
+        match self {
+            Self::Write { err, .. } | Self::Flush(err) => err.kind() == ErrorKind::BrokenPipe,
+            _ => false,
+        }
+    }
+
+    pub fn index(&self) -> Option<usize> {
+        match self {
+            Self::MovedOffTape { index }
+            | Self::EndOfInput { index }
+            | Self::UndefinedProcedure { index, .. }
+            | Self::StepLimit { index, .. }
+            | Self::Read { index, .. }
+            | Self::Write { index, .. } => Some(*index),
+            Self::Flush(_) => None,
+        }
+    }
+}
+
+// The types cells can be, which wrap around like the cells of compiled programs do. Amounts
+// and factors are sign-extended to the width of the cell.
+trait Cell: Copy + Default + Eq + Into<u32> {
+    const MAX: Self;
+
+    fn from_byte(byte: u8) -> Self;
+    fn add(self, amount: i8) -> Self;
+    fn add_cell(self, other: Self) -> Self;
+    fn mul(self, factor: i8) -> Self;
+    // Only the low byte of a cell is written.
+    fn low_byte(self) -> u8;
+
+    #[inline]
+    fn is_zero(self) -> bool {
+        self == Self::default()
+    }
+}
+
+macro_rules! cell {
+    ($cell:ty, $signed:ty) => {
+        impl Cell for $cell {
+            const MAX: Self = <$cell>::MAX;
+
+            #[inline]
+            fn from_byte(byte: u8) -> Self {
+                byte as Self
+            }
+
+            #[inline]
+            fn add(self, amount: i8) -> Self {
+                self.wrapping_add(amount as $signed as Self)
+            }
+
+            #[inline]
+            fn add_cell(self, other: Self) -> Self {
+                self.wrapping_add(other)
+            }
+
+            #[inline]
+            fn mul(self, factor: i8) -> Self {
+                self.wrapping_mul(factor as $signed as Self)
+            }
+
+            #[inline]
+            fn low_byte(self) -> u8 {
+                self as u8
+            }
+        }
+    };
+}
+
+cell!(u8, i8);
+cell!(u16, i16);
+cell!(u32, i32);
+
+struct Memory<C> {
     head: usize,
-    tape: Vec<u8>,
+    tape: Vec<C>,
     size: TapeSize,
 }
 
-impl Memory {
+impl<C: Cell> Memory<C> {
     fn new(size: TapeSize) -> Self {
         let length = if let TapeSize::Finite(tape_size) = size {
             tape_size
@@ -84,34 +309,42 @@ impl Memory {
 
         Self {
             head: 0,
-            tape: vec![0; length],
+            tape: vec![C::default(); length],
             size,
         }
     }
 
-    fn move_head(&mut self, amount: isize) -> Result<(), ()> {
+    // Returns the index of the cell `offset` away from the head. A finite tape wraps in
+    // both directions, while an infinite tape has nothing left of its first cell.
+    fn index_at(&self, offset: isize) -> Option<usize> {
         match self.size {
             TapeSize::Finite(tape_size) => {
-                self.head = ((self.head as isize + amount) % tape_size as isize) as usize;
-                Ok(())
-            }
-            TapeSize::Infinite => {
-                let new_head = self.head as isize + amount;
+                let distance = offset.unsigned_abs() % tape_size;
 
-                if new_head >= 0 {
-                    self.head = new_head as usize;
-                    Ok(())
+                if offset >= 0 {
+                    Some((self.head + distance) % tape_size)
                 } else {
-                    Err(())
+                    Some((self.head + (tape_size - distance)) % tape_size)
                 }
             }
+            TapeSize::Infinite => self.head.checked_add_signed(offset),
+        }
+    }
+
+    fn move_head(&mut self, amount: isize) -> Result<(), ()> {
+        match self.index_at(amount) {
+            Some(head) => {
+                self.head = head;
+                Ok(())
+            }
+            None => Err(()),
         }
     }
 
     fn move_head_right(&mut self, amount: usize) {
         match self.size {
             TapeSize::Finite(tape_size) => {
-                self.head = (self.head + amount) % tape_size;
+                self.head = (self.head + amount % tape_size) % tape_size;
             }
             TapeSize::Infinite => {
                 self.head += amount;
@@ -122,7 +355,7 @@ impl Memory {
     fn move_head_left(&mut self, amount: usize) -> Result<(), ()> {
         match self.size {
             TapeSize::Finite(tape_size) => {
-                self.head = ((self.head as isize + amount as isize) % tape_size as isize) as usize;
+                self.head = (self.head + (tape_size - amount % tape_size)) % tape_size;
                 Ok(())
             }
             TapeSize::Infinite => {
@@ -137,12 +370,12 @@ impl Memory {
     }
 
     #[inline]
-    fn current_cell_value(&self) -> u8 {
+    fn current_cell_value(&self) -> C {
         self.get_cell_value(self.head)
     }
 
     #[inline]
-    fn current_cell_mut(&mut self) -> &mut u8 {
+    fn current_cell_mut(&mut self) -> &mut C {
         self.get_cell_mut(self.head)
     }
 
@@ -157,7 +390,8 @@ impl Memory {
             }
             TapeSize::Infinite => {
                 if self.head + VECTOR_SIZE >= self.tape.len() {
-                    self.tape.extend(iter::repeat(0).take(VECTOR_SIZE));
+                    self.tape
+                        .resize(self.tape.len() + VECTOR_SIZE, C::default());
                 }
 
                 let head0 = self.head;
@@ -169,7 +403,7 @@ impl Memory {
         }
     }
 
-    fn get_cell_value(&self, index: usize) -> u8 {
+    fn get_cell_value(&self, index: usize) -> C {
         match self.size {
             TapeSize::Finite(tape_size) => {
                 let wrapped_index = index % tape_size;
@@ -179,7 +413,7 @@ impl Memory {
         }
     }
 
-    fn get_cell_mut(&mut self, index: usize) -> &mut u8 {
+    fn get_cell_mut(&mut self, index: usize) -> &mut C {
         match self.size {
             TapeSize::Finite(tape_size) => {
                 let wrapped_index = index % tape_size;
@@ -189,8 +423,7 @@ impl Memory {
                 let tape_size = self.tape.len();
 
                 if index >= tape_size {
-                    self.tape
-                        .extend(iter::repeat(0).take(index + 1 - tape_size));
+                    self.tape.resize(index + 1, C::default());
                 }
 
                 unsafe { self.tape.get_unchecked_mut(index) }
@@ -199,107 +432,455 @@ impl Memory {
     }
 }
 
+// The tape as a program left it when it ended.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct FinalState {
+    pub instructions_executed: usize,
+    pub head: usize,
+    // Every cell the program could have touched, whatever their width. The cells past these
+    // are all zero.
+    pub tape: Vec<u32>,
+}
+
+impl FinalState {
+    pub fn cell(&self, index: usize) -> u32 {
+        self.tape.get(index).copied().unwrap_or_default()
+    }
+
+    pub fn current_cell(&self) -> u32 {
+        self.cell(self.head)
+    }
+}
+
+// Runs programs with the options it was built with, which default to an infinite tape of
+// 8-bit cells, reading standard input and writing standard output:
+//
+//     let interpreter = Interpreter::builder()
+//         .tape(TapeSize::Finite(30_000))
+//         .cell_width(CellWidth::U16)
+//         .eof(EofMode::Zero)
+//         .input(input)
+//         .output(output)
+//         .build();
+//
+// Without std, there's no input or output to build with, and programs are run with
+// callbacks that give them each byte they read and take each byte they write.
+//
+// Programs optimized for 8-bit cells can behave differently with wider ones.
+pub struct Interpreter {
+    settings: Settings,
+    #[cfg(feature = "std")]
+    streams: Streams,
+}
+
+#[derive(Default)]
+pub struct InterpreterBuilder {
+    settings: Settings,
+    #[cfg(feature = "std")]
+    input: Option<InputSource>,
+    #[cfg(feature = "std")]
+    output: Option<OutputSource>,
+}
+
+impl InterpreterBuilder {
+    pub fn tape(mut self, tape_size: TapeSize) -> Self {
+        self.settings.tape_size = tape_size;
+        self
+    }
+
+    pub fn cell_width(mut self, cell_width: CellWidth) -> Self {
+        self.settings.cell_width = cell_width;
+        self
+    }
+
+    // What reads store once input runs out. Without a mode, reading past the end of the
+    // input stops the program with `RuntimeError::EndOfInput`.
+    pub fn eof(mut self, eof_mode: EofMode) -> Self {
+        self.settings.eof_mode = Some(eof_mode);
+        self
+    }
+
+    // Stops the program with `RuntimeError::StepLimit` once it's run this many
+    // instructions, for running programs that might never end.
+    pub fn max_steps(mut self, max_steps: usize) -> Self {
+        self.settings.max_steps = Some(max_steps);
+        self
+    }
+
+    #[cfg(feature = "std")]
+    pub fn input(mut self, input: InputSource) -> Self {
+        self.input = Some(input);
+        self
+    }
+
+    #[cfg(feature = "std")]
+    pub fn output(mut self, output: OutputSource) -> Self {
+        self.output = Some(output);
+        self
+    }
+
+    pub fn build(self) -> Interpreter {
+        Interpreter {
+            settings: self.settings,
+            #[cfg(feature = "std")]
+            streams: Streams {
+                input: self
+                    .input
+                    .unwrap_or_else(|| InputSource::Stdin(io::stdin())),
+                output: self
+                    .output
+                    .unwrap_or_else(|| OutputSource::Stdout(io::stdout())),
+            },
+        }
+    }
+}
+
+impl Interpreter {
+    pub fn builder() -> InterpreterBuilder {
+        InterpreterBuilder::default()
+    }
+
+    #[cfg(feature = "std")]
+    pub fn run(self, instructions: &[Instruction]) -> Result<FinalState, RuntimeError> {
+        self.settings
+            .execute::<_, false>(instructions, self.streams, &mut [])
+    }
+
+    // Counts how many times each instruction runs into `hits`, which has a count for each
+    // of them. The counts are kept even if the program stops on an error.
+    #[cfg(feature = "std")]
+    pub fn run_with_profile(
+        self,
+        instructions: &[Instruction],
+        hits: &mut [u64],
+    ) -> Result<FinalState, RuntimeError> {
+        assert_eq!(hits.len(), instructions.len());
+        self.settings
+            .execute::<_, true>(instructions, self.streams, hits)
+    }
+
+    // Runs the program with `read` giving it each byte it reads, or None once there are no
+    // more, and `write` taking each byte it writes, instead of the input and output it was
+    // built with.
+    pub fn run_with(
+        self,
+        instructions: &[Instruction],
+        read: impl FnMut() -> Option<u8>,
+        write: impl FnMut(u8),
+    ) -> Result<FinalState, RuntimeError> {
+        self.settings
+            .execute::<_, false>(instructions, Callbacks { read, write }, &mut [])
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Settings {
+    tape_size: TapeSize,
+    cell_width: CellWidth,
+    eof_mode: Option<EofMode>,
+    max_steps: Option<usize>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            tape_size: TapeSize::Infinite,
+            cell_width: CellWidth::default(),
+            eof_mode: None,
+            max_steps: None,
+        }
+    }
+}
+
+impl Settings {
+    fn execute<P: Ports, const PROFILE: bool>(
+        self,
+        instructions: &[Instruction],
+        ports: P,
+        hits: &mut [u64],
+    ) -> Result<FinalState, RuntimeError> {
+        let span = tracing::debug_span!(
+            "run",
+            instructions = instructions.len(),
+            tape = ?self.tape_size,
+            cell_width = ?self.cell_width,
+        );
+        let _entered = span.enter();
+
+        let result = match self.cell_width {
+            CellWidth::U8 => execute::<u8, P, PROFILE>(instructions, ports, self, hits),
+            CellWidth::U16 => execute::<u16, P, PROFILE>(instructions, ports, self, hits),
+            CellWidth::U32 => execute::<u32, P, PROFILE>(instructions, ports, self, hits),
+        };
+
+        match &result {
+            Ok(state) => tracing::debug!(steps = state.instructions_executed, "finished"),
+            Err(err) => tracing::debug!(error = %err, "stopped"),
+        }
+
+        result
+    }
+}
+
+// Where a running program's bytes come from and go to.
+trait Ports {
+    // Fills as much of the buffer as there's input left for, returning how much that was.
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, IoError>;
+    fn write(&mut self, bytes: &[u8]) -> Result<(), IoError>;
+    fn flush(&mut self) -> Result<(), IoError>;
+}
+
+#[cfg(feature = "std")]
+struct Streams {
+    input: InputSource,
+    output: OutputSource,
+}
+
+#[cfg(feature = "std")]
+impl Ports for Streams {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let mut read = 0;
+
+        while read < buffer.len() {
+            match self.input.read(&mut buffer[read..]) {
+                Ok(0) => break,
+                Ok(count) => read += count,
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(read)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let _lock = if let OutputSource::Stdout(ref stdout) = self.output {
+            Some(stdout.lock())
+        } else {
+            None
+        };
+
+        self.output.write_all(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+struct Callbacks<R, W> {
+    read: R,
+    write: W,
+}
+
+impl<R: FnMut() -> Option<u8>, W: FnMut(u8)> Ports for Callbacks<R, W> {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, IoError> {
+        for (read, byte) in buffer.iter_mut().enumerate() {
+            match (self.read)() {
+                Some(next) => *byte = next,
+                None => return Ok(read),
+            }
+        }
+
+        Ok(buffer.len())
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), IoError> {
+        bytes.iter().for_each(|&byte| (self.write)(byte));
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), IoError> {
+        Ok(())
+    }
+}
+
+// Returns the number of instructions executed.
+#[cfg(feature = "std")]
 pub fn interpret(
     instructions: &[Instruction],
-    mut input: InputSource,
-    mut output: OutputSource,
+    input: InputSource,
+    output: OutputSource,
+    tape_size: TapeSize,
+) -> Result<usize, RuntimeError> {
+    interpret_with_state(instructions, input, output, tape_size)
+        .map(|state| state.instructions_executed)
+}
+
+#[cfg(feature = "std")]
+pub fn interpret_with_state(
+    instructions: &[Instruction],
+    input: InputSource,
+    output: OutputSource,
     tape_size: TapeSize,
-) -> usize {
+) -> Result<FinalState, RuntimeError> {
+    Interpreter::builder()
+        .tape(tape_size)
+        .input(input)
+        .output(output)
+        .build()
+        .run(instructions)
+}
+
+#[cfg(feature = "std")]
+pub fn interpret_with_profile(
+    instructions: &[Instruction],
+    input: InputSource,
+    output: OutputSource,
+    tape_size: TapeSize,
+    hits: &mut [u64],
+) -> Result<FinalState, RuntimeError> {
+    Interpreter::builder()
+        .tape(tape_size)
+        .input(input)
+        .output(output)
+        .build()
+        .run_with_profile(instructions, hits)
+}
+
+// Counting is left out of the loop entirely unless it's profiling, so that it costs plain
+// runs nothing.
+// The part of the buffer that I/O of the given amount goes through, growing it as far as the
+// largest chunk if it has to.
+fn io_chunk(io_buffer: &mut Vec<u8>, amount: usize) -> &mut [u8] {
+    let length = amount.min(MAX_IO_CHUNK_SIZE);
+
+    if length > io_buffer.len() {
+        io_buffer.resize(length, 0);
+    }
+
+    &mut io_buffer[..length]
+}
+
+fn execute<C: Cell, P: Ports, const PROFILE: bool>(
+    instructions: &[Instruction],
+    mut ports: P,
+    settings: Settings,
+    hits: &mut [u64],
+) -> Result<FinalState, RuntimeError> {
     let mut program_counter = 0;
-    let mut memory = Memory::new(tape_size);
+    let mut memory = Memory::<C>::new(settings.tape_size);
 
     let mut io_buffer = vec![0u8; DEFAULT_INPUT_BUFFER_SIZE];
 
+    // Where the body of each pbrain procedure starts, by the value of the cell it was
+    // defined on, and where each call in progress returns to. Cells wider than a byte can
+    // number more procedures than there are to begin with.
+    let mut procedures: Vec<Option<usize>> = vec![None; 256];
+    let mut return_stack = Vec::new();
+
     let mut instructions_executed = 0;
+    let max_steps = settings.max_steps.unwrap_or(usize::MAX);
 
     while let Some(instruction) = instructions.get(program_counter) {
+        let index = program_counter;
+
+        if instructions_executed == max_steps {
+            return Err(RuntimeError::StepLimit {
+                index,
+                limit: max_steps,
+            });
+        }
+
         program_counter += 1;
         instructions_executed += 1;
 
+        if PROFILE {
+            hits[index] += 1;
+        }
+
         match instruction {
             Instruction::Add(amount) => {
                 let cell = memory.current_cell_mut();
-                *cell = (*cell as i8).wrapping_add(*amount) as u8;
+                *cell = cell.add(*amount);
             }
-            Instruction::Move(amount) => match memory.move_head(*amount) {
-                Ok(_) => {}
-                Err(_) => {
-                    // TODO: Throw an error here; the tape was moved out of bounds.
-                    todo!()
+            Instruction::Move(amount) => {
+                if memory.move_head(*amount).is_err() {
+                    return Err(RuntimeError::MovedOffTape { index });
                 }
-            },
+            }
+            // Large amounts go through the buffer a chunk at a time, so that they can't make it
+            // grow without bound.
             Instruction::Write(amount) => {
-                let amount = *amount;
-                let cell = memory.current_cell_value();
+                let chunk = io_chunk(&mut io_buffer, *amount);
+                chunk.fill(memory.current_cell_value().low_byte());
 
-                if amount >= io_buffer.len() {
-                    io_buffer.extend(iter::repeat(0).take(amount + 1 - io_buffer.len()));
-                }
+                let mut remaining = *amount;
 
-                let slice = &mut io_buffer[0..amount];
-                slice.fill(cell);
+                while remaining > 0 {
+                    let length = remaining.min(chunk.len());
 
-                let _lock = if let OutputSource::Stdout(ref stdout) = output {
-                    Some(stdout.lock())
-                } else {
-                    None
-                };
-
-                match output.write_all(slice) {
-                    Ok(_) => {}
-                    Err(_) => {
-                        // TODO: Throw an error here; failed to write all output.
-                        todo!()
+                    if let Err(err) = ports.write(&chunk[..length]) {
+                        return Err(RuntimeError::Write { index, err });
                     }
+
+                    remaining -= length;
                 }
             }
             Instruction::Read(amount) => {
-                let amount = *amount;
+                let chunk = io_chunk(&mut io_buffer, *amount);
+                let mut remaining = *amount;
+                let mut last_byte = None;
 
-                if amount >= io_buffer.len() {
-                    io_buffer.extend(iter::repeat(0).take(amount + 1 - io_buffer.len()));
-                }
+                while remaining > 0 {
+                    let length = remaining.min(chunk.len());
+
+                    let read = match ports.read(&mut chunk[..length]) {
+                        Ok(read) => read,
+                        Err(err) => return Err(RuntimeError::Read { index, err }),
+                    };
+
+                    if read > 0 {
+                        last_byte = Some(chunk[read - 1]);
+                    }
+
+                    remaining -= read;
 
-                match input.read_exact(&mut io_buffer[0..amount]) {
-                    Ok(_) => {
-                        let cell = memory.current_cell_mut();
-                        *cell = io_buffer[amount - 1];
+                    if read < length {
+                        break;
                     }
-                    Err(_) => {
-                        // TODO: Throw an error here; reading from input source failed.
-                        todo!()
+                }
+
+                // Like compiled programs, the cell keeps the last byte read even when input
+                // runs out partway through.
+                if let Some(byte) = last_byte {
+                    *memory.current_cell_mut() = C::from_byte(byte);
+                }
+
+                if remaining > 0 {
+                    match settings.eof_mode {
+                        None => return Err(RuntimeError::EndOfInput { index }),
+                        Some(EofMode::Unchanged) => {}
+                        Some(EofMode::Zero) => *memory.current_cell_mut() = C::default(),
+                        Some(EofMode::NegativeOne) => *memory.current_cell_mut() = C::MAX,
                     }
                 }
             }
             Instruction::JumpIfZero { location } => {
                 let cell = memory.current_cell_value();
 
-                if cell == 0 {
+                if cell.is_zero() {
                     program_counter = *location;
                 }
             }
             Instruction::JumpIfNotZero { location } => {
                 let cell = memory.current_cell_value();
 
-                if cell != 0 {
+                if !cell.is_zero() {
                     program_counter = *location;
                 }
             }
 
             Instruction::SetValue(value) => {
                 let cell = memory.current_cell_mut();
-                *cell = *value as u8;
+                *cell = C::default().add(*value);
             }
             Instruction::AddRelative { offset, amount } => {
-                let head = memory.head as isize;
-                let index = head + *offset;
-
-                if index >= 0 {
-                    let cell = memory.get_cell_mut(index as usize);
-                    *cell = (*cell as i8).wrapping_add(*amount) as u8;
+                if let Some(index) = memory.index_at(*offset) {
+                    let cell = memory.get_cell_mut(index);
+                    *cell = cell.add(*amount);
                 } else {
-                    // TODO: Throw an error here; tried to add to a negative index.
-                    todo!()
+                    return Err(RuntimeError::MovedOffTape { index });
                 }
             }
             Instruction::AddVector { vector: amount } => {
@@ -309,15 +890,27 @@ pub fn interpret(
                 unsafe {
                     for i in 0..VECTOR_SIZE {
                         let cell = memory.tape.get_unchecked_mut(vector[i]);
-                        *cell = (*cell as i8).wrapping_add(amount[i]) as u8;
+                        *cell = cell.add(amount[i]);
+                    }
+                }
+            }
+            Instruction::MulAdd { offset, factor } => {
+                let value = memory.current_cell_value();
+
+                if !value.is_zero() {
+                    if let Some(index) = memory.index_at(*offset) {
+                        let cell = memory.get_cell_mut(index);
+                        *cell = cell.add_cell(value.mul(*factor));
+                    } else {
+                        return Err(RuntimeError::MovedOffTape { index });
                     }
                 }
             }
             Instruction::MoveRightToZero { increment, stride } => {
                 let mut cell = memory.current_cell_mut();
 
-                while *cell != 0 {
-                    *cell = (*cell as i8).wrapping_add(*increment) as u8;
+                while !cell.is_zero() {
+                    *cell = cell.add(*increment);
                     memory.move_head_right(*stride);
                     cell = memory.current_cell_mut();
                 }
@@ -325,28 +918,56 @@ pub fn interpret(
             Instruction::MoveLeftToZero { increment, stride } => {
                 let mut cell = memory.current_cell_mut();
 
-                while *cell != 0 {
-                    *cell = (*cell as i8).wrapping_add(*increment) as u8;
-
-                    match memory.move_head_left(*stride) {
-                        Ok(_) => {
-                            cell = memory.current_cell_mut();
-                        }
-                        Err(_) => {
-                            // TODO: Throw an error here; the tape was moved out of bounds.
-                            todo!()
-                        }
+                while !cell.is_zero() {
+                    *cell = cell.add(*increment);
+
+                    if memory.move_head_left(*stride).is_err() {
+                        return Err(RuntimeError::MovedOffTape { index });
                     }
+
+                    cell = memory.current_cell_mut();
                 }
             }
-        }
-    }
 
-    match output.flush() {
-        Ok(_) => instructions_executed,
-        Err(_) => {
-            // TODO: Throw an error here; we failed to flush output!
-            todo!()
+            Instruction::DefineProc { location } => {
+                let number = memory.current_cell_value().into() as usize;
+
+                if number >= procedures.len() {
+                    procedures.resize(number + 1, None);
+                }
+
+                procedures[number] = Some(program_counter);
+                program_counter = *location + 1;
+            }
+            Instruction::EndProc => {
+                if let Some(location) = return_stack.pop() {
+                    program_counter = location;
+                }
+            }
+            Instruction::CallProc => match procedures
+                .get(memory.current_cell_value().into() as usize)
+                .copied()
+                .flatten()
+            {
+                Some(location) => {
+                    return_stack.push(program_counter);
+                    program_counter = location;
+                }
+                None => {
+                    return Err(RuntimeError::UndefinedProcedure {
+                        index,
+                        cell: memory.current_cell_value().into(),
+                    })
+                }
+            },
         }
     }
+
+    ports.flush().map_err(RuntimeError::Flush)?;
+
+    Ok(FinalState {
+        instructions_executed,
+        head: memory.head,
+        tape: memory.tape.into_iter().map(Into::into).collect(),
+    })
 }
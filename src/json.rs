@@ -0,0 +1,392 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+// JSON values, for protocols that send JSON back and forth. Objects keep their keys in the
+// order they were written, and numbers are kept as f64 the way JavaScript does.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Error)]
+#[error("{message} at offset {offset}")]
+pub struct JsonError {
+    pub offset: usize,
+    pub message: &'static str,
+}
+
+impl Value {
+    // The value of the key if this is an object that has it. Indexing a chain of keys with
+    // `get` on anything else gives None rather than failing.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Self::Object(members) => members
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Self::Number(number) if number.fract() == 0.0 && *number >= 0.0 => Some(*number as u64),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Self::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn object<K: Into<String>>(members: impl IntoIterator<Item = (K, Value)>) -> Self {
+        Self::Object(
+            members
+                .into_iter()
+                .map(|(key, value)| (key.into(), value))
+                .collect(),
+        )
+    }
+}
+
+impl From<&str> for Value {
+    fn from(string: &str) -> Self {
+        Self::String(string.to_owned())
+    }
+}
+
+impl From<String> for Value {
+    fn from(string: String) -> Self {
+        Self::String(string)
+    }
+}
+
+impl From<usize> for Value {
+    fn from(number: usize) -> Self {
+        Self::Number(number as f64)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+// Writes the value on one line, without any spaces.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Null => f.write_str("null"),
+            Self::Bool(value) => write!(f, "{}", value),
+            Self::Number(number) if number.is_finite() => write!(f, "{}", number),
+            Self::Number(_) => f.write_str("null"),
+            Self::String(string) => f.write_str(&quote(string)),
+            Self::Array(values) => {
+                f.write_str("[")?;
+
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(",")?;
+                    }
+
+                    write!(f, "{}", value)?;
+                }
+
+                f.write_str("]")
+            }
+            Self::Object(members) => {
+                f.write_str("{")?;
+
+                for (index, (key, value)) in members.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(",")?;
+                    }
+
+                    write!(f, "{}:{}", quote(key), value)?;
+                }
+
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+impl FromStr for Value {
+    type Err = JsonError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut reader = Reader {
+            text: text.as_bytes(),
+            offset: 0,
+        };
+
+        let value = reader.value(0)?;
+        reader.skip_whitespace();
+
+        if reader.offset < text.len() {
+            return Err(reader.error("unexpected text after the value"));
+        }
+
+        Ok(value)
+    }
+}
+
+// The string as a JSON string, in quotes and with the characters JSON can't hold escaped.
+pub fn quote(string: &str) -> String {
+    let mut escaped = String::with_capacity(string.len() + 2);
+    escaped.push('"');
+
+    for character in string.chars() {
+        match character {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            character if character < ' ' => {
+                escaped.push_str(&format!("\\u{:04x}", character as u32));
+            }
+            character => escaped.push(character),
+        }
+    }
+
+    escaped.push('"');
+    escaped
+}
+
+// Values nested deeper than this are rejected, rather than overflowing the stack.
+const MAX_DEPTH: usize = 128;
+
+struct Reader<'a> {
+    text: &'a [u8],
+    offset: usize,
+}
+
+impl Reader<'_> {
+    fn error(&self, message: &'static str) -> JsonError {
+        JsonError {
+            offset: self.offset,
+            message,
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.text.get(self.offset) {
+            self.offset += 1;
+        }
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        self.skip_whitespace();
+
+        if self.text.get(self.offset) == Some(&byte) {
+            self.offset += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str, value: Value) -> Result<Value, JsonError> {
+        if self.text[self.offset..].starts_with(keyword.as_bytes()) {
+            self.offset += keyword.len();
+            Ok(value)
+        } else {
+            Err(self.error("expected a value"))
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, JsonError> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("values are nested too deeply"));
+        }
+
+        self.skip_whitespace();
+
+        match self.text.get(self.offset) {
+            Some(b'n') => self.keyword("null", Value::Null),
+            Some(b't') => self.keyword("true", Value::Bool(true)),
+            Some(b'f') => self.keyword("false", Value::Bool(false)),
+            Some(b'"') => self.string().map(Value::String),
+            Some(b'[') => {
+                self.offset += 1;
+                let mut values = Vec::new();
+
+                if self.eat(b']') {
+                    return Ok(Value::Array(values));
+                }
+
+                loop {
+                    values.push(self.value(depth + 1)?);
+
+                    if self.eat(b']') {
+                        return Ok(Value::Array(values));
+                    }
+
+                    if !self.eat(b',') {
+                        return Err(self.error("expected ',' or ']'"));
+                    }
+                }
+            }
+            Some(b'{') => {
+                self.offset += 1;
+                let mut members = Vec::new();
+
+                if self.eat(b'}') {
+                    return Ok(Value::Object(members));
+                }
+
+                loop {
+                    self.skip_whitespace();
+
+                    if self.text.get(self.offset) != Some(&b'"') {
+                        return Err(self.error("expected a key"));
+                    }
+
+                    let key = self.string()?;
+
+                    if !self.eat(b':') {
+                        return Err(self.error("expected ':'"));
+                    }
+
+                    members.push((key, self.value(depth + 1)?));
+
+                    if self.eat(b'}') {
+                        return Ok(Value::Object(members));
+                    }
+
+                    if !self.eat(b',') {
+                        return Err(self.error("expected ',' or '}'"));
+                    }
+                }
+            }
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            _ => Err(self.error("expected a value")),
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, JsonError> {
+        let start = self.offset;
+
+        while let Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9') = self.text.get(self.offset)
+        {
+            self.offset += 1;
+        }
+
+        // Only ASCII was consumed, so the slice is UTF-8.
+        std::str::from_utf8(&self.text[start..self.offset])
+            .ok()
+            .and_then(|number| number.parse().ok())
+            .map(Value::Number)
+            .ok_or(JsonError {
+                offset: start,
+                message: "invalid number",
+            })
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        // Skips the opening quote.
+        self.offset += 1;
+        let mut bytes = Vec::new();
+
+        loop {
+            let byte = *self
+                .text
+                .get(self.offset)
+                .ok_or_else(|| self.error("unterminated string"))?;
+            self.offset += 1;
+
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let escape = *self
+                        .text
+                        .get(self.offset)
+                        .ok_or_else(|| self.error("unterminated string"))?;
+                    self.offset += 1;
+
+                    let character = match escape {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => self.unicode_escape()?,
+                        _ => return Err(self.error("invalid escape")),
+                    };
+
+                    let mut buffer = [0; 4];
+                    bytes.extend_from_slice(character.encode_utf8(&mut buffer).as_bytes());
+                }
+                byte if byte < b' ' => return Err(self.error("control character in string")),
+                byte => bytes.push(byte),
+            }
+        }
+
+        String::from_utf8(bytes).map_err(|_| self.error("invalid UTF-8 in string"))
+    }
+
+    // The character of a \u escape, which takes a pair of them outside the BMP. Unpaired
+    // surrogates become the replacement character.
+    fn unicode_escape(&mut self) -> Result<char, JsonError> {
+        let first = self.hex()?;
+
+        if !(0xd800..0xdc00).contains(&first) {
+            return Ok(char::from_u32(first).unwrap_or(char::REPLACEMENT_CHARACTER));
+        }
+
+        if !self.text[self.offset..].starts_with(b"\\u") {
+            return Ok(char::REPLACEMENT_CHARACTER);
+        }
+
+        self.offset += 2;
+        let second = self.hex()?;
+
+        if !(0xdc00..0xe000).contains(&second) {
+            return Ok(char::REPLACEMENT_CHARACTER);
+        }
+
+        let code = 0x10000 + ((first - 0xd800) << 10) + (second - 0xdc00);
+        Ok(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER))
+    }
+
+    fn hex(&mut self) -> Result<u32, JsonError> {
+        let digits = self
+            .text
+            .get(self.offset..self.offset + 4)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| self.error("invalid \\u escape"))?;
+
+        self.offset += 4;
+        Ok(digits)
+    }
+}
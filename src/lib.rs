@@ -4,9 +4,96 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-pub mod compiler;
+// Without the `std` feature, only the core of membrane is built, which parses, optimizes,
+// and interprets programs with nothing more than an allocator.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+// Implements Display and FromStr for an enum of options by the name of each one, given
+// its `ALL` and `name`, so it can be used on the command line. Callers import fmt,
+// Formatter, and FromStr.
+macro_rules! named_option {
+    ($option:ty) => {
+        impl fmt::Display for $option {
+            fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+                f.write_str(self.name())
+            }
+        }
+
+        impl FromStr for $option {
+            type Err = $crate::error::UnknownValueError;
+
+            fn from_str(name: &str) -> Result<Self, Self::Err> {
+                Self::ALL
+                    .iter()
+                    .copied()
+                    .find(|option| option.name() == name)
+                    .ok_or_else(|| $crate::error::UnknownValueError {
+                        value: name.into(),
+                        expected: Self::ALL.iter().map(Self::name).collect(),
+                    })
+            }
+        }
+    };
+}
+
+pub mod dialect;
+pub mod error;
 pub mod instruction;
 pub mod interpreter;
-pub mod lister;
 pub mod optimizer;
 pub mod parser;
+pub mod program;
+pub mod span;
+
+#[cfg(feature = "std")]
+pub mod analysis;
+#[cfg(feature = "std")]
+pub mod cache;
+#[cfg(feature = "std")]
+pub mod canonicalizer;
+#[cfg(feature = "std")]
+pub mod compilers;
+#[cfg(feature = "std")]
+pub mod config;
+#[cfg(feature = "std")]
+pub mod explainer;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "std")]
+pub mod formatter;
+#[cfg(feature = "std")]
+pub mod frontend;
+#[cfg(feature = "std")]
+pub mod generator;
+#[cfg(feature = "std")]
+pub mod json;
+#[cfg(feature = "std")]
+pub mod lister;
+#[cfg(feature = "std")]
+pub mod loader;
+#[cfg(feature = "std")]
+pub mod lowering;
+#[cfg(feature = "std")]
+pub mod lsp;
+#[cfg(feature = "std")]
+pub mod minifier;
+#[cfg(feature = "std")]
+pub mod preprocessor;
+#[cfg(feature = "std")]
+pub mod profile;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(feature = "std")]
+pub mod testgen;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "std")]
+pub mod watcher;
+
+#[cfg(feature = "std")]
+mod streams;
+
+#[cfg(feature = "std")]
+pub use error::Error;
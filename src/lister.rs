@@ -4,33 +4,628 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use std::fs::File;
-use std::io::{BufWriter, Result as IOResult, Write};
-use std::path::Path;
-
-use crate::instruction::Instruction;
-
-pub fn create_listing<P: AsRef<Path>>(instructions: &[Instruction], path: P) -> IOResult<()> {
-    let file = File::create(path)?;
-
-    if !instructions.is_empty() {
-        let padding = log10(instructions.len()) + 1;
-        let mut writer = BufWriter::new(file);
-
-        for (index, instruction) in instructions.iter().enumerate() {
-            writeln!(
-                writer,
-                "{:0padding$}  {}",
-                index,
-                instruction,
-                padding = padding
-            )?;
+use std::fmt;
+use std::fmt::Formatter;
+use std::io::{Result as IOResult, Write};
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::analysis::Metrics;
+use crate::instruction::{Instruction, ParseInstructionError};
+use crate::json::Value;
+use crate::profile::Profile;
+use crate::program::Program;
+use crate::span::{Location, Span};
+
+// How listings are written. Text lines up the instructions for reading, while JSON and CSV
+// have one record per instruction for other tools to read.
+#[derive(Copy, Clone, Eq, PartialEq, Default, Debug)]
+pub enum ListingFormat {
+    #[default]
+    Text,
+    Json,
+    Csv,
+}
+
+impl ListingFormat {
+    pub const ALL: &'static [Self] = &[Self::Text, Self::Json, Self::Csv];
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Text => "text",
+            Self::Json => "json",
+            Self::Csv => "csv",
+        }
+    }
+}
+
+named_option!(ListingFormat);
+
+#[derive(Copy, Clone, Default, Debug)]
+pub struct ListingOptions<'a> {
+    pub format: ListingFormat,
+    // The source the program was parsed from, which text listings show beside each
+    // instruction.
+    pub source: Option<&'a [u8]>,
+    // How many times each instruction ran, which must be of the same program.
+    pub profile: Option<&'a Profile>,
+    // Whether to end text and JSON listings with a summary of the program. CSV has nowhere
+    // to put one.
+    pub summary: bool,
+    // How many instructions the program had before it was optimized, which the summary
+    // compares it with.
+    pub unoptimized: Option<usize>,
+}
+
+// Instructions are indented by how deeply they're nested in loops and procedures, and jumps
+// point at their partners by index, such as `[ → 0042`.
+pub fn create_listing<W: Write>(instructions: &[Instruction], writer: &mut W) -> IOResult<()> {
+    for line in listing_lines(instructions) {
+        writeln!(writer, "{}", line)?;
+    }
+
+    writer.flush()
+}
+
+// Lists the instructions with the source each of them came from in a column to their right,
+// so that fused instructions can be told apart by what they were made from. Instructions
+// whose spans don't know their line, such as those of bytecode, have none.
+pub fn create_listing_with_source<W: Write>(
+    program: &Program,
+    source: &[u8],
+    writer: &mut W,
+) -> IOResult<()> {
+    let options = ListingOptions {
+        source: Some(source),
+        ..ListingOptions::default()
+    };
+
+    write_text(program, &options, writer)
+}
+
+// With a profile, each instruction is preceded by how many times it ran and its share of
+// the total, and the hottest are marked with a `*`.
+fn write_text<W: Write>(
+    program: &Program,
+    options: &ListingOptions,
+    writer: &mut W,
+) -> IOResult<()> {
+    const SOURCE_LIMIT: usize = 40;
+
+    let mut lines = listing_lines(&program.instructions);
+
+    if let Some(profile) = options.profile {
+        let hot = profile.hot();
+        let width = profile
+            .hits
+            .iter()
+            .max()
+            .map_or(1, |&hits| hits.to_string().len());
+
+        for (index, line) in lines.iter_mut().enumerate() {
+            // Every line starts with its index, which the counts go after.
+            let (number, rest) = line.split_once("  ").unwrap();
+
+            *line = format!(
+                "{}  {:>width$} {:>6.2}% {} {}",
+                number,
+                profile.hits.get(index).copied().unwrap_or_default(),
+                profile.percentage(index),
+                if hot.get(index) == Some(&true) {
+                    '*'
+                } else {
+                    ' '
+                },
+                rest,
+                width = width
+            );
+        }
+    }
+
+    let width = lines
+        .iter()
+        .map(|line| line.chars().count())
+        .max()
+        .unwrap_or_default();
+
+    let spans = (program.spans.len() == program.instructions.len()).then_some(&program.spans);
+
+    for (index, line) in lines.iter().enumerate() {
+        let code = options
+            .source
+            .zip(spans)
+            .map(|(source, spans)| (source, spans[index]))
+            .filter(|(_, span)| span.location().is_some())
+            .and_then(|(source, span)| source.get(span.range()))
+            .map(|code| {
+                let code = String::from_utf8_lossy(code);
+                let code = code.split_whitespace().collect::<Vec<_>>().join(" ");
+
+                match code.char_indices().nth(SOURCE_LIMIT) {
+                    Some((end, _)) => format!("{}...", &code[..end]),
+                    None => code,
+                }
+            });
+
+        match code {
+            Some(code) => writeln!(writer, "{:width$}  ; {}", line, code, width = width)?,
+            None => writeln!(writer, "{}", line.trim_end())?,
         }
     }
 
+    if options.summary {
+        write_summary(program, options.unoptimized, writer)?;
+    }
+
+    writer.flush()
+}
+
+// The summary is made of comments, so that listings with one can still be read back.
+fn write_summary<W: Write>(
+    program: &Program,
+    unoptimized: Option<usize>,
+    writer: &mut W,
+) -> IOResult<()> {
+    let metrics = Metrics::of(program);
+    let stats = metrics.stats;
+
+    writeln!(writer)?;
+    writeln!(
+        writer,
+        "# {} instruction(s), {} loop(s), {} procedure(s), nested {} deep",
+        stats.instructions, stats.loops, stats.procedures, stats.max_depth
+    )?;
+
+    if let Some(unoptimized) = unoptimized {
+        writeln!(
+            writer,
+            "# {} instruction(s) before optimization ({:.1}% fewer after)",
+            unoptimized,
+            100.0 - stats.instructions as f64 * 100.0 / unoptimized.max(1) as f64
+        )?;
+    }
+
+    for (name, count) in &metrics.histogram {
+        writeln!(writer, "# {:>8}  {}", count, name)?;
+    }
+
     Ok(())
 }
 
+fn summary_json(program: &Program, unoptimized: Option<usize>) -> Value {
+    let metrics = Metrics::of(program);
+    let stats = metrics.stats;
+
+    Value::object([
+        ("instructions", Value::from(stats.instructions)),
+        ("unoptimized", unoptimized.map_or(Value::Null, Value::from)),
+        ("loops", Value::from(stats.loops)),
+        ("procedures", Value::from(stats.procedures)),
+        ("max_depth", Value::from(stats.max_depth)),
+        (
+            "histogram",
+            Value::object(
+                metrics
+                    .histogram
+                    .iter()
+                    .map(|&(name, count)| (name, Value::from(count))),
+            ),
+        ),
+    ])
+}
+
+fn listing_lines(instructions: &[Instruction]) -> Vec<String> {
+    let padding = log10(instructions.len().max(1)) + 1;
+    let mut depth = 0usize;
+    let mut lines = Vec::with_capacity(instructions.len());
+
+    for (index, instruction) in instructions.iter().enumerate() {
+        if matches!(
+            instruction,
+            Instruction::JumpIfNotZero { .. } | Instruction::EndProc
+        ) {
+            depth = depth.saturating_sub(1);
+        }
+
+        let text = match instruction {
+            Instruction::JumpIfZero { location } => {
+                format!("{:16}[ → {:0padding$}", "JumpIfZero", location)
+            }
+            Instruction::JumpIfNotZero { location } => {
+                format!("{:16}] → {:0padding$}", "JumpIfNotZero", location)
+            }
+            Instruction::DefineProc { location } => {
+                format!("{:16}( → {:0padding$}", "DefineProc", location)
+            }
+            instruction => instruction.to_string(),
+        };
+
+        lines.push(format!(
+            "{:0padding$}  {:indent$}{}",
+            index,
+            "",
+            text,
+            indent = depth * 2
+        ));
+
+        if matches!(
+            instruction,
+            Instruction::JumpIfZero { .. } | Instruction::DefineProc { .. }
+        ) {
+            depth += 1;
+        }
+    }
+
+    lines
+}
+
+// Lists the program in the format. Records carry the span of source each instruction came
+// from, or null (an empty field in CSV) for programs without spans, along with how many
+// times the instruction ran if there's a profile. Text listings show the source itself
+// when it's given. JSON listings with a summary are an object holding the array of
+// records as `instructions` next to the `summary`.
+pub fn write_listing<W: Write>(
+    program: &Program,
+    options: &ListingOptions,
+    writer: &mut W,
+) -> IOResult<()> {
+    let spans = (program.spans.len() == program.instructions.len()).then_some(&program.spans);
+    let records = program
+        .instructions
+        .iter()
+        .enumerate()
+        .map(|(index, instruction)| (index, instruction, spans.map(|spans| spans[index])));
+    let hits = |index: usize| {
+        options
+            .profile
+            .map(|profile| profile.hits.get(index).copied().unwrap_or_default())
+    };
+
+    match options.format {
+        ListingFormat::Text => return write_text(program, options, writer),
+        ListingFormat::Json => {
+            let indent = if options.summary {
+                writeln!(writer, "{{")?;
+                writeln!(writer, "  \"instructions\": [")?;
+                "    "
+            } else {
+                writeln!(writer, "[")?;
+                "  "
+            };
+
+            for (index, instruction, span) in records {
+                let span = match span {
+                    Some(span) => Value::object([
+                        ("start", Value::from(span.start)),
+                        ("end", Value::from(span.end)),
+                        ("line", Value::from(span.line)),
+                        ("column", Value::from(span.column)),
+                    ]),
+                    None => Value::Null,
+                };
+
+                let mut record = vec![
+                    ("index", Value::from(index)),
+                    ("opcode", Value::from(instruction.name())),
+                    ("operands", Value::object(operands(instruction))),
+                    ("span", span),
+                ];
+
+                if let Some(hits) = hits(index) {
+                    record.push(("hits", Value::Number(hits as f64)));
+                }
+
+                let record = Value::object(record);
+
+                let separator = if index + 1 < program.instructions.len() {
+                    ","
+                } else {
+                    ""
+                };
+
+                writeln!(writer, "{}{}{}", indent, record, separator)?;
+            }
+
+            if options.summary {
+                let summary = summary_json(program, options.unoptimized);
+                writeln!(writer, "  ],")?;
+                writeln!(writer, "  \"summary\": {}", summary)?;
+                writeln!(writer, "}}")?;
+            } else {
+                writeln!(writer, "]")?;
+            }
+        }
+        ListingFormat::Csv => {
+            let header = "index,opcode,operands,start,end,line,column";
+
+            match options.profile {
+                Some(_) => writeln!(writer, "{},hits", header)?,
+                None => writeln!(writer, "{}", header)?,
+            }
+
+            for (index, instruction, span) in records {
+                let operands = operands(instruction)
+                    .into_iter()
+                    .map(|(name, value)| format!("{}={}", name, value))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+
+                let span = span.map_or(",,,".to_owned(), |span| {
+                    format!("{},{},{},{}", span.start, span.end, span.line, span.column)
+                });
+
+                write!(
+                    writer,
+                    "{},{},{},{}",
+                    index,
+                    instruction.name(),
+                    csv_field(&operands),
+                    span
+                )?;
+
+                match hits(index) {
+                    Some(hits) => writeln!(writer, ",{}", hits)?,
+                    None => writeln!(writer)?,
+                }
+            }
+        }
+    }
+
+    writer.flush()
+}
+
+// The operands of the instruction by name, as they're held in it.
+fn operands(instruction: &Instruction) -> Vec<(&'static str, Value)> {
+    let number = |value: i64| Value::Number(value as f64);
+
+    match *instruction {
+        Instruction::Add(amount) => vec![("amount", number(amount as i64))],
+        Instruction::Move(amount) => vec![("amount", number(amount as i64))],
+        Instruction::Write(count) | Instruction::Read(count) => {
+            vec![("count", number(count as i64))]
+        }
+        Instruction::JumpIfZero { location }
+        | Instruction::JumpIfNotZero { location }
+        | Instruction::DefineProc { location } => vec![("location", number(location as i64))],
+
+        Instruction::SetValue(value) => vec![("value", number(value as i64))],
+        Instruction::AddRelative { offset, amount } => vec![
+            ("offset", number(offset as i64)),
+            ("amount", number(amount as i64)),
+        ],
+        Instruction::AddVector { vector } => vec![(
+            "vector",
+            Value::Array(vector.iter().map(|&lane| number(lane as i64)).collect()),
+        )],
+        Instruction::MulAdd { offset, factor } => vec![
+            ("offset", number(offset as i64)),
+            ("factor", number(factor as i64)),
+        ],
+        Instruction::MoveRightToZero { increment, stride }
+        | Instruction::MoveLeftToZero { increment, stride } => vec![
+            ("increment", number(increment as i64)),
+            ("stride", number(stride as i64)),
+        ],
+
+        Instruction::EndProc | Instruction::CallProc => Vec::new(),
+    }
+}
+
+// Quotes the field if it holds anything CSV gives a meaning to.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Error)]
+#[error("{} at {}", self.message(), self.location())]
+pub enum ListingError {
+    // A line with an index but nothing after it.
+    Syntax {
+        location: Location,
+    },
+    UnknownInstruction {
+        location: Location,
+        mnemonic: String,
+    },
+    InvalidOperand {
+        location: Location,
+        operand: String,
+    },
+    // A JumpIfNotZero or EndProc with nothing of its kind left to close.
+    UnmatchedJump {
+        location: Location,
+    },
+    // A JumpIfZero or DefineProc still open when the listing ends.
+    UnclosedJump {
+        location: Location,
+    },
+}
+
+impl ListingError {
+    pub fn location(&self) -> Location {
+        match self {
+            Self::Syntax { location }
+            | Self::UnknownInstruction { location, .. }
+            | Self::InvalidOperand { location, .. }
+            | Self::UnmatchedJump { location }
+            | Self::UnclosedJump { location } => *location,
+        }
+    }
+
+    // What's wrong, without where.
+    pub fn message(&self) -> String {
+        match self {
+            Self::Syntax { .. } => "expected an instruction".to_owned(),
+            Self::UnknownInstruction { mnemonic, .. } => {
+                format!("unknown instruction '{}'", mnemonic)
+            }
+            Self::InvalidOperand { operand, .. } => format!("invalid operand '{}'", operand),
+            Self::UnmatchedJump { .. } => "nothing left for this to close".to_owned(),
+            Self::UnclosedJump { .. } => "never closed".to_owned(),
+        }
+    }
+}
+
+// Reads a text listing back into the program it lists, so that it can be edited by hand
+// and run again. Each line is an optional index, the hit counts of a profiled listing, and
+// then an instruction the way it's listed:
+//
+//     0003  MulAdd          +1*+2   ; [->++<]
+//
+// Blank lines and lines starting with `#` are skipped, and everything from a `;` on is a
+// comment. The targets of jumps are worked out from how they nest, like in source, so the
+// ones written after them are only there to read and don't need updating. Each
+// instruction's span is the line it's on.
+pub fn parse_listing(text: &str) -> Result<Program, ListingError> {
+    let mut program = Program::default();
+    let mut open: Vec<(usize, Location)> = Vec::new();
+    let mut location = Location::START;
+
+    for line in text.split_inclusive('\n') {
+        let start = location;
+        location.offset += line.len();
+        location.line += 1;
+
+        let code = line.split(';').next().unwrap_or_default().trim();
+
+        if code.is_empty() || code.starts_with('#') {
+            continue;
+        }
+
+        let code = skip_columns(code);
+        let (mnemonic, operand) = code.split_once(char::is_whitespace).unwrap_or((code, ""));
+        let operand = operand.trim();
+
+        // Jumps are their bracket, optionally followed by the index of their partner, as in
+        // `[ → 0042` or `[42`. Their targets are filled in once their partners are found.
+        let bracket = match mnemonic {
+            "JumpIfZero" => Some('['),
+            "JumpIfNotZero" => Some(']'),
+            "DefineProc" => Some('('),
+            _ => None,
+        };
+
+        let parsed = match bracket {
+            Some(bracket) if is_jump(operand, bracket) => {
+                format!("{} {}0", mnemonic, bracket).parse::<Instruction>()
+            }
+            _ => code.parse::<Instruction>(),
+        };
+
+        let instruction = parsed.map_err(|err| match err {
+            ParseInstructionError::Empty => ListingError::Syntax { location: start },
+            ParseInstructionError::UnknownMnemonic(mnemonic) => ListingError::UnknownInstruction {
+                location: start,
+                mnemonic,
+            },
+            ParseInstructionError::InvalidOperand(operand) => ListingError::InvalidOperand {
+                location: start,
+                operand,
+            },
+        })?;
+
+        let index = program.instructions.len();
+        let instruction = match instruction {
+            Instruction::JumpIfZero { .. } | Instruction::DefineProc { .. } => {
+                open.push((index, start));
+                instruction
+            }
+            Instruction::JumpIfNotZero { .. } => match open.pop() {
+                Some((partner, _))
+                    if matches!(
+                        program.instructions[partner],
+                        Instruction::JumpIfZero { .. }
+                    ) =>
+                {
+                    program.instructions[partner] = Instruction::JumpIfZero { location: index };
+                    Instruction::JumpIfNotZero { location: partner }
+                }
+                _ => return Err(ListingError::UnmatchedJump { location: start }),
+            },
+            Instruction::EndProc => match open.pop() {
+                Some((partner, _))
+                    if matches!(
+                        program.instructions[partner],
+                        Instruction::DefineProc { .. }
+                    ) =>
+                {
+                    program.instructions[partner] = Instruction::DefineProc { location: index };
+                    Instruction::EndProc
+                }
+                _ => return Err(ListingError::UnmatchedJump { location: start }),
+            },
+            instruction => instruction,
+        };
+
+        program.instructions.push(instruction);
+        program.spans.push(Span {
+            start: start.offset,
+            end: start.offset + line.trim_end().len(),
+            line: start.line,
+            column: 1,
+        });
+    }
+
+    match open.pop() {
+        Some((_, location)) => Err(ListingError::UnclosedJump { location }),
+        None => Ok(program),
+    }
+}
+
+// The line past its index, and past the hits, percentage and hot mark of a profiled
+// listing.
+fn skip_columns(code: &str) -> &str {
+    let digits = |token: &str| !token.is_empty() && token.bytes().all(|byte| byte.is_ascii_digit());
+    let mut tokens = code.split_whitespace().peekable();
+    let mut skipped = 0;
+
+    if tokens.peek().copied().is_some_and(digits) {
+        tokens.next();
+        skipped = 1;
+    }
+
+    let mut profiled = tokens.clone();
+
+    if profiled.next().is_some_and(digits)
+        && profiled
+            .next()
+            .and_then(|percentage| percentage.strip_suffix('%'))
+            .is_some_and(|percentage| percentage.parse::<f64>().is_ok())
+    {
+        skipped += 2;
+
+        if profiled.next() == Some("*") {
+            skipped += 1;
+        }
+    }
+
+    let mut rest = code;
+
+    for _ in 0..skipped {
+        rest = rest.trim_start();
+        rest = &rest[rest.find(char::is_whitespace).unwrap_or(rest.len())..];
+    }
+
+    rest.trim_start()
+}
+
+// Whether the operand is the bracket, followed by nothing but its partner's index.
+fn is_jump(operand: &str, bracket: char) -> bool {
+    match operand.strip_prefix(bracket) {
+        Some(target) => {
+            let target = target.trim_start();
+            let target = target.strip_prefix('→').unwrap_or(target).trim_start();
+            target.bytes().all(|byte| byte.is_ascii_digit())
+        }
+        None => false,
+    }
+}
+
 // TODO: Remove this in favor of std's log10 once it gets stabilized.
 fn log10(value: usize) -> usize {
     let zeros = value.leading_zeros() as usize;
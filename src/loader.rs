@@ -0,0 +1,74 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::io::Read;
+
+use thiserror::Error;
+
+use crate::compilers::bytecode::{self, BytecodeError, Header, MAGIC, SOURCE_KEY};
+use crate::error;
+use crate::frontend::Frontend;
+use crate::parser::ParseError;
+use crate::program::Program;
+
+// Loads programs from either source or bytecode, telling bytecode apart by its magic, so
+// anything that takes a program takes either one.
+#[derive(Debug, Error)]
+pub enum LoadError {
+    // Written one to a line.
+    #[error("{}", error::lines(.0))]
+    Parse(Vec<ParseError>),
+    #[error("{0}")]
+    Bytecode(BytecodeError),
+}
+
+pub fn is_bytecode(source: &[u8]) -> bool {
+    source.starts_with(MAGIC)
+}
+
+// Loads source that's already in memory, which lets frontends parse it in parallel.
+pub fn load_bytes(
+    source: &[u8],
+    frontend: &dyn Frontend,
+) -> Result<(Program, Option<Header>), LoadError> {
+    if is_bytecode(source) {
+        return load_reader(source, frontend);
+    }
+
+    frontend
+        .parse(source)
+        .map(|program| (program, None))
+        .map_err(LoadError::Parse)
+}
+
+// Returns the program along with its header, if it was bytecode. Source is parsed by the
+// frontend, while bytecode is read the same whatever the frontend.
+pub fn load_reader<R: Read>(
+    mut reader: R,
+    frontend: &dyn Frontend,
+) -> Result<(Program, Option<Header>), LoadError> {
+    let mut start = Vec::with_capacity(MAGIC.len());
+
+    (&mut reader)
+        .take(MAGIC.len() as u64)
+        .read_to_end(&mut start)
+        .map_err(|err| LoadError::Parse(vec![err.into()]))?;
+
+    let mut reader = start.as_slice().chain(reader);
+
+    if !is_bytecode(&start) {
+        return frontend
+            .parse_reader(&mut reader)
+            .map(|program| (program, None))
+            .map_err(LoadError::Parse);
+    }
+
+    let (mut program, header) = bytecode::decode(&mut reader).map_err(LoadError::Bytecode)?;
+    program.source_name = header.metadata.get(SOURCE_KEY).cloned();
+    program.dialect = Some("bytecode".to_owned());
+
+    Ok((program, Some(header)))
+}
@@ -0,0 +1,186 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::io::{Result as IOResult, Write};
+use std::iter;
+
+use crate::instruction::Instruction;
+
+// Expands (possibly fused) instructions back into the eight canonical Brainfuck commands,
+// plus pbrain's three for procedures. The result is semantically equivalent to the input
+// under the same tape size.
+pub fn lower(instructions: &[Instruction]) -> Vec<Instruction> {
+    let mut code = Vec::new();
+    let mut lowered = Vec::with_capacity(instructions.len());
+    let mut jump_stack = Vec::new();
+
+    let mut index = 0;
+
+    while index < instructions.len() {
+        code.clear();
+        index = expand(instructions, index, &mut code);
+
+        for command in &code {
+            let lowered_instruction = match command {
+                b'+' => Instruction::Add(1),
+                b'-' => Instruction::Add(-1),
+                b'>' => Instruction::Move(1),
+                b'<' => Instruction::Move(-1),
+                b'.' => Instruction::Write(1),
+                b',' => Instruction::Read(1),
+                b'[' => {
+                    jump_stack.push(lowered.len());
+                    Instruction::JumpIfZero { location: 0 }
+                }
+                b']' => {
+                    let loop_start = jump_stack.pop().unwrap_or_default();
+                    let loop_end = lowered.len();
+
+                    if let Some(Instruction::JumpIfZero { location }) = lowered.get_mut(loop_start)
+                    {
+                        *location = loop_end;
+                    }
+
+                    Instruction::JumpIfNotZero {
+                        location: loop_start,
+                    }
+                }
+                b'(' => {
+                    jump_stack.push(lowered.len());
+                    Instruction::DefineProc { location: 0 }
+                }
+                b')' => {
+                    let start = jump_stack.pop().unwrap_or_default();
+                    let end = lowered.len();
+
+                    if let Some(Instruction::DefineProc { location }) = lowered.get_mut(start) {
+                        *location = end;
+                    }
+
+                    Instruction::EndProc
+                }
+                b':' => Instruction::CallProc,
+                _ => unreachable!(),
+            };
+
+            lowered.push(lowered_instruction);
+        }
+    }
+
+    lowered
+}
+
+pub fn to_brainfuck(instructions: &[Instruction]) -> String {
+    let mut code = Vec::with_capacity(instructions.len());
+    let mut index = 0;
+
+    while index < instructions.len() {
+        index = expand(instructions, index, &mut code);
+    }
+
+    // Only ASCII commands are ever emitted.
+    String::from_utf8(code).unwrap()
+}
+
+pub fn write_brainfuck<W: Write>(instructions: &[Instruction], writer: &mut W) -> IOResult<()> {
+    let mut code = Vec::new();
+    let mut index = 0;
+
+    while index < instructions.len() {
+        code.clear();
+        index = expand(instructions, index, &mut code);
+        writer.write_all(&code)?;
+    }
+
+    Ok(())
+}
+
+// Returns the index of the next instruction to expand, since some instructions are only
+// meaningful as a run.
+fn expand(instructions: &[Instruction], index: usize, code: &mut Vec<u8>) -> usize {
+    match instructions[index] {
+        Instruction::Add(amount) => expand_add(amount, code),
+        Instruction::Move(amount) => expand_move(amount, code),
+        Instruction::Write(amount) => code.extend(iter::repeat_n(b'.', amount)),
+        Instruction::Read(amount) => code.extend(iter::repeat_n(b',', amount)),
+        Instruction::JumpIfZero { .. } => code.push(b'['),
+        Instruction::JumpIfNotZero { .. } => code.push(b']'),
+
+        Instruction::SetValue(value) => {
+            code.extend_from_slice(b"[-]");
+            expand_add(value, code);
+        }
+        Instruction::AddRelative { offset, amount } => {
+            expand_move(offset, code);
+            expand_add(amount, code);
+            expand_move(-offset, code);
+        }
+        Instruction::AddVector { vector } => {
+            let mut position = 0;
+
+            for (lane, amount) in vector.into_iter().enumerate() {
+                if amount != 0 {
+                    expand_move(lane as isize - position, code);
+                    expand_add(amount, code);
+                    position = lane as isize;
+                }
+            }
+
+            expand_move(-position, code);
+        }
+        Instruction::MulAdd { .. } => {
+            // The store that always follows the run takes care of whatever value the
+            // current cell is supposed to end up with.
+            let run_end = instructions[index..]
+                .iter()
+                .position(|instruction| !matches!(instruction, Instruction::MulAdd { .. }))
+                .map_or(instructions.len(), |length| index + length);
+
+            code.extend_from_slice(b"[-");
+
+            for instruction in &instructions[index..run_end] {
+                if let Instruction::MulAdd { offset, factor } = *instruction {
+                    expand_move(offset, code);
+                    expand_add(factor, code);
+                    expand_move(-offset, code);
+                }
+            }
+
+            code.push(b']');
+            return run_end;
+        }
+        Instruction::MoveRightToZero { increment, stride } => {
+            code.push(b'[');
+            expand_add(increment, code);
+            code.extend(iter::repeat_n(b'>', stride));
+            code.push(b']');
+        }
+        Instruction::MoveLeftToZero { increment, stride } => {
+            code.push(b'[');
+            expand_add(increment, code);
+            code.extend(iter::repeat_n(b'<', stride));
+            code.push(b']');
+        }
+
+        Instruction::DefineProc { .. } => code.push(b'('),
+        Instruction::EndProc => code.push(b')'),
+        Instruction::CallProc => code.push(b':'),
+    }
+
+    index + 1
+}
+
+#[inline]
+fn expand_add(amount: i8, code: &mut Vec<u8>) {
+    let command = if amount < 0 { b'-' } else { b'+' };
+    code.extend(iter::repeat_n(command, amount.unsigned_abs() as usize));
+}
+
+#[inline]
+fn expand_move(amount: isize, code: &mut Vec<u8>) {
+    let command = if amount < 0 { b'<' } else { b'>' };
+    code.extend(iter::repeat_n(command, amount.unsigned_abs()));
+}
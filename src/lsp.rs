@@ -0,0 +1,552 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use crate::formatter::{self, FormatOptions};
+use crate::frontend::Registry;
+use crate::instruction::Instruction;
+use crate::json::Value;
+use crate::optimizer::{self, OptimizeOptions};
+use crate::parser::{self, ParseOptions};
+use crate::program::Program;
+use crate::span::Span;
+
+// The error codes of JSON-RPC that the server answers with.
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_NOT_INITIALIZED: i64 = -32002;
+
+// The protocol's kinds of document symbols. It has none for loops, so they're shown as
+// namespaces, which editors draw as blocks.
+const LOOP_SYMBOL: usize = 3;
+const PROCEDURE_SYMBOL: usize = 12;
+
+// The largest message the server reads. The buffer for a message is allocated up front from
+// its Content-Length, which a client could otherwise set as large as it likes.
+pub const MAX_MESSAGE_LENGTH: usize = 64 * 1024 * 1024;
+
+// A language server for Brainfuck and the dialects membrane knows, speaking the Language
+// Server Protocol over the reader and writer. It reports bracket errors as diagnostics,
+// shows the optimized instructions under the cursor on hover, lists top-level loops and
+// procedures as symbols, and formats documents with the formatter. Returns once the client
+// says to exit, with whether it asked the server to shut down first.
+pub fn serve<R: BufRead, W: Write>(mut reader: R, mut writer: W) -> io::Result<bool> {
+    let mut server = Server::default();
+
+    loop {
+        let message = match read_message(&mut reader)? {
+            Some(message) => message,
+            // The client went away without saying to exit.
+            None => return Ok(false),
+        };
+
+        let message = match message.parse::<Value>() {
+            Ok(message) => message,
+            Err(err) => {
+                write_message(
+                    &mut writer,
+                    &error_response(Value::Null, PARSE_ERROR, err.to_string()),
+                )?;
+                continue;
+            }
+        };
+
+        let method = match message.get("method").and_then(Value::as_str) {
+            Some(method) => method,
+            // Responses to requests the server never makes are ignored.
+            None if message.get("id").is_some() => continue,
+            None => {
+                let response =
+                    error_response(Value::Null, INVALID_REQUEST, "expected a method".to_owned());
+                write_message(&mut writer, &response)?;
+                continue;
+            }
+        };
+
+        if method == "exit" {
+            return Ok(server.shut_down);
+        }
+
+        let params = message.get("params").unwrap_or(&Value::Null);
+
+        // Requests have an ID to answer with, while notifications are never answered.
+        let id = match message.get("id") {
+            Some(id) => id.clone(),
+            None => {
+                for notification in server.notify(method, params) {
+                    write_message(&mut writer, &notification)?;
+                }
+
+                continue;
+            }
+        };
+
+        let response = match server.request(method, params) {
+            Ok(result) => Value::object([
+                ("jsonrpc", Value::from("2.0")),
+                ("id", id),
+                ("result", result),
+            ]),
+            Err((code, message)) => error_response(id, code, message),
+        };
+
+        write_message(&mut writer, &response)?;
+    }
+}
+
+// Reads the content of the next message, after its headers. Returns None at the end of
+// the input.
+fn read_message<R: BufRead>(reader: &mut R) -> io::Result<Option<String>> {
+    let mut length = None;
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let header = line.trim_end();
+
+        if header.is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let length = length.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "a message had no Content-Length",
+        )
+    })?;
+
+    if length > MAX_MESSAGE_LENGTH {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "a message was {} bytes long, more than the {} allowed",
+                length, MAX_MESSAGE_LENGTH
+            ),
+        ));
+    }
+
+    let mut content = vec![0; length];
+    reader.read_exact(&mut content)?;
+
+    String::from_utf8(content)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+fn write_message<W: Write>(writer: &mut W, message: &Value) -> io::Result<()> {
+    let content = message.to_string();
+
+    write!(
+        writer,
+        "Content-Length: {}\r\n\r\n{}",
+        content.len(),
+        content
+    )?;
+    writer.flush()
+}
+
+fn error_response(id: Value, code: i64, message: String) -> Value {
+    Value::object([
+        ("jsonrpc", Value::from("2.0")),
+        ("id", id),
+        (
+            "error",
+            Value::object([
+                ("code", Value::Number(code as f64)),
+                ("message", Value::from(message)),
+            ]),
+        ),
+    ])
+}
+
+#[derive(Default)]
+struct Server {
+    initialized: bool,
+    shut_down: bool,
+    // The open documents by their URI, along with how to parse each one.
+    documents: HashMap<String, (String, ParseOptions)>,
+}
+
+type RequestResult = Result<Value, (i64, String)>;
+
+impl Server {
+    fn request(&mut self, method: &str, params: &Value) -> RequestResult {
+        if method == "initialize" {
+            self.initialized = true;
+
+            return Ok(Value::object([
+                (
+                    "capabilities",
+                    Value::object([
+                        // The whole document is sent with every change.
+                        ("textDocumentSync", Value::from(1)),
+                        ("hoverProvider", Value::from(true)),
+                        ("documentSymbolProvider", Value::from(true)),
+                        ("documentFormattingProvider", Value::from(true)),
+                    ]),
+                ),
+                (
+                    "serverInfo",
+                    Value::object([
+                        ("name", Value::from("membrane")),
+                        ("version", Value::from(env!("CARGO_PKG_VERSION"))),
+                    ]),
+                ),
+            ]));
+        }
+
+        if !self.initialized {
+            return Err((
+                SERVER_NOT_INITIALIZED,
+                "the server hasn't been initialized".to_owned(),
+            ));
+        }
+
+        match method {
+            "shutdown" => {
+                self.shut_down = true;
+                Ok(Value::Null)
+            }
+            "textDocument/hover" => {
+                let (text, options) = self.document(params)?;
+                let offset = offset_of(text, position_param(params)?);
+                Ok(hover(text, options, offset).unwrap_or(Value::Null))
+            }
+            "textDocument/documentSymbol" => {
+                let (text, options) = self.document(params)?;
+                Ok(symbols(text, options).unwrap_or(Value::Null))
+            }
+            "textDocument/formatting" => {
+                let (text, options) = self.document(params)?;
+                let indent = params
+                    .get("options")
+                    .and_then(|options| options.get("tabSize"))
+                    .and_then(Value::as_u64)
+                    .map_or(FormatOptions::default().indent, |size| size as usize);
+
+                Ok(format(text, options, indent).unwrap_or(Value::Null))
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method '{}'", method))),
+        }
+    }
+
+    // Handles the notification, returning the ones to send back.
+    fn notify(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let document = params.get("textDocument");
+        let uri = document
+            .and_then(|document| document.get("uri"))
+            .and_then(Value::as_str);
+
+        let uri = match uri {
+            Some(uri) => uri.to_owned(),
+            None => return Vec::new(),
+        };
+
+        match method {
+            "textDocument/didOpen" => {
+                let text = document
+                    .and_then(|document| document.get("text"))
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+
+                self.documents
+                    .insert(uri.clone(), (text.to_owned(), parse_options(&uri)));
+            }
+            "textDocument/didChange" => {
+                // With full syncing, the last change holds the whole document.
+                let text = params
+                    .get("contentChanges")
+                    .and_then(Value::as_array)
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change.get("text"))
+                    .and_then(Value::as_str);
+
+                match (self.documents.get_mut(&uri), text) {
+                    (Some((document, _)), Some(text)) => *document = text.to_owned(),
+                    _ => return Vec::new(),
+                }
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![publish_diagnostics(&uri, Vec::new())];
+            }
+            _ => return Vec::new(),
+        }
+
+        let (text, options) = &self.documents[&uri];
+        vec![publish_diagnostics(&uri, diagnostics(text, options))]
+    }
+
+    fn document(&self, params: &Value) -> Result<(&str, &ParseOptions), (i64, String)> {
+        let uri = params
+            .get("textDocument")
+            .and_then(|document| document.get("uri"))
+            .and_then(Value::as_str)
+            .ok_or((INVALID_PARAMS, "expected a text document".to_owned()))?;
+
+        self.documents
+            .get(uri)
+            .map(|(text, options)| (text.as_str(), options))
+            .ok_or_else(|| (INVALID_PARAMS, format!("'{}' isn't open", uri)))
+    }
+}
+
+// Documents are read in the dialect their extension is for, the way files are.
+fn parse_options(uri: &str) -> ParseOptions {
+    let registry = Registry::builtin();
+    let name = uri.rsplit('/').next().unwrap_or(uri);
+
+    name.rsplit_once('.')
+        .and_then(|(_, extension)| registry.for_extension(extension))
+        .and_then(|frontend| frontend.options().cloned())
+        .unwrap_or_default()
+}
+
+fn position_param(params: &Value) -> Result<(u64, u64), (i64, String)> {
+    let position = params.get("position");
+    let field = |name| {
+        position
+            .and_then(|position| position.get(name))
+            .and_then(Value::as_u64)
+    };
+
+    match (field("line"), field("character")) {
+        (Some(line), Some(character)) => Ok((line, character)),
+        _ => Err((INVALID_PARAMS, "expected a position".to_owned())),
+    }
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Value>) -> Value {
+    Value::object([
+        ("jsonrpc", Value::from("2.0")),
+        ("method", Value::from("textDocument/publishDiagnostics")),
+        (
+            "params",
+            Value::object([
+                ("uri", Value::from(uri)),
+                ("diagnostics", Value::Array(diagnostics)),
+            ]),
+        ),
+    ])
+}
+
+fn diagnostics(text: &str, options: &ParseOptions) -> Vec<Value> {
+    let errors = match parser::parse_bytes_with(text.as_bytes(), options) {
+        Ok(_) => return Vec::new(),
+        Err(errors) => errors,
+    };
+
+    errors
+        .iter()
+        .map(|err| {
+            let span = err.location().map_or(Span::new(0, 0), Span::of);
+
+            Value::object([
+                ("range", range(text, span)),
+                // Errors, rather than warnings or hints.
+                ("severity", Value::from(1)),
+                ("source", Value::from("membrane")),
+                ("message", Value::from(err.to_string())),
+            ])
+        })
+        .collect()
+}
+
+// Shows the instructions the command under the cursor is optimized into, along with the
+// source they were optimized from.
+fn hover(text: &str, options: &ParseOptions, offset: usize) -> Option<Value> {
+    let mut program = parser::parse_bytes_with(text.as_bytes(), options).ok()?;
+    optimizer::optimize_program(&mut program, &OptimizeOptions::default()).ok()?;
+
+    // Optimized instructions span everything they were made from, so the narrowest span
+    // is the one that's most specific to the command.
+    let span = program
+        .spans
+        .iter()
+        .filter(|span| span.range().contains(&offset))
+        .min_by_key(|span| span.len())?;
+
+    let instructions = program
+        .instructions
+        .iter()
+        .zip(&program.spans)
+        .filter(|(_, other)| *other == span)
+        .map(|(instruction, _)| instruction.to_string())
+        .collect::<Vec<_>>();
+
+    let source = text.get(span.range()).unwrap_or_default();
+    let value = format!(
+        "```\n{}\n```\nfrom `{}`",
+        instructions.join("\n"),
+        abbreviate(source)
+    );
+
+    Some(Value::object([
+        (
+            "contents",
+            Value::object([
+                ("kind", Value::from("markdown")),
+                ("value", Value::from(value)),
+            ]),
+        ),
+        ("range", range(text, *span)),
+    ]))
+}
+
+// Source short enough to show on one line of a hover.
+fn abbreviate(source: &str) -> String {
+    const LIMIT: usize = 40;
+
+    let source = source.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    match source.char_indices().nth(LIMIT) {
+        Some((end, _)) => format!("{}...", &source[..end]),
+        None => source,
+    }
+}
+
+// The loops and procedures at the top level of the program, named after the comment on the
+// line before them if they have one.
+fn symbols(text: &str, options: &ParseOptions) -> Option<Value> {
+    let program = parser::parse_bytes_with(
+        text.as_bytes(),
+        &ParseOptions {
+            trivia: true,
+            ..options.clone()
+        },
+    )
+    .ok()?;
+
+    let mut symbols = Vec::new();
+    let mut index = 0;
+
+    while index < program.instructions.len() {
+        let (end, kind, noun) = match program.instructions[index] {
+            Instruction::JumpIfZero { location } => (location, LOOP_SYMBOL, "loop"),
+            Instruction::DefineProc { location } => (location, PROCEDURE_SYMBOL, "procedure"),
+            _ => {
+                index += 1;
+                continue;
+            }
+        };
+
+        let start = program.spans[index];
+        let whole = Span {
+            end: program.spans[end].end,
+            ..start
+        };
+
+        let name = symbol_name(&program, index)
+            .unwrap_or_else(|| format!("{} at line {}", noun, start.line));
+
+        symbols.push(Value::object([
+            ("name", Value::from(name)),
+            ("kind", Value::from(kind)),
+            ("range", range(text, whole)),
+            ("selectionRange", range(text, start)),
+        ]));
+
+        index = end + 1;
+    }
+
+    Some(Value::Array(symbols))
+}
+
+fn symbol_name(program: &Program, index: usize) -> Option<String> {
+    let leading = program.trivia.as_ref()?.leading(index);
+
+    leading
+        .split(|&byte| byte == b'\n')
+        .map(<[u8]>::trim_ascii)
+        .rfind(|line| !line.is_empty())
+        .map(|line| String::from_utf8_lossy(line).into_owned())
+}
+
+// Replaces the whole document with it formatted, unless it's formatted already. Documents
+// that don't parse are left alone.
+fn format(text: &str, parse_options: &ParseOptions, indent: usize) -> Option<Value> {
+    let options = FormatOptions {
+        indent,
+        ..FormatOptions::default()
+    };
+
+    let formatted = formatter::format(text.as_bytes(), parse_options, &options);
+    let formatted = String::from_utf8(formatted.ok()?).ok()?;
+
+    if formatted == text {
+        return Some(Value::Array(Vec::new()));
+    }
+
+    Some(Value::Array(vec![Value::object([
+        ("range", range(text, Span::new(0, text.len()))),
+        ("newText", Value::from(formatted)),
+    ])]))
+}
+
+fn range(text: &str, span: Span) -> Value {
+    Value::object([
+        ("start", position_of(text, span.start)),
+        ("end", position_of(text, span.end)),
+    ])
+}
+
+// Positions count lines from zero, and characters in UTF-16 code units.
+fn position_of(text: &str, offset: usize) -> Value {
+    let offset = offset.min(text.len());
+    let before = &text.as_bytes()[..offset];
+    let line_start = before
+        .iter()
+        .rposition(|&byte| byte == b'\n')
+        .map_or(0, |newline| newline + 1);
+
+    let line = before.iter().filter(|&&byte| byte == b'\n').count();
+    let character = String::from_utf8_lossy(&before[line_start..])
+        .encode_utf16()
+        .count();
+
+    Value::object([
+        ("line", Value::from(line)),
+        ("character", Value::from(character)),
+    ])
+}
+
+fn offset_of(text: &str, (line, character): (u64, u64)) -> usize {
+    let line_start = match line {
+        0 => 0,
+        line => match text.match_indices('\n').nth(line as usize - 1) {
+            Some((newline, _)) => newline + 1,
+            None => return text.len(),
+        },
+    };
+
+    let mut units = 0;
+
+    for (offset, found) in text[line_start..].char_indices() {
+        if found == '\n' || units >= character {
+            return line_start + offset;
+        }
+
+        units += found.len_utf16() as u64;
+    }
+
+    text.len()
+}
@@ -4,25 +4,215 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use std::fs::File;
-use std::io::{self, BufReader, BufWriter, Cursor, Read, Seek, SeekFrom};
-use std::time::Instant;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Cursor, IsTerminal, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use clap::{ArgAction, Parser};
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
+use tracing::Level;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::fmt::time;
 
-use membrane::interpreter::{InputSource, OutputSource, TapeSize};
+use membrane::analysis::{Metrics, NGramMiner};
+use membrane::cache::{Cache, CacheKey};
+use membrane::compilers::bytecode::{Header, MAGIC};
+use membrane::compilers::native::Toolchain;
+use membrane::compilers::source_map::json_string;
+use membrane::compilers::{
+    rust, Annotations, Backend, CodegenChecks, CompileError, CompileOptions, ProgramInfo, Registry,
+};
+use membrane::config::Config;
+use membrane::dialect::{Dialect, DialectMap};
+use membrane::formatter::FormatOptions;
+use membrane::frontend::{Frontend, ParserFrontend};
+use membrane::instruction::Instruction;
+use membrane::interpreter::{
+    CellWidth, EofMode, InputSource, Interpreter, InterpreterBuilder, OutputSource, RuntimeError,
+    TapeSize, WrapSemantics,
+};
+use membrane::lister::{ListingFormat, ListingOptions};
+use membrane::loader::LoadError;
+use membrane::optimizer::{OptimizeOptions, OptimizeReport};
+use membrane::parser::{ParseError, ParseOptions, PARALLEL_THRESHOLD};
+use membrane::profile::Profile;
+use membrane::program::Program;
+use membrane::span::Location;
 use membrane::*;
 
+// The codes membrane exits with, which the help lists so that scripts can tell the ways it
+// fails apart. Clap also exits with EXIT_USAGE when the arguments are wrong.
+const EXIT_FAILURE: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_RUNTIME_ERROR: i32 = 3;
+const EXIT_FUEL_EXHAUSTED: i32 = 4;
+const EXIT_IO_ERROR: i32 = 5;
+
 #[derive(Parser)]
-#[clap(version, about, long_about = None)]
-struct Args {
+#[clap(
+    version,
+    about,
+    long_about = None,
+    after_help = "EXIT CODES:\n    0    Success.\n    1    The program couldn't be parsed or optimized, or failed a check; diff also exits with 1 when the programs differ.\n    2    The arguments or the configuration were wrong.\n    3    The program failed while it ran, such as by moving left of the first cell.\n    4    --opt-fuel ran out before the optimizer was done. Everything else was still done.\n    5    A file, standard input, or standard output couldn't be read or written."
+)]
+struct Cli {
+    #[clap(
+        long,
+        global = true,
+        value_name = "FILE",
+        help = "Read defaults for the options from FILE, instead of membrane.toml in the current directory or $XDG_CONFIG_HOME/membrane (~/.config/membrane)."
+    )]
+    config: Option<String>,
+
+    #[clap(
+        long,
+        global = true,
+        conflicts_with = "config",
+        help = "Ignore any membrane.toml, using the usual defaults for the options."
+    )]
+    no_config: bool,
+
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    #[clap(about = "Interpret a Brainfuck program, optionally listing and compiling it first.")]
+    Run(RunArgs),
+
+    #[clap(about = "Time how long a Brainfuck program takes to run.")]
+    Bench(BenchArgs),
+
+    #[clap(about = "Check whether two programs are equivalent after canonicalization.")]
+    Diff(DiffArgs),
+
+    #[clap(
+        about = "Parse Brainfuck programs without running them, reporting every error in them."
+    )]
+    Check(CheckArgs),
+
+    #[clap(about = "Reflow Brainfuck programs, indenting loops by how deeply they nest.")]
+    Fmt(FmtArgs),
+
+    #[clap(about = "Shrink a Brainfuck program down to its commands.")]
+    Minify(MinifyArgs),
+
+    #[clap(about = "Rewrite a program from one dialect of Brainfuck in another.")]
+    Convert(ConvertArgs),
+
+    #[clap(about = "Compile a Brainfuck program to another format.")]
+    Compile(CompileArgs),
+
+    #[clap(about = "List the instructions a Brainfuck program is parsed or optimized into.")]
+    List(ListArgs),
+
+    #[clap(about = "Report metrics of a Brainfuck program, or analyze a corpus of them.")]
+    Analyze(AnalyzeArgs),
+
+    #[clap(about = "Walk through a Brainfuck program, explaining what each part of it does.")]
+    Explain(ExplainArgs),
+
+    #[clap(subcommand, about = "Generate Brainfuck programs.")]
+    Generate(GenerateCommand),
+
+    #[clap(
+        about = "Run a language server for Brainfuck over standard input and output, for editors."
+    )]
+    Lsp,
+}
+
+#[derive(Subcommand)]
+enum GenerateCommand {
+    #[clap(about = "Generate a short program that writes the text given.")]
+    Text(GenerateTextArgs),
+}
+
+#[derive(Args, Clone)]
+struct OptimizeArgs {
+    #[clap(
+        short = 'O',
+        long,
+        help = "Perform optimizations before interpreting, listing, and compiling."
+    )]
+    optimize: bool,
+
+    #[clap(
+        long,
+        help = "Only permit the first N individual optimizer rewrites. Bisecting on this pinpoints the rewrite responsible for a miscompile. The cache is never used with it."
+    )]
+    opt_fuel: Option<usize>,
+
+    #[clap(
+        long,
+        help = "Always optimize from scratch instead of reusing (or storing) cached results."
+    )]
+    no_cache: bool,
+
+    #[clap(
+        long,
+        help = "The directory to cache optimized programs in. Defaults to $XDG_CACHE_HOME/membrane or ~/.cache/membrane."
+    )]
+    cache_dir: Option<String>,
+
+    #[clap(
+        long,
+        help = "Dump the instructions to stderr if the optimizer fails an internal consistency check."
+    )]
+    dump_ir: bool,
+}
+
+// How results meant for people to read are printed, for the subcommands that can print
+// them for tools as well.
+#[derive(Copy, Clone, Eq, PartialEq, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Args)]
+struct DialectArgs {
+    #[clap(
+        long,
+        conflicts_with = "dialect-map",
+        help = "The dialect the program is written in. One of: brainfuck, ook, pbrain, listing. Defaults to ook for .ook files, listing for .lst files, and brainfuck otherwise. Programs with pbrain's procedures can only be run or compiled to the rust, bytecode, and brainfuck formats."
+    )]
+    dialect: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "FILE",
+        help = "Read the program in a dialect that spells the commands with other tokens, given by a file with a command and its token on each line, such as '+ inc'."
+    )]
+    dialect_map: Option<String>,
+}
+
+#[derive(Args)]
+struct RunArgs {
     #[clap(
         short,
         long,
         action = ArgAction::Count,
         help = "Print additional information during program execution."
     )]
-    verbose: u32,
+    verbose: u8,
+
+    #[clap(
+        long,
+        value_enum,
+        default_value = "text",
+        help = "How to print what --verbose reports. With json, it's written to standard error as one object once the program ends, apart from the program's own output."
+    )]
+    format: OutputFormat,
+
+    #[clap(flatten)]
+    optimize_args: OptimizeArgs,
+
+    #[clap(flatten)]
+    dialect_args: DialectArgs,
 
     #[clap(
         short,
@@ -32,11 +222,14 @@ struct Args {
     partial: bool,
 
     #[clap(
-        short = 'O',
         long,
-        help = "Perform optimizations before interpreting, listing, and compiling."
+        value_name = "CELL",
+        min_values = 0,
+        require_equals = true,
+        conflicts_with = "partial",
+        help = "Exit with the value of the cell the head ends on, or of the cell given by its index, such as --exit-cell=0, once the program ends. Errors still exit with their own codes. With several programs, the cell is the last one's."
     )]
-    optimize: bool,
+    exit_cell: Option<Option<usize>>,
 
     #[clap(
         short = 'R',
@@ -55,10 +248,23 @@ struct Args {
     #[clap(
         short,
         long = "tape",
-        help = "The tape size to use while optimizing, interpreting, and compiling. Zero (0) corresponds to a right-infinite tape, and positive values correspond to a finite, wrapping tape.",
-        default_value_t = 0
+        value_name = "CELLS",
+        value_parser = interpreter::parse_tape_size,
+        help = "The tape size to use while optimizing, interpreting, and compiling. Zero (0) corresponds to a right-infinite tape, and positive values correspond to a finite, wrapping tape. Sizes can end in k, M, or G, or Ki, Mi, or Gi for powers of two, such as 30k or 64Ki. Defaults to 0."
+    )]
+    tape_size: Option<usize>,
+
+    #[clap(
+        long,
+        help = "The width of every cell, in bits. One of: 8, 16, 32. Wider cells can't be optimized. Defaults to 8."
+    )]
+    cell_width: Option<CellWidth>,
+
+    #[clap(
+        long = "eof",
+        help = "What reads store once input runs out. One of: unchanged, zero, negative-one. Defaults to unchanged, as in compiled programs."
     )]
-    tape_size: usize,
+    eof_mode: Option<EofMode>,
 
     #[clap(
         short,
@@ -77,98 +283,2700 @@ struct Args {
     #[clap(
         short,
         long = "listing",
-        help = "An optional listing file to fill with instructions and membrane data. (Created after optimizations.)"
+        help = "An optional listing file to fill with instructions and membrane data, or - to print the listing to standard output before the program runs. (Created after optimizations.)"
     )]
     listing_file: Option<String>,
 
+    #[clap(
+        long,
+        value_name = "FORMAT",
+        requires = "listing-file",
+        help = "How to write the listing. One of: text, json, csv. JSON and CSV have one record per instruction, with its opcode, operands, and the span of source it came from. Defaults to text."
+    )]
+    listing_format: Option<ListingFormat>,
+
+    #[clap(
+        long,
+        value_name = "FILE",
+        conflicts_with = "partial",
+        help = "Count how many times each instruction runs, and write the counts to the file as JSON once the program ends, for membrane list --profile to show."
+    )]
+    profile: Option<String>,
+
+    #[clap(
+        long,
+        conflicts_with = "read-file",
+        help = "End the program at the first ! outside of a loop, and use everything after it as the program's input, the way many archives store programs."
+    )]
+    inline_input: bool,
+
+    #[clap(
+        long,
+        help = "Expand @define, @include, and @name macros before parsing the program."
+    )]
+    preprocess: bool,
+
+    #[clap(
+        short,
+        long,
+        value_name = "CODE",
+        conflicts_with = "brainfuck-files",
+        help = "Run the code given instead of a file, such as -e '++++++++[>++++++++<-]>+.'."
+    )]
+    eval: Option<String>,
+
+    #[clap(
+        long,
+        conflicts_with = "eval",
+        help = "Run the program again every time it, or the file given with --read, changes, clearing the screen between runs."
+    )]
+    watch: bool,
+
+    #[clap(
+        required_unless_present = "eval",
+        help = "The Brainfuck file to interpret, or - to read it from standard input, which leaves the program no input unless it's given with --read. Given several, they run at the same time, with the output of each piped into the input of the next, as in a shell pipeline."
+    )]
+    brainfuck_files: Vec<String>,
+}
+
+#[derive(Args)]
+struct BenchArgs {
+    #[clap(flatten)]
+    optimize_args: OptimizeArgs,
+
+    #[clap(flatten)]
+    dialect_args: DialectArgs,
+
+    #[clap(
+        long,
+        conflicts_with = "optimize",
+        help = "Time the program both without and with optimizations, and compare the two."
+    )]
+    compare: bool,
+
+    #[clap(
+        short = 'n',
+        long,
+        help = "The number of timed runs.",
+        value_parser = clap::value_parser!(u32).range(1..),
+        default_value_t = 10
+    )]
+    runs: u32,
+
+    #[clap(
+        long,
+        help = "The number of untimed runs before the timed ones.",
+        default_value_t = 1
+    )]
+    warmup: u32,
+
+    #[clap(
+        short,
+        long = "tape",
+        value_name = "CELLS",
+        value_parser = interpreter::parse_tape_size,
+        help = "The tape size to use while optimizing and interpreting. Zero (0) corresponds to a right-infinite tape, and positive values correspond to a finite, wrapping tape. Sizes can end in k, M, or G, or Ki, Mi, or Gi for powers of two, such as 30k or 64Ki. Defaults to 0."
+    )]
+    tape_size: Option<usize>,
+
     #[clap(
         short,
-        long = "compile",
-        help = "An optional C file to compile the input code to. (Created after optimizations.)"
+        long = "read",
+        help = "A file of input to give the program on every run. The program gets no input without it, and must not read past the end of what it's given."
+    )]
+    read_file: Option<String>,
+
+    #[clap(
+        long,
+        conflicts_with = "read-file",
+        help = "End the program at the first ! outside of a loop, and use everything after it as the program's input."
     )]
-    c_file: Option<String>,
+    inline_input: bool,
 
-    #[clap(help = "The Brainfuck file to interpret or compile.")]
+    #[clap(
+        long,
+        help = "Expand @define, @include, and @name macros before parsing the program."
+    )]
+    preprocess: bool,
+
+    #[clap(help = "The Brainfuck file to time, or - to read it from standard input.")]
     brainfuck_file: String,
 }
 
-fn main() {
-    let args = Args::parse();
+#[derive(Args)]
+struct CompileArgs {
+    #[clap(
+        short,
+        long,
+        action = ArgAction::Count,
+        help = "Print additional information while compiling."
+    )]
+    verbose: u8,
 
-    let mut instructions = parser::parse_file(&args.brainfuck_file).unwrap();
+    #[clap(flatten)]
+    optimize_args: OptimizeArgs,
 
-    let tape_size = if args.tape_size == 0 {
-        TapeSize::Infinite
-    } else {
-        TapeSize::Finite(args.tape_size)
-    };
+    #[clap(flatten)]
+    dialect_args: DialectArgs,
 
-    if args.optimize {
-        optimizer::optimize(args.verbose > 1, &mut instructions, tape_size);
-    }
+    #[clap(
+        short,
+        long,
+        help = "The format to compile to. One of: bytecode, c, x86_64, wat, wasm, csharp, lua, java, brainfuck, rust, bundle, qbe, typescript, and object when built with the cranelift feature. Inferred from the extension of the output file if it's left out, and defaults to c with --native and rust with --project."
+    )]
+    format: Option<String>,
 
-    if let Some(listing_file) = args.listing_file {
-        lister::create_listing(&instructions, listing_file).unwrap();
-    }
+    #[clap(
+        long,
+        help = "Build an executable from the generated C, Rust, bundle, or object file, instead of writing it out."
+    )]
+    native: bool,
 
-    if let Some(c_file) = args.c_file {
-        compiler::compile_to_c(&instructions, c_file).unwrap();
-    }
+    #[clap(
+        long,
+        requires = "native",
+        help = "The compiler to build the executable with. Defaults to $CC or cc for C and object files, and $RUSTC or rustc for Rust and bundles."
+    )]
+    compiler: Option<String>,
 
-    if !args.partial {
-        let input = if let Some(filename) = args.read_file {
-            let mut file = File::open(filename).unwrap();
+    #[clap(
+        long,
+        conflicts_with = "native",
+        help = "Also write a JSON source map next to the output, as OUTPUT_FILE.map, recording which source offsets each generated line came from. Supported by the c, x86_64, wat, csharp, lua, rust, qbe, and typescript formats."
+    )]
+    source_map: bool,
 
-            if args.buffer_read {
-                InputSource::FileBuffer(BufReader::new(file))
-            } else {
-                let mut contents = match file.seek(SeekFrom::End(0)) {
-                    Ok(end) => match file.seek(SeekFrom::Start(0)) {
-                        Ok(start) => Vec::with_capacity((end - start) as usize),
-                        Err(_) => Vec::new(),
-                    },
-                    Err(_) => Vec::new(),
-                };
-
-                match file.read_to_end(&mut contents) {
-                    Ok(_) => InputSource::File(Cursor::new(contents)),
-                    Err(_) => {
-                        // TODO: Throw an error here; failed to read contents of file.
-                        return;
-                    }
-                }
-            }
-        } else if args.buffer_read {
-            InputSource::StdinBuffer(BufReader::new(io::stdin()))
-        } else {
-            InputSource::Stdin(io::stdin())
-        };
+    #[clap(
+        long,
+        value_name = "DIR",
+        conflicts_with_all = &["native", "source-map", "output-file"],
+        help = "Write a Cargo package to DIR, with the compiled program as src/main.rs and its source in a README, instead of a single file. Only works with the rust format."
+    )]
+    project: Option<String>,
 
-        let output = if let Some(filename) = args.write_file {
-            let file = File::create(filename).unwrap();
+    #[clap(
+        long,
+        help = "Precede the code written for each instruction with a comment showing the Brainfuck it came from and its offset in the source."
+    )]
+    annotate: bool,
 
-            if args.buffer_write {
-                OutputSource::FileBuffer(BufWriter::new(file))
-            } else {
-                OutputSource::File(file)
-            }
-        } else if args.buffer_write {
-            OutputSource::StdoutBuffer(BufWriter::new(io::stdout()))
-        } else {
-            OutputSource::Stdout(io::stdout())
-        };
+    #[clap(
+        short,
+        long = "tape",
+        value_name = "CELLS",
+        value_parser = interpreter::parse_tape_size,
+        help = "The tape size to compile for. Zero (0) corresponds to a right-infinite tape, and positive values correspond to a finite, wrapping tape. Sizes can end in k, M, or G, or Ki, Mi, or Gi for powers of two, such as 30k or 64Ki. Defaults to 0."
+    )]
+    tape_size: Option<usize>,
 
-        let start_time = (args.verbose > 0).then(Instant::now);
-        let instructions_executed = interpreter::interpret(&instructions, input, output, tape_size);
+    #[clap(
+        long,
+        help = "The width of every cell, in bits. One of: 8, 16, 32. Wider cells can't be optimized. Defaults to 8."
+    )]
+    cell_width: Option<CellWidth>,
 
-        if let Some(time) = start_time {
-            let elapsed = time.elapsed();
-            let elapsed_ms = elapsed.as_millis();
-            let inst_per_sec = (instructions_executed as f64) / elapsed.as_secs_f64();
-            println!(
-                "Execution took {} ms ({:} inst/sec).",
-                elapsed_ms, inst_per_sec as usize,
-            );
-        }
-    }
+    #[clap(
+        long = "eof",
+        help = "What reads store once input runs out. One of: unchanged, zero, negative-one. Defaults to unchanged."
+    )]
+    eof_mode: Option<EofMode>,
+
+    #[clap(
+        long,
+        help = "What happens when the head moves past either end of a finite tape. One of: wrap, abort.",
+        default_value_t = WrapSemantics::Wrap
+    )]
+    wrap_semantics: WrapSemantics,
+
+    #[clap(
+        long,
+        help = "How much the generated code checks for moving past the ends of the tape. One of: full, minimal (fail without saying why), none (for programs known to stay on it). Only the c and rust formats tell full and minimal apart.",
+        default_value_t = CodegenChecks::Full
+    )]
+    codegen_checks: CodegenChecks,
+
+    #[clap(
+        long,
+        conflicts_with = "codegen-checks",
+        help = "Leave out the checks for moving past the ends of the tape. The same as --codegen-checks none."
+    )]
+    no_runtime_checks: bool,
+
+    #[clap(
+        long,
+        help = "Count how often each loop runs, and print the counts to stderr when the program exits. Loops are identified by the index of the instruction they start at. Only works with the c and rust formats."
+    )]
+    instrument: bool,
+
+    #[clap(
+        long,
+        help = "End the program at the first ! outside of a loop, leaving out the input after it, the way many archives store programs."
+    )]
+    inline_input: bool,
+
+    #[clap(
+        long,
+        help = "Expand @define, @include, and @name macros before parsing the program."
+    )]
+    preprocess: bool,
+
+    #[clap(
+        short,
+        long,
+        value_name = "CODE",
+        help = "Compile the code given instead of a file. The only file given is then the one to write to."
+    )]
+    eval: Option<String>,
+
+    #[clap(
+        required_unless_present = "eval",
+        help = "The Brainfuck file to compile, or - to read it from standard input."
+    )]
+    brainfuck_file: Option<String>,
+
+    #[clap(
+        required_unless_present_any = &["project", "eval"],
+        help = "The file to write the compiled program to, or - for standard output."
+    )]
+    output_file: Option<String>,
+}
+
+#[derive(Args)]
+struct CheckArgs {
+    #[clap(
+        short = 'O',
+        long,
+        help = "Also optimize the programs, reporting anything the optimizer finds wrong with them."
+    )]
+    optimize: bool,
+
+    #[clap(flatten)]
+    dialect_args: DialectArgs,
+
+    #[clap(
+        short,
+        long = "tape",
+        value_name = "CELLS",
+        value_parser = interpreter::parse_tape_size,
+        help = "The tape size to optimize for. Zero (0) corresponds to a right-infinite tape, and positive values correspond to a finite, wrapping tape. Sizes can end in k, M, or G, or Ki, Mi, or Gi for powers of two, such as 30k or 64Ki. Defaults to 0."
+    )]
+    tape_size: Option<usize>,
+
+    #[clap(
+        long,
+        help = "End each program at the first ! outside of a loop, ignoring the input after it."
+    )]
+    inline_input: bool,
+
+    #[clap(
+        long,
+        help = "Expand @define, @include, and @name macros before parsing the programs."
+    )]
+    preprocess: bool,
+
+    #[clap(
+        long,
+        help = "Check the programs again every time one of them changes, clearing the screen between checks."
+    )]
+    watch: bool,
+
+    #[clap(
+        long,
+        value_enum,
+        default_value = "text",
+        help = "How to report problems. With json, they're printed to standard output as one object once every program is checked."
+    )]
+    format: OutputFormat,
+
+    #[clap(
+        required = true,
+        help = "The Brainfuck files to check, or - to read one from standard input."
+    )]
+    brainfuck_files: Vec<String>,
+}
+
+#[derive(Args)]
+struct FmtArgs {
+    #[clap(flatten)]
+    dialect_args: DialectArgs,
+
+    #[clap(
+        long,
+        help = "The widest a line of code can be. Comments are never wrapped.",
+        default_value_t = 80
+    )]
+    width: usize,
+
+    #[clap(
+        long,
+        help = "The number of spaces to indent by for each level of nesting.",
+        default_value_t = 4
+    )]
+    indent: usize,
+
+    #[clap(
+        long,
+        help = "Leave out comments, keeping only the code and blank lines."
+    )]
+    strip_comments: bool,
+
+    #[clap(
+        long,
+        help = "Report the files that aren't formatted instead of formatting them, exiting with 1 if there are any."
+    )]
+    check: bool,
+
+    #[clap(
+        long,
+        help = "End each program at the first ! outside of a loop, keeping the input after it as it is."
+    )]
+    inline_input: bool,
+
+    #[clap(
+        required = true,
+        help = "The Brainfuck files to format in place, or - to format standard input to standard output."
+    )]
+    brainfuck_files: Vec<String>,
+}
+
+#[derive(Args)]
+struct MinifyArgs {
+    #[clap(
+        short = 'O',
+        long,
+        help = "Also optimize the program and write it back out as Brainfuck, keeping whichever is shorter."
+    )]
+    optimize: bool,
+
+    #[clap(flatten)]
+    dialect_args: DialectArgs,
+
+    #[clap(
+        short,
+        long = "tape",
+        value_name = "CELLS",
+        value_parser = interpreter::parse_tape_size,
+        help = "The tape size to optimize for. Zero (0) corresponds to a right-infinite tape, and positive values correspond to a finite, wrapping tape. Sizes can end in k, M, or G, or Ki, Mi, or Gi for powers of two, such as 30k or 64Ki. Defaults to 0."
+    )]
+    tape_size: Option<usize>,
+
+    #[clap(
+        long,
+        help = "End the program at the first ! outside of a loop, keeping the input after it as it is."
+    )]
+    inline_input: bool,
+
+    #[clap(
+        long,
+        help = "Expand @define, @include, and @name macros before parsing the program."
+    )]
+    preprocess: bool,
+
+    #[clap(help = "The Brainfuck file to minify, or - to read it from standard input.")]
+    brainfuck_file: String,
+
+    #[clap(help = "The file to write the minified program to. Defaults to standard output.")]
+    output_file: Option<String>,
+}
+
+#[derive(Args)]
+struct ListArgs {
+    #[clap(flatten)]
+    optimize_args: OptimizeArgs,
+
+    #[clap(flatten)]
+    dialect_args: DialectArgs,
+
+    #[clap(
+        short,
+        long = "tape",
+        value_name = "CELLS",
+        value_parser = interpreter::parse_tape_size,
+        help = "The tape size to optimize for. Zero (0) corresponds to a right-infinite tape, and positive values correspond to a finite, wrapping tape. Sizes can end in k, M, or G, or Ki, Mi, or Gi for powers of two, such as 30k or 64Ki. Defaults to 0."
+    )]
+    tape_size: Option<usize>,
+
+    #[clap(
+        long,
+        value_name = "FORMAT",
+        help = "How to write the listing. One of: text, json, csv. JSON and CSV have one record per instruction, with its opcode, operands, and the span of source it came from. Defaults to text."
+    )]
+    format: Option<ListingFormat>,
+
+    #[clap(
+        long,
+        value_name = "FILE",
+        help = "Add how many times each instruction ran, and its share of the total, from a profile written by membrane run --profile with the same options. The hottest instructions are marked with a *."
+    )]
+    profile: Option<String>,
+
+    #[clap(
+        long,
+        help = "End the listing with a summary of the program: how many of each instruction it has, its loops and how deeply they nest, and with -O, how many instructions it had before it was optimized. CSV listings can't have one."
+    )]
+    summary: bool,
+
+    #[clap(
+        long,
+        help = "End the program at the first ! outside of a loop, keeping the input after it as it is."
+    )]
+    inline_input: bool,
+
+    #[clap(
+        short,
+        long,
+        value_name = "CODE",
+        conflicts_with = "brainfuck-file",
+        help = "List the code given instead of a file, such as -e '++++++++[>++++++++<-]>+.'."
+    )]
+    eval: Option<String>,
+
+    #[clap(
+        required_unless_present = "eval",
+        help = "The Brainfuck file to list, or - to read it from standard input."
+    )]
+    brainfuck_file: Option<String>,
+}
+
+#[derive(Args)]
+struct ExplainArgs {
+    #[clap(flatten)]
+    dialect_args: DialectArgs,
+
+    #[clap(
+        short,
+        long = "tape",
+        value_name = "CELLS",
+        value_parser = interpreter::parse_tape_size,
+        help = "The tape size to optimize for. Zero (0) corresponds to a right-infinite tape, and positive values correspond to a finite, wrapping tape. Sizes can end in k, M, or G, or Ki, Mi, or Gi for powers of two, such as 30k or 64Ki. Defaults to 0."
+    )]
+    tape_size: Option<usize>,
+
+    #[clap(help = "The Brainfuck file to explain, or - to read it from standard input.")]
+    brainfuck_file: String,
+}
+
+#[derive(Args)]
+struct ConvertArgs {
+    #[clap(
+        long,
+        value_name = "DIALECT",
+        conflicts_with = "from-map",
+        help = "The dialect to convert from. One of: brainfuck, ook, pbrain, listing. Defaults to ook for .ook files, listing for .lst files, and brainfuck otherwise."
+    )]
+    from: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "FILE",
+        help = "Convert from a dialect given by a map file, in the same form as --dialect-map."
+    )]
+    from_map: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "DIALECT",
+        conflicts_with = "to-map",
+        help = "The dialect to convert to. One of: brainfuck, ook, pbrain. Defaults to the one the output file's extension is for."
+    )]
+    to: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "FILE",
+        help = "Convert to a dialect given by a map file, in the same form as --dialect-map. Commands with several tokens are written with the shortest."
+    )]
+    to_map: Option<String>,
+
+    #[clap(help = "The file to convert, or - to read it from standard input.")]
+    input_file: String,
+
+    #[clap(help = "The file to write the converted program to. Defaults to standard output.")]
+    output_file: Option<String>,
+}
+
+#[derive(Args)]
+struct GenerateTextArgs {
+    #[clap(
+        long,
+        help = "Try many ways of laying out the text on the tape and keep the shortest, instead of the usual one."
+    )]
+    optimize_size: bool,
+
+    #[clap(help = "The text for the program to write.")]
+    text: String,
+
+    #[clap(help = "The file to write the program to. Defaults to standard output.")]
+    output_file: Option<String>,
+}
+
+#[derive(Args)]
+struct DiffArgs {
+    #[clap(help = "The original Brainfuck file, or - to read it from standard input.")]
+    original_file: String,
+
+    #[clap(
+        help = "The Brainfuck file to compare against the original, or - to read it from standard input."
+    )]
+    modified_file: String,
+}
+
+#[derive(Args)]
+struct AnalyzeArgs {
+    #[clap(
+        short = 'O',
+        long,
+        help = "Also report the metrics of the program after optimization."
+    )]
+    optimize: bool,
+
+    #[clap(long, help = "Print the metrics as JSON, the same as --format json.")]
+    json: bool,
+
+    #[clap(
+        long,
+        value_enum,
+        default_value = "text",
+        help = "How to print the metrics."
+    )]
+    format: OutputFormat,
+
+    #[clap(flatten)]
+    dialect_args: DialectArgs,
+
+    #[clap(
+        long,
+        conflicts_with_all = &["optimize", "json", "format"],
+        help = "Report the most frequent instruction sequences after optimization, as candidates for new fused instructions."
+    )]
+    suggest_superinstructions: bool,
+
+    #[clap(
+        long,
+        help = "The shortest instruction sequence to consider.",
+        default_value_t = 2
+    )]
+    min_length: usize,
+
+    #[clap(
+        long,
+        help = "The longest instruction sequence to consider.",
+        default_value_t = 4
+    )]
+    max_length: usize,
+
+    #[clap(
+        short = 'n',
+        long,
+        help = "The number of candidates to report.",
+        default_value_t = 20
+    )]
+    top: usize,
+
+    #[clap(
+        help = "The Brainfuck file to report metrics of, or - to read it from standard input. With --suggest-superinstructions, the directory of Brainfuck (.b/.bf) files to analyze."
+    )]
+    path: String,
+}
+
+// Fills in the options left off the command line from the configuration. Flags can only
+// turn options on, so options the configuration turns on stay on.
+fn apply_config(command: &mut Command, config: &Config) {
+    let optimize = config.optimize.unwrap_or(false);
+
+    match command {
+        Command::Run(args) => {
+            args.tape_size = args.tape_size.or(config.tape_size);
+            args.cell_width = args.cell_width.or(config.cell_width);
+            args.eof_mode = args.eof_mode.or(config.eof_mode);
+            args.optimize_args.optimize |= optimize;
+            args.buffer_read |= config.buffer_read.unwrap_or(false);
+            args.buffer_write |= config.buffer_write.unwrap_or(false);
+        }
+        Command::Bench(args) => {
+            args.tape_size = args.tape_size.or(config.tape_size);
+            args.optimize_args.optimize |= optimize && !args.compare;
+        }
+        Command::Check(args) => {
+            args.tape_size = args.tape_size.or(config.tape_size);
+            args.optimize |= optimize;
+        }
+        Command::Minify(args) => {
+            args.tape_size = args.tape_size.or(config.tape_size);
+            args.optimize |= optimize;
+        }
+        Command::List(args) => {
+            args.tape_size = args.tape_size.or(config.tape_size);
+            args.optimize_args.optimize |= optimize;
+        }
+        Command::Explain(args) => args.tape_size = args.tape_size.or(config.tape_size),
+        Command::Compile(args) => {
+            args.tape_size = args.tape_size.or(config.tape_size);
+            args.cell_width = args.cell_width.or(config.cell_width);
+            args.eof_mode = args.eof_mode.or(config.eof_mode);
+            args.optimize_args.optimize |= optimize;
+        }
+        Command::Diff(_)
+        | Command::Convert(_)
+        | Command::Fmt(_)
+        | Command::Analyze(_)
+        | Command::Generate(_)
+        | Command::Lsp => {}
+    }
+}
+
+// Zero stands for a right-infinite tape, and is the default.
+fn tape_size(size: Option<usize>) -> TapeSize {
+    match size.unwrap_or(0) {
+        0 => TapeSize::Infinite,
+        size => TapeSize::Finite(size),
+    }
+}
+
+// The tape, cells, and end of input a program is run or compiled with. Bytecode records the
+// ones it was compiled for, which fill in whatever the command line leaves out.
+#[derive(Copy, Clone, Default)]
+struct Machine {
+    tape_size: Option<TapeSize>,
+    cell_width: Option<CellWidth>,
+    eof_mode: Option<EofMode>,
+}
+
+impl Machine {
+    fn new(
+        tape_size: Option<usize>,
+        cell_width: Option<CellWidth>,
+        eof_mode: Option<EofMode>,
+    ) -> Self {
+        Self {
+            tape_size: tape_size.map(|size| self::tape_size(Some(size))),
+            cell_width,
+            eof_mode,
+        }
+    }
+
+    fn tape_size(&self) -> TapeSize {
+        self.tape_size.unwrap_or(TapeSize::Infinite)
+    }
+
+    fn cell_width(&self) -> CellWidth {
+        self.cell_width.unwrap_or_default()
+    }
+
+    fn eof_mode(&self) -> EofMode {
+        self.eof_mode.unwrap_or_default()
+    }
+
+    // Programs run the way they would once compiled, so reads past the end of input never
+    // stop them.
+    fn interpreter(&self) -> InterpreterBuilder {
+        Interpreter::builder()
+            .tape(self.tape_size())
+            .cell_width(self.cell_width())
+            .eof(self.eof_mode())
+    }
+
+    // Exits if the command line asked for something other than what the bytecode was
+    // compiled for, since it may have been optimized for it.
+    fn apply_header(&mut self, header: &Header, name: &str) {
+        let cells = |tape_size| match tape_size {
+            TapeSize::Finite(size) => size,
+            TapeSize::Infinite => 0,
+        };
+        let cell_width = CellWidth::ALL
+            .iter()
+            .copied()
+            .find(|width| width.bytes() == header.cell_width)
+            .unwrap_or_default();
+
+        let conflict = match *self {
+            Self {
+                tape_size: Some(tape_size),
+                ..
+            } if tape_size != header.tape_size => Some((
+                "--tape",
+                cells(header.tape_size).to_string(),
+                cells(tape_size).to_string(),
+            )),
+            Self {
+                cell_width: Some(width),
+                ..
+            } if width != cell_width => Some((
+                "--cell-width",
+                cell_width.name().to_owned(),
+                width.name().to_owned(),
+            )),
+            Self {
+                eof_mode: Some(eof_mode),
+                ..
+            } if eof_mode != header.eof_mode => Some((
+                "--eof",
+                header.eof_mode.name().to_owned(),
+                eof_mode.name().to_owned(),
+            )),
+            _ => None,
+        };
+
+        if let Some((flag, compiled, given)) = conflict {
+            eprintln!(
+                "error: {} was compiled with {} {}, so it can't be used with {} {}",
+                name, flag, compiled, flag, given
+            );
+            process::exit(EXIT_USAGE);
+        }
+
+        *self = Self {
+            tape_size: Some(header.tape_size),
+            cell_width: Some(cell_width),
+            eof_mode: Some(header.eof_mode),
+        };
+    }
+}
+
+// Renders what the library reports through tracing to standard error, timed from when
+// membrane started, with each -v showing more: what was loaded and run, then each
+// optimizer pass and how long it and the interpreter took, then everything.
+fn init_tracing(verbose: u8) {
+    let level = match verbose {
+        0 => return,
+        1 => Level::INFO,
+        2 => Level::DEBUG,
+        _ => Level::TRACE,
+    };
+
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_target(false)
+        .with_timer(time::uptime())
+        .with_writer(io::stderr)
+        .init();
+}
+
+fn main() {
+    let mut cli = Cli::parse();
+
+    if !cli.no_config {
+        let path = cli.config.map(PathBuf::from).or_else(Config::find);
+
+        if let Some(path) = path {
+            match Config::load_file(&path) {
+                Ok(config) => apply_config(&mut cli.command, &config),
+                Err(err) => {
+                    eprintln!("error: {}: {}", path.display(), err);
+                    process::exit(EXIT_USAGE);
+                }
+            }
+        }
+    }
+
+    match cli.command {
+        Command::Run(args) => run(args),
+        Command::Bench(args) => bench(args),
+        Command::Diff(args) => diff(args),
+        Command::Check(args) => check(args),
+        Command::Fmt(args) => fmt(args),
+        Command::Minify(args) => minify(args),
+        Command::Convert(args) => convert(args),
+        Command::Compile(args) => compile(args),
+        Command::Analyze(args) => analyze(args),
+        Command::List(args) => list(args),
+        Command::Explain(args) => explain(args),
+        Command::Generate(GenerateCommand::Text(args)) => generate_text(args),
+        Command::Lsp => lsp(),
+    }
+}
+
+fn run(args: RunArgs) {
+    if args.watch {
+        let files = args.brainfuck_files.iter().chain(&args.read_file);
+        watch(files.map(String::as_str).collect());
+    }
+
+    let tape_size = tape_size(args.tape_size);
+
+    // Cells past the end of a finite tape would wrap around to another one.
+    if let (TapeSize::Finite(size), Some(Some(index))) = (tape_size, args.exit_cell) {
+        if index >= size {
+            eprintln!(
+                "error: cell {} is past the end of the tape, which has {} cell(s)",
+                index, size
+            );
+            process::exit(EXIT_USAGE);
+        }
+    }
+
+    let mut machine = Machine::new(args.tape_size, args.cell_width, args.eof_mode);
+
+    if args.brainfuck_files.len() > 1 {
+        return run_pipeline(args, machine);
+    }
+
+    let file = args.brainfuck_files.first().map(String::as_str);
+    let source = Source::open_or_eval(file, args.eval.as_deref());
+    let frontend = frontend(&source, &args.dialect_args, args.inline_input);
+
+    // As JSON, nothing is printed until the program ends.
+    let json = args.format == OutputFormat::Json;
+    let mut report = None;
+
+    if !json {
+        init_tracing(args.verbose);
+    }
+
+    let mut program = load_program(
+        &source,
+        &args.optimize_args,
+        frontend.as_ref(),
+        args.preprocess,
+        &mut machine,
+        &mut report,
+    );
+    describe_program(&mut program, &source, frontend.as_ref());
+
+    let stats = program.stats();
+    tracing::info!(
+        instructions = stats.instructions,
+        loops = stats.loops,
+        depth = stats.max_depth,
+        "loaded the program"
+    );
+
+    if let Some(listing_file) = &args.listing_file {
+        // Spans of preprocessed programs point into the expanded source, rather than the
+        // source as it's read, and listings would only be repeated beside themselves.
+        let text = (!args.preprocess && frontend.name() != "listing")
+            .then(|| source.read().ok())
+            .flatten();
+        let options = ListingOptions {
+            format: args.listing_format.unwrap_or_default(),
+            source: text.as_deref(),
+            ..ListingOptions::default()
+        };
+
+        write_listing(listing_file, &program, &options);
+    }
+
+    let mut execution = None;
+    let mut exit_cell = None;
+
+    if !args.partial {
+        let input = match program.input.take() {
+            Some(input) => InputSource::File(Cursor::new(input)),
+            None => open_input(&args),
+        };
+        let output = open_output(&args);
+
+        let start_time = (args.verbose > 0).then(Instant::now);
+        let interpreter = machine.interpreter().input(input).output(output).build();
+
+        let result = match &args.profile {
+            Some(path) => {
+                let mut profile = Profile::new(program.len());
+                let result = interpreter.run_with_profile(&program.instructions, &mut profile.hits);
+
+                // Profiles of programs that fail are still written, since where they spent
+                // their time can be why.
+                let written = File::create(path)
+                    .and_then(|file| profile.write_json(&mut BufWriter::new(file)));
+
+                if let Err(err) = written {
+                    eprintln!("error: failed to write {}: {}", path, err);
+                    process::exit(EXIT_IO_ERROR);
+                }
+
+                result
+            }
+            None => interpreter.run(&program.instructions),
+        };
+
+        let state = result.unwrap_or_else(|err| runtime_error(&program, source.name(), err));
+        let instructions_executed = state.instructions_executed;
+
+        exit_cell = args.exit_cell.map(|cell| match cell {
+            Some(index) => state.cell(index),
+            None => state.current_cell(),
+        });
+
+        if let Some(time) = start_time {
+            let elapsed = time.elapsed();
+            execution = Some((instructions_executed, elapsed));
+
+            let inst_per_sec = (instructions_executed as f64) / elapsed.as_secs_f64();
+            tracing::info!(
+                elapsed_ms = elapsed.as_millis() as u64,
+                instructions_per_second = inst_per_sec as u64,
+                "ran the program"
+            );
+        }
+    }
+
+    if json && args.verbose > 0 {
+        if let Err(err) = write_run_json(&mut io::stderr(), &program, report.as_ref(), execution) {
+            eprintln!("error: failed to write the report: {}", err);
+            process::exit(EXIT_IO_ERROR);
+        }
+    }
+
+    if let Some(value) = exit_cell {
+        process::exit(value as i32);
+    }
+
+    exit_if_out_of_fuel(&report);
+}
+
+// Writes the listing to the file, or to standard output for -.
+fn write_listing(path: &str, program: &Program, options: &ListingOptions) {
+    let written = match path {
+        "-" => lister::write_listing(program, options, &mut io::stdout().lock()),
+        path => File::create(path)
+            .and_then(|file| lister::write_listing(program, options, &mut BufWriter::new(file))),
+    };
+
+    if let Err(err) = written {
+        let name = match path {
+            "-" => "standard output",
+            path => path,
+        };
+
+        eprintln!("error: failed to write {}: {}", name, err);
+        process::exit(EXIT_IO_ERROR);
+    }
+}
+
+// Runs each program on a thread of its own, with the output of each piped into the input
+// of the next. The first reads what a lone program would, and the last writes where it
+// would, but a program with input inline keeps to it.
+fn run_pipeline(args: RunArgs, machine: Machine) {
+    if args.partial || args.verbose > 0 || args.listing_file.is_some() || args.profile.is_some() {
+        eprintln!(
+            "error: --partial, --verbose, --listing, and --profile only work with one program"
+        );
+        process::exit(EXIT_USAGE);
+    }
+
+    let mut reports = Vec::new();
+    let mut programs = args
+        .brainfuck_files
+        .iter()
+        .map(|file| {
+            let source = Source::open(file);
+            let frontend = frontend(&source, &args.dialect_args, args.inline_input);
+
+            let mut report = None;
+            let mut machine = machine;
+            let mut program = load_program(
+                &source,
+                &args.optimize_args,
+                frontend.as_ref(),
+                args.preprocess,
+                &mut machine,
+                &mut report,
+            );
+            describe_program(&mut program, &source, frontend.as_ref());
+
+            reports.push(report);
+            (source.name().to_owned(), program, machine)
+        })
+        .collect::<Vec<_>>();
+
+    let last = programs.len() - 1;
+    let mut piped = Some(open_input(&args));
+    let mut streams = Vec::new();
+
+    for (index, (_, program, _)) in programs.iter_mut().enumerate() {
+        let input = match program.input.take() {
+            Some(input) => InputSource::File(Cursor::new(input)),
+            None => piped.take().unwrap(),
+        };
+
+        let output = if index == last {
+            open_output(&args)
+        } else {
+            let (writer, reader) = interpreter::pipe();
+            piped = Some(InputSource::Pipe(reader));
+            OutputSource::Pipe(writer)
+        };
+
+        streams.push((input, output));
+    }
+
+    let results = thread::scope(|scope| {
+        let threads = programs
+            .iter()
+            .zip(streams)
+            .map(|((_, program, machine), (input, output))| {
+                scope.spawn(move || {
+                    machine
+                        .interpreter()
+                        .input(input)
+                        .output(output)
+                        .build()
+                        .run(&program.instructions)
+                })
+            })
+            .collect::<Vec<_>>();
+
+        threads
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+
+    let mut exit_cell = None;
+
+    // Errors are reported from the start of the pipeline, since a program that fails
+    // leaves the ones after it with input cut short.
+    for (index, (result, (name, program, _))) in results.into_iter().zip(&programs).enumerate() {
+        match result {
+            Ok(state) if index == last => {
+                exit_cell = args.exit_cell.map(|cell| match cell {
+                    Some(index) => state.cell(index),
+                    None => state.current_cell(),
+                });
+            }
+            Ok(_) => {}
+            // The next program stopped reading, which ends this one the way it would in a
+            // shell.
+            Err(err) if err.is_broken_pipe() && index < last => {}
+            Err(err) => runtime_error(program, name, err),
+        }
+    }
+
+    if let Some(value) = exit_cell {
+        process::exit(value as i32);
+    }
+
+    reports.iter().for_each(exit_if_out_of_fuel);
+}
+
+// Where the program reads from when its input isn't inline.
+fn open_input(args: &RunArgs) -> InputSource {
+    let filename = match &args.read_file {
+        Some(filename) => filename,
+        None if args.buffer_read => return InputSource::StdinBuffer(BufReader::new(io::stdin())),
+        None => return InputSource::Stdin(io::stdin()),
+    };
+
+    let mut file = File::open(filename).unwrap_or_else(|err| {
+        eprintln!("error: failed to read {}: {}", filename, err);
+        process::exit(EXIT_IO_ERROR);
+    });
+
+    if args.buffer_read {
+        return InputSource::FileBuffer(BufReader::new(file));
+    }
+
+    let mut contents = match file.seek(SeekFrom::End(0)) {
+        Ok(end) => match file.seek(SeekFrom::Start(0)) {
+            Ok(start) => Vec::with_capacity((end - start) as usize),
+            Err(_) => Vec::new(),
+        },
+        Err(_) => Vec::new(),
+    };
+
+    match file.read_to_end(&mut contents) {
+        Ok(_) => InputSource::File(Cursor::new(contents)),
+        Err(err) => {
+            eprintln!("error: failed to read {}: {}", filename, err);
+            process::exit(EXIT_IO_ERROR);
+        }
+    }
+}
+
+fn open_output(args: &RunArgs) -> OutputSource {
+    let filename = match &args.write_file {
+        Some(filename) => filename,
+        None if args.buffer_write => {
+            return OutputSource::StdoutBuffer(BufWriter::new(io::stdout()))
+        }
+        None => return OutputSource::Stdout(io::stdout()),
+    };
+
+    let file = File::create(filename).unwrap_or_else(|err| {
+        eprintln!("error: failed to write {}: {}", filename, err);
+        process::exit(EXIT_IO_ERROR);
+    });
+
+    if args.buffer_write {
+        OutputSource::FileBuffer(BufWriter::new(file))
+    } else {
+        OutputSource::File(file)
+    }
+}
+
+// Exits once the program has stopped on a runtime error, pointing at the command it
+// stopped at when that's known.
+fn runtime_error(program: &Program, name: &str, err: RuntimeError) -> ! {
+    let location = err
+        .index()
+        .and_then(|index| program.spans.get(index)?.location());
+    let err = membrane::Error::from(err);
+
+    Diagnostic::error(name, location, err.to_string()).print();
+    process::exit(exit_code(&err));
+}
+
+// Exits with EXIT_FUEL_EXHAUSTED if --opt-fuel ran out, which is left until everything else
+// is done so that bisecting on it still gets the program's output.
+fn exit_if_out_of_fuel(report: &Option<OptimizeReport>) {
+    if report.as_ref().is_some_and(|report| report.fuel_exhausted) {
+        process::exit(EXIT_FUEL_EXHAUSTED);
+    }
+}
+
+// The statistics --verbose prints, as one JSON object. The optimizer and execution are null
+// when the program wasn't optimized or run.
+fn write_run_json<W: Write>(
+    writer: &mut W,
+    program: &Program,
+    report: Option<&OptimizeReport>,
+    execution: Option<(usize, Duration)>,
+) -> io::Result<()> {
+    let stats = program.stats();
+
+    writeln!(writer, "{{")?;
+    writeln!(writer, "  \"program\": {{")?;
+    writeln!(writer, "    \"instructions\": {},", stats.instructions)?;
+    writeln!(writer, "    \"loops\": {},", stats.loops)?;
+    writeln!(writer, "    \"procedures\": {},", stats.procedures)?;
+    writeln!(writer, "    \"max_depth\": {}", stats.max_depth)?;
+    writeln!(writer, "  }},")?;
+    write!(writer, "  \"optimizer\": ")?;
+
+    match report {
+        Some(report) => report.write_json(writer, "  ")?,
+        None => write!(writer, "null")?,
+    }
+
+    writeln!(writer, ",")?;
+    write!(writer, "  \"execution\": ")?;
+
+    match execution {
+        Some((instructions, elapsed)) => {
+            writeln!(writer, "{{")?;
+            writeln!(writer, "    \"instructions\": {},", instructions)?;
+            writeln!(
+                writer,
+                "    \"milliseconds\": {:.3},",
+                elapsed.as_secs_f64() * 1000.0
+            )?;
+            writeln!(
+                writer,
+                "    \"instructions_per_second\": {}",
+                (instructions as f64 / elapsed.as_secs_f64()) as usize
+            )?;
+            writeln!(writer, "  }}")?;
+        }
+        None => writeln!(writer, "null")?,
+    }
+
+    writeln!(writer, "}}")
+}
+
+// Reports problems as FILE:LINE:COLUMN: error: MESSAGE, the way compilers do, so editors
+// and hooks can pick them up. Exits with an error's code if any of the programs has one.
+fn check(args: CheckArgs) {
+    if args.watch {
+        watch(args.brainfuck_files.iter().map(String::as_str).collect());
+    }
+
+    let tape_size = tape_size(args.tape_size);
+
+    let mut diagnostics = Vec::new();
+    let mut status = 0;
+
+    for file in &args.brainfuck_files {
+        let source = Source::open(file);
+        let frontend = frontend(&source, &args.dialect_args, args.inline_input);
+        let name = source.name();
+
+        let (mut program, header) = match source.try_parse(frontend.as_ref(), args.preprocess) {
+            Ok(loaded) => loaded,
+            Err(err) => {
+                status = status.max(exit_code(&err));
+
+                match err {
+                    membrane::Error::Io(err) => diagnostics.push(Diagnostic::error(
+                        name,
+                        None,
+                        format!("failed to read: {}", err),
+                    )),
+                    membrane::Error::Preprocess(err) => diagnostics.push(Diagnostic::error(
+                        &err.file,
+                        Some(err.location),
+                        err.to_string(),
+                    )),
+                    membrane::Error::Parse(errors) => {
+                        for err in errors {
+                            diagnostics.push(Diagnostic::error(
+                                name,
+                                err.location(),
+                                err.to_string(),
+                            ));
+                        }
+                    }
+                    err => diagnostics.push(Diagnostic::error(name, None, err.to_string())),
+                }
+
+                continue;
+            }
+        };
+
+        if !args.optimize || header.is_some_and(|header| header.optimized) {
+            continue;
+        }
+
+        let options = OptimizeOptions {
+            tape_size,
+            ..OptimizeOptions::default()
+        };
+
+        if let Err(err) = optimizer::optimize_program(&mut program, &options) {
+            if err.is_fatal() {
+                status = status.max(EXIT_FAILURE);
+            }
+
+            diagnostics.push(Diagnostic {
+                severity: if err.is_fatal() { "error" } else { "warning" },
+                ..Diagnostic::error(name, None, err.to_string())
+            });
+        }
+    }
+
+    match args.format {
+        OutputFormat::Text => diagnostics.iter().for_each(Diagnostic::print),
+        OutputFormat::Json => {
+            let mut stdout = io::stdout().lock();
+            let written = (|| {
+                write!(stdout, "{{\n  \"diagnostics\": [")?;
+
+                for (index, diagnostic) in diagnostics.iter().enumerate() {
+                    write!(stdout, "{}\n    ", if index > 0 { "," } else { "" })?;
+                    diagnostic.write_json(&mut stdout)?;
+                }
+
+                if !diagnostics.is_empty() {
+                    write!(stdout, "\n  ")?;
+                }
+
+                writeln!(stdout, "]\n}}")
+            })();
+
+            if let Err(err) = written {
+                eprintln!("error: failed to write the diagnostics: {}", err);
+                process::exit(EXIT_IO_ERROR);
+            }
+        }
+    }
+
+    if status != 0 {
+        process::exit(status);
+    }
+}
+
+// Runs membrane again, without --watch, whenever one of the files changes. Every run is a
+// process of its own, so a program that fails to parse, or stops the interpreter, only ends
+// that run, and its errors are shown under its output until the next.
+fn watch(files: Vec<&str>) -> ! {
+    if files.contains(&"-") {
+        eprintln!("error: --watch can't watch standard input");
+        process::exit(EXIT_USAGE);
+    }
+
+    let executable = env::current_exe().unwrap_or_else(|err| {
+        eprintln!("error: failed to find membrane itself: {}", err);
+        process::exit(EXIT_IO_ERROR);
+    });
+
+    let arguments = env::args_os()
+        .skip(1)
+        .filter(|argument| argument != "--watch")
+        .collect::<Vec<_>>();
+
+    let mut watcher = watcher::Watcher::new(&files);
+    let clear = io::stdout().is_terminal();
+
+    loop {
+        if clear {
+            print!("\x1b[2J\x1b[H");
+            let _ = io::stdout().flush();
+        }
+
+        let mut child = process::Command::new(&executable)
+            .args(&arguments)
+            .spawn()
+            .unwrap_or_else(|err| {
+                eprintln!("error: failed to run membrane: {}", err);
+                process::exit(EXIT_IO_ERROR);
+            });
+
+        // A program that's still running when its files change, such as one stuck in a loop
+        // or waiting on input, is stopped so the new version can be run.
+        let status = loop {
+            match child.try_wait() {
+                Ok(Some(status)) => break Some(status),
+                Ok(None) => {}
+                Err(err) => {
+                    eprintln!("error: failed to wait on membrane: {}", err);
+                    process::exit(EXIT_IO_ERROR);
+                }
+            }
+
+            if !watcher.settle().is_empty() {
+                let _ = child.kill();
+                let _ = child.wait();
+                break None;
+            }
+
+            thread::sleep(watcher::INTERVAL);
+        };
+
+        let status = match status {
+            Some(status) => status,
+            None => {
+                eprintln!("\n[stopped; {} changed]", files.join(", "));
+                continue;
+            }
+        };
+
+        let outcome = match status.code() {
+            Some(0) => "finished".to_owned(),
+            Some(code) => format!("failed with exit code {}", code),
+            None => "stopped by a signal".to_owned(),
+        };
+
+        eprintln!(
+            "\n[{}; waiting for changes to {}]",
+            outcome,
+            files.join(", ")
+        );
+
+        watcher.wait();
+    }
+}
+
+fn report_parse_error(name: &str, err: &ParseError) {
+    Diagnostic::error(name, err.location(), err.to_string()).print();
+}
+
+// A problem found in a program, which is printed as FILE:LINE:COLUMN: SEVERITY: MESSAGE,
+// leaving out the line and column if it isn't at any one place.
+struct Diagnostic {
+    file: String,
+    location: Option<Location>,
+    severity: &'static str,
+    message: String,
+}
+
+impl Diagnostic {
+    fn error(file: &str, location: Option<Location>, message: String) -> Self {
+        Self {
+            file: file.to_owned(),
+            location,
+            severity: "error",
+            message,
+        }
+    }
+
+    fn print(&self) {
+        match self.location {
+            Some(location) => eprintln!(
+                "{}:{}:{}: {}: {}",
+                self.file, location.line, location.column, self.severity, self.message
+            ),
+            None => eprintln!("{}: {}: {}", self.file, self.severity, self.message),
+        }
+    }
+
+    fn write_json<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let (line, column) = match self.location {
+            Some(location) => (location.line.to_string(), location.column.to_string()),
+            None => ("null".to_owned(), "null".to_owned()),
+        };
+
+        write!(
+            writer,
+            "{{ \"file\": {}, \"line\": {}, \"column\": {}, \"severity\": \"{}\", \"message\": {} }}",
+            json_string(Some(&self.file)),
+            line,
+            column,
+            self.severity,
+            json_string(Some(&self.message))
+        )
+    }
+}
+
+fn fmt(args: FmtArgs) {
+    let options = FormatOptions {
+        width: args.width,
+        indent: args.indent,
+        strip_comments: args.strip_comments,
+    };
+
+    let mut failed = false;
+
+    for file in &args.brainfuck_files {
+        let source = Source::open(file);
+        let frontend = frontend(&source, &args.dialect_args, args.inline_input);
+        let name = source.name();
+
+        let text = source.read().unwrap_or_else(|err| {
+            eprintln!("error: failed to read {}: {}", name, err);
+            process::exit(EXIT_IO_ERROR);
+        });
+
+        let parse_options = match frontend.options() {
+            Some(parse_options) if !loader::is_bytecode(&text) => parse_options,
+            _ => {
+                eprintln!("{}: error: only source can be formatted", name);
+                failed = true;
+                continue;
+            }
+        };
+
+        let formatted = match formatter::format(&text, parse_options, &options) {
+            Ok(formatted) => formatted,
+            Err(errors) => {
+                for err in errors {
+                    report_parse_error(name, &err);
+                }
+
+                failed = true;
+                continue;
+            }
+        };
+
+        if args.check {
+            if formatted != text {
+                eprintln!("{}: not formatted", name);
+                failed = true;
+            }
+
+            continue;
+        }
+
+        let written = match source.path() {
+            Some(path) if formatted != text => fs::write(path, &formatted),
+            Some(_) => Ok(()),
+            None => io::stdout().write_all(&formatted),
+        };
+
+        if let Err(err) = written {
+            eprintln!("error: failed to write {}: {}", name, err);
+            process::exit(EXIT_IO_ERROR);
+        }
+    }
+
+    if failed {
+        process::exit(EXIT_FAILURE);
+    }
+}
+
+// The sizes are reported on standard error, so the program can be written to standard
+// output.
+fn minify(args: MinifyArgs) {
+    let source = Source::open(&args.brainfuck_file);
+    let frontend = frontend(&source, &args.dialect_args, args.inline_input);
+    let (program, _) = source.parse(frontend.as_ref(), args.preprocess);
+
+    let original_size = source.read().map_or(0, |source| source.len());
+
+    let options = args.optimize.then(|| OptimizeOptions {
+        tape_size: tape_size(args.tape_size),
+        ..OptimizeOptions::default()
+    });
+
+    let minified = minifier::minify(&program, options.as_ref()).unwrap_or_else(|err| {
+        eprintln!("error: {}", err);
+        process::exit(EXIT_FAILURE);
+    });
+
+    let written = match &args.output_file {
+        Some(path) => fs::write(path, &minified),
+        None => io::stdout().write_all(&minified),
+    };
+
+    if let Err(err) = written {
+        eprintln!("error: failed to write the minified program: {}", err);
+        process::exit(EXIT_IO_ERROR);
+    }
+
+    let saved = 100.0 - minified.len() as f64 * 100.0 / original_size.max(1) as f64;
+    eprintln!(
+        "Minified {} from {} to {} bytes ({:.1}% smaller).",
+        source.name(),
+        original_size,
+        minified.len(),
+        saved
+    );
+}
+
+fn list(args: ListArgs) {
+    let format = args.format.unwrap_or_default();
+
+    if args.summary && format == ListingFormat::Csv {
+        eprintln!("error: CSV listings can't have a summary");
+        process::exit(EXIT_USAGE);
+    }
+
+    let source = Source::open_or_eval(args.brainfuck_file.as_deref(), args.eval.as_deref());
+    let frontend = frontend(&source, &args.dialect_args, args.inline_input);
+
+    // The cache doesn't keep the size of the program before it was optimized, so it's
+    // parsed again for that.
+    let unoptimized = (args.summary && args.optimize_args.optimize)
+        .then(|| source.parse(frontend.as_ref(), false).0.len());
+
+    let mut report = None;
+    let mut program = load_program(
+        &source,
+        &args.optimize_args,
+        frontend.as_ref(),
+        false,
+        &mut Machine::new(args.tape_size, None, None),
+        &mut report,
+    );
+    describe_program(&mut program, &source, frontend.as_ref());
+
+    let profile = args.profile.as_ref().map(|path| {
+        let profile = Profile::load_file(path).unwrap_or_else(|err| {
+            eprintln!("error: {}: {}", path, err);
+            process::exit(EXIT_IO_ERROR);
+        });
+
+        if profile.hits.len() != program.len() {
+            eprintln!(
+                "error: {} is a profile of {} instruction(s), but the program has {} (profile it with the same options, such as -O)",
+                path,
+                profile.hits.len(),
+                program.len()
+            );
+            process::exit(EXIT_FAILURE);
+        }
+
+        profile
+    });
+
+    // Listings would only be repeated beside themselves.
+    let text = (frontend.name() != "listing")
+        .then(|| source.read().ok())
+        .flatten();
+    let options = ListingOptions {
+        format,
+        source: text.as_deref(),
+        profile: profile.as_ref(),
+        summary: args.summary,
+        unoptimized,
+    };
+
+    write_listing("-", &program, &options);
+    exit_if_out_of_fuel(&report);
+}
+
+// Each region is printed with where it starts and its source, indented by how deeply it's
+// nested, followed by what it does.
+fn explain(args: ExplainArgs) {
+    let source = Source::open(&args.brainfuck_file);
+    let frontend = frontend(&source, &args.dialect_args, false);
+    let (mut program, _) = source.parse(frontend.as_ref(), false);
+    let text = source.read().unwrap_or_default();
+
+    let options = OptimizeOptions {
+        tape_size: tape_size(args.tape_size),
+        ..OptimizeOptions::default()
+    };
+
+    match optimizer::optimize_program(&mut program, &options) {
+        Err(err) if !err.is_fatal() => eprintln!("warning: {}", err),
+        Err(err) => {
+            eprintln!("error: {}", err);
+            process::exit(EXIT_FAILURE);
+        }
+        Ok(_) => {}
+    }
+
+    for region in explainer::explain(&program) {
+        let indent = "    ".repeat(region.depth);
+
+        let (place, code) = match region.span.location() {
+            Some(location) => (
+                format!("{}:{}", location.line, location.column),
+                String::from_utf8_lossy(text.get(region.span.range()).unwrap_or_default())
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ),
+            // Bytecode has no source to show, so its instructions stand in for it.
+            None => (
+                String::new(),
+                region
+                    .instructions
+                    .iter()
+                    .map(|instruction| instruction.to_string().split_whitespace().collect())
+                    .collect::<Vec<String>>()
+                    .join(" "),
+            ),
+        };
+
+        let code = match code.char_indices().nth(60) {
+            Some((end, _)) => format!("{}...", &code[..end]),
+            None => code,
+        };
+
+        println!("{:>8}  {}{}", place, indent, code);
+        println!("{:8}  {}  {}", "", indent, region.explanation);
+    }
+}
+
+// Programs are written out from their instructions, so comments in the original are lost.
+fn convert(args: ConvertArgs) {
+    let source = Source::open(&args.input_file);
+    let dialect_args = DialectArgs {
+        dialect: args.from,
+        dialect_map: args.from_map,
+    };
+
+    let frontend = frontend(&source, &dialect_args, false);
+    let (program, _) = source.parse(frontend.as_ref(), false);
+
+    let (name, map, procedures) = match &args.to_map {
+        Some(path) => (path.clone(), Some(read_dialect_map(path)), false),
+        None => {
+            let dialect = match &args.to {
+                Some(name) => name.parse().unwrap_or_else(|err| {
+                    eprintln!("error: invalid value for '--to': {}", err);
+                    process::exit(EXIT_USAGE);
+                }),
+                None => args
+                    .output_file
+                    .as_ref()
+                    .and_then(|path| Path::new(path).extension()?.to_str())
+                    .and_then(Dialect::for_extension)
+                    .unwrap_or_else(|| {
+                        eprintln!("error: no dialect to convert to (pass --to or --to-map)");
+                        process::exit(EXIT_USAGE);
+                    }),
+            };
+
+            (
+                dialect.name().to_owned(),
+                dialect.map(),
+                dialect.has_procedures(),
+            )
+        }
+    };
+
+    let code = lowering::to_brainfuck(&program.instructions);
+
+    if !procedures && code.contains(['(', ')', ':']) {
+        eprintln!(
+            "error: {} uses pbrain's procedures, which {} doesn't have",
+            source.name(),
+            name
+        );
+        process::exit(EXIT_FAILURE);
+    }
+
+    let converted = match map {
+        Some(map) => {
+            let code = compilers::brainfuck::minify(code.as_bytes());
+
+            map.write_code(&code, 80).unwrap_or_else(|err| {
+                eprintln!(
+                    "error: failed to write {} in {}: {}",
+                    source.name(),
+                    name,
+                    err
+                );
+                process::exit(EXIT_FAILURE);
+            })
+        }
+        None => {
+            let mut converted = Vec::new();
+            // Writing to memory can't fail.
+            compilers::brainfuck::compile(&program.instructions, &mut converted).unwrap();
+            converted
+        }
+    };
+
+    let written = match &args.output_file {
+        Some(path) => fs::write(path, &converted),
+        None => io::stdout().write_all(&converted),
+    };
+
+    if let Err(err) = written {
+        eprintln!("error: failed to write the converted program: {}", err);
+        process::exit(EXIT_IO_ERROR);
+    }
+}
+
+// Output is thrown away, so only the interpreter is timed.
+fn bench(args: BenchArgs) {
+    let source = Source::open(&args.brainfuck_file);
+    let frontend = frontend(&source, &args.dialect_args, args.inline_input);
+
+    let read_file = args.read_file.as_ref().map(|path| {
+        fs::read(path).unwrap_or_else(|err| {
+            eprintln!("error: failed to read {}: {}", path, err);
+            process::exit(EXIT_IO_ERROR);
+        })
+    });
+
+    let variants = if args.compare {
+        vec![false, true]
+    } else {
+        vec![args.optimize_args.optimize]
+    };
+
+    let mut medians = Vec::new();
+
+    for optimize in variants {
+        let optimize_args = OptimizeArgs {
+            optimize,
+            ..args.optimize_args.clone()
+        };
+
+        let mut machine = Machine::new(args.tape_size, None, None);
+        let program = load_program(
+            &source,
+            &optimize_args,
+            frontend.as_ref(),
+            args.preprocess,
+            &mut machine,
+            &mut None,
+        );
+
+        let input = program.input.as_ref().or(read_file.as_ref());
+        let run = || {
+            let input = InputSource::File(Cursor::new(input.cloned().unwrap_or_default()));
+            let output = OutputSource::Sink(io::sink());
+
+            let start = Instant::now();
+            let executed = machine
+                .interpreter()
+                .input(input)
+                .output(output)
+                .build()
+                .run(&program.instructions)
+                .map(|state| state.instructions_executed)
+                .unwrap_or_else(|err| runtime_error(&program, source.name(), err));
+            (start.elapsed(), executed)
+        };
+
+        for _ in 0..args.warmup {
+            run();
+        }
+
+        let mut times = Vec::new();
+        let mut executed = 0;
+
+        for _ in 0..args.runs {
+            let (time, count) = run();
+            times.push(time);
+            executed = count;
+        }
+
+        times.sort();
+        let median = times[times.len() / 2];
+        let mean = times.iter().sum::<Duration>() / args.runs;
+
+        println!(
+            "{}: {} run(s), min {:.2?}, median {:.2?}, mean {:.2?} ({} inst/sec)",
+            if optimize { "optimized" } else { "unoptimized" },
+            args.runs,
+            times[0],
+            median,
+            mean,
+            (executed as f64 / mean.as_secs_f64()) as usize
+        );
+
+        medians.push(median);
+    }
+
+    if let [unoptimized, optimized] = medians[..] {
+        println!(
+            "Optimizing makes it {:.2}x as fast, by median.",
+            unoptimized.as_secs_f64() / optimized.as_secs_f64()
+        );
+    }
+}
+
+fn compile(mut args: CompileArgs) {
+    // The code from --eval takes the place of the source file, so the one file given is
+    // where to write it, which clap can't tell on its own.
+    if args.eval.is_some() {
+        if args.output_file.is_some() {
+            eprintln!("error: only the output file can be given with --eval");
+            process::exit(EXIT_USAGE);
+        }
+
+        args.output_file = args.brainfuck_file.take();
+
+        match (&args.output_file, &args.project) {
+            (Some(_), Some(_)) => {
+                eprintln!("error: the output file can't be given with --project");
+                process::exit(EXIT_USAGE);
+            }
+            (None, None) => {
+                eprintln!("error: pass the file to write the compiled program to");
+                process::exit(EXIT_USAGE);
+            }
+            _ => {}
+        }
+    }
+
+    let registry = Registry::builtin();
+
+    let format = match (&args.format, &args.output_file) {
+        (Some(format), _) => format.as_str(),
+        (None, _) if args.project.is_some() => "rust",
+        (None, _) if args.native => "c",
+        (None, Some(output_file)) => match infer_format(&registry, output_file) {
+            Some(format) => format,
+            None if output_file == "-" => {
+                eprintln!("error: pass a format with -f to write to standard output");
+                process::exit(EXIT_USAGE);
+            }
+            None => {
+                let mut extensions = Vec::new();
+
+                for backend in registry.iter() {
+                    let extension = format!(".{}", backend.file_extension());
+
+                    if !extensions.contains(&extension) {
+                        extensions.push(extension);
+                    }
+                }
+
+                eprintln!(
+                    "error: can't tell the format from the name of '{}'; pass one with -f (known extensions: {})",
+                    output_file,
+                    extensions.join(", ")
+                );
+                process::exit(EXIT_USAGE);
+            }
+        },
+        // Clap makes sure there's an output file whenever there's no project.
+        (None, None) => unreachable!(),
+    };
+
+    let backend = match registry.get(format) {
+        Some(backend) => backend,
+        None => {
+            eprintln!(
+                "error: unknown format '{}' (expected one of: {})",
+                format,
+                registry.names().join(", ")
+            );
+            process::exit(EXIT_USAGE);
+        }
+    };
+
+    // Checked before anything is compiled, so a bad format fails fast.
+    let toolchain = if args.native {
+        match Toolchain::for_format(format) {
+            Ok(mut toolchain) => {
+                if let Some(compiler) = &args.compiler {
+                    toolchain.compiler = compiler.clone();
+                }
+
+                Some(toolchain)
+            }
+            Err(err) => {
+                eprintln!("error: {}", err);
+                process::exit(EXIT_USAGE);
+            }
+        }
+    } else {
+        None
+    };
+
+    if args.instrument && format != "c" && format != "rust" {
+        eprintln!("error: --instrument only works with the c and rust formats");
+        process::exit(EXIT_USAGE);
+    }
+
+    if args.project.is_some() && format != "rust" {
+        eprintln!("error: --project only works with the rust format");
+        process::exit(EXIT_USAGE);
+    }
+
+    if args.output_file.as_deref() == Some("-") {
+        if args.native {
+            eprintln!("error: executables can't be written to standard output");
+            process::exit(EXIT_USAGE);
+        }
+
+        if args.source_map {
+            eprintln!("error: source maps can't be written next to standard output");
+            process::exit(EXIT_USAGE);
+        }
+    }
+
+    init_tracing(args.verbose);
+    let mut machine = Machine::new(args.tape_size, args.cell_width, args.eof_mode);
+
+    let source = Source::open_or_eval(args.brainfuck_file.as_deref(), args.eval.as_deref());
+    let frontend = frontend(&source, &args.dialect_args, args.inline_input);
+    let mut report = None;
+    let mut program = load_program(
+        &source,
+        &args.optimize_args,
+        frontend.as_ref(),
+        args.preprocess,
+        &mut machine,
+        &mut report,
+    );
+    describe_program(&mut program, &source, frontend.as_ref());
+
+    let annotations = if args.annotate {
+        match source.read() {
+            Ok(text) => Some(Annotations::new(&program, &String::from_utf8_lossy(&text))),
+            Err(err) => {
+                eprintln!("error: failed to read {}: {}", source.name(), err);
+                process::exit(EXIT_IO_ERROR);
+            }
+        }
+    } else {
+        None
+    };
+
+    let info = ProgramInfo {
+        source_path: program.source_name.clone(),
+        source_hash: source.read().ok().map(|text| crc32fast::hash(&text)),
+        optimized: args.optimize_args.optimize,
+        annotations,
+    };
+
+    let options = CompileOptions {
+        tape_size: machine.tape_size(),
+        cell_width: machine.cell_width(),
+        eof_mode: machine.eof_mode(),
+        wrap_semantics: args.wrap_semantics,
+        checks: if args.no_runtime_checks {
+            CodegenChecks::None
+        } else {
+            args.codegen_checks
+        },
+        instrument: args.instrument,
+    };
+
+    if let Some(directory) = &args.project {
+        // The README is left out if the source can't be read again.
+        let readme = source
+            .read()
+            .ok()
+            .and_then(|text| String::from_utf8(text).ok());
+
+        if let Err(err) = rust::write_project(
+            &program.instructions,
+            &options,
+            &info,
+            directory,
+            readme.as_deref(),
+        ) {
+            compile_failed(&source, directory, err);
+        }
+
+        exit_if_out_of_fuel(&report);
+        return;
+    }
+
+    // Clap makes sure there's an output file whenever there's no project.
+    let output_file = args.output_file.as_deref().unwrap();
+
+    if let Some(toolchain) = toolchain {
+        let mut generated = Vec::new();
+
+        if let Err(err) = backend.compile(&program, &options, &info, &mut generated) {
+            compile_failed(&source, "the generated program", err);
+        }
+
+        if let Err(err) = toolchain.build(&generated, output_file) {
+            let err = membrane::Error::from(err);
+            eprintln!("error: {}", err);
+            process::exit(exit_code(&err));
+        }
+    } else if args.source_map {
+        write_with_source_map(&source, backend, &program, &options, &info, output_file);
+    } else if output_file == "-" {
+        if let Err(err) = compilers::compile_stdout(backend, &program, &options, &info) {
+            compile_failed(&source, "standard output", err);
+        }
+    } else if let Err(err) =
+        compilers::compile_file(backend, &program, &options, &info, output_file)
+    {
+        compile_failed(&source, output_file, err);
+    }
+
+    exit_if_out_of_fuel(&report);
+}
+
+fn infer_format(registry: &Registry, output_file: &str) -> Option<&'static str> {
+    let extension = Path::new(output_file).extension()?.to_str()?;
+    registry
+        .for_extension(extension)
+        .map(|backend| backend.name())
+}
+
+// Exits because the program couldn't be compiled to the output, either because the backend
+// can't compile it or because writing failed.
+fn compile_failed(source: &Source, output: &str, err: CompileError) -> ! {
+    match err {
+        CompileError::Io(_) => eprintln!("error: failed to write {}: {}", output, err),
+        _ => eprintln!("error: failed to compile {}: {}", source.name(), err),
+    }
+
+    process::exit(exit_code(&err.into()));
+}
+
+fn write_with_source_map(
+    source: &Source,
+    backend: &dyn Backend,
+    program: &Program,
+    options: &CompileOptions,
+    info: &ProgramInfo,
+    output_file: &str,
+) {
+    let mut output = Vec::new();
+
+    let mut source_map = match backend.compile_with_source_map(program, options, info, &mut output)
+    {
+        Ok(Some(source_map)) => source_map,
+        Ok(None) => {
+            eprintln!(
+                "error: the {} format doesn't support source maps",
+                backend.name()
+            );
+            process::exit(EXIT_USAGE);
+        }
+        Err(err) => compile_failed(source, output_file, err),
+    };
+
+    // The map names the output by its file name, since they sit in the same directory.
+    source_map.file = Path::new(output_file)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned());
+
+    if let Err(err) = fs::write(output_file, &output) {
+        eprintln!("error: failed to write {}: {}", output_file, err);
+        process::exit(EXIT_IO_ERROR);
+    }
+
+    let map_file = format!("{}.map", output_file);
+
+    if let Err(err) = File::create(&map_file).and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        source_map.write_json(&mut writer)?;
+        writer.flush()
+    }) {
+        eprintln!("error: failed to write {}: {}", map_file, err);
+        process::exit(EXIT_IO_ERROR);
+    }
+}
+
+fn diff(args: DiffArgs) {
+    if args.original_file == "-" && args.modified_file == "-" {
+        eprintln!("error: only one of the programs can be read from standard input");
+        process::exit(EXIT_USAGE);
+    }
+
+    let frontend = ParserFrontend::for_dialect(Dialect::Brainfuck, &ParseOptions::default());
+    let original = Source::open(&args.original_file)
+        .parse(&frontend, false)
+        .0
+        .instructions;
+    let modified = Source::open(&args.modified_file)
+        .parse(&frontend, false)
+        .0
+        .instructions;
+
+    let original = canonicalizer::canonicalize(&original);
+    let modified = canonicalizer::canonicalize(&modified);
+
+    // Jump locations are implied by the rest of the program, so they're left out of the
+    // comparison to report the first difference that actually matters.
+    let mismatch = original
+        .iter()
+        .zip(&modified)
+        .position(|(a, b)| match (a, b) {
+            (Instruction::JumpIfZero { .. }, Instruction::JumpIfZero { .. })
+            | (Instruction::JumpIfNotZero { .. }, Instruction::JumpIfNotZero { .. }) => false,
+            _ => a != b,
+        })
+        .or_else(|| (original.len() != modified.len()).then(|| original.len().min(modified.len())));
+
+    match mismatch {
+        None => println!("The programs are equivalent."),
+        Some(index) => {
+            println!("The programs differ at canonical instruction {}:", index);
+
+            match original.get(index) {
+                Some(instruction) => println!("- {}", instruction),
+                None => println!("- <end of program>"),
+            }
+
+            match modified.get(index) {
+                Some(instruction) => println!("+ {}", instruction),
+                None => println!("+ <end of program>"),
+            }
+
+            process::exit(EXIT_FAILURE);
+        }
+    }
+}
+
+fn analyze(args: AnalyzeArgs) {
+    if !args.suggest_superinstructions {
+        report_metrics(args);
+        return;
+    }
+
+    if args.min_length < 2 || args.min_length > args.max_length {
+        eprintln!("error: sequences must be at least 2 instructions long, and --min-length can't exceed --max-length");
+        process::exit(EXIT_USAGE);
+    }
+
+    let mut files = Vec::new();
+
+    if let Err(err) = collect_brainfuck_files(Path::new(&args.path), &mut files) {
+        eprintln!("error: failed to read {}: {}", args.path, err);
+        process::exit(EXIT_IO_ERROR);
+    }
+
+    files.sort();
+
+    let mut miner = NGramMiner::new(args.min_length..=args.max_length);
+
+    for file in &files {
+        let path = file.to_string_lossy();
+        let Program {
+            mut instructions,
+            mut spans,
+            ..
+        } = match parser::parse_file(&path) {
+            Ok(program) => program,
+            Err(errors) => {
+                eprintln!("warning: skipping {}: {}", path, errors[0]);
+                continue;
+            }
+        };
+
+        match optimizer::optimize(&mut instructions, &mut spans, &OptimizeOptions::default()) {
+            Err(err) if err.is_fatal() => {
+                eprintln!("warning: skipping {}: {}", path, err);
+                continue;
+            }
+            Err(err) => eprintln!("warning: {}: {}", path, err),
+            Ok(_) => {}
+        }
+
+        miner.add_program(&instructions);
+    }
+
+    println!(
+        "Mined {} program(s) with {} instruction(s) after optimization.",
+        miner.programs(),
+        miner.instructions()
+    );
+
+    let candidates = miner.candidates();
+
+    if candidates.is_empty() {
+        return;
+    }
+
+    // Without execution profiles, the dynamic frequency is estimated by the static count.
+    println!();
+    println!(
+        "{:>4}  {:>8}  {:>8}  {:>8}  sequence",
+        "rank", "count", "programs", "saved"
+    );
+
+    for (rank, candidate) in candidates.iter().take(args.top).enumerate() {
+        println!(
+            "{:>4}  {:>8}  {:>8}  {:>8}  {}",
+            rank + 1,
+            candidate.count,
+            candidate.programs,
+            candidate.dispatches_saved(),
+            candidate.names.join(" ")
+        );
+    }
+}
+
+fn report_metrics(args: AnalyzeArgs) {
+    let source = Source::open(&args.path);
+    let frontend = frontend(&source, &args.dialect_args, false);
+    let (mut program, header) = source.parse(frontend.as_ref(), false);
+
+    let mut reports = vec![("original", Metrics::of(&program))];
+
+    if args.optimize && !header.is_some_and(|header| header.optimized) {
+        match optimizer::optimize_program(&mut program, &OptimizeOptions::default()) {
+            Err(err) if !err.is_fatal() => eprintln!("warning: {}", err),
+            Err(err) => {
+                eprintln!("error: {}", err);
+                process::exit(EXIT_FAILURE);
+            }
+            Ok(_) => {}
+        }
+
+        reports.push(("optimized", Metrics::of(&program)));
+    }
+
+    if args.json || args.format == OutputFormat::Json {
+        let mut stdout = io::stdout().lock();
+        let written = (|| {
+            write!(stdout, "{{")?;
+
+            for (index, (name, metrics)) in reports.iter().enumerate() {
+                write!(
+                    stdout,
+                    "{}\n  \"{}\": ",
+                    if index > 0 { "," } else { "" },
+                    name
+                )?;
+                metrics.write_json(&mut stdout, "  ")?;
+            }
+
+            writeln!(stdout, "\n}}")
+        })();
+
+        if let Err(err) = written {
+            eprintln!("error: failed to write the metrics: {}", err);
+            process::exit(EXIT_IO_ERROR);
+        }
+
+        return;
+    }
+
+    for (index, (name, metrics)) in reports.iter().enumerate() {
+        if index > 0 {
+            println!();
+        }
+
+        let stats = &metrics.stats;
+        println!("{} ({}):", source.name(), name);
+        println!(
+            "  {} instruction(s), {} loop(s), {} procedure(s), nested {} deep",
+            stats.instructions, stats.loops, stats.procedures, stats.max_depth
+        );
+        println!(
+            "  about {} cell(s) of tape{}",
+            metrics.tape_cells,
+            if metrics.moves_left {
+                ", some left of the first"
+            } else {
+                ""
+            }
+        );
+
+        let io = match (metrics.reads, metrics.writes) {
+            (true, true) => "reads and writes",
+            (true, false) => "reads, but never writes",
+            (false, true) => "writes, but never reads",
+            (false, false) => "never reads or writes",
+        };
+        println!("  {}", io);
+
+        for (name, count) in &metrics.histogram {
+            println!("  {:>8}  {}", count, name);
+        }
+    }
+}
+
+fn generate_text(args: GenerateTextArgs) {
+    let instructions = generator::text(args.text.as_bytes(), args.optimize_size);
+
+    let written = match &args.output_file {
+        Some(path) => File::create(path)
+            .and_then(|mut file| compilers::brainfuck::compile(&instructions, &mut file)),
+        None => compilers::brainfuck::compile(&instructions, &mut io::stdout().lock()),
+    };
+
+    if let Err(err) = written {
+        eprintln!("error: failed to write the program: {}", err);
+        process::exit(EXIT_IO_ERROR);
+    }
+}
+
+// Exits with 1 if the client exits without shutting the server down first, as the protocol
+// asks.
+fn lsp() {
+    match lsp::serve(io::stdin().lock(), io::stdout().lock()) {
+        Ok(true) => {}
+        Ok(false) => process::exit(EXIT_FAILURE),
+        Err(err) => {
+            eprintln!("error: the language server failed: {}", err);
+            process::exit(EXIT_IO_ERROR);
+        }
+    }
+}
+
+fn collect_brainfuck_files(directory: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            collect_brainfuck_files(&path, files)?;
+        } else if matches!(
+            path.extension().and_then(|extension| extension.to_str()),
+            Some("b" | "bf")
+        ) {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+// The code to exit with for the error. Failing to read or write is an I/O error, while
+// anything wrong with the program itself is a failure, or a runtime error if it was
+// running.
+fn exit_code(err: &membrane::Error) -> i32 {
+    match err {
+        err if err.is_io() => EXIT_IO_ERROR,
+        membrane::Error::Runtime(_) => EXIT_RUNTIME_ERROR,
+        membrane::Error::Config(_) => EXIT_USAGE,
+        // Asking for something the format can't do, like cells wider than it supports, is
+        // a mistake in how it was run rather than in the program.
+        membrane::Error::Compile(CompileError::Unsupported(_)) => EXIT_USAGE,
+        _ => EXIT_FAILURE,
+    }
+}
+
+// Where a program is read from: a file, or standard input when its path is `-`, or the
+// command line with --eval. Standard input can only be read once, so it's read up front and
+// kept for every later read.
+enum Source {
+    File(String),
+    Stdin(Vec<u8>),
+    Eval(Vec<u8>),
+}
+
+impl Source {
+    // The program from --eval if there is one, or else the file, which clap makes sure is
+    // given without it.
+    fn open_or_eval(path: Option<&str>, eval: Option<&str>) -> Self {
+        match (eval, path) {
+            (Some(code), _) => Self::Eval(code.as_bytes().to_vec()),
+            (None, Some(path)) => Self::open(path),
+            (None, None) => unreachable!(),
+        }
+    }
+
+    fn open(path: &str) -> Self {
+        if path != "-" {
+            return Self::File(path.to_owned());
+        }
+
+        let mut source = Vec::new();
+
+        if let Err(err) = io::stdin().lock().read_to_end(&mut source) {
+            eprintln!("error: failed to read standard input: {}", err);
+            process::exit(EXIT_IO_ERROR);
+        }
+
+        Self::Stdin(source)
+    }
+
+    // The path of the file, which standard input doesn't have.
+    fn path(&self) -> Option<&str> {
+        match self {
+            Self::File(path) => Some(path),
+            Self::Stdin(_) | Self::Eval(_) => None,
+        }
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            Self::File(path) => path,
+            Self::Stdin(_) => "<stdin>",
+            Self::Eval(_) => "<eval>",
+        }
+    }
+
+    fn reader(&self) -> io::Result<Box<dyn Read + '_>> {
+        match self {
+            Self::File(path) => Ok(Box::new(File::open(path)?)),
+            Self::Stdin(source) | Self::Eval(source) => Ok(Box::new(source.as_slice())),
+        }
+    }
+
+    fn is_bytecode(&self) -> bool {
+        let mut start = Vec::new();
+
+        self.reader()
+            .and_then(|reader| reader.take(MAGIC.len() as u64).read_to_end(&mut start))
+            .is_ok_and(|_| loader::is_bytecode(&start))
+    }
+
+    fn read(&self) -> io::Result<Vec<u8>> {
+        match self {
+            Self::File(path) => fs::read(path),
+            Self::Stdin(source) | Self::Eval(source) => Ok(source.clone()),
+        }
+    }
+
+    // Parses the program, or decodes it if it's bytecode, along with the bytecode's header.
+    // Exits with every parse error if it's malformed. Macros in source are expanded first
+    // when `preprocess` is set.
+    fn parse(&self, frontend: &dyn Frontend, preprocess: bool) -> (Program, Option<Header>) {
+        self.try_parse(frontend, preprocess).unwrap_or_else(|err| {
+            let code = exit_code(&err);
+
+            match err {
+                membrane::Error::Io(err) => {
+                    eprintln!("error: failed to read {}: {}", self.name(), err)
+                }
+                membrane::Error::Preprocess(err) => eprintln!("error: {}", err),
+                membrane::Error::Parse(errors) => {
+                    for err in errors {
+                        eprintln!("error: {}: {}", self.name(), err);
+                    }
+                }
+                err => eprintln!("error: {}: {}", self.name(), err),
+            }
+
+            process::exit(code);
+        })
+    }
+
+    fn try_parse(
+        &self,
+        frontend: &dyn Frontend,
+        preprocess: bool,
+    ) -> Result<(Program, Option<Header>), membrane::Error> {
+        if preprocess {
+            self.load_expanded(frontend)
+        } else {
+            Ok(self.load(frontend)?)
+        }
+    }
+
+    // Sources long enough to be parsed in parallel are read whole first, while the rest are
+    // parsed as they're read.
+    fn load(&self, frontend: &dyn Frontend) -> Result<(Program, Option<Header>), LoadError> {
+        let to_parse_error = |err| LoadError::Parse(vec![ParseError::from(err)]);
+
+        let path = match self {
+            Self::File(path) => path,
+            Self::Stdin(source) | Self::Eval(source) => {
+                return loader::load_bytes(source, frontend)
+            }
+        };
+
+        if cfg!(feature = "parallel")
+            && fs::metadata(path).is_ok_and(|metadata| metadata.len() >= PARALLEL_THRESHOLD as u64)
+        {
+            let source = fs::read(path).map_err(to_parse_error)?;
+            return loader::load_bytes(&source, frontend);
+        }
+
+        File::open(path)
+            .map_err(to_parse_error)
+            .and_then(|file| loader::load_reader(file, frontend))
+    }
+
+    // Spans and errors point back at the source itself, rather than the expanded source.
+    fn load_expanded(
+        &self,
+        frontend: &dyn Frontend,
+    ) -> Result<(Program, Option<Header>), membrane::Error> {
+        let source = self.read()?;
+
+        // Bytecode has no macros to expand.
+        if loader::is_bytecode(&source) {
+            return Ok(self.load(frontend)?);
+        }
+
+        let expansion = preprocessor::preprocess(&source, self.path().map(Path::new))?;
+
+        match frontend.parse(&expansion.source) {
+            Ok(mut program) => {
+                expansion.remap(&mut program.spans);
+                Ok((program, None))
+            }
+            Err(errors) => Err(membrane::Error::Parse(
+                errors
+                    .into_iter()
+                    .map(|err| err.map_locations(|location| expansion.locate(location)))
+                    .collect(),
+            )),
+        }
+    }
+}
+
+// The frontend for the dialect from the flag, or else the one the source's extension is
+// for. Exits if the dialect isn't known, or its map can't be read.
+fn frontend(source: &Source, args: &DialectArgs, inline_input: bool) -> Box<dyn Frontend> {
+    let options = ParseOptions {
+        inline_input,
+        ..ParseOptions::default()
+    };
+
+    if let Some(path) = &args.dialect_map {
+        return Box::new(ParserFrontend::new(
+            path.clone(),
+            ParseOptions {
+                dialect: Some(read_dialect_map(path)),
+                ..options
+            },
+        ));
+    }
+
+    let mut registry = frontend::Registry::builtin_with(&options);
+
+    let name = match &args.dialect {
+        Some(name) => name.clone(),
+        None => source
+            .path()
+            .and_then(|path| Path::new(path).extension()?.to_str())
+            .and_then(|extension| registry.for_extension(extension))
+            .map_or(Dialect::default().name(), |frontend| frontend.name())
+            .to_owned(),
+    };
+
+    registry.remove(&name).unwrap_or_else(|| {
+        eprintln!(
+            "error: unknown dialect '{}' (expected one of: {})",
+            name,
+            registry.names().join(", ")
+        );
+        process::exit(EXIT_USAGE);
+    })
+}
+
+// Exits if the map can't be read.
+fn read_dialect_map(path: &str) -> DialectMap {
+    let map = fs::read_to_string(path).unwrap_or_else(|err| {
+        eprintln!("error: failed to read {}: {}", path, err);
+        process::exit(EXIT_IO_ERROR);
+    });
+
+    map.parse().unwrap_or_else(|err| {
+        eprintln!("error: {}: {}", path, err);
+        process::exit(EXIT_IO_ERROR);
+    })
+}
+
+// Names the program after its source, and the dialect it was read in, unless it was
+// bytecode that already knew them.
+fn describe_program(program: &mut Program, source: &Source, frontend: &dyn Frontend) {
+    if program.source_name.is_none() {
+        program.source_name = source.path().map(str::to_owned);
+    }
+
+    program
+        .dialect
+        .get_or_insert_with(|| frontend.name().to_owned());
+}
+
+fn load_program(
+    source: &Source,
+    args: &OptimizeArgs,
+    frontend: &dyn Frontend,
+    preprocess: bool,
+    machine: &mut Machine,
+    report: &mut Option<OptimizeReport>,
+) -> Program {
+    let options = OptimizeOptions {
+        tape_size: machine.tape_size(),
+        fuel: args.opt_fuel,
+        ..OptimizeOptions::default()
+    };
+
+    // The cache is keyed on the source alone, and only holds instructions and spans, so
+    // programs parsed any other way, or that include other files, are always optimized
+    // from scratch. Bytecode is left out as well, since its header has to be read, and so
+    // are runs with fuel, which have to find out whether it runs out.
+    let plain = frontend.options() == Some(&ParseOptions::default());
+    let cache = if !args.optimize
+        || args.no_cache
+        || args.opt_fuel.is_some()
+        || preprocess
+        || !plain
+        || source.is_bytecode()
+    {
+        None
+    } else {
+        args.cache_dir
+            .clone()
+            .map(PathBuf::from)
+            .or_else(Cache::default_directory)
+            .map(Cache::new)
+    };
+
+    let key = cache
+        .as_ref()
+        .and_then(|_| CacheKey::new(source.reader().ok()?, &options).ok());
+
+    if let (Some(cache), Some(key)) = (&cache, key) {
+        if let Some((instructions, spans)) = cache.load(key) {
+            tracing::debug!(directory = %cache.directory().display(), "loaded from the cache");
+
+            return Program::new(instructions, spans);
+        }
+    }
+
+    let (mut program, header) = source.parse(frontend, preprocess);
+
+    if let Some(header) = &header {
+        machine.apply_header(header, source.name());
+    }
+
+    // Bytecode that was optimized when it was compiled is used as it is.
+    if !args.optimize || header.is_some_and(|header| header.optimized) {
+        return program;
+    }
+
+    // The optimizer folds adds into 8-bit amounts, which would truncate them for wider
+    // cells.
+    if machine.cell_width() != CellWidth::U8 {
+        eprintln!("error: only programs with 8-bit cells can be optimized");
+        process::exit(EXIT_USAGE);
+    }
+
+    let options = OptimizeOptions {
+        tape_size: machine.tape_size(),
+        ..options
+    };
+    let result = optimizer::optimize_program_with_report(
+        &mut program,
+        &options,
+        report.insert(OptimizeReport::default()),
+    );
+
+    match result {
+        Err(err) if !err.is_fatal() => eprintln!("warning: {}", err),
+        Err(err) => {
+            eprintln!("error: {}", err);
+
+            if args.dump_ir {
+                let instructions = program.instructions.iter().zip(&program.spans);
+
+                for (index, (instruction, span)) in instructions.enumerate() {
+                    eprintln!("{:8}  {}  ; {}", index, instruction, span);
+                }
+            }
+
+            process::exit(EXIT_FAILURE);
+        }
+        Ok(_) => {}
+    }
+
+    // Programs the optimizer gave up on are left out of the cache, so that the warning
+    // isn't lost on later runs.
+    if let (Some(cache), Some(key), Ok(_)) = (&cache, key, &result) {
+        match cache.store(key, &program.instructions, &program.spans) {
+            Ok(_) => {
+                tracing::debug!(directory = %cache.directory().display(), "stored in the cache")
+            }
+            Err(err) => tracing::debug!(error = %err, "failed to store in the cache"),
+        }
+    }
+
+    program
 }
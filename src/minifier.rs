@@ -0,0 +1,50 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use crate::compilers::brainfuck;
+use crate::lowering;
+use crate::optimizer::{self, OptimizeError, OptimizeOptions};
+use crate::program::Program;
+
+// Writes the program as nothing but its commands, in plain Brainfuck whatever dialect it
+// was read from, leaving out adds and moves that cancel out and loops that can never run.
+// A program ended by `!` keeps its input after it.
+//
+// With optimize options, the program is also optimized and written back out, and whichever
+// of the two is shorter is returned. Only the optimizer's internal errors are returned,
+// since a program it gave up on partway is still equivalent.
+pub fn minify(
+    program: &Program,
+    optimize: Option<&OptimizeOptions>,
+) -> Result<Vec<u8>, OptimizeError> {
+    let mut code = commands(program);
+
+    if let Some(options) = optimize {
+        let mut optimized = program.clone();
+
+        match optimizer::optimize_program(&mut optimized, options) {
+            Err(err) if err.is_fatal() => return Err(err),
+            _ => {}
+        }
+
+        let optimized = commands(&optimized);
+
+        if optimized.len() < code.len() {
+            code = optimized;
+        }
+    }
+
+    if let Some(input) = &program.input {
+        code.push(b'!');
+        code.extend_from_slice(input);
+    }
+
+    Ok(code)
+}
+
+fn commands(program: &Program) -> Vec<u8> {
+    brainfuck::minify(lowering::to_brainfuck(&program.instructions).as_bytes())
+}
@@ -4,77 +4,511 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use std::cmp::Ordering;
-use std::mem;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::hash::{Hash, Hasher};
+use core::mem;
+use core::ops::Range;
+#[cfg(feature = "std")]
+use std::io::{Result as IOResult, Write};
+
+use thiserror::Error;
 
 use crate::instruction::Instruction;
 use crate::interpreter::TapeSize;
+use crate::program::Program;
+use crate::span::Span;
+
+// Bumped whenever a pass changes what it turns programs into, so that programs cached by an
+// older optimizer aren't reused.
+pub const VERSION: u32 = 2;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Error)]
+pub enum OptimizeError {
+    #[error(
+        "internal optimizer error: JumpIfZero at instruction {index} has no matching JumpIfNotZero"
+    )]
+    UnmatchedJumpIfZero { index: usize },
+    #[error(
+        "internal optimizer error: JumpIfNotZero at instruction {index} has no matching JumpIfZero"
+    )]
+    UnmatchedJumpIfNotZero { index: usize },
+    #[error("internal optimizer error: DefineProc at instruction {index} has no matching EndProc")]
+    UnmatchedDefineProc { index: usize },
+    #[error("internal optimizer error: EndProc at instruction {index} has no matching DefineProc")]
+    UnmatchedEndProc { index: usize },
+    #[error("the optimizer didn't reach a fixpoint within {passes} passes")]
+    PassBudgetExhausted { passes: usize },
+    #[error("the optimizer started cycling through the same {period} program(s) at pass {pass}")]
+    Oscillation { pass: usize, period: usize },
+}
 
-// TODO: Improve optimizations by taking the tape size into account.
-pub fn optimize(verbose: bool, instructions: &mut Vec<Instruction>, _tape_size: TapeSize) {
-    let raw_count = instructions.len();
-    let mut buffer = Vec::with_capacity(raw_count);
+impl OptimizeError {
+    // The budget and oscillation checks stop the optimizer early, but what it hands back is
+    // still a valid (if not fully optimized) program.
+    #[inline]
+    pub const fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            Self::UnmatchedJumpIfZero { .. }
+                | Self::UnmatchedJumpIfNotZero { .. }
+                | Self::UnmatchedDefineProc { .. }
+                | Self::UnmatchedEndProc { .. }
+        )
+    }
+}
 
-    if verbose {
-        println!("INIT: {} instruction(s)", raw_count);
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct OptimizeOptions {
+    pub tape_size: TapeSize,
+    // The number of individual rewrites that may be performed before every pass becomes a
+    // no-op; `None` means unlimited. Bisecting on this pinpoints a miscompiling rewrite.
+    pub fuel: Option<usize>,
+    // Whether large programs may have their top-level segments optimized in parallel before
+    // the whole program is. Ignored when fuel is limited, since the order of rewrites has to
+    // be deterministic for bisecting to work.
+    pub parallel: bool,
+    // The number of times the passes may run over the whole program before the optimizer
+    // gives up on reaching a fixpoint.
+    pub max_passes: usize,
+}
+
+impl Default for OptimizeOptions {
+    fn default() -> Self {
+        Self {
+            tape_size: TapeSize::Infinite,
+            fuel: None,
+            parallel: true,
+            max_passes: 256,
+        }
     }
+}
 
-    loop {
-        let start_instruction_count = instructions.len();
-        {
-            squash_and_clean(instructions, &mut buffer);
+// What the optimizer did to a program, which it also reports to tracing as it goes.
+#[derive(Clone, Eq, PartialEq, Default, Debug)]
+pub struct OptimizeReport {
+    pub initial_instructions: usize,
+    // The instructions left after the program's segments were optimized in parallel, if
+    // it was large enough for them to be.
+    pub segment_instructions: Option<usize>,
+    // The instructions left after each pass over the whole program.
+    pub passes: Vec<usize>,
+    pub fuel_exhausted: bool,
+}
+
+impl OptimizeReport {
+    // Writes the report as a JSON object, with each line after the first indented by the
+    // given amount so it can be nested in another object.
+    #[cfg(feature = "std")]
+    pub fn write_json<W: Write>(&self, writer: &mut W, indent: &str) -> IOResult<()> {
+        writeln!(writer, "{{")?;
+        writeln!(
+            writer,
+            "{}  \"initial_instructions\": {},",
+            indent, self.initial_instructions
+        )?;
+
+        match self.segment_instructions {
+            Some(count) => writeln!(writer, "{}  \"segment_instructions\": {},", indent, count)?,
+            None => writeln!(writer, "{}  \"segment_instructions\": null,", indent)?,
+        }
+
+        let passes = self
+            .passes
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        writeln!(writer, "{}  \"passes\": [{}],", indent, passes.join(", "))?;
+        writeln!(
+            writer,
+            "{}  \"fuel_exhausted\": {}",
+            indent, self.fuel_exhausted
+        )?;
+        write!(writer, "{}}}", indent)
+    }
+}
+
+struct Fuel {
+    remaining: Option<usize>,
+}
+
+impl Fuel {
+    #[inline]
+    fn is_exhausted(&self) -> bool {
+        self.remaining == Some(0)
+    }
+
+    #[inline]
+    fn consume(&mut self) -> bool {
+        match &mut self.remaining {
+            Some(0) => false,
+            Some(remaining) => {
+                *remaining -= 1;
+                true
+            }
+            None => true,
+        }
+    }
+}
 
-            substitute_patterns_4(instructions, &mut buffer);
-            substitute_patterns_3(instructions, &mut buffer);
-            substitute_patterns_2(instructions, &mut buffer);
+// Every pass rewrites instructions and their source spans in lockstep, so that a fused
+// instruction always carries the merged span of everything it was built from.
+#[derive(Default)]
+struct Stream {
+    instructions: Vec<Instruction>,
+    spans: Vec<Span>,
+}
 
-            remove_spurious_loops(instructions, &mut buffer);
+impl Stream {
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            instructions: Vec::with_capacity(capacity),
+            spans: Vec::with_capacity(capacity),
         }
-        let end_instruction_count = instructions.len();
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.instructions.len()
+    }
 
-        if verbose {
-            println!(
-                "PASS: {} instruction(s) [{:.2}% -- decreased by {} instruction(s)]",
-                end_instruction_count,
-                (end_instruction_count as f32) / (raw_count as f32),
-                start_instruction_count - end_instruction_count
-            );
+    #[inline]
+    fn push(&mut self, instruction: Instruction, span: Span) {
+        self.instructions.push(instruction);
+        self.spans.push(span);
+    }
+
+    #[inline]
+    fn extend(&mut self, instructions: &[Instruction], span: Span) {
+        for instruction in instructions {
+            self.push(*instruction, span);
         }
+    }
+
+    #[inline]
+    fn extend_from(&mut self, other: &Stream, start: usize) {
+        self.instructions
+            .extend_from_slice(&other.instructions[start..]);
+        self.spans.extend_from_slice(&other.spans[start..]);
+    }
+
+    #[inline]
+    fn clear(&mut self) {
+        self.instructions.clear();
+        self.spans.clear();
+    }
+
+    fn span_of(&self, range: Range<usize>) -> Span {
+        self.spans[range]
+            .iter()
+            .copied()
+            .reduce(Span::merge)
+            .unwrap_or_default()
+    }
+
+    fn finish(&mut self, buffer: &mut Stream) {
+        self.clear();
+        mem::swap(self, buffer);
+    }
+}
+
+// Trivia is dropped, since it no longer lines up with the instructions.
+pub fn optimize_program(
+    program: &mut Program,
+    options: &OptimizeOptions,
+) -> Result<(), OptimizeError> {
+    optimize_program_with_report(program, options, &mut OptimizeReport::default())
+}
+
+pub fn optimize_program_with_report(
+    program: &mut Program,
+    options: &OptimizeOptions,
+    report: &mut OptimizeReport,
+) -> Result<(), OptimizeError> {
+    program.trivia = None;
+    optimize_with_report(
+        &mut program.instructions,
+        &mut program.spans,
+        options,
+        report,
+    )
+}
+
+pub fn optimize(
+    instructions: &mut Vec<Instruction>,
+    spans: &mut Vec<Span>,
+    options: &OptimizeOptions,
+) -> Result<(), OptimizeError> {
+    optimize_with_report(instructions, spans, options, &mut OptimizeReport::default())
+}
 
-        if end_instruction_count >= start_instruction_count {
+// Fills in the report as well, which is kept even when optimizing fails.
+// TODO: Improve optimizations by taking the tape size into account.
+pub fn optimize_with_report(
+    instructions: &mut Vec<Instruction>,
+    spans: &mut Vec<Span>,
+    options: &OptimizeOptions,
+    report: &mut OptimizeReport,
+) -> Result<(), OptimizeError> {
+    debug_assert_eq!(instructions.len(), spans.len());
+
+    let raw_count = instructions.len();
+    let mut stream = Stream {
+        instructions: mem::take(instructions),
+        spans: mem::take(spans),
+    };
+
+    *report = OptimizeReport {
+        initial_instructions: raw_count,
+        ..OptimizeReport::default()
+    };
+
+    // Each pass gets a span of its own inside this one, so that subscribers can time them.
+    let span = tracing::debug_span!("optimize", instructions = raw_count);
+    let _entered = span.enter();
+
+    #[cfg(feature = "parallel")]
+    if options.parallel && options.fuel.is_none() && raw_count >= PARALLEL_THRESHOLD {
+        stream = parallel::optimize_segments(stream, options.tape_size);
+        report.segment_instructions = Some(stream.len());
+
+        tracing::debug!(
+            instructions = stream.len(),
+            "optimized segments in parallel"
+        );
+    }
+
+    let mut buffer = Stream::with_capacity(stream.len());
+    let mut fuel = Fuel {
+        remaining: options.fuel,
+    };
+
+    // Rewrites aren't required to shrink the program, so two of them could undo each other
+    // forever. Remembering every program seen so far catches that.
+    let mut seen = BTreeMap::from([(hash_instructions(&stream.instructions), 0)]);
+    let mut stopped_early = None;
+
+    for pass in 1.. {
+        if pass > options.max_passes {
+            stopped_early = Some(OptimizeError::PassBudgetExhausted {
+                passes: options.max_passes,
+            });
             break;
         }
+
+        let span = tracing::debug_span!("pass", pass);
+        let _entered = span.enter();
+
+        let start_instruction_count = stream.len();
+        {
+            run_local_passes(&mut stream, &mut buffer, &mut fuel, options.tape_size);
+
+            remove_spurious_loops(&mut stream, &mut buffer, &mut fuel);
+            fold_known_zero_prologue(&mut stream, &mut buffer, &mut fuel, options.tape_size);
+        }
+        let end_instruction_count = stream.len();
+        report.passes.push(end_instruction_count);
+
+        tracing::debug!(
+            instructions = end_instruction_count,
+            removed = start_instruction_count as isize - end_instruction_count as isize,
+            "finished the pass"
+        );
+
+        match seen.insert(hash_instructions(&stream.instructions), pass) {
+            Some(previous) if previous == pass - 1 => break,
+            Some(previous) => {
+                stopped_early = Some(OptimizeError::Oscillation {
+                    pass,
+                    period: pass - previous,
+                });
+                break;
+            }
+            None => {}
+        }
+    }
+
+    report.fuel_exhausted = fuel.is_exhausted();
+
+    if fuel.is_exhausted() {
+        tracing::debug!("ran out of fuel");
+    }
+
+    // The stream is handed back even if the loops can't be fixed, so that callers can
+    // inspect the broken IR.
+    let result = fix_loops(&mut stream.instructions);
+
+    *instructions = stream.instructions;
+    *spans = stream.spans;
+
+    match stopped_early {
+        Some(err) => result.and(Err(err)),
+        None => result,
+    }
+}
+
+fn hash_instructions(instructions: &[Instruction]) -> u64 {
+    let mut hasher = Fnv::default();
+    instructions.hash(&mut hasher);
+    hasher.finish()
+}
+
+// FNV-1a, since std's hasher isn't there without std. The cache keys entries with it as
+// well, which relies on it giving the same hashes from one release to the next, unlike
+// std's.
+pub(crate) struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+// These passes only ever look at a small window of instructions, so they give the same
+// result on any part of a program as they would on the whole.
+fn run_local_passes(
+    stream: &mut Stream,
+    buffer: &mut Stream,
+    fuel: &mut Fuel,
+    tape_size: TapeSize,
+) {
+    squash_and_clean(stream, buffer, fuel);
+
+    substitute_patterns_4(stream, buffer, fuel, tape_size);
+    substitute_patterns_3(stream, buffer, fuel, tape_size);
+    substitute_patterns_2(stream, buffer, fuel);
+
+    schedule_adds(stream, buffer, fuel);
+    reduce_balanced_loops(stream, buffer, fuel, tape_size);
+}
+
+#[cfg(feature = "parallel")]
+const PARALLEL_THRESHOLD: usize = 1 << 16;
+
+#[cfg(feature = "parallel")]
+mod parallel {
+    use std::ops::Range;
+
+    use rayon::prelude::*;
+
+    use super::{run_local_passes, Fuel, Stream};
+    use crate::instruction::Instruction;
+    use crate::interpreter::TapeSize;
+
+    const MINIMUM_SEGMENT_SIZE: usize = 1 << 12;
+
+    // Splits the program between top-level loops and runs the local passes to a fixpoint on
+    // each segment independently. The global passes still have to run over the result.
+    pub(super) fn optimize_segments(stream: Stream, tape_size: TapeSize) -> Stream {
+        let target_size =
+            (stream.len() / (rayon::current_num_threads() * 4)).max(MINIMUM_SEGMENT_SIZE);
+
+        let segments = split_segments(&stream.instructions, target_size);
+        let optimized = segments
+            .into_par_iter()
+            .map(|range| {
+                let mut segment = Stream {
+                    instructions: stream.instructions[range.clone()].to_vec(),
+                    spans: stream.spans[range].to_vec(),
+                };
+                let mut buffer = Stream::with_capacity(segment.len());
+                let mut fuel = Fuel { remaining: None };
+
+                loop {
+                    let start_instruction_count = segment.len();
+                    run_local_passes(&mut segment, &mut buffer, &mut fuel, tape_size);
+
+                    if segment.len() >= start_instruction_count {
+                        break segment;
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut joined = Stream::with_capacity(optimized.iter().map(Stream::len).sum());
+
+        for segment in &optimized {
+            joined.extend_from(segment, 0);
+        }
+
+        joined
     }
 
-    fix_loops(instructions);
+    fn split_segments(instructions: &[Instruction], target_size: usize) -> Vec<Range<usize>> {
+        let mut segments = Vec::new();
+        let mut segment_start = 0;
+        let mut loop_depth = 0usize;
+
+        for (index, instruction) in instructions.iter().enumerate() {
+            match instruction {
+                Instruction::JumpIfZero { .. } => {
+                    if loop_depth == 0 && index - segment_start >= target_size {
+                        segments.push(segment_start..index);
+                        segment_start = index;
+                    }
+
+                    loop_depth += 1;
+                }
+                Instruction::JumpIfNotZero { .. } => {
+                    loop_depth = loop_depth.saturating_sub(1);
+                }
+                _ => {}
+            }
+        }
+
+        segments.push(segment_start..instructions.len());
+        segments
+    }
 }
 
-fn squash_and_clean(instructions: &mut Vec<Instruction>, buffer: &mut Vec<Instruction>) {
+fn squash_and_clean(stream: &mut Stream, buffer: &mut Stream, fuel: &mut Fuel) {
     {
-        let mut iterator = instructions.drain(..).peekable();
+        let instructions = &stream.instructions;
+        let mut index = 0;
+
+        while let Some(&instruction) = instructions.get(index) {
+            if fuel.is_exhausted() {
+                break;
+            }
+
+            let start = index;
+            let buffer_start = buffer.len();
+            index += 1;
 
-        while let Some(instruction) = iterator.next() {
             match instruction {
                 Instruction::Add(start_amount) => {
                     let mut accumulator = start_amount;
 
-                    while let Some(Instruction::Add(next_amount)) = iterator.peek() {
+                    while let Some(Instruction::Add(next_amount)) = instructions.get(index) {
                         accumulator = accumulator.wrapping_add(*next_amount);
-                        iterator.next();
+                        index += 1;
                     }
 
                     if accumulator != 0 {
-                        buffer.push(Instruction::Add(accumulator));
+                        buffer.push(Instruction::Add(accumulator), stream.span_of(start..index));
                     }
                 }
                 Instruction::Move(start_amount) => {
                     let mut accumulator = start_amount;
 
-                    while let Some(Instruction::Move(next_amount)) = iterator.peek() {
+                    while let Some(Instruction::Move(next_amount)) = instructions.get(index) {
                         match accumulator.checked_add(*next_amount) {
                             Some(new_accumulator) => {
                                 accumulator = new_accumulator;
-                                iterator.next();
+                                index += 1;
                             }
                             None => {
                                 break;
@@ -83,17 +517,17 @@ fn squash_and_clean(instructions: &mut Vec<Instruction>, buffer: &mut Vec<Instru
                     }
 
                     if accumulator != 0 {
-                        buffer.push(Instruction::Move(accumulator));
+                        buffer.push(Instruction::Move(accumulator), stream.span_of(start..index));
                     }
                 }
                 Instruction::Write(start_amount) => {
                     let mut accumulator = start_amount;
 
-                    while let Some(Instruction::Write(next_amount)) = iterator.peek() {
+                    while let Some(Instruction::Write(next_amount)) = instructions.get(index) {
                         match accumulator.checked_add(*next_amount) {
                             Some(new_accumulator) => {
                                 accumulator = new_accumulator;
-                                iterator.next();
+                                index += 1;
                             }
                             None => {
                                 break;
@@ -102,17 +536,20 @@ fn squash_and_clean(instructions: &mut Vec<Instruction>, buffer: &mut Vec<Instru
                     }
 
                     if accumulator != 0 {
-                        buffer.push(Instruction::Write(accumulator));
+                        buffer.push(
+                            Instruction::Write(accumulator),
+                            stream.span_of(start..index),
+                        );
                     }
                 }
                 Instruction::Read(start_amount) => {
                     let mut accumulator = start_amount;
 
-                    while let Some(Instruction::Read(next_amount)) = iterator.peek() {
+                    while let Some(Instruction::Read(next_amount)) = instructions.get(index) {
                         match accumulator.checked_add(*next_amount) {
                             Some(new_accumulator) => {
                                 accumulator = new_accumulator;
-                                iterator.next();
+                                index += 1;
                             }
                             None => {
                                 break;
@@ -121,19 +558,22 @@ fn squash_and_clean(instructions: &mut Vec<Instruction>, buffer: &mut Vec<Instru
                     }
 
                     if accumulator != 0 {
-                        buffer.push(Instruction::Read(accumulator));
+                        buffer.push(Instruction::Read(accumulator), stream.span_of(start..index));
                     }
                 }
 
                 Instruction::SetValue(start_value) => {
                     let mut final_value = start_value;
 
-                    while let Some(Instruction::SetValue(next_value)) = iterator.peek() {
+                    while let Some(Instruction::SetValue(next_value)) = instructions.get(index) {
                         final_value = *next_value;
-                        iterator.next();
+                        index += 1;
                     }
 
-                    buffer.push(Instruction::SetValue(final_value));
+                    buffer.push(
+                        Instruction::SetValue(final_value),
+                        stream.span_of(start..index),
+                    );
                 }
 
                 Instruction::AddRelative {
@@ -145,24 +585,29 @@ fn squash_and_clean(instructions: &mut Vec<Instruction>, buffer: &mut Vec<Instru
                     while let Some(Instruction::AddRelative {
                         offset: next_offset,
                         amount: next_amount,
-                    }) = iterator.peek()
+                    }) = instructions.get(index)
                     {
                         if offset == *next_offset {
                             total_amount = total_amount.wrapping_add(*next_amount);
-                            iterator.next();
+                            index += 1;
                         } else {
                             break;
                         }
                     }
 
                     if total_amount != 0 {
+                        let span = stream.span_of(start..index);
+
                         if offset != 0 {
-                            buffer.push(Instruction::AddRelative {
-                                offset,
-                                amount: total_amount,
-                            });
+                            buffer.push(
+                                Instruction::AddRelative {
+                                    offset,
+                                    amount: total_amount,
+                                },
+                                span,
+                            );
                         } else {
-                            buffer.push(Instruction::Add(total_amount));
+                            buffer.push(Instruction::Add(total_amount), span);
                         }
                     }
                 }
@@ -171,422 +616,840 @@ fn squash_and_clean(instructions: &mut Vec<Instruction>, buffer: &mut Vec<Instru
 
                     while let Some(Instruction::AddVector {
                         vector: next_vector,
-                    }) = iterator.peek()
+                    }) = instructions.get(index)
                     {
                         accumulator[0] = accumulator[0].wrapping_add(next_vector[0]);
                         accumulator[1] = accumulator[1].wrapping_add(next_vector[1]);
                         accumulator[2] = accumulator[2].wrapping_add(next_vector[2]);
                         accumulator[3] = accumulator[3].wrapping_add(next_vector[3]);
-                        iterator.next();
+                        index += 1;
                     }
 
+                    let span = stream.span_of(start..index);
+
                     match accumulator {
                         [0, 0, 0, 0] => {}
-                        [amount, 0, 0, 0] => buffer.push(Instruction::Add(amount)),
+                        [amount, 0, 0, 0] => buffer.push(Instruction::Add(amount), span),
                         [0, amount, 0, 0] => {
-                            buffer.push(Instruction::AddRelative { offset: 1, amount });
+                            buffer.push(Instruction::AddRelative { offset: 1, amount }, span);
                         }
                         [0, 0, amount, 0] => {
-                            buffer.push(Instruction::AddRelative { offset: 2, amount });
+                            buffer.push(Instruction::AddRelative { offset: 2, amount }, span);
                         }
                         [0, 0, 0, amount] => {
-                            buffer.push(Instruction::AddRelative { offset: 3, amount });
+                            buffer.push(Instruction::AddRelative { offset: 3, amount }, span);
                         }
-                        _ => buffer.push(Instruction::AddVector {
-                            vector: accumulator,
-                        }),
+                        _ => buffer.push(
+                            Instruction::AddVector {
+                                vector: accumulator,
+                            },
+                            span,
+                        ),
                     }
                 }
 
-                inst @ Instruction::MoveRightToZero { .. }
-                | inst @ Instruction::MoveLeftToZero { .. } => {
+                Instruction::MoveRightToZero { .. } | Instruction::MoveLeftToZero { .. } => {
                     while let Some(
                         Instruction::MoveRightToZero { .. } | Instruction::MoveLeftToZero { .. },
-                    ) = iterator.peek()
+                    ) = instructions.get(index)
                     {
-                        iterator.next();
+                        index += 1;
                     }
 
-                    buffer.push(inst);
+                    buffer.push(instruction, stream.span_of(start..index));
                 }
 
                 _ => {
-                    buffer.push(instruction);
+                    buffer.push(instruction, stream.spans[start]);
                 }
             }
+
+            if index - start > 1 || buffer.instructions[buffer_start..] != [instruction] {
+                fuel.consume();
+            }
         }
+
+        buffer.extend_from(stream, index);
     }
 
-    mem::swap(instructions, buffer);
+    stream.finish(buffer);
 }
 
-fn substitute_patterns_2(instructions: &mut Vec<Instruction>, buffer: &mut Vec<Instruction>) {
-    if instructions.len() < 2 {
+fn substitute_patterns_2(stream: &mut Stream, buffer: &mut Stream, fuel: &mut Fuel) {
+    if stream.len() < 2 {
         return;
     }
 
-    let mut matched = false;
-    let mut iterator = instructions.windows(2);
-
-    while let Some(window) = iterator.next() {
-        matched = false;
+    {
+        let instructions = &stream.instructions;
+        let mut index = 0;
 
-        match window {
-            [Instruction::Add(_), Instruction::SetValue(value)] => {
-                matched = true;
-                buffer.push(Instruction::SetValue(*value));
-            }
-            [Instruction::Add(a), Instruction::AddRelative { offset, amount: b }]
-            | [Instruction::AddRelative { offset, amount: b }, Instruction::Add(a)] => {
-                let offset = *offset;
+        while index + 2 <= instructions.len() && !fuel.is_exhausted() {
+            let window = &instructions[index..index + 2];
+            let span = stream.span_of(index..index + 2);
+            let mut matched = false;
 
-                if offset > 0 && offset < 4 {
+            match window {
+                [Instruction::Add(_), Instruction::SetValue(value)] => {
                     matched = true;
+                    buffer.push(Instruction::SetValue(*value), span);
+                }
+                [Instruction::Add(a), Instruction::AddRelative { offset, amount: b }]
+                | [Instruction::AddRelative { offset, amount: b }, Instruction::Add(a)] => {
+                    let offset = *offset;
+
+                    if offset > 0 && offset < 4 {
+                        matched = true;
 
-                    let mut vector = [0; 4];
-                    vector[0] = *a;
-                    vector[offset as usize] = *b;
+                        let mut vector = [0; 4];
+                        vector[0] = *a;
+                        vector[offset as usize] = *b;
 
-                    buffer.push(Instruction::AddVector { vector });
-                } else if offset == 0 {
-                    buffer.push(Instruction::Add(a.wrapping_add(*b)));
+                        buffer.push(Instruction::AddVector { vector }, span);
+                    } else if offset == 0 {
+                        matched = true;
+                        buffer.push(Instruction::Add(a.wrapping_add(*b)), span);
+                    }
                 }
-            }
-            [Instruction::Add(amount), Instruction::AddVector { vector }]
-            | [Instruction::AddVector { vector }, Instruction::Add(amount)] => {
-                matched = true;
-                buffer.push(Instruction::AddVector {
-                    vector: [
-                        vector[0].wrapping_add(*amount),
-                        vector[1],
-                        vector[2],
-                        vector[3],
-                    ],
-                });
-            }
-            [Instruction::SetValue(value), Instruction::Add(amount)] => {
-                matched = true;
-                buffer.push(Instruction::SetValue(value.wrapping_add(*amount)));
-            }
-            [Instruction::SetValue(0), Instruction::MoveRightToZero { .. } | Instruction::MoveLeftToZero { .. }] =>
-            {
-                matched = true;
-                buffer.push(Instruction::SetValue(0));
-            }
-            [Instruction::AddRelative { offset, amount }, Instruction::AddVector { vector }] => {
-                let offset = *offset;
+                [Instruction::Add(amount), Instruction::AddVector { vector }]
+                | [Instruction::AddVector { vector }, Instruction::Add(amount)] => {
+                    matched = true;
+                    buffer.push(
+                        Instruction::AddVector {
+                            vector: [
+                                vector[0].wrapping_add(*amount),
+                                vector[1],
+                                vector[2],
+                                vector[3],
+                            ],
+                        },
+                        span,
+                    );
+                }
+                [Instruction::SetValue(value), Instruction::Add(amount)]
+                | [Instruction::SetValue(value), Instruction::AddRelative { offset: 0, amount }] => {
+                    matched = true;
+                    buffer.push(Instruction::SetValue(value.wrapping_add(*amount)), span);
+                }
+                [Instruction::SetValue(value), Instruction::AddVector { vector }]
+                    if vector[0] != 0 =>
+                {
+                    matched = true;
+                    buffer.extend(
+                        &[
+                            Instruction::SetValue(value.wrapping_add(vector[0])),
+                            Instruction::AddVector {
+                                vector: [0, vector[1], vector[2], vector[3]],
+                            },
+                        ],
+                        span,
+                    );
+                }
+                [Instruction::AddVector { vector }, Instruction::SetValue(value)]
+                    if vector[0] != 0 =>
+                {
+                    matched = true;
+                    buffer.extend(
+                        &[
+                            Instruction::AddVector {
+                                vector: [0, vector[1], vector[2], vector[3]],
+                            },
+                            Instruction::SetValue(*value),
+                        ],
+                        span,
+                    );
+                }
+                // A multiplication by a known value is just an add. The store is kept after it,
+                // since the rest of the run still needs it.
+                [Instruction::SetValue(value), Instruction::MulAdd { offset, factor }] => {
+                    matched = true;
 
-                if (0..4).contains(&offset) {
+                    let amount = value.wrapping_mul(*factor);
+
+                    if amount != 0 {
+                        buffer.push(
+                            Instruction::AddRelative {
+                                offset: *offset,
+                                amount,
+                            },
+                            span,
+                        );
+                    }
+
+                    buffer.push(Instruction::SetValue(*value), span);
+                }
+                [Instruction::SetValue(0), Instruction::MoveRightToZero { .. } | Instruction::MoveLeftToZero { .. }] =>
+                {
+                    matched = true;
+                    buffer.push(Instruction::SetValue(0), span);
+                }
+                [Instruction::AddRelative { offset, amount }, Instruction::AddVector { vector }]
+                    if (0..4).contains(offset) =>
+                {
                     matched = true;
 
+                    let offset = *offset as usize;
                     let mut vector = *vector;
-                    vector[offset as usize] = vector[offset as usize].wrapping_add(*amount);
+                    vector[offset] = vector[offset].wrapping_add(*amount);
 
-                    buffer.push(Instruction::AddVector { vector });
+                    buffer.push(Instruction::AddVector { vector }, span);
                 }
+                [first @ Instruction::MoveRightToZero { .. }
+                | first @ Instruction::MoveLeftToZero { .. }, Instruction::Add(amount)] => {
+                    matched = true;
+                    buffer.extend(&[*first, Instruction::SetValue(*amount)], span);
+                }
+                [first @ Instruction::MoveRightToZero { .. }
+                | first @ Instruction::MoveLeftToZero { .. }, Instruction::SetValue(0)] => {
+                    matched = true;
+                    buffer.push(*first, span);
+                }
+                _ => {}
             }
-            [first @ Instruction::MoveRightToZero { .. }
-            | first @ Instruction::MoveLeftToZero { .. }, Instruction::Add(amount)] => {
-                matched = true;
-                buffer.extend_from_slice(&[*first, Instruction::SetValue(*amount)]);
-            }
-            [first @ Instruction::MoveRightToZero { .. }
-            | first @ Instruction::MoveLeftToZero { .. }, Instruction::SetValue(0)] => {
-                matched = true;
-                buffer.push(*first);
-            }
-            _ => {}
-        }
 
-        if matched {
-            iterator.next();
-        } else {
-            buffer.push(window[0]);
+            if matched {
+                fuel.consume();
+                index += 2;
+            } else {
+                buffer.push(window[0], stream.spans[index]);
+                index += 1;
+            }
         }
-    }
 
-    if !matched {
-        buffer.push(instructions[instructions.len() - 1]);
+        buffer.extend_from(stream, index);
     }
 
-    instructions.clear();
-    mem::swap(instructions, buffer);
+    stream.finish(buffer);
 }
 
-fn substitute_patterns_3(instructions: &mut Vec<Instruction>, buffer: &mut Vec<Instruction>) {
-    if instructions.len() < 3 {
+fn substitute_patterns_3(
+    stream: &mut Stream,
+    buffer: &mut Stream,
+    fuel: &mut Fuel,
+    tape_size: TapeSize,
+) {
+    if stream.len() < 3 {
         return;
     }
 
-    let mut matched = false;
-    let mut iterator = instructions.windows(3);
+    {
+        let instructions = &stream.instructions;
+        let mut index = 0;
+
+        while index + 3 <= instructions.len() && !fuel.is_exhausted() {
+            let window = &instructions[index..index + 3];
+            let span = stream.span_of(index..index + 3);
+            let mut matched = false;
+
+            match window {
+                [Instruction::Add(a), Instruction::Move(stride), Instruction::Add(b)] => {
+                    let stride = *stride;
+
+                    match stride {
+                        -3..=-1 => {
+                            matched = true;
+
+                            let mut vector = [0; 4];
+                            vector[0] = *b;
+                            vector[-stride as usize] = *a;
+
+                            buffer.extend(
+                                &[Instruction::Move(stride), Instruction::AddVector { vector }],
+                                span,
+                            );
+                        }
+                        0..=3 => {
+                            matched = true;
+
+                            let mut vector = [0; 4];
+                            vector[0] = *a;
+                            vector[stride as usize] = *b;
+
+                            buffer.extend(
+                                &[Instruction::AddVector { vector }, Instruction::Move(stride)],
+                                span,
+                            );
+                        }
+                        _ => {}
+                    }
+                }
+                [Instruction::Move(move1), Instruction::Add(amount), Instruction::Move(move2)] => {
+                    let move1 = *move1;
+                    let move2 = *move2;
+                    let amount = *amount;
+
+                    matched = true;
+
+                    if move1 == -move2 {
+                        buffer.push(
+                            Instruction::AddRelative {
+                                offset: move1,
+                                amount,
+                            },
+                            span,
+                        );
+                    } else {
+                        buffer.extend(
+                            &[
+                                Instruction::AddRelative {
+                                    offset: move1,
+                                    amount,
+                                },
+                                Instruction::Move(move1 + move2),
+                            ],
+                            span,
+                        );
+                    }
+                }
+                [Instruction::JumpIfZero { .. }, Instruction::Add(1 | -1), Instruction::JumpIfNotZero { .. }] =>
+                {
+                    matched = true;
+                    buffer.push(Instruction::SetValue(0), span);
+                }
+                [Instruction::JumpIfZero { .. }, Instruction::Move(stride), Instruction::JumpIfNotZero { .. }] =>
+                {
+                    matched = true;
+                    let stride = *stride;
+
+                    match stride.cmp(&0) {
+                        Ordering::Greater => {
+                            buffer.push(
+                                Instruction::MoveRightToZero {
+                                    increment: 0,
+                                    stride: stride as usize,
+                                },
+                                span,
+                            );
+                        }
+                        Ordering::Less => {
+                            buffer.push(
+                                Instruction::MoveLeftToZero {
+                                    increment: 0,
+                                    stride: stride.unsigned_abs(),
+                                },
+                                span,
+                            );
+                        }
+                        _ => {}
+                    }
+                }
+                [first, inst, second] if inst.preserves_tape_head() => {
+                    if let Some(fused) = fuse_adds(first, second) {
+                        if add_commutes_with(second, inst, tape_size) {
+                            matched = true;
+                            buffer.extend(&[fused, *inst], span);
+                        } else if add_commutes_with(first, inst, tape_size) {
+                            matched = true;
+                            buffer.extend(&[*inst, fused], span);
+                        }
+                    }
+                }
+                _ => {}
+            }
+
+            if matched {
+                fuel.consume();
+                index += 3;
+            } else {
+                buffer.push(window[0], stream.spans[index]);
+                index += 1;
+            }
+        }
 
-    while let Some(window) = iterator.next() {
-        matched = false;
+        buffer.extend_from(stream, index);
+    }
+
+    stream.finish(buffer);
+}
 
-        match window {
-            [Instruction::Add(a), Instruction::Move(stride), Instruction::Add(b)] => {
-                let stride = *stride;
+fn substitute_patterns_4(
+    stream: &mut Stream,
+    buffer: &mut Stream,
+    fuel: &mut Fuel,
+    tape_size: TapeSize,
+) {
+    if stream.len() < 4 {
+        return;
+    }
 
-                match stride {
-                    -3..=-1 => {
+    {
+        let instructions = &stream.instructions;
+        let mut index = 0;
+
+        while index + 4 <= instructions.len() && !fuel.is_exhausted() {
+            let window = &instructions[index..index + 4];
+            let span = stream.span_of(index..index + 4);
+            let mut matched = false;
+
+            match window {
+                [Instruction::Add(a), Instruction::Move(move1), Instruction::Add(b), Instruction::Move(move2)] =>
+                {
+                    let move1 = *move1;
+                    let move2 = *move2;
+                    let total_move = move1 + move2;
+
+                    if move1 > 0 && move2 > 0 && total_move < 4 {
                         matched = true;
 
                         let mut vector = [0; 4];
-                        vector[0] = *b;
-                        vector[-stride as usize] = *a;
+                        vector[0] = *a;
+                        vector[move1 as usize] = *b;
+
+                        buffer.extend(
+                            &[
+                                Instruction::AddVector { vector },
+                                Instruction::Move(total_move),
+                            ],
+                            span,
+                        );
+                    } else if move1 < 0 && move2 < 0 && total_move > -4 {
+                        matched = true;
 
-                        buffer.extend_from_slice(&[
-                            Instruction::Move(stride),
-                            Instruction::AddVector { vector },
-                        ]);
+                        let mut vector = [0; 4];
+                        vector[-move2 as usize] = *b;
+                        vector[-total_move as usize] = *a;
+
+                        buffer.extend(
+                            &[
+                                Instruction::Move(total_move),
+                                Instruction::AddVector { vector },
+                            ],
+                            span,
+                        );
                     }
-                    0..=3 => {
+                }
+                [Instruction::Move(move1), Instruction::Add(a), Instruction::Move(move2), Instruction::Add(b)] =>
+                {
+                    let move1 = *move1;
+                    let move2 = *move2;
+                    let total_move = move1 + move2;
+
+                    if move1 > 0 && move2 > 0 && total_move < 4 {
                         matched = true;
 
                         let mut vector = [0; 4];
-                        vector[0] = *a;
-                        vector[stride as usize] = *b;
+                        vector[move1 as usize] = *a;
+                        vector[total_move as usize] = *b;
+
+                        buffer.extend(
+                            &[
+                                Instruction::AddVector { vector },
+                                Instruction::Move(total_move),
+                            ],
+                            span,
+                        );
+                    } else if move1 < 0 && move2 < 0 && total_move > -4 {
+                        matched = true;
 
-                        buffer.extend_from_slice(&[
-                            Instruction::AddVector { vector },
-                            Instruction::Move(stride),
-                        ]);
+                        let mut vector = [0; 4];
+                        vector[0] = *b;
+                        vector[-move2 as usize] = *a;
+
+                        buffer.extend(
+                            &[
+                                Instruction::Move(total_move),
+                                Instruction::AddVector { vector },
+                            ],
+                            span,
+                        );
                     }
-                    _ => {}
-                }
-            }
-            [Instruction::Move(move1), Instruction::Add(amount), Instruction::Move(move2)] => {
-                let move1 = *move1;
-                let move2 = *move2;
-                let amount = *amount;
-
-                matched = true;
-
-                if move1 == -move2 {
-                    buffer.push(Instruction::AddRelative {
-                        offset: move1,
-                        amount,
-                    });
-                } else {
-                    buffer.extend_from_slice(&[
-                        Instruction::AddRelative {
-                            offset: move1,
-                            amount,
-                        },
-                        Instruction::Move(move1 + move2),
-                    ]);
                 }
-            }
-            [Instruction::JumpIfZero { .. }, Instruction::Add(1 | -1), Instruction::JumpIfNotZero { .. }] =>
-            {
-                matched = true;
-                buffer.push(Instruction::SetValue(0));
-            }
-            [Instruction::JumpIfZero { .. }, Instruction::Move(stride), Instruction::JumpIfNotZero { .. }] =>
-            {
-                matched = true;
-                let stride = *stride;
+                [Instruction::JumpIfZero { .. }, Instruction::Add(increment), Instruction::Move(stride), Instruction::JumpIfNotZero { .. }] =>
+                {
+                    matched = true;
 
-                match stride.cmp(&0) {
-                    Ordering::Greater => {
-                        buffer.push(Instruction::MoveRightToZero {
-                            increment: 0,
-                            stride: stride as usize,
-                        });
+                    if *stride > 0 {
+                        buffer.push(
+                            Instruction::MoveRightToZero {
+                                increment: *increment,
+                                stride: *stride as usize,
+                            },
+                            span,
+                        );
+                    } else if *stride < 0 {
+                        buffer.push(
+                            Instruction::MoveLeftToZero {
+                                increment: *increment,
+                                stride: stride.unsigned_abs(),
+                            },
+                            span,
+                        );
+                    } else if *increment == 1 || *increment == -1 {
+                        buffer.push(Instruction::SetValue(0), span);
                     }
-                    Ordering::Less => {
-                        buffer.push(Instruction::MoveLeftToZero {
-                            increment: 0,
-                            stride: stride.unsigned_abs(),
-                        });
+                }
+                [first, inst1, inst2, second]
+                    if inst1.preserves_tape_head() && inst2.preserves_tape_head() =>
+                {
+                    if let Some(fused) = fuse_adds(first, second) {
+                        if add_commutes_with(second, inst1, tape_size)
+                            && add_commutes_with(second, inst2, tape_size)
+                        {
+                            matched = true;
+                            buffer.extend(&[fused, *inst1, *inst2], span);
+                        } else if add_commutes_with(first, inst1, tape_size)
+                            && add_commutes_with(first, inst2, tape_size)
+                        {
+                            matched = true;
+                            buffer.extend(&[*inst1, *inst2, fused], span);
+                        }
                     }
-                    _ => {}
                 }
+                _ => {}
             }
-            [Instruction::AddRelative {
-                offset: offset1,
-                amount: amount1,
-            }, inst, Instruction::AddRelative {
-                offset: offset2,
-                amount: amount2,
-            }] => {
-                if *offset1 == *offset2 && inst.is_add_friendly() {
-                    matched = true;
-                    buffer.extend_from_slice(&[
-                        Instruction::AddRelative {
-                            offset: *offset1,
-                            amount: *amount1 + *amount2,
-                        },
-                        *inst,
-                    ]);
+
+            if matched {
+                fuel.consume();
+                index += 4;
+            } else {
+                buffer.push(window[0], stream.spans[index]);
+                index += 1;
+            }
+        }
+
+        buffer.extend_from(stream, index);
+    }
+
+    stream.finish(buffer);
+}
+
+// Adds always commute with each other, and moves between them only shift their offsets, so
+// a straight-line run of both can be rearranged freely. Sorting the run by offset (and moving
+// the tape head once at the end) brings the lanes of a vector next to each other even when
+// the source interleaves them with adds elsewhere.
+fn schedule_adds(stream: &mut Stream, buffer: &mut Stream, fuel: &mut Fuel) {
+    {
+        let instructions = &stream.instructions;
+        let mut scheduled = Vec::new();
+        let mut index = 0;
+
+        while let Some(&instruction) = instructions.get(index) {
+            if fuel.is_exhausted() {
+                break;
+            }
+
+            let run_length = instructions[index..]
+                .iter()
+                .position(|instruction| {
+                    !matches!(instruction, Instruction::Move(_))
+                        && add_deltas(instruction).is_none()
+                })
+                .unwrap_or(instructions.len() - index);
+
+            if run_length < 2 {
+                buffer.push(instruction, stream.spans[index]);
+                index += 1;
+                continue;
+            }
+
+            let run = index..index + run_length;
+            index = run.end;
+
+            scheduled.clear();
+
+            if schedule_run(&instructions[run.clone()], &mut scheduled).is_some()
+                && scheduled[..] != instructions[run.clone()]
+                && fuel.consume()
+            {
+                buffer.extend(&scheduled, stream.span_of(run));
+            } else {
+                for (instruction, span) in instructions[run.clone()].iter().zip(&stream.spans[run])
+                {
+                    buffer.push(*instruction, *span);
                 }
             }
-            _ => {}
         }
 
-        if matched {
-            iterator.next();
-            iterator.next();
-        } else {
-            buffer.push(window[0]);
+        buffer.extend_from(stream, index);
+    }
+
+    stream.finish(buffer);
+}
+
+fn schedule_run(run: &[Instruction], scheduled: &mut Vec<Instruction>) -> Option<()> {
+    let mut deltas = BTreeMap::new();
+    let mut position: isize = 0;
+
+    for instruction in run {
+        if let Instruction::Move(amount) = *instruction {
+            position = position.checked_add(amount)?;
+            continue;
+        }
+
+        for (offset, amount) in add_deltas(instruction).into_iter().flatten() {
+            let delta: &mut i8 = deltas.entry(position.checked_add(offset)?).or_default();
+            *delta = delta.wrapping_add(amount);
         }
     }
 
-    if !matched {
-        buffer.push(instructions[instructions.len() - 2]);
-        buffer.push(instructions[instructions.len() - 1]);
+    schedule_deltas(&deltas, scheduled);
+
+    if position != 0 {
+        scheduled.push(Instruction::Move(position));
     }
 
-    instructions.clear();
-    mem::swap(instructions, buffer);
+    Some(())
 }
 
-fn substitute_patterns_4(instructions: &mut Vec<Instruction>, buffer: &mut Vec<Instruction>) {
-    if instructions.len() < 4 {
-        return;
+fn add_deltas(instruction: &Instruction) -> Option<impl Iterator<Item = (isize, i8)>> {
+    let (offset, lanes) = match *instruction {
+        Instruction::AddRelative { offset, amount } => (offset, [amount, 0, 0, 0]),
+        _ => (0, add_lanes(instruction)?),
+    };
+
+    Some(
+        (0..4)
+            .map(move |lane| (offset + lane as isize, lanes[lane]))
+            .filter(|(_, amount)| *amount != 0),
+    )
+}
+
+// Offsets within reach of a vector are gathered into one, and everything else becomes a
+// relative add, all in order of offset.
+fn schedule_deltas(deltas: &BTreeMap<isize, i8>, scheduled: &mut Vec<Instruction>) {
+    let mut vector = [0; 4];
+
+    let relative = |(&offset, &amount): (&isize, &i8)| {
+        (amount != 0).then_some(Instruction::AddRelative { offset, amount })
+    };
+
+    scheduled.extend(deltas.range(..0).filter_map(relative));
+
+    for (&offset, &amount) in deltas.range(0..4) {
+        vector[offset as usize] = amount;
     }
 
-    let mut matched = false;
-    let mut iterator = instructions.windows(4);
+    match vector {
+        [0, 0, 0, 0] => {}
+        [amount, 0, 0, 0] => scheduled.push(Instruction::Add(amount)),
+        [0, amount, 0, 0] => scheduled.push(Instruction::AddRelative { offset: 1, amount }),
+        [0, 0, amount, 0] => scheduled.push(Instruction::AddRelative { offset: 2, amount }),
+        [0, 0, 0, amount] => scheduled.push(Instruction::AddRelative { offset: 3, amount }),
+        _ => scheduled.push(Instruction::AddVector { vector }),
+    }
 
-    while let Some(window) = iterator.next() {
-        matched = false;
+    scheduled.extend(deltas.range(4..).filter_map(relative));
+}
 
-        match window {
-            [Instruction::Add(a), Instruction::Move(move1), Instruction::Add(b), Instruction::Move(move2)] =>
-            {
-                let move1 = *move1;
-                let move2 = *move2;
-                let total_move = move1 + move2;
+// Adds always commute with each other, so any two of them can be fused once they can be
+// brought next to each other.
+fn fuse_adds(first: &Instruction, second: &Instruction) -> Option<Instruction> {
+    if let (
+        Instruction::AddRelative {
+            offset: offset1,
+            amount: amount1,
+        },
+        Instruction::AddRelative {
+            offset: offset2,
+            amount: amount2,
+        },
+    ) = (first, second)
+    {
+        if offset1 == offset2 {
+            return Some(Instruction::AddRelative {
+                offset: *offset1,
+                amount: amount1.wrapping_add(*amount2),
+            });
+        }
+    }
 
-                if move1 > 0 && move2 > 0 && total_move < 4 {
-                    matched = true;
+    let mut vector = add_lanes(first)?;
+    let other = add_lanes(second)?;
 
-                    let mut vector = [0; 4];
-                    vector[0] = *a;
-                    vector[move1 as usize] = *b;
+    for (lane, amount) in vector.iter_mut().zip(other) {
+        *lane = lane.wrapping_add(amount);
+    }
 
-                    buffer.extend_from_slice(&[
-                        Instruction::AddVector { vector },
-                        Instruction::Move(total_move),
-                    ]);
-                } else if move1 < 0 && move2 < 0 && total_move > -4 {
-                    matched = true;
+    Some(Instruction::AddVector { vector })
+}
+
+fn add_lanes(instruction: &Instruction) -> Option<[i8; 4]> {
+    match *instruction {
+        Instruction::Add(amount) => Some([amount, 0, 0, 0]),
+        Instruction::AddRelative { offset, amount } if (0..4).contains(&offset) => {
+            let mut vector = [0; 4];
+            vector[offset as usize] = amount;
+            Some(vector)
+        }
+        Instruction::AddVector { vector } => Some(vector),
+        _ => None,
+    }
+}
 
-                    let mut vector = [0; 4];
-                    vector[1] = *b;
-                    vector[(-move1 as usize) + 1] = *a;
+fn add_commutes_with(add: &Instruction, instruction: &Instruction, tape_size: TapeSize) -> bool {
+    // A finite tape wraps around, so an offset that is a multiple of its size lands back on
+    // the current cell.
+    let wrap = |offset: isize| match tape_size {
+        TapeSize::Finite(size) => offset.rem_euclid(size as isize),
+        TapeSize::Infinite => offset,
+    };
+
+    match *add {
+        Instruction::Add(_) => instruction.is_add_friendly(0),
+        Instruction::AddRelative { offset, .. } => instruction.is_add_friendly(wrap(offset)),
+        Instruction::AddVector { vector } => {
+            (0..4).all(|lane| vector[lane] == 0 || instruction.is_add_friendly(wrap(lane as isize)))
+        }
+        _ => false,
+    }
+}
 
-                    buffer.extend_from_slice(&[
-                        Instruction::Move(total_move),
-                        Instruction::AddVector { vector },
-                    ]);
-                }
+// Loops that are balanced (see `BalancedLoop`) become a run of multiplications followed by
+// a store. Only innermost loops are considered, so the outer loop of a nested multiplication
+// stays a loop: its effect is the product of two cells, which no run of `MulAdd`s can give.
+fn reduce_balanced_loops(
+    stream: &mut Stream,
+    buffer: &mut Stream,
+    fuel: &mut Fuel,
+    tape_size: TapeSize,
+) {
+    {
+        let instructions = &stream.instructions;
+        let mut index = 0;
+
+        while let Some(&instruction) = instructions.get(index) {
+            if fuel.is_exhausted() {
+                break;
             }
-            [Instruction::Move(move1), Instruction::Add(a), Instruction::Move(move2), Instruction::Add(b)] =>
-            {
-                let move1 = *move1;
-                let move2 = *move2;
-                let total_move = move1 + move2;
 
-                if move1 > 0 && move2 > 0 && total_move < 4 {
-                    matched = true;
+            if let Instruction::JumpIfZero { .. } = instruction {
+                let balanced = innermost_loop_end(instructions, index).and_then(|loop_end| {
+                    Some((
+                        loop_end,
+                        BalancedLoop::analyze(&instructions[index + 1..loop_end], tape_size)?,
+                    ))
+                });
 
-                    let mut vector = [0; 4];
-                    vector[move1 as usize] = *a;
-                    vector[total_move as usize] = *b;
+                if let Some((loop_end, balanced)) = balanced {
+                    fuel.consume();
 
-                    buffer.extend_from_slice(&[
-                        Instruction::AddVector { vector },
-                        Instruction::Move(total_move),
-                    ]);
-                } else if move1 < 0 && move2 < 0 && total_move > -4 {
-                    matched = true;
+                    let span = stream.span_of(index..loop_end + 1);
 
-                    let mut vector = [0; 4];
-                    vector[0] = *b;
-                    vector[-move2 as usize] = *a;
+                    for multiplication in balanced.multiplications() {
+                        buffer.push(multiplication, span);
+                    }
 
-                    buffer.extend_from_slice(&[
-                        Instruction::Move(total_move),
-                        Instruction::AddVector { vector },
-                    ]);
+                    buffer.push(Instruction::SetValue(0), span);
+                    index = loop_end + 1;
+                    continue;
                 }
             }
-            [Instruction::JumpIfZero { .. }, Instruction::Add(increment), Instruction::Move(stride), Instruction::JumpIfNotZero { .. }] =>
-            {
-                matched = true;
-
-                if *stride > 0 {
-                    buffer.push(Instruction::MoveRightToZero {
-                        increment: *increment,
-                        stride: *stride as usize,
-                    });
-                } else if *stride < 0 {
-                    buffer.push(Instruction::MoveLeftToZero {
-                        increment: *increment,
-                        stride: stride.unsigned_abs(),
-                    });
-                } else if *increment == 1 || *increment == -1 {
-                    buffer.push(Instruction::SetValue(0));
+
+            buffer.push(instruction, stream.spans[index]);
+            index += 1;
+        }
+
+        buffer.extend_from(stream, index);
+    }
+
+    stream.finish(buffer);
+}
+
+fn innermost_loop_end(instructions: &[Instruction], loop_start: usize) -> Option<usize> {
+    for (index, instruction) in instructions.iter().enumerate().skip(loop_start + 1) {
+        match instruction {
+            Instruction::JumpIfZero { .. } => return None,
+            Instruction::JumpIfNotZero { .. } => return Some(index),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+// A loop body made of adds and moves that leaves the tape head where it started, and that
+// changes the current cell by an odd amount, runs exactly as many times as it takes for that
+// amount to wrap the current cell around to zero. Every other add in it is then just the
+// current cell times a constant.
+pub(crate) struct BalancedLoop {
+    step: i8,
+    deltas: BTreeMap<isize, i8>,
+}
+
+impl BalancedLoop {
+    // On a finite tape an add at a multiple of its size lands on the current cell, which
+    // would change how many times the loop runs, so those loops are left alone.
+    pub(crate) fn analyze(body: &[Instruction], tape_size: TapeSize) -> Option<Self> {
+        let mut deltas = BTreeMap::new();
+        let mut position: isize = 0;
+
+        let mut add = |offset: isize, amount: i8| {
+            let delta: &mut i8 = deltas.entry(offset).or_default();
+            *delta = delta.wrapping_add(amount);
+        };
+
+        for instruction in body {
+            match *instruction {
+                Instruction::Add(amount) => add(position, amount),
+                Instruction::Move(amount) => position = position.checked_add(amount)?,
+                Instruction::AddRelative { offset, amount } => {
+                    add(position.checked_add(offset)?, amount)
                 }
-            }
-            [Instruction::AddRelative {
-                offset: offset1,
-                amount: amount1,
-            }, inst1, inst2, Instruction::AddRelative {
-                offset: offset2,
-                amount: amount2,
-            }] => {
-                if *offset1 == *offset2 && inst1.is_add_friendly() && inst2.is_add_friendly() {
-                    matched = true;
-                    buffer.extend_from_slice(&[
-                        Instruction::AddRelative {
-                            offset: *offset1,
-                            amount: *amount1 + *amount2,
-                        },
-                        *inst1,
-                        *inst2,
-                    ]);
+                Instruction::AddVector { vector } => {
+                    for (lane, amount) in vector.into_iter().enumerate() {
+                        add(position.checked_add(lane as isize)?, amount);
+                    }
                 }
-            }
-            _ => {
-                matched = false;
+                _ => return None,
             }
         }
 
-        if matched {
-            iterator.next();
-            iterator.next();
-            iterator.next();
-        } else {
-            buffer.push(window[0]);
+        if position != 0 {
+            return None;
+        }
+
+        let step = deltas.remove(&0).unwrap_or_default();
+
+        if let TapeSize::Finite(size) = tape_size {
+            if deltas
+                .iter()
+                .any(|(offset, amount)| *amount != 0 && offset.rem_euclid(size as isize) == 0)
+            {
+                return None;
+            }
         }
-    }
 
-    if !matched {
-        buffer.push(instructions[instructions.len() - 3]);
-        buffer.push(instructions[instructions.len() - 2]);
-        buffer.push(instructions[instructions.len() - 1]);
+        (step % 2 != 0).then_some(Self { step, deltas })
     }
 
-    instructions.clear();
-    mem::swap(instructions, buffer);
+    // A loop starting at `cell` runs `-cell / step` times (mod 256), and odd steps always
+    // have an inverse.
+    pub(crate) fn multiplications(&self) -> impl Iterator<Item = Instruction> + '_ {
+        let mut inverse = self.step;
+
+        for _ in 0..3 {
+            inverse = inverse.wrapping_mul(2i8.wrapping_sub(self.step.wrapping_mul(inverse)));
+        }
+
+        let scale = inverse.wrapping_neg();
+
+        self.deltas
+            .iter()
+            .filter(|(_, amount)| **amount != 0)
+            .map(move |(&offset, &amount)| Instruction::MulAdd {
+                offset,
+                factor: amount.wrapping_mul(scale),
+            })
+    }
 }
 
-fn remove_spurious_loops(instructions: &mut Vec<Instruction>, buffer: &mut Vec<Instruction>) {
+fn remove_spurious_loops(stream: &mut Stream, buffer: &mut Stream, fuel: &mut Fuel) {
     {
+        let instructions = &stream.instructions;
         let mut cell_is_zero = true;
-        let mut iterator = instructions.drain(..);
+        let mut index = 0;
+
+        'loop_squash: while let Some(&instruction) = instructions.get(index) {
+            index += 1;
 
-        'loop_squash: while let Some(instruction) = iterator.next() {
             match instruction {
                 Instruction::JumpIfZero { .. } => {
-                    if cell_is_zero {
+                    if cell_is_zero && fuel.consume() {
                         let mut loop_depth = 0;
 
-                        for next_instruction in &mut iterator {
+                        while let Some(next_instruction) = instructions.get(index) {
+                            index += 1;
+
                             match next_instruction {
                                 Instruction::JumpIfZero { .. } => {
                                     loop_depth += 1;
@@ -603,17 +1466,22 @@ fn remove_spurious_loops(instructions: &mut Vec<Instruction>, buffer: &mut Vec<I
                         }
                     }
                 }
-                Instruction::Write(_) => {}
+                Instruction::Write(_) | Instruction::MulAdd { .. } => {}
                 Instruction::JumpIfNotZero { .. }
                 | Instruction::MoveRightToZero { .. }
                 | Instruction::MoveLeftToZero { .. } => {
                     cell_is_zero = true;
                 }
+                // Procedure bodies run with whatever the caller left on the tape, and calls
+                // can leave anything behind.
                 Instruction::Add(_)
                 | Instruction::Move(_)
                 | Instruction::Read(_)
                 | Instruction::AddRelative { .. }
-                | Instruction::AddVector { .. } => {
+                | Instruction::AddVector { .. }
+                | Instruction::DefineProc { .. }
+                | Instruction::EndProc
+                | Instruction::CallProc => {
                     cell_is_zero = false;
                 }
                 Instruction::SetValue(value) => {
@@ -621,33 +1489,225 @@ fn remove_spurious_loops(instructions: &mut Vec<Instruction>, buffer: &mut Vec<I
                 }
             }
 
-            buffer.push(instruction)
+            buffer.push(instruction, stream.spans[index - 1]);
         }
     }
 
-    mem::swap(instructions, buffer);
+    stream.finish(buffer);
 }
 
-fn fix_loops(instructions: &mut [Instruction]) {
-    let mut jump_stack = Vec::new();
+// Every cell starts out as zero, so until the tape head's position becomes unknown (at the
+// first loop that may run) we can track the range of cells that have been touched. Loops and
+// scans on untouched cells never run, and the first add to an untouched cell is a store.
+fn fold_known_zero_prologue(
+    stream: &mut Stream,
+    buffer: &mut Stream,
+    fuel: &mut Fuel,
+    tape_size: TapeSize,
+) {
+    {
+        let instructions = &stream.instructions;
+        let mut region = TouchedRegion::new(tape_size);
+        let mut index = 0;
 
-    for (index, instruction) in instructions.iter_mut().enumerate() {
-        match instruction {
-            Instruction::JumpIfZero { location } => {
-                *location = index;
-                jump_stack.push(instruction);
+        while let Some(&instruction) = instructions.get(index) {
+            if fuel.is_exhausted() {
+                break;
             }
-            Instruction::JumpIfNotZero {
-                location: loop_start,
-            } => {
-                if let Some(Instruction::JumpIfZero { location: loop_end }) = jump_stack.pop() {
-                    *loop_start = *loop_end;
-                    *loop_end = index;
-                } else {
-                    unreachable!()
+
+            let span = stream.spans[index];
+
+            match instruction {
+                Instruction::Add(amount) if region.is_zero(0) => {
+                    fuel.consume();
+                    region.touch(0);
+                    buffer.push(Instruction::SetValue(amount), span);
+                }
+                Instruction::SetValue(0) if region.is_zero(0) => {
+                    fuel.consume();
+                }
+                Instruction::JumpIfZero { .. } if region.is_zero(0) => {
+                    fuel.consume();
+                    index = matching_jump(instructions, index);
+                }
+                Instruction::MoveRightToZero { .. }
+                | Instruction::MoveLeftToZero { .. }
+                | Instruction::MulAdd { .. }
+                    if region.is_zero(0) =>
+                {
+                    fuel.consume();
+                }
+
+                Instruction::Add(_) | Instruction::Read(_) | Instruction::SetValue(_) => {
+                    region.touch(0);
+                    buffer.push(instruction, span);
+                }
+                Instruction::Write(_) => {
+                    buffer.push(instruction, span);
+                }
+                Instruction::Move(amount) => {
+                    if !region.shift(amount) {
+                        break;
+                    }
+
+                    buffer.push(instruction, span);
+                }
+                Instruction::AddRelative { offset, .. } => {
+                    if !region.touch(offset) {
+                        break;
+                    }
+
+                    buffer.push(instruction, span);
+                }
+                Instruction::MulAdd { offset, .. } => {
+                    if !region.touch(offset) {
+                        break;
+                    }
+
+                    buffer.push(instruction, span);
+                }
+                Instruction::AddVector { vector } => {
+                    if !(0..4).all(|lane| vector[lane] == 0 || region.touch(lane as isize)) {
+                        break;
+                    }
+
+                    buffer.push(instruction, span);
+                }
+
+                Instruction::JumpIfZero { .. }
+                | Instruction::JumpIfNotZero { .. }
+                | Instruction::MoveRightToZero { .. }
+                | Instruction::MoveLeftToZero { .. }
+                | Instruction::DefineProc { .. }
+                | Instruction::EndProc
+                | Instruction::CallProc => {
+                    break;
                 }
             }
+
+            index += 1;
+        }
+
+        buffer.extend_from(stream, index);
+    }
+
+    stream.finish(buffer);
+}
+
+struct TouchedRegion {
+    head: isize,
+    touched: Option<(isize, isize)>,
+    tape_size: TapeSize,
+}
+
+impl TouchedRegion {
+    fn new(tape_size: TapeSize) -> Self {
+        Self {
+            head: 0,
+            touched: None,
+            tape_size,
+        }
+    }
+
+    // Wrapping around a finite tape is deliberately not modelled; leaving the tape's
+    // bounds simply ends the analysis.
+    fn cell(&self, offset: isize) -> Option<isize> {
+        let cell = self.head.checked_add(offset)?;
+
+        match self.tape_size {
+            TapeSize::Finite(tape_size) if cell >= 0 && (cell as usize) < tape_size => Some(cell),
+            TapeSize::Infinite if cell >= 0 => Some(cell),
+            _ => None,
+        }
+    }
+
+    fn is_zero(&self, offset: isize) -> bool {
+        match (self.cell(offset), self.touched) {
+            (Some(cell), Some((low, high))) => cell < low || cell > high,
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+
+    fn touch(&mut self, offset: isize) -> bool {
+        match self.cell(offset) {
+            Some(cell) => {
+                self.touched = Some(match self.touched {
+                    Some((low, high)) => (low.min(cell), high.max(cell)),
+                    None => (cell, cell),
+                });
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn shift(&mut self, amount: isize) -> bool {
+        match self.cell(amount) {
+            Some(cell) => {
+                self.head = cell;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+// Jump locations aren't fixed until every pass has run, so loops have to be matched by depth.
+fn matching_jump(instructions: &[Instruction], loop_start: usize) -> usize {
+    let mut loop_depth = 0;
+
+    for (index, instruction) in instructions.iter().enumerate().skip(loop_start + 1) {
+        match instruction {
+            Instruction::JumpIfZero { .. } => loop_depth += 1,
+            Instruction::JumpIfNotZero { .. } if loop_depth == 0 => return index,
+            Instruction::JumpIfNotZero { .. } => loop_depth -= 1,
             _ => {}
         }
     }
+
+    instructions.len()
+}
+
+fn fix_loops(instructions: &mut [Instruction]) -> Result<(), OptimizeError> {
+    let mut jump_stack = Vec::new();
+    let mut procedure_stack = Vec::new();
+
+    for index in 0..instructions.len() {
+        match instructions[index] {
+            Instruction::JumpIfZero { .. } => {
+                jump_stack.push(index);
+            }
+            Instruction::JumpIfNotZero { .. } => {
+                let loop_start = jump_stack
+                    .pop()
+                    .ok_or(OptimizeError::UnmatchedJumpIfNotZero { index })?;
+
+                instructions[loop_start] = Instruction::JumpIfZero { location: index };
+                instructions[index] = Instruction::JumpIfNotZero {
+                    location: loop_start,
+                };
+            }
+            Instruction::DefineProc { .. } => {
+                procedure_stack.push(index);
+            }
+            Instruction::EndProc => {
+                let start = procedure_stack
+                    .pop()
+                    .ok_or(OptimizeError::UnmatchedEndProc { index })?;
+
+                instructions[start] = Instruction::DefineProc { location: index };
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(index) = procedure_stack.pop() {
+        return Err(OptimizeError::UnmatchedDefineProc { index });
+    }
+
+    match jump_stack.pop() {
+        Some(index) => Err(OptimizeError::UnmatchedJumpIfZero { index }),
+        None => Ok(()),
+    }
 }
@@ -4,72 +4,681 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::mem;
+#[cfg(feature = "std")]
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+#[cfg(feature = "std")]
+use std::io::{self, Read};
 
+use thiserror::Error;
+
+use crate::dialect::{DialectMap, Token};
 use crate::instruction::Instruction;
+use crate::program::{Program, Trivia};
+use crate::span::{Location, Span};
+
+#[derive(Debug, Error)]
+pub enum ParseError {
+    // A `]` with no loop left to close. Its partner is the `[` of the last loop closed
+    // before it, which is usually the loop it was meant for.
+    #[error(
+        "unmatched ']' at {location} (offset {}){}",
+        .location.offset,
+        last_loop(.partner)
+    )]
+    UnmatchedClose {
+        location: Location,
+        partner: Option<Location>,
+    },
+    // A `[` still open when the source ends. Its partner is the end of the source, where
+    // the `]` was expected.
+    #[error(
+        "unclosed '[' at {location} (offset {}); the source ends at {partner}",
+        .location.offset
+    )]
+    UnclosedOpen {
+        location: Location,
+        partner: Location,
+    },
+    // A pbrain `)` with no procedure left to end.
+    #[error("unmatched ')' at {location} (offset {})", .location.offset)]
+    UnmatchedEndProc { location: Location },
+    // A pbrain `(` still open when the source ends.
+    #[error(
+        "unclosed '(' at {location} (offset {}); the source ends at {partner}",
+        .location.offset
+    )]
+    UnclosedProc {
+        location: Location,
+        partner: Location,
+    },
+    // A `[` or `(` nested deeper than `ParseOptions::max_depth` allows. The rest of the
+    // source isn't parsed.
+    #[error(
+        "nested too deeply at {location} (offset {}); the limit is {limit} levels",
+        .location.offset
+    )]
+    TooDeep { location: Location, limit: usize },
+    // Source a frontend couldn't make sense of, such as a line of a listing that isn't an
+    // instruction.
+    #[error("{message} at {location}")]
+    Malformed { location: Location, message: String },
+    #[cfg(feature = "std")]
+    #[error("failed to read the source: {0}")]
+    Io(#[from] io::Error),
+}
+
+// Points an unmatched `]` at the loop it was probably meant to close, if there was one.
+fn last_loop(partner: &Option<Location>) -> String {
+    match partner {
+        Some(partner) => format!("; the last loop before it opened at {}", partner),
+        None => String::new(),
+    }
+}
+
+impl ParseError {
+    // Where the error is in the source, for every error but failing to read it.
+    pub fn location(&self) -> Option<Location> {
+        match self {
+            Self::UnmatchedClose { location, .. }
+            | Self::UnclosedOpen { location, .. }
+            | Self::UnmatchedEndProc { location }
+            | Self::UnclosedProc { location, .. }
+            | Self::TooDeep { location, .. }
+            | Self::Malformed { location, .. } => Some(*location),
+            #[cfg(feature = "std")]
+            Self::Io(_) => None,
+        }
+    }
+
+    // Moves every location in the error, such as to point errors in expanded source back at
+    // the source it was expanded from.
+    pub fn map_locations(self, f: impl Fn(Location) -> Location) -> Self {
+        match self {
+            Self::UnmatchedClose { location, partner } => Self::UnmatchedClose {
+                location: f(location),
+                partner: partner.map(&f),
+            },
+            Self::UnclosedOpen { location, partner } => Self::UnclosedOpen {
+                location: f(location),
+                partner: f(partner),
+            },
+            Self::UnmatchedEndProc { location } => Self::UnmatchedEndProc {
+                location: f(location),
+            },
+            Self::UnclosedProc { location, partner } => Self::UnclosedProc {
+                location: f(location),
+                partner: f(partner),
+            },
+            Self::TooDeep { location, limit } => Self::TooDeep {
+                location: f(location),
+                limit,
+            },
+            Self::Malformed { location, message } => Self::Malformed {
+                location: f(location),
+                message,
+            },
+            #[cfg(feature = "std")]
+            Self::Io(err) => Self::Io(err),
+        }
+    }
+}
+
+// How source is read, beyond the commands themselves.
+#[derive(Clone, Eq, PartialEq, Default, Debug)]
+pub struct ParseOptions {
+    // Whether the first `!` outside of any loop ends the program, leaving the rest of the
+    // source as its input, the way many archives store programs with their input.
+    pub inline_input: bool,
+    // The tokens that stand for each command, when they aren't the usual characters.
+    pub dialect: Option<DialectMap>,
+    // Whether pbrain's `(`, `)`, and `:` define and call procedures.
+    pub procedures: bool,
+    // How deeply loops and procedures can nest, if there's a limit, so that untrusted
+    // source can't grow the stack of open brackets without bound.
+    pub max_depth: Option<usize>,
+    // Whether to keep the comments and whitespace between instructions as the program's
+    // trivia, such as for formatting it.
+    pub trivia: bool,
+}
+
+// Every parse returns all the errors found in the source, in the order they appear, rather
+// than stopping at the first. Brackets left open are reported once the source ends, so
+// they come after the rest, outermost first.
+#[cfg(feature = "std")]
+pub fn parse_file(filename: &str) -> Result<Program, Vec<ParseError>> {
+    parse_reader(File::open(filename).map_err(|err| vec![err.into()])?)
+}
+
+pub fn parse_string(string: &str) -> Result<Program, Vec<ParseError>> {
+    parse_string_with(string, &ParseOptions::default())
+}
+
+pub fn parse_string_with(string: &str, options: &ParseOptions) -> Result<Program, Vec<ParseError>> {
+    parse_bytes_with(string.as_bytes(), options)
+}
+
+// Sources at least this long are split into chunks that are parsed in parallel, when they're
+// parsed whole and the `parallel` feature is on.
+pub const PARALLEL_THRESHOLD: usize = 1 << 22;
+
+// Parses source that's already in memory, which can be done in parallel when it's long.
+pub fn parse_bytes_with(bytes: &[u8], options: &ParseOptions) -> Result<Program, Vec<ParseError>> {
+    #[cfg(feature = "parallel")]
+    if bytes.len() >= PARALLEL_THRESHOLD {
+        if let Some(program) = parallel::parse(bytes, options) {
+            return Ok(program);
+        }
+    }
+
+    let mut parser = Parser::new(options);
+    parser.parse(bytes, false);
+    parser.finish()
+}
 
-pub fn parse_file(filename: &str) -> Result<Vec<Instruction>, String> {
-    let file = File::open(filename).map_err(|err| err.to_string())?;
+// Parses source as it's read, a buffer at a time, so programs never have to fit in memory
+// as text. The source doesn't have to be valid UTF-8, since every command is one byte.
+#[cfg(feature = "std")]
+pub fn parse_reader<R: Read>(reader: R) -> Result<Program, Vec<ParseError>> {
+    parse_reader_with(reader, &ParseOptions::default())
+}
+
+#[cfg(feature = "std")]
+pub fn parse_reader_with<R: Read>(
+    mut reader: R,
+    options: &ParseOptions,
+) -> Result<Program, Vec<ParseError>> {
+    let mut parser = Parser::new(options);
+    let mut buffer = [0; 8192];
 
-    let mut instructions = Vec::new();
-    let mut jump_stack = Vec::new();
+    loop {
+        let length = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(length) => length,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => {
+                parser.errors.push(err.into());
+                return Err(parser.errors);
+            }
+        };
 
-    for line in BufReader::new(file).lines() {
-        let line = line.map_err(|err| err.to_string())?;
-        parse(&mut instructions, &mut jump_stack, &line)?;
+        parser.parse(&buffer[..length], false);
     }
 
-    Ok(instructions)
+    parser.finish()
 }
 
-pub fn parse_string(string: &str) -> Result<Vec<Instruction>, String> {
-    let mut instructions = Vec::new();
-    let mut jump_stack = Vec::new();
-    parse(&mut instructions, &mut jump_stack, string)?;
-    Ok(instructions)
+// Parses source fed to it in pieces, keeping track of where each piece starts.
+struct Parser<'a> {
+    options: &'a ParseOptions,
+    instructions: Vec<Instruction>,
+    spans: Vec<Span>,
+    // The instruction index, location, and bracket of every `[` and `(` that's still open.
+    // They share the stack, so that loops and procedures can't end inside each other.
+    open: Vec<(usize, Location, u8)>,
+    last_closed: Option<Location>,
+    location: Location,
+    errors: Vec<ParseError>,
+    // The input after the program, once its `!` has been found.
+    input: Option<Vec<u8>>,
+    // The start of a token that may carry on into the next piece.
+    pending: Vec<u8>,
+    // Whether the source nested too deeply, which stops the parse.
+    too_deep: bool,
+    trivia: Option<Trivia>,
 }
 
-fn parse(
-    instructions: &mut Vec<Instruction>,
-    jump_stack: &mut Vec<usize>,
-    string: &str,
-) -> Result<(), String> {
-    for c in string.chars() {
-        match c {
-            '+' => instructions.push(Instruction::Add(1)),
-            '-' => instructions.push(Instruction::Add(-1)),
-            '>' => instructions.push(Instruction::Move(1)),
-            '<' => instructions.push(Instruction::Move(-1)),
-            '.' => instructions.push(Instruction::Write(1)),
-            ',' => instructions.push(Instruction::Read(1)),
-            '[' => {
-                jump_stack.push(instructions.len());
-                instructions.push(Instruction::JumpIfZero { location: 0 });
+impl<'a> Parser<'a> {
+    fn new(options: &'a ParseOptions) -> Self {
+        Self {
+            options,
+            instructions: Vec::new(),
+            spans: Vec::new(),
+            open: Vec::new(),
+            last_closed: None,
+            location: Location::START,
+            errors: Vec::new(),
+            input: None,
+            pending: Vec::new(),
+            too_deep: false,
+            trivia: options.trivia.then(Trivia::default),
+        }
+    }
+
+    // Parses the next piece of source, which is the last when `end` is set.
+    fn parse(&mut self, bytes: &[u8], end: bool) {
+        if let Some(input) = &mut self.input {
+            input.extend_from_slice(bytes);
+            return;
+        }
+
+        if self.too_deep {
+            return;
+        }
+
+        let mut pending = mem::take(&mut self.pending);
+        let bytes = if pending.is_empty() {
+            bytes
+        } else {
+            pending.extend_from_slice(bytes);
+            &pending
+        };
+
+        let options = self.options;
+        let mut index = 0;
+
+        while index < bytes.len() {
+            let rest = &bytes[index..];
+            let (command, length) = match &options.dialect {
+                None => (Some(rest[0]), 1),
+                Some(dialect) => match dialect.token(rest, end) {
+                    Token::Command(command, length) => (Some(command), length),
+                    Token::Comment => (None, 1),
+                    Token::Partial => {
+                        self.pending = rest.to_vec();
+                        return;
+                    }
+                },
+            };
+
+            let location = self.location;
+
+            for &byte in &rest[..length] {
+                self.location.advance(byte);
+            }
+
+            index += length;
+
+            // A `!` that isn't part of a dialect's token can still end the program.
+            let command = match command {
+                Some(command) => command,
+                None if rest[0] == b'!' => b'!',
+                None => {
+                    self.keep_trivia(&rest[..length]);
+                    continue;
+                }
+            };
+
+            if command == b'!' && options.inline_input && self.open.is_empty() {
+                self.input = Some(bytes[index..].to_vec());
+                return;
             }
-            ']' => {
-                if let Some(loop_start) = jump_stack.pop() {
-                    let instruction_count = instructions.len();
-
-                    if let Some(Instruction::JumpIfZero { location: loop_end }) =
-                        instructions.get_mut(loop_start)
-                    {
-                        *loop_end = instruction_count;
-                        instructions.push(Instruction::JumpIfNotZero {
-                            location: loop_start,
-                        });
-                    } else {
-                        // TODO: Throw a proper error here; ice.
-                        return Err("ERROR".to_owned());
+
+            if self.extend(command, location, length) {
+                continue;
+            }
+
+            if let (b'[' | b'(', Some(limit)) = (command, options.max_depth) {
+                if self.open.len() >= limit && (command == b'[' || options.procedures) {
+                    self.errors.push(ParseError::TooDeep { location, limit });
+                    self.too_deep = true;
+                    return;
+                }
+            }
+
+            let instruction = match command {
+                b'+' => Instruction::Add(1),
+                b'-' => Instruction::Add(-1),
+                b'>' => Instruction::Move(1),
+                b'<' => Instruction::Move(-1),
+                b'.' => Instruction::Write(1),
+                b',' => Instruction::Read(1),
+                b'[' => {
+                    self.open.push((self.instructions.len(), location, b'['));
+                    Instruction::JumpIfZero { location: 0 }
+                }
+                b']' => {
+                    // Unmatched brackets are left out, so the rest of the source still
+                    // pairs up the way it would without them.
+                    let (start, start_location) = match self.open.last() {
+                        Some(&(start, start_location, b'[')) => {
+                            self.open.pop();
+                            (start, start_location)
+                        }
+                        _ => {
+                            self.errors.push(ParseError::UnmatchedClose {
+                                location,
+                                partner: self.last_closed,
+                            });
+                            continue;
+                        }
+                    };
+
+                    self.instructions[start] = Instruction::JumpIfZero {
+                        location: self.instructions.len(),
+                    };
+                    self.last_closed = Some(start_location);
+
+                    Instruction::JumpIfNotZero { location: start }
+                }
+                b'(' if options.procedures => {
+                    self.open.push((self.instructions.len(), location, b'('));
+                    Instruction::DefineProc { location: 0 }
+                }
+                b')' if options.procedures => match self.open.last() {
+                    Some(&(start, _, b'(')) => {
+                        self.open.pop();
+                        self.instructions[start] = Instruction::DefineProc {
+                            location: self.instructions.len(),
+                        };
+
+                        Instruction::EndProc
                     }
-                } else {
-                    // TODO: Throw a proper error here.
-                    return Err("ERROR".to_owned());
+                    _ => {
+                        self.errors.push(ParseError::UnmatchedEndProc { location });
+                        continue;
+                    }
+                },
+                b':' if options.procedures => Instruction::CallProc,
+                _ => {
+                    self.keep_trivia(&rest[..length]);
+                    continue;
+                }
+            };
+
+            if let Some(trivia) = &mut self.trivia {
+                trivia.push_instruction();
+            }
+
+            self.instructions.push(instruction);
+            self.spans.push(Span {
+                end: location.offset + length,
+                ..Span::of(location)
+            });
+        }
+    }
+
+    fn keep_trivia(&mut self, bytes: &[u8]) {
+        if let Some(trivia) = &mut self.trivia {
+            trivia.push_bytes(bytes);
+        }
+    }
+
+    // Folds the command into the instruction before it when it repeats the same command
+    // right after it, such as `+++` into one Add(3), returning whether it did.
+    fn extend(&mut self, byte: u8, location: Location, length: usize) -> bool {
+        let (instruction, span) = match (self.instructions.last_mut(), self.spans.last_mut()) {
+            (Some(instruction), Some(span)) if span.end == location.offset => (instruction, span),
+            _ => return false,
+        };
+
+        match fold(*instruction, byte) {
+            Some(extended) => {
+                *instruction = extended;
+                span.end += length;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn finish(mut self) -> Result<Program, Vec<ParseError>> {
+        if !self.pending.is_empty() {
+            self.parse(&[], true);
+        }
+
+        // Everything left open is expected when the parse stopped partway.
+        if self.too_deep {
+            self.open.clear();
+        }
+
+        for &(_, location, bracket) in &self.open {
+            self.errors.push(if bracket == b'(' {
+                ParseError::UnclosedProc {
+                    location,
+                    partner: self.location,
+                }
+            } else {
+                ParseError::UnclosedOpen {
+                    location,
+                    partner: self.location,
+                }
+            });
+        }
+
+        if self.errors.is_empty() {
+            Ok(Program {
+                input: self.input,
+                trivia: self.trivia,
+                ..Program::new(self.instructions, self.spans)
+            })
+        } else {
+            Err(self.errors)
+        }
+    }
+}
+
+// The instruction with the command folded into it, if the command continues the run the
+// instruction stands for. Runs are split once the instruction can't count any higher.
+fn fold(instruction: Instruction, command: u8) -> Option<Instruction> {
+    match (instruction, command) {
+        (Instruction::Add(amount @ 1..), b'+') => amount.checked_add(1).map(Instruction::Add),
+        (Instruction::Add(amount @ ..=-1), b'-') => amount.checked_sub(1).map(Instruction::Add),
+        (Instruction::Move(amount @ 1..), b'>') => amount.checked_add(1).map(Instruction::Move),
+        (Instruction::Move(amount @ ..=-1), b'<') => amount.checked_sub(1).map(Instruction::Move),
+        (Instruction::Write(count), b'.') => count.checked_add(1).map(Instruction::Write),
+        (Instruction::Read(count), b',') => count.checked_add(1).map(Instruction::Read),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "parallel")]
+mod parallel {
+    use std::mem;
+    use std::ops::Range;
+
+    use rayon::prelude::*;
+
+    use super::{fold, ParseOptions};
+    use crate::instruction::Instruction;
+    use crate::program::Program;
+    use crate::span::{Location, Span};
+
+    const MINIMUM_CHUNK_SIZE: usize = 1 << 20;
+
+    struct Chunk {
+        range: Range<usize>,
+        start: Location,
+        // The index of the chunk's first instruction in the program.
+        first: usize,
+        length: usize,
+    }
+
+    // Parses chunks of the source in parallel: once to count their instructions and lines,
+    // so each knows where it starts, and again to write them in place. Brackets are paired
+    // up across chunks afterwards. Options only the sequential parser handles return None,
+    // as does source with bracket errors, so the sequential parser can report them the
+    // usual way.
+    pub(super) fn parse(bytes: &[u8], options: &ParseOptions) -> Option<Program> {
+        let plain = ParseOptions {
+            max_depth: options.max_depth,
+            ..ParseOptions::default()
+        };
+
+        if *options != plain {
+            return None;
+        }
+
+        let target_size =
+            (bytes.len() / (rayon::current_num_threads() * 4)).max(MINIMUM_CHUNK_SIZE);
+
+        let counts = split_chunks(bytes, target_size)
+            .into_par_iter()
+            .map(|range| {
+                let mut length = 0;
+                let start = Location {
+                    offset: range.start,
+                    ..Location::START
+                };
+
+                let end = parse_chunk(&bytes[range.clone()], start, |_, _| length += 1);
+                (range, length, end)
+            })
+            .collect::<Vec<_>>();
+
+        let mut chunks = Vec::with_capacity(counts.len());
+        let mut start = Location::START;
+        let mut first = 0;
+
+        for (range, length, end) in counts {
+            chunks.push(Chunk {
+                range,
+                start,
+                first,
+                length,
+            });
+
+            start = continue_from(start, end);
+            first += length;
+        }
+
+        let mut instructions = vec![Instruction::Add(0); first];
+        let mut spans = vec![Span::default(); first];
+        let mut rest = (instructions.as_mut_slice(), spans.as_mut_slice());
+        let mut parts = Vec::with_capacity(chunks.len());
+
+        for chunk in &chunks {
+            let (instructions, rest_instructions) =
+                mem::take(&mut rest.0).split_at_mut(chunk.length);
+            let (spans, rest_spans) = mem::take(&mut rest.1).split_at_mut(chunk.length);
+            rest = (rest_instructions, rest_spans);
+            parts.push((chunk, instructions, spans));
+        }
+
+        let brackets = parts
+            .into_par_iter()
+            .map(|(chunk, instructions, spans)| {
+                let mut brackets = Vec::new();
+                let mut index = 0;
+
+                parse_chunk(
+                    &bytes[chunk.range.clone()],
+                    chunk.start,
+                    |instruction, span| {
+                        if let Instruction::JumpIfZero { .. } | Instruction::JumpIfNotZero { .. } =
+                            instruction
+                        {
+                            brackets.push(chunk.first + index);
+                        }
+
+                        instructions[index] = instruction;
+                        spans[index] = span;
+                        index += 1;
+                    },
+                );
+
+                brackets
+            })
+            .collect::<Vec<_>>();
+
+        let mut open = Vec::new();
+
+        for index in brackets.into_iter().flatten() {
+            if let Instruction::JumpIfZero { .. } = instructions[index] {
+                if options.max_depth.is_some_and(|limit| open.len() >= limit) {
+                    return None;
+                }
+
+                open.push(index);
+                continue;
+            }
+
+            let partner = open.pop()?;
+            instructions[partner] = Instruction::JumpIfZero { location: index };
+            instructions[index] = Instruction::JumpIfNotZero { location: partner };
+        }
+
+        if !open.is_empty() {
+            return None;
+        }
+
+        Some(Program::new(instructions, spans))
+    }
+
+    // Splits the source into ranges of about the size, without splitting a run of one
+    // command, so every run folds the same way it would if the source were parsed whole.
+    fn split_chunks(bytes: &[u8], target_size: usize) -> Vec<Range<usize>> {
+        let mut chunks = Vec::new();
+        let mut start = 0;
+
+        while start < bytes.len() {
+            let mut end = (start + target_size).min(bytes.len());
+
+            while end < bytes.len() && bytes[end] == bytes[end - 1] {
+                end += 1;
+            }
+
+            chunks.push(start..end);
+            start = end;
+        }
+
+        chunks
+    }
+
+    // Calls `emit` with each of the chunk's instructions and its span, folding runs the way
+    // the sequential parser does, and returns where the chunk ends. Jumps are left for the
+    // caller to pair up.
+    fn parse_chunk(
+        bytes: &[u8],
+        start: Location,
+        mut emit: impl FnMut(Instruction, Span),
+    ) -> Location {
+        let mut location = start;
+        let mut last: Option<(Instruction, Span)> = None;
+
+        for &byte in bytes {
+            let here = location;
+            location.advance(byte);
+
+            let instruction = match byte {
+                b'+' => Instruction::Add(1),
+                b'-' => Instruction::Add(-1),
+                b'>' => Instruction::Move(1),
+                b'<' => Instruction::Move(-1),
+                b'.' => Instruction::Write(1),
+                b',' => Instruction::Read(1),
+                b'[' => Instruction::JumpIfZero { location: 0 },
+                b']' => Instruction::JumpIfNotZero { location: 0 },
+                _ => continue,
+            };
+
+            if let Some((instruction, span)) = &mut last {
+                if let (true, Some(folded)) = (span.end == here.offset, fold(*instruction, byte)) {
+                    *instruction = folded;
+                    span.end += 1;
+                    continue;
                 }
+
+                emit(*instruction, *span);
             }
-            _ => {}
+
+            last = Some((instruction, Span::of(here)));
         }
+
+        if let Some((instruction, span)) = last {
+            emit(instruction, span);
+        }
+
+        location
     }
 
-    Ok(())
+    // Where a location counted from the start of a chunk really is, given where the chunk
+    // starts.
+    fn continue_from(start: Location, location: Location) -> Location {
+        if location.line == 1 {
+            Location {
+                offset: location.offset,
+                column: start.column + location.column - 1,
+                ..start
+            }
+        } else {
+            Location {
+                line: start.line + location.line - 1,
+                ..location
+            }
+        }
+    }
 }
@@ -0,0 +1,441 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::span::{Location, Span};
+
+// Expands macros in source before it's parsed, so larger programs can be split across
+// files and reuse snippets:
+//
+//     @define clear { [-] }
+//     @include "lib.bf"
+//     +++ @clear
+//
+// A definition's body is expanded each time its name is used, so it can use names defined
+// after it. Included files are expanded in place, relative to the file including them, and
+// their definitions stay defined after them. Definitions can be replaced by later ones.
+#[derive(Debug, Error)]
+#[error("{file}: {kind} at {location}")]
+pub struct PreprocessError {
+    // The file the error is in, as it was named to the preprocessor.
+    pub file: String,
+    pub location: Location,
+    pub kind: PreprocessErrorKind,
+}
+
+#[derive(Debug, Error)]
+pub enum PreprocessErrorKind {
+    #[error("expected a name after '@'")]
+    ExpectedName,
+    #[error("expected '{{' after the name")]
+    ExpectedBody,
+    #[error("expected a quoted path")]
+    ExpectedPath,
+    #[error("unclosed '{{'")]
+    UnclosedBody,
+    #[error("'{name}' isn't defined")]
+    Undefined { name: String },
+    #[error("'{name}' expands to itself")]
+    RecursiveMacro { name: String },
+    #[error("'{path}' includes itself")]
+    RecursiveInclude { path: String },
+    #[error("failed to include '{path}': {err}")]
+    Io { path: String, err: io::Error },
+}
+
+// The expanded source, along with where each part of it came from.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Expansion {
+    pub source: Vec<u8>,
+    // In order of where they start in the expanded source.
+    segments: Vec<Segment>,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+struct Segment {
+    start: usize,
+    origin: Origin,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Origin {
+    // Copied from the top-level source, starting at the location.
+    Verbatim(Location),
+    // Expanded from the directive at the span of the top-level source.
+    Expanded(Span),
+}
+
+// Where a lookup last landed, so lookups that move forward through a segment don't start
+// over from its beginning.
+struct Cursor {
+    segment: usize,
+    location: Location,
+    original: Location,
+}
+
+impl Expansion {
+    // The location in the top-level source that the location in the expanded source came
+    // from. Anything expanded from a directive comes from the directive's start.
+    pub fn locate(&self, location: Location) -> Location {
+        self.locate_from(&mut None, location.offset)
+    }
+
+    // Points the spans of a program parsed from the expanded source back at the top-level
+    // source. Code expanded from a directive is given the directive's span.
+    pub fn remap(&self, spans: &mut [Span]) {
+        let mut cursor = None;
+
+        for span in spans {
+            if span.location().is_none() {
+                continue;
+            }
+
+            let start = self.span_from(&mut cursor, span.start);
+            let end = self.span_from(&mut cursor, span.end - 1);
+            *span = start.merge(end);
+        }
+    }
+
+    fn span_from(&self, cursor: &mut Option<Cursor>, offset: usize) -> Span {
+        let segment = self.segment(offset);
+
+        match self.segments.get(segment).map(|segment| segment.origin) {
+            Some(Origin::Expanded(span)) => span,
+            _ => Span::of(self.locate_from(cursor, offset)),
+        }
+    }
+
+    fn locate_from(&self, cursor: &mut Option<Cursor>, offset: usize) -> Location {
+        let index = self.segment(offset);
+        let segment = match self.segments.get(index) {
+            Some(segment) => segment,
+            None => return Location::START,
+        };
+
+        let start = match segment.origin {
+            Origin::Expanded(span) => return span.location().unwrap_or(Location::START),
+            Origin::Verbatim(start) => start,
+        };
+
+        let mut current = match cursor.take() {
+            Some(cursor) if cursor.segment == index && cursor.location.offset <= offset => cursor,
+            _ => Cursor {
+                segment: index,
+                location: Location {
+                    offset: segment.start,
+                    ..Location::START
+                },
+                original: start,
+            },
+        };
+
+        while current.location.offset < offset {
+            let byte = self.source[current.location.offset];
+            current.location.advance(byte);
+            current.original.advance(byte);
+        }
+
+        let original = current.original;
+        *cursor = Some(current);
+        original
+    }
+
+    // The index of the segment the offset is in.
+    fn segment(&self, offset: usize) -> usize {
+        self.segments
+            .partition_point(|segment| segment.start <= offset)
+            .saturating_sub(1)
+    }
+}
+
+// Expands the top-level source, whose includes are relative to the directory it's in, or
+// the working directory when there's no path.
+pub fn preprocess(source: &[u8], path: Option<&Path>) -> Result<Expansion, PreprocessError> {
+    let file = File {
+        name: path.map_or("<stdin>".to_owned(), |path| path.display().to_string()),
+        directory: path
+            .and_then(Path::parent)
+            .map(Path::to_path_buf)
+            .unwrap_or_default(),
+    };
+
+    let mut preprocessor = Preprocessor {
+        definitions: HashMap::new(),
+        expanding: Vec::new(),
+        including: path
+            .and_then(|path| fs::canonicalize(path).ok())
+            .into_iter()
+            .collect(),
+        output: Vec::new(),
+        segments: Vec::new(),
+    };
+
+    preprocessor.expand(source, &file, Location::START, None)?;
+
+    Ok(Expansion {
+        source: preprocessor.output,
+        segments: preprocessor.segments,
+    })
+}
+
+pub fn preprocess_file(path: &Path) -> Result<Expansion, PreprocessError> {
+    let source = fs::read(path).map_err(|err| PreprocessError {
+        file: path.display().to_string(),
+        location: Location::START,
+        kind: PreprocessErrorKind::Io {
+            path: path.display().to_string(),
+            err,
+        },
+    })?;
+
+    preprocess(&source, Some(path))
+}
+
+// The file some source was read from.
+#[derive(Clone, Debug)]
+struct File {
+    name: String,
+    directory: PathBuf,
+}
+
+#[derive(Clone, Debug)]
+struct Definition {
+    body: Vec<u8>,
+    file: File,
+    location: Location,
+}
+
+struct Preprocessor {
+    definitions: HashMap<String, Definition>,
+    // The names and files being expanded, innermost last, to catch ones that expand to
+    // themselves. Files are kept by their canonical path, so any path to them matches.
+    expanding: Vec<String>,
+    including: Vec<PathBuf>,
+    output: Vec<u8>,
+    segments: Vec<Segment>,
+}
+
+impl Preprocessor {
+    // Expands source from the file, which starts at the location. Source expanded from a
+    // directive of the top-level source has that directive as its origin.
+    fn expand(
+        &mut self,
+        source: &[u8],
+        file: &File,
+        start: Location,
+        origin: Option<Span>,
+    ) -> Result<(), PreprocessError> {
+        let mut scanner = Scanner {
+            source,
+            index: 0,
+            location: start,
+        };
+
+        loop {
+            let text_location = scanner.location;
+            let text = scanner.take_while(|byte| byte != b'@');
+            self.emit(
+                text,
+                origin.map_or(Origin::Verbatim(text_location), Origin::Expanded),
+            );
+
+            let location = scanner.location;
+            let error = |kind| PreprocessError {
+                file: file.name.clone(),
+                location,
+                kind,
+            };
+
+            if scanner.next().is_none() {
+                return Ok(());
+            }
+
+            let name = scanner
+                .name()
+                .ok_or_else(|| error(PreprocessErrorKind::ExpectedName))?;
+
+            if name == "define" {
+                scanner.skip_whitespace();
+                let name = scanner
+                    .name()
+                    .ok_or_else(|| error(PreprocessErrorKind::ExpectedName))?;
+                scanner.skip_whitespace();
+
+                if scanner.next() != Some(b'{') {
+                    return Err(error(PreprocessErrorKind::ExpectedBody));
+                }
+
+                let body_location = scanner.location;
+                let body = scanner
+                    .body()
+                    .ok_or_else(|| error(PreprocessErrorKind::UnclosedBody))?;
+
+                let definition = Definition {
+                    body: body.to_vec(),
+                    file: file.clone(),
+                    location: body_location,
+                };
+
+                self.definitions.insert(name, definition);
+                continue;
+            }
+
+            let directive = origin.unwrap_or(Span {
+                end: scanner.location.offset,
+                ..Span::of(location)
+            });
+
+            if name == "include" {
+                scanner.skip_whitespace();
+                let path = scanner
+                    .path()
+                    .ok_or_else(|| error(PreprocessErrorKind::ExpectedPath))?;
+                let path = file.directory.join(path);
+                let canonical = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+
+                if self.including.contains(&canonical) {
+                    return Err(error(PreprocessErrorKind::RecursiveInclude {
+                        path: path.display().to_string(),
+                    }));
+                }
+
+                let source = fs::read(&path).map_err(|err| {
+                    error(PreprocessErrorKind::Io {
+                        path: path.display().to_string(),
+                        err,
+                    })
+                })?;
+
+                let included = File {
+                    name: path.display().to_string(),
+                    directory: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+                };
+
+                self.including.push(canonical);
+                self.expand(&source, &included, Location::START, Some(directive))?;
+                self.including.pop();
+                continue;
+            }
+
+            let definition = match self.definitions.get(&name) {
+                Some(definition) => definition.clone(),
+                None => return Err(error(PreprocessErrorKind::Undefined { name })),
+            };
+
+            if self.expanding.contains(&name) {
+                return Err(error(PreprocessErrorKind::RecursiveMacro { name }));
+            }
+
+            self.expanding.push(name);
+            self.expand(
+                &definition.body,
+                &definition.file,
+                definition.location,
+                Some(directive),
+            )?;
+            self.expanding.pop();
+        }
+    }
+
+    fn emit(&mut self, text: &[u8], origin: Origin) {
+        if text.is_empty() {
+            return;
+        }
+
+        let continues = matches!(origin, Origin::Expanded(_))
+            && self.segments.last().map(|segment| segment.origin) == Some(origin);
+
+        if !continues {
+            self.segments.push(Segment {
+                start: self.output.len(),
+                origin,
+            });
+        }
+
+        self.output.extend_from_slice(text);
+    }
+}
+
+// Reads through source, keeping track of the location.
+struct Scanner<'a> {
+    source: &'a [u8],
+    index: usize,
+    location: Location,
+}
+
+impl<'a> Scanner<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.source.get(self.index).copied()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let byte = self.peek()?;
+        self.index += 1;
+        self.location.advance(byte);
+        Some(byte)
+    }
+
+    fn take_while(&mut self, predicate: impl Fn(u8) -> bool) -> &'a [u8] {
+        let start = self.index;
+
+        while self.peek().is_some_and(&predicate) {
+            self.next();
+        }
+
+        &self.source[start..self.index]
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.take_while(|byte| byte.is_ascii_whitespace());
+    }
+
+    // Names are made of letters, digits, and underscores, so they never contain commands.
+    fn name(&mut self) -> Option<String> {
+        let name = self.take_while(|byte| byte.is_ascii_alphanumeric() || byte == b'_');
+
+        if name.is_empty() {
+            return None;
+        }
+
+        Some(String::from_utf8_lossy(name).into_owned())
+    }
+
+    // The body of a definition, up to the `}` matching the `{` just read.
+    fn body(&mut self) -> Option<&'a [u8]> {
+        let start = self.index;
+        let mut depth = 0;
+
+        loop {
+            match self.next()? {
+                b'{' => depth += 1,
+                b'}' if depth == 0 => return Some(&self.source[start..self.index - 1]),
+                b'}' => depth -= 1,
+                _ => {}
+            }
+        }
+    }
+
+    // A path in double quotes, on one line.
+    fn path(&mut self) -> Option<String> {
+        if self.next()? != b'"' {
+            return None;
+        }
+
+        let path = self.take_while(|byte| byte != b'"' && byte != b'\n');
+
+        if self.next()? != b'"' {
+            return None;
+        }
+
+        Some(String::from_utf8_lossy(path).into_owned())
+    }
+}
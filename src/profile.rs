@@ -0,0 +1,119 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::fs;
+use std::io::{self, Result as IOResult, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::json::{JsonError, Value};
+
+// How many times each instruction of a program ran, by index. Profiles are kept as JSON,
+// with the total alongside the counts for anyone reading them by hand:
+//
+//     {"executed":1234,"hits":[1,64,...]}
+//
+// Only the counts are read back, so a profile only matches the program it was captured
+// from when it's parsed and optimized the same way.
+#[derive(Clone, Eq, PartialEq, Default, Debug)]
+pub struct Profile {
+    pub hits: Vec<u64>,
+}
+
+#[derive(Debug, Error)]
+pub enum ProfileError {
+    #[error("invalid JSON: {0}")]
+    Json(JsonError),
+    // The JSON isn't an object with an array of counts.
+    #[error("expected an object with an array of hit counts")]
+    Malformed,
+    #[error("failed to read the profile: {0}")]
+    Io(#[from] io::Error),
+}
+
+impl Profile {
+    pub fn new(instructions: usize) -> Self {
+        Self {
+            hits: vec![0; instructions],
+        }
+    }
+
+    pub fn load_file(path: impl AsRef<Path>) -> Result<Self, ProfileError> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    // The number of instructions executed, all told.
+    pub fn total(&self) -> u64 {
+        self.hits.iter().sum()
+    }
+
+    // The share of every instruction executed that the one at the index accounts for, as a
+    // percentage.
+    pub fn percentage(&self, index: usize) -> f64 {
+        let hits = self.hits.get(index).copied().unwrap_or_default();
+        hits as f64 * 100.0 / self.total().max(1) as f64
+    }
+
+    // Whether the instruction is one of the hottest, which between them account for at
+    // least half of every instruction executed.
+    pub fn hot(&self) -> Vec<bool> {
+        let mut order = (0..self.hits.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&index| std::cmp::Reverse(self.hits[index]));
+
+        let half = self.total().div_ceil(2);
+        let mut hot = vec![false; self.hits.len()];
+        let mut covered = 0;
+
+        for index in order {
+            if covered >= half || self.hits[index] == 0 {
+                break;
+            }
+
+            hot[index] = true;
+            covered += self.hits[index];
+        }
+
+        hot
+    }
+
+    pub fn write_json<W: Write>(&self, writer: &mut W) -> IOResult<()> {
+        let profile = Value::object([
+            ("executed", Value::Number(self.total() as f64)),
+            (
+                "hits",
+                Value::Array(
+                    self.hits
+                        .iter()
+                        .map(|&hits| Value::Number(hits as f64))
+                        .collect(),
+                ),
+            ),
+        ]);
+
+        writeln!(writer, "{}", profile)
+    }
+}
+
+impl FromStr for Profile {
+    type Err = ProfileError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let value = text.parse::<Value>().map_err(ProfileError::Json)?;
+
+        let hits = value
+            .get("hits")
+            .and_then(Value::as_array)
+            .ok_or(ProfileError::Malformed)?
+            .iter()
+            .map(Value::as_u64)
+            .collect::<Option<Vec<_>>>()
+            .ok_or(ProfileError::Malformed)?;
+
+        Ok(Self { hits })
+    }
+}
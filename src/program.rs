@@ -0,0 +1,126 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::instruction::Instruction;
+use crate::span::Span;
+
+// Instructions together with the source span of each one. Programs that weren't parsed from
+// source (such as decoded bytecode) have empty spans. Sources can carry the program's input
+// after it, which is kept in `input`.
+#[derive(Clone, Eq, PartialEq, Default, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Program {
+    pub instructions: Vec<Instruction>,
+    pub spans: Vec<Span>,
+    pub input: Option<Vec<u8>>,
+    // Where the program was read from, when it has a name, such as the path of its file.
+    pub source_name: Option<String>,
+    // The dialect the program was written in, or the path of the map it was read with.
+    pub dialect: Option<String>,
+    // The comments and whitespace between instructions, when the parser was asked to keep
+    // them. They only line up with the instructions as they were parsed.
+    pub trivia: Option<Trivia>,
+}
+
+// Everything in the source that isn't a command, in between the instructions.
+#[derive(Clone, Eq, PartialEq, Default, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Trivia {
+    bytes: Vec<u8>,
+    // Where the trivia before each instruction ends in `bytes`.
+    ends: Vec<usize>,
+}
+
+impl Trivia {
+    pub fn push_bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    // Ends the trivia before the next instruction.
+    pub fn push_instruction(&mut self) {
+        self.ends.push(self.bytes.len());
+    }
+
+    // The trivia between the instruction and the one before it, or the start of the source.
+    pub fn leading(&self, index: usize) -> &[u8] {
+        let start = match index {
+            0 => 0,
+            index => self.ends[index - 1],
+        };
+
+        &self.bytes[start..self.ends[index]]
+    }
+
+    // The trivia after the last instruction.
+    pub fn trailing(&self) -> &[u8] {
+        &self.bytes[self.ends.last().copied().unwrap_or(0)..]
+    }
+}
+
+// What a program is made of, counted from its instructions.
+#[derive(Copy, Clone, Eq, PartialEq, Default, Debug)]
+pub struct ProgramStats {
+    pub instructions: usize,
+    pub loops: usize,
+    pub procedures: usize,
+    // How deeply loops and procedures nest, where a program without any is zero deep.
+    pub max_depth: usize,
+}
+
+impl Program {
+    pub fn new(instructions: Vec<Instruction>, spans: Vec<Span>) -> Self {
+        debug_assert_eq!(instructions.len(), spans.len());
+        Self {
+            instructions,
+            spans,
+            ..Self::default()
+        }
+    }
+
+    pub fn without_spans(instructions: Vec<Instruction>) -> Self {
+        let spans = vec![Span::default(); instructions.len()];
+        Self::new(instructions, spans)
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.instructions.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.instructions.is_empty()
+    }
+
+    pub fn stats(&self) -> ProgramStats {
+        let mut stats = ProgramStats {
+            instructions: self.len(),
+            ..ProgramStats::default()
+        };
+        let mut depth = 0;
+
+        for instruction in &self.instructions {
+            match instruction {
+                Instruction::JumpIfZero { .. } => stats.loops += 1,
+                Instruction::DefineProc { .. } => stats.procedures += 1,
+                Instruction::JumpIfNotZero { .. } | Instruction::EndProc => {
+                    depth = usize::saturating_sub(depth, 1);
+                    continue;
+                }
+                _ => continue,
+            }
+
+            depth += 1;
+            stats.max_depth = stats.max_depth.max(depth);
+        }
+
+        stats
+    }
+}
@@ -0,0 +1,154 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+// A Python extension module, such as for grading exercises, built with maturin from
+// pyproject.toml:
+//
+//     import membrane
+//
+//     program = membrane.optimize(membrane.parse(source))
+//     result = membrane.run(program, input="abc", tape=30000, max_steps=1_000_000)
+//     assert result.error is None and result.output == "cba"
+//
+// Programs can be run from their source as well, and reads past the end of the input
+// store zero.
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+
+use crate::interpreter::{EofMode, Interpreter, TapeSize};
+use crate::optimizer::{self, OptimizeOptions};
+use crate::parser;
+use crate::program::Program;
+
+#[pyclass(name = "Program", module = "membrane", frozen)]
+#[derive(Clone)]
+pub struct PyProgram {
+    program: Program,
+}
+
+#[pymethods]
+impl PyProgram {
+    fn __len__(&self) -> usize {
+        self.program.len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("<membrane.Program of {} instructions>", self.program.len())
+    }
+}
+
+// What a run wrote, and why it stopped if it didn't reach the end.
+#[pyclass(name = "RunResult", module = "membrane", frozen, get_all)]
+pub struct PyRunResult {
+    // The output as text, with anything that isn't UTF-8 replaced.
+    output: String,
+    output_bytes: Vec<u8>,
+    // How many instructions ran, if the program reached its end.
+    steps: Option<usize>,
+    error: Option<String>,
+}
+
+#[derive(FromPyObject)]
+enum Code<'py> {
+    Program(PyRef<'py, PyProgram>),
+    Source(String),
+}
+
+#[derive(FromPyObject)]
+enum Input {
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+// Raises ValueError with every parse error, one to a line.
+#[pyfunction]
+fn parse(source: &str) -> PyResult<PyProgram> {
+    match parser::parse_string(source) {
+        Ok(program) => Ok(PyProgram { program }),
+        Err(errors) => Err(PyValueError::new_err(
+            errors
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("\n"),
+        )),
+    }
+}
+
+// Returns an optimized copy of the program. Only raises if the optimizer broke it, since
+// stopping early still leaves one that runs.
+#[pyfunction]
+fn optimize(program: &PyProgram) -> PyResult<PyProgram> {
+    let mut program = program.clone();
+
+    match optimizer::optimize_program(&mut program.program, &OptimizeOptions::default()) {
+        Err(err) if err.is_fatal() => Err(PyValueError::new_err(err.to_string())),
+        _ => Ok(program),
+    }
+}
+
+// Runs a program, or source that's parsed first, on a tape of `tape` cells, or an infinite
+// one without it. Other threads can run while it does.
+#[pyfunction]
+#[pyo3(signature = (code, input = None, tape = None, max_steps = None))]
+fn run(
+    py: Python<'_>,
+    code: Code<'_>,
+    input: Option<Input>,
+    tape: Option<usize>,
+    max_steps: Option<usize>,
+) -> PyResult<PyRunResult> {
+    let program = match code {
+        Code::Program(program) => program.program.clone(),
+        Code::Source(source) => parse(&source)?.program,
+    };
+
+    let input = match input {
+        Some(Input::Text(text)) => text.into_bytes(),
+        Some(Input::Bytes(bytes)) => bytes,
+        None => Vec::new(),
+    };
+
+    let mut builder = Interpreter::builder()
+        .tape(tape.map_or(TapeSize::Infinite, TapeSize::Finite))
+        .eof(EofMode::Zero);
+
+    if let Some(max_steps) = max_steps {
+        builder = builder.max_steps(max_steps);
+    }
+
+    let mut output = Vec::new();
+    let result = py.allow_threads(|| {
+        let mut input = input.into_iter();
+        builder.build().run_with(
+            &program.instructions,
+            || input.next(),
+            |byte| output.push(byte),
+        )
+    });
+
+    let (steps, error) = match result {
+        Ok(state) => (Some(state.instructions_executed), None),
+        Err(err) => (None, Some(err.to_string())),
+    };
+
+    Ok(PyRunResult {
+        output: String::from_utf8_lossy(&output).into_owned(),
+        output_bytes: output,
+        steps,
+        error,
+    })
+}
+
+#[pymodule]
+pub fn membrane(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyProgram>()?;
+    module.add_class::<PyRunResult>()?;
+    module.add_function(wrap_pyfunction!(parse, module)?)?;
+    module.add_function(wrap_pyfunction!(optimize, module)?)?;
+    module.add_function(wrap_pyfunction!(run, module)?)?;
+    Ok(())
+}
@@ -0,0 +1,138 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use core::cmp::{self, Ordering};
+use core::fmt;
+use core::fmt::Formatter;
+use core::ops::Range;
+
+// A position in the source. Lines and columns are counted from one, and columns count
+// characters rather than bytes.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Location {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Location {
+    pub const START: Self = Self {
+        offset: 0,
+        line: 1,
+        column: 1,
+    };
+
+    // Moves past a byte of UTF-8. Continuation bytes stay in the column of the byte that
+    // started their character, so each character counts once.
+    pub fn advance(&mut self, byte: u8) {
+        self.offset += 1;
+
+        if byte == b'\n' {
+            self.line += 1;
+            self.column = 1;
+        } else if byte & 0xc0 != 0x80 {
+            self.column += 1;
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+/// A half-open range of byte offsets into the original source.
+/// The line and column are those of the start, and are zero when they aren't known, such
+/// as for spans loaded from bytecode.
+#[derive(Copy, Clone, Eq, PartialEq, Default, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    #[inline]
+    pub const fn new(start: usize, end: usize) -> Self {
+        Self {
+            start,
+            end,
+            line: 0,
+            column: 0,
+        }
+    }
+
+    #[inline]
+    pub const fn at(offset: usize) -> Self {
+        Self::new(offset, offset + 1)
+    }
+
+    // The span of the single byte at the location.
+    #[inline]
+    pub const fn of(location: Location) -> Self {
+        Self {
+            start: location.offset,
+            end: location.offset + 1,
+            line: location.line,
+            column: location.column,
+        }
+    }
+
+    // Where the span starts, if its line is known.
+    #[inline]
+    pub const fn location(&self) -> Option<Location> {
+        if self.line == 0 {
+            return None;
+        }
+
+        Some(Location {
+            offset: self.start,
+            line: self.line,
+            column: self.column,
+        })
+    }
+
+    #[inline]
+    pub const fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    #[inline]
+    pub const fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    // The smallest span covering both, which starts on the line and column of whichever
+    // starts first.
+    #[inline]
+    pub fn merge(self, other: Self) -> Self {
+        let first = match (self.start.cmp(&other.start), self.line) {
+            (Ordering::Less, _) | (Ordering::Equal, 1..) => self,
+            _ => other,
+        };
+
+        Self {
+            start: first.start,
+            end: cmp::max(self.end, other.end),
+            line: first.line,
+            column: first.column,
+        }
+    }
+
+    #[inline]
+    pub const fn range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
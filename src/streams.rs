@@ -0,0 +1,133 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+// Where the interpreter reads and writes when it has std, which `interpreter` exports
+// alongside the rest of it.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Cursor, ErrorKind, Read, Sink, Stdin, Stdout, Write};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+
+pub enum InputSource {
+    Stdin(Stdin),
+    StdinBuffer(BufReader<Stdin>),
+    File(Cursor<Vec<u8>>),
+    FileBuffer(BufReader<File>),
+    Pipe(PipeReader),
+}
+
+impl Read for InputSource {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Stdin(stdin) => stdin.read(buf),
+            Self::StdinBuffer(reader) => reader.read(buf),
+            Self::File(cursor) => cursor.read(buf),
+            Self::FileBuffer(reader) => reader.read(buf),
+            Self::Pipe(reader) => reader.read(buf),
+        }
+    }
+}
+
+pub enum OutputSource {
+    Stdout(Stdout),
+    StdoutBuffer(BufWriter<Stdout>),
+    File(File),
+    FileBuffer(BufWriter<File>),
+    // Throws the output away, such as when only the time a program takes matters.
+    Sink(Sink),
+    Pipe(PipeWriter),
+}
+
+impl Write for OutputSource {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Stdout(stdout) => stdout.write(buf),
+            Self::StdoutBuffer(writer) => writer.write(buf),
+            Self::File(file) => file.write(buf),
+            Self::FileBuffer(writer) => writer.write(buf),
+            Self::Sink(sink) => sink.write(buf),
+            Self::Pipe(writer) => writer.write(buf),
+        }
+    }
+
+    #[inline]
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::Stdout(stdout) => stdout.flush(),
+            Self::StdoutBuffer(writer) => writer.flush(),
+            Self::File(file) => file.flush(),
+            Self::FileBuffer(writer) => writer.flush(),
+            Self::Sink(sink) => sink.flush(),
+            Self::Pipe(writer) => writer.flush(),
+        }
+    }
+}
+
+// How many writes a pipe holds before the program writing to it waits for the other to
+// catch up.
+const PIPE_CAPACITY: usize = 64;
+
+// Connects the output of one program to the input of another, for running them at the
+// same time on different threads. Once the writer is dropped, the reader gets the end of
+// its input, and once the reader is dropped, writes fail with BrokenPipe.
+pub fn pipe() -> (PipeWriter, PipeReader) {
+    let (sender, receiver) = mpsc::sync_channel(PIPE_CAPACITY);
+
+    (
+        PipeWriter { sender },
+        PipeReader {
+            receiver,
+            chunk: Cursor::new(Vec::new()),
+        },
+    )
+}
+
+pub struct PipeWriter {
+    sender: SyncSender<Vec<u8>>,
+}
+
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        self.sender
+            .send(buf.to_vec())
+            .map_err(|_| io::Error::from(ErrorKind::BrokenPipe))?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub struct PipeReader {
+    receiver: Receiver<Vec<u8>>,
+    // What's left of the last write received.
+    chunk: Cursor<Vec<u8>>,
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.chunk.read(buf)?;
+
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+
+            match self.receiver.recv() {
+                Ok(chunk) => self.chunk = Cursor::new(chunk),
+                Err(_) => return Ok(0),
+            }
+        }
+    }
+}
@@ -0,0 +1,137 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::slice;
+
+use crate::parser;
+use crate::program::Program;
+
+// Generates random programs whose brackets always match, for property tests and fuzzers,
+// such as for checking that optimizing never changes what a program does:
+//
+//     let options = GenerateOptions {
+//         size: 200,
+//         ..GenerateOptions::default()
+//     };
+//     let program = testgen::program(seed, &options);
+//
+// The same seed and options always make the same program. Fuzzers can use
+// `program_from_bytes`, which takes each choice from the next of their bytes, so that
+// mutating the bytes mutates the program. Random programs often loop forever, so they're
+// best run with `InterpreterBuilder::max_steps`.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct GenerateOptions {
+    // How many commands the source has, brackets included. Programs made from bytes stop
+    // short of it once the bytes run out.
+    pub size: usize,
+    // How deeply loops can nest. Zero leaves them out.
+    pub max_depth: usize,
+    // The percentage of commands that read or write.
+    pub io_density: u8,
+}
+
+impl Default for GenerateOptions {
+    fn default() -> Self {
+        Self {
+            size: 64,
+            max_depth: 4,
+            io_density: 10,
+        }
+    }
+}
+
+pub fn program(seed: u64, options: &GenerateOptions) -> Program {
+    parse(&source(seed, options))
+}
+
+pub fn program_from_bytes(bytes: &[u8], options: &GenerateOptions) -> Program {
+    parse(&source_from_bytes(bytes, options))
+}
+
+pub fn source(seed: u64, options: &GenerateOptions) -> String {
+    generate(&mut SplitMix(seed), options)
+}
+
+pub fn source_from_bytes(bytes: &[u8], options: &GenerateOptions) -> String {
+    generate(&mut bytes.iter(), options)
+}
+
+fn parse(source: &str) -> Program {
+    parser::parse_string(source).expect("generated programs always parse")
+}
+
+// Where the generator's choices come from, one byte at a time, until there are no more.
+trait Choices {
+    fn next_byte(&mut self) -> Option<u8>;
+}
+
+// SplitMix64, which is all it takes to spread any seed over every program.
+struct SplitMix(u64);
+
+impl Choices for SplitMix {
+    fn next_byte(&mut self) -> Option<u8> {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut value = self.0;
+        value = (value ^ (value >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        value = (value ^ (value >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        Some((value ^ (value >> 31)) as u8)
+    }
+}
+
+impl Choices for slice::Iter<'_, u8> {
+    fn next_byte(&mut self) -> Option<u8> {
+        self.next().copied()
+    }
+}
+
+// Each command takes two choices: whether it reads or writes, and then which command it
+// is. A loop is only opened when there's room left to close it and every loop around it,
+// and the head only moves left when it's right of where it started, counting each loop
+// once, so that fewer programs move off the start of the tape.
+fn generate(choices: &mut impl Choices, options: &GenerateOptions) -> String {
+    let mut source = String::with_capacity(options.size);
+    let mut depth = 0;
+    let mut offset = 0;
+
+    while source.len() + depth < options.size {
+        let (io, pick) = match (choices.next_byte(), choices.next_byte()) {
+            (Some(io), Some(pick)) => (io, pick),
+            _ => break,
+        };
+
+        let room = options.size - source.len() - depth;
+        let command = if usize::from(io) * 100 < usize::from(options.io_density) * 256 {
+            if pick % 2 == 0 {
+                '.'
+            } else {
+                ','
+            }
+        } else {
+            match pick % 8 {
+                0 if depth < options.max_depth && room >= 2 => '[',
+                1 if depth > 0 => ']',
+                0 | 2 => '+',
+                1 | 3 => '-',
+                6 | 7 if offset > 0 => '<',
+                _ => '>',
+            }
+        };
+
+        match command {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            '>' => offset += 1,
+            '<' => offset -= 1,
+            _ => {}
+        }
+
+        source.push(command);
+    }
+
+    source.extend((0..depth).map(|_| ']'));
+    source
+}
@@ -0,0 +1,132 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+// Bindings for JavaScript, such as for a playground in the browser, built with
+// wasm-pack or wasm-bindgen for wasm32-unknown-unknown. Programs stay on the Rust side, and
+// JavaScript holds a handle to each of them:
+//
+//     const program = parse(source);
+//     optimize(program);
+//     const result = run(program, "input", 1_000_000);
+//     console.log(result.output, result.error);
+//
+// Runs are limited to a number of steps, since a program that never ends would hang the
+// page.
+use wasm_bindgen::prelude::*;
+
+use crate::interpreter::{EofMode, Interpreter};
+use crate::optimizer::{self, OptimizeOptions};
+use crate::parser;
+use crate::program::Program;
+
+#[wasm_bindgen(js_name = Program)]
+pub struct WasmProgram {
+    program: Program,
+}
+
+#[wasm_bindgen(js_class = Program)]
+impl WasmProgram {
+    // The number of instructions, which shrinks once the program is optimized.
+    #[wasm_bindgen(getter)]
+    pub fn length(&self) -> usize {
+        self.program.len()
+    }
+}
+
+// What a run wrote, and why it stopped if it didn't reach the end.
+#[wasm_bindgen]
+pub struct RunResult {
+    output: Vec<u8>,
+    steps: Option<usize>,
+    error: Option<String>,
+}
+
+#[wasm_bindgen]
+impl RunResult {
+    // The output as text, with anything that isn't UTF-8 replaced.
+    #[wasm_bindgen(getter)]
+    pub fn output(&self) -> String {
+        String::from_utf8_lossy(&self.output).into_owned()
+    }
+
+    #[wasm_bindgen(getter, js_name = outputBytes)]
+    pub fn output_bytes(&self) -> Vec<u8> {
+        self.output.clone()
+    }
+
+    // How many instructions ran, if the program reached its end.
+    #[wasm_bindgen(getter)]
+    pub fn steps(&self) -> Option<usize> {
+        self.steps
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn error(&self) -> Option<String> {
+        self.error.clone()
+    }
+}
+
+// Throws every parse error, one to a line.
+#[wasm_bindgen]
+pub fn parse(source: &str) -> Result<WasmProgram, JsError> {
+    match parser::parse_string(source) {
+        Ok(program) => Ok(WasmProgram { program }),
+        Err(errors) => Err(JsError::new(
+            &errors
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("\n"),
+        )),
+    }
+}
+
+// Only throws if the optimizer broke the program, since stopping early still leaves one
+// that runs.
+#[wasm_bindgen]
+pub fn optimize(program: &mut WasmProgram) -> Result<(), JsError> {
+    // There are no threads to optimize on in the browser.
+    let options = OptimizeOptions {
+        parallel: false,
+        ..OptimizeOptions::default()
+    };
+
+    match optimizer::optimize_program(&mut program.program, &options) {
+        Err(err) if err.is_fatal() => Err(JsError::new(&err.to_string())),
+        _ => Ok(()),
+    }
+}
+
+// Runs the program on the bytes of the input, stopping after `max_steps` instructions.
+// Reads past the end of the input store zero, so that loops like `,[.,]` end with it.
+#[wasm_bindgen]
+pub fn run(program: &WasmProgram, input: &str, max_steps: usize) -> RunResult {
+    let mut input = input.bytes();
+    let mut output = Vec::new();
+
+    let result = Interpreter::builder()
+        .eof(EofMode::Zero)
+        .max_steps(max_steps)
+        .build()
+        .run_with(
+            &program.program.instructions,
+            || input.next(),
+            |byte| output.push(byte),
+        );
+
+    match result {
+        Ok(state) => RunResult {
+            output,
+            steps: Some(state.instructions_executed),
+            error: None,
+        },
+        Err(err) => RunResult {
+            output,
+            steps: None,
+            error: Some(err.to_string()),
+        },
+    }
+}
@@ -0,0 +1,107 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+// How often files are checked for changes while waiting on them.
+pub const INTERVAL: Duration = Duration::from_millis(200);
+
+// Watches files for changes by polling their metadata, which needs nothing from the
+// platform and is cheap enough for the handful of files a program is made of.
+#[derive(Clone, Debug)]
+pub struct Watcher {
+    files: Vec<(PathBuf, Option<Stamp>)>,
+}
+
+// What's compared to tell whether a file changed. The length is kept as well as the time
+// it was modified, since some filesystems only keep that to the second. A file that can't
+// be read, such as while an editor replaces it, has none.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+struct Stamp {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+impl Watcher {
+    pub fn new<P: AsRef<Path>>(paths: impl IntoIterator<Item = P>) -> Self {
+        let files = paths
+            .into_iter()
+            .map(|path| {
+                let path = path.as_ref().to_owned();
+                let stamp = stamp(&path);
+                (path, stamp)
+            })
+            .collect();
+
+        Self { files }
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.files.iter().map(|(path, _)| path.as_path())
+    }
+
+    // The files that changed since they were last checked, which are then taken to be as
+    // they are now.
+    pub fn poll(&mut self) -> Vec<PathBuf> {
+        let mut changed = Vec::new();
+
+        for (path, last) in &mut self.files {
+            let current = stamp(path);
+
+            if current != *last {
+                *last = current;
+                changed.push(path.clone());
+            }
+        }
+
+        changed
+    }
+
+    // Blocks until at least one of the files changes, returning the ones that did.
+    pub fn wait(&mut self) -> Vec<PathBuf> {
+        loop {
+            thread::sleep(INTERVAL);
+
+            let changed = self.settle();
+
+            if !changed.is_empty() {
+                return changed;
+            }
+        }
+    }
+
+    // Like poll, but editors often save a file in several writes, so when any of the files
+    // changed they're left to settle for another interval before returning.
+    pub fn settle(&mut self) -> Vec<PathBuf> {
+        let mut changed = self.poll();
+
+        if changed.is_empty() {
+            return changed;
+        }
+
+        thread::sleep(INTERVAL);
+
+        for path in self.poll() {
+            if !changed.contains(&path) {
+                changed.push(path);
+            }
+        }
+
+        changed
+    }
+}
+
+fn stamp(path: &Path) -> Option<Stamp> {
+    let metadata = fs::metadata(path).ok()?;
+
+    Some(Stamp {
+        modified: metadata.modified().ok(),
+        len: metadata.len(),
+    })
+}
@@ -0,0 +1,29 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use membrane::analysis::Metrics;
+use membrane::parser;
+
+#[test]
+fn metrics_follow_the_moves_through_every_loop_once() {
+    let program = parser::parse_string(">>+[<+>-]<<<.").unwrap();
+    let metrics = Metrics::of(&program);
+
+    assert_eq!(metrics.stats.loops, 1);
+    assert_eq!(metrics.tape_cells, 4);
+    assert!(metrics.moves_left);
+    assert!(metrics.writes && !metrics.reads);
+    assert_eq!(
+        metrics.histogram,
+        [
+            ("Move", 4),
+            ("Add", 3),
+            ("JumpIfNotZero", 1),
+            ("JumpIfZero", 1),
+            ("Write", 1)
+        ]
+    );
+}
@@ -0,0 +1,269 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::HashSet;
+use std::io::Write;
+
+use membrane::compilers::ast;
+use membrane::compilers::{
+    Annotations, Backend, CodegenChecks, CompileError, CompileOptions, ProgramInfo, Registry,
+};
+use membrane::parser;
+use membrane::program::Program;
+
+// Writes how many instructions the program has, standing in for a backend from another
+// crate.
+struct CountingBackend(&'static str);
+
+impl Backend for CountingBackend {
+    fn name(&self) -> &'static str {
+        self.0
+    }
+
+    fn file_extension(&self) -> &'static str {
+        "count"
+    }
+
+    fn compile(
+        &self,
+        program: &Program,
+        _options: &CompileOptions,
+        _info: &ProgramInfo,
+        writer: &mut dyn Write,
+    ) -> Result<(), CompileError> {
+        write!(writer, "{}", program.len())?;
+        Ok(())
+    }
+}
+
+fn compile_with(registry: &Registry, name: &str, source: &str) -> Vec<u8> {
+    let program = parser::parse_string(source).unwrap();
+
+    let mut output = Vec::new();
+    registry
+        .get(name)
+        .unwrap()
+        .compile(
+            &program,
+            &CompileOptions::default(),
+            &ProgramInfo::default(),
+            &mut output,
+        )
+        .unwrap();
+
+    output
+}
+
+#[test]
+fn builtin_backends_have_distinct_names() {
+    let registry = Registry::builtin();
+    let names = registry.names();
+
+    assert_eq!(names.iter().collect::<HashSet<_>>().len(), names.len());
+    assert!(registry.get("c").is_some());
+    assert!(registry.get("cobol").is_none());
+}
+
+#[test]
+fn backends_can_be_found_by_extension() {
+    let registry = Registry::builtin();
+
+    let name = |extension| {
+        registry
+            .for_extension(extension)
+            .map(|backend| backend.name())
+    };
+
+    assert_eq!(name("c"), Some("c"));
+    assert_eq!(name("BFC"), Some("bytecode"));
+    assert_eq!(name("rs"), Some("rust"));
+    assert_eq!(name("txt"), None);
+}
+
+#[test]
+fn builtin_backends_write_the_same_output_every_time() {
+    let registry = Registry::builtin();
+
+    for name in registry.names() {
+        let output = compile_with(&registry, name, "+[>,.<-]");
+        assert_eq!(
+            compile_with(&registry, name, "+[>,.<-]"),
+            output,
+            "{}",
+            name
+        );
+
+        // Every format that writes code starts with a header naming the version.
+        if !["bytecode", "wasm", "object", "brainfuck"].contains(&name) {
+            let code = String::from_utf8(output).unwrap();
+            let version = format!("Generated by membrane {}.", env!("CARGO_PKG_VERSION"));
+            assert!(code.lines().next().unwrap().contains(&version), "{}", name);
+        }
+    }
+}
+
+#[test]
+fn registered_backends_can_be_selected_by_name() {
+    let mut registry = Registry::builtin();
+    assert!(registry
+        .register(Box::new(CountingBackend("count")))
+        .is_none());
+
+    assert_eq!(registry.names().last(), Some(&"count"));
+    assert_eq!(compile_with(&registry, "count", "+>+[-]."), b"7");
+}
+
+#[test]
+fn registering_a_taken_name_replaces_the_backend() {
+    let mut registry = Registry::builtin();
+    let length = registry.names().len();
+
+    let replaced = registry.register(Box::new(CountingBackend("brainfuck")));
+
+    assert_eq!(replaced.map(|backend| backend.file_extension()), Some("bf"));
+    assert_eq!(registry.names().len(), length);
+    assert_eq!(compile_with(&registry, "brainfuck", "+-+"), b"3");
+}
+
+#[test]
+fn source_maps_point_at_the_lines_written_for_each_instruction() {
+    let program = parser::parse_string("+[-]").unwrap();
+
+    let mut output = Vec::new();
+    let source_map = Registry::builtin()
+        .get("c")
+        .unwrap()
+        .compile_with_source_map(
+            &program,
+            &CompileOptions::default(),
+            &ProgramInfo::default(),
+            &mut output,
+        )
+        .unwrap()
+        .unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    let lines = output.lines().collect::<Vec<_>>();
+
+    let mapped = source_map
+        .entries
+        .iter()
+        .map(|entry| {
+            assert_eq!(entry.lines.start(), entry.lines.end());
+            (lines[entry.lines.start() - 1].trim(), entry.span.start)
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        mapped,
+        [
+            ("tape[head] += 1u;", 0),
+            ("while (tape[head] != 0) {", 1),
+            ("tape[head] += 255u;", 2),
+            ("}", 3),
+        ]
+    );
+}
+
+#[test]
+fn binary_backends_have_no_source_maps() {
+    let program = Program::without_spans(parser::parse_string("+.").unwrap().instructions);
+
+    let source_map = Registry::builtin()
+        .get("wasm")
+        .unwrap()
+        .compile_with_source_map(
+            &program,
+            &CompileOptions::default(),
+            &ProgramInfo::default(),
+            &mut Vec::new(),
+        )
+        .unwrap();
+
+    assert!(source_map.is_none());
+}
+
+#[test]
+fn annotations_show_the_source_of_each_instruction() {
+    let source = "two ++ then [-]";
+    let program = parser::parse_string(source).unwrap();
+
+    let info = ProgramInfo {
+        annotations: Some(Annotations::new(&program, source)),
+        ..ProgramInfo::default()
+    };
+
+    let mut output = Vec::new();
+    Registry::builtin()
+        .get("lua")
+        .unwrap()
+        .compile(&program, &CompileOptions::default(), &info, &mut output)
+        .unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    let comments = output
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.starts_with("--") && line.ends_with(")"))
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        comments,
+        [
+            "-- ++ (offset 4)",
+            "-- [ (offset 12)",
+            "-- - (offset 13)",
+            "-- ] (offset 14)",
+        ]
+    );
+}
+
+#[test]
+fn loops_are_identified_by_their_first_instruction() {
+    let program = parser::parse_string("+[>[-]<-]>[-]").unwrap();
+    let nodes = ast::build(&program.instructions).unwrap();
+
+    assert_eq!(ast::loop_ids(&nodes), [1, 3, 10]);
+}
+
+#[test]
+fn codegen_checks_choose_what_c_programs_check() {
+    let program = parser::parse_string("<+").unwrap();
+    let registry = Registry::builtin();
+    let backend = registry.get("c").unwrap();
+
+    for (name, checks, defines) in [
+        (
+            "full",
+            CodegenChecks::Full,
+            "#define RUNTIME_CHECKS 1\n#define CHECK_MESSAGES 1\n",
+        ),
+        (
+            "minimal",
+            CodegenChecks::Minimal,
+            "#define RUNTIME_CHECKS 1\n#define CHECK_MESSAGES 0\n",
+        ),
+        (
+            "none",
+            CodegenChecks::None,
+            "#define RUNTIME_CHECKS 0\n#define CHECK_MESSAGES 0\n",
+        ),
+    ] {
+        assert_eq!(name.parse::<CodegenChecks>().unwrap(), checks);
+
+        let options = CompileOptions {
+            checks,
+            ..CompileOptions::default()
+        };
+
+        let mut output = Vec::new();
+        backend
+            .compile(&program, &options, &ProgramInfo::default(), &mut output)
+            .unwrap();
+
+        assert!(String::from_utf8(output).unwrap().contains(defines));
+    }
+}
@@ -0,0 +1,380 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeMap, HashSet};
+
+use membrane::compilers::bytecode::{self, BytecodeError, Header};
+use membrane::frontend;
+use membrane::instruction::Instruction;
+use membrane::interpreter::{EofMode, TapeSize};
+use membrane::loader;
+use membrane::optimizer::{self, OptimizeOptions};
+use membrane::parser;
+use membrane::program::Program;
+
+// Version 2 encodes instructions exactly like the current version, but without anything
+// between the header and the first instruction that depends on its contents. That's the
+// magic, version, an infinite tape size, the cell width, and a count below 128.
+const V2_HEADER_LENGTH: usize = 3 + 1 + 1 + 1 + 1;
+
+fn encode_with(instructions: &[Instruction], header: &Header) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytecode::encode(instructions, header, &mut bytes).unwrap();
+    bytes
+}
+
+fn encode(instructions: &[Instruction], tape_size: TapeSize) -> Vec<u8> {
+    encode_with(instructions, &Header::new(tape_size))
+}
+
+fn encode_v2(instructions: &[Instruction]) -> Vec<u8> {
+    let header = Header {
+        version: 2,
+        ..Header::new(TapeSize::Infinite)
+    };
+
+    encode_with(instructions, &header)
+}
+
+fn every_instruction() -> Vec<Instruction> {
+    vec![
+        Instruction::JumpIfZero { location: 11 },
+        Instruction::Add(-3),
+        Instruction::Move(-70_000),
+        Instruction::Write(2),
+        Instruction::Read(1),
+        Instruction::SetValue(7),
+        Instruction::AddRelative {
+            offset: -5,
+            amount: 9,
+        },
+        Instruction::AddVector {
+            vector: [1, -1, 2, -2],
+        },
+        Instruction::MulAdd {
+            offset: 3,
+            factor: -4,
+        },
+        Instruction::MoveRightToZero {
+            increment: 1,
+            stride: 2,
+        },
+        Instruction::MoveLeftToZero {
+            increment: -1,
+            stride: 300,
+        },
+        Instruction::JumpIfNotZero { location: 0 },
+    ]
+}
+
+#[test]
+fn header_records_tape_size_and_cell_width() {
+    let bytes = encode(&[], TapeSize::Finite(30_000));
+    let payload = [0];
+
+    assert_eq!(&bytes[0..3], bytecode::MAGIC);
+    assert_eq!(bytes[3], bytecode::VERSION);
+    assert_eq!(bytes[4], 0);
+    assert_eq!(bytes[5..8], [0xb0, 0xea, 0x01]);
+    assert_eq!(bytes[8], 1);
+    assert_eq!(bytes[9], 0);
+    assert_eq!(bytes[10], payload.len() as u8);
+    assert_eq!(bytes[11..15], crc32fast::hash(&payload).to_le_bytes());
+    assert_eq!(bytes[15..], payload);
+
+    let bytes = encode(&[], TapeSize::Infinite);
+    assert_eq!(bytes[5], 0);
+}
+
+#[test]
+fn every_instruction_gets_a_distinct_opcode() {
+    let instructions = every_instruction();
+    let mut opcodes = HashSet::new();
+
+    for instruction in &instructions {
+        let bytes = encode_v2(&[*instruction]);
+
+        assert!(
+            bytes.len() > V2_HEADER_LENGTH,
+            "{} was dropped",
+            instruction
+        );
+        opcodes.insert(bytes[V2_HEADER_LENGTH]);
+    }
+
+    assert_eq!(opcodes.len(), instructions.len());
+}
+
+#[test]
+fn operands_are_varints() {
+    let cases = [
+        (Instruction::Move(1), &[0x01][..]),
+        (Instruction::Move(-1), &[0x7f][..]),
+        (Instruction::Move(64), &[0xc0, 0x00][..]),
+        (Instruction::Move(-70_000), &[0x90, 0xdd, 0x7b][..]),
+        (Instruction::Write(300), &[0xac, 0x02][..]),
+    ];
+
+    for (instruction, operand) in cases {
+        let bytes = encode_v2(&[instruction]);
+        assert_eq!(&bytes[V2_HEADER_LENGTH + 1..], operand, "{}", instruction);
+    }
+}
+
+#[test]
+fn extreme_operands_round_trip() {
+    let instructions = vec![
+        Instruction::Move(isize::MIN),
+        Instruction::Move(isize::MAX),
+        Instruction::Write(isize::MAX as usize),
+        Instruction::Read(isize::MAX as usize),
+        Instruction::AddRelative {
+            offset: isize::MIN,
+            amount: i8::MIN,
+        },
+        Instruction::MulAdd {
+            offset: isize::MAX,
+            factor: i8::MAX,
+        },
+        Instruction::MoveLeftToZero {
+            increment: -128,
+            stride: usize::MAX,
+        },
+    ];
+
+    let bytes = encode(&instructions, TapeSize::Finite(usize::MAX));
+    let (decoded, header) = decode(&bytes).unwrap();
+
+    assert_eq!(decoded, instructions);
+    assert_eq!(header.tape_size, TapeSize::Finite(usize::MAX));
+}
+
+#[test]
+fn jump_targets_are_encoded() {
+    let bytes = encode_v2(&every_instruction());
+
+    assert_eq!(bytes[V2_HEADER_LENGTH - 1], 12);
+    assert_eq!(
+        bytes[V2_HEADER_LENGTH..V2_HEADER_LENGTH + 2],
+        [bytecode::opcode::JUMP_IF_ZERO, 11]
+    );
+    assert_eq!(
+        bytes[bytes.len() - 2..],
+        [bytecode::opcode::JUMP_IF_NOT_ZERO, 0]
+    );
+}
+
+#[test]
+fn version_1_files_are_still_decoded() {
+    let instructions = every_instruction();
+    let header = Header {
+        version: 1,
+        ..Header::new(TapeSize::Finite(300))
+    };
+
+    let bytes = encode_with(&instructions, &header);
+    assert_eq!(bytes[4..12], 300u64.to_le_bytes());
+
+    let (decoded, decoded_header) = decode(&bytes).unwrap();
+
+    assert_eq!(decoded, instructions);
+    assert_eq!(decoded_header, header);
+}
+
+#[test]
+fn version_2_files_are_still_decoded() {
+    let instructions = every_instruction();
+    let (decoded, header) = decode(&encode_v2(&instructions)).unwrap();
+
+    assert_eq!(decoded, instructions);
+    assert_eq!(header.version, 2);
+}
+
+#[test]
+fn flags_and_metadata_round_trip() {
+    let header = Header {
+        optimized: true,
+        eof_mode: EofMode::NegativeOne,
+        metadata: BTreeMap::from([
+            (
+                bytecode::SOURCE_KEY.to_owned(),
+                "examples/fib.bf".to_owned(),
+            ),
+            ("comment".to_owned(), "ünïcode".to_owned()),
+        ]),
+        ..Header::new(TapeSize::Finite(30_000))
+    };
+
+    let bytes = encode_with(&every_instruction(), &header);
+    assert_eq!(bytes[4], 0b101);
+
+    let (_, decoded_header) = decode(&bytes).unwrap();
+    assert_eq!(decoded_header, header);
+}
+
+#[test]
+fn rejects_corrupted_payloads() {
+    let mut bytes = encode(&every_instruction(), TapeSize::Infinite);
+    let last = bytes.len() - 1;
+    bytes[last] ^= 0x01;
+
+    assert!(matches!(
+        decode(&bytes),
+        Err(BytecodeError::ChecksumMismatch { .. })
+    ));
+}
+
+#[test]
+fn rejects_unknown_flags() {
+    let mut bytes = encode(&[], TapeSize::Infinite);
+    bytes[4] = 0x80;
+
+    assert!(matches!(
+        decode(&bytes),
+        Err(BytecodeError::UnsupportedFlags { flags: 0x80 })
+    ));
+}
+
+fn decode(bytes: &[u8]) -> Result<(Vec<Instruction>, Header), BytecodeError> {
+    let (program, header) = bytecode::decode(&mut &bytes[..])?;
+    Ok((program.instructions, header))
+}
+
+#[test]
+fn every_instruction_round_trips() {
+    let instructions = every_instruction();
+    let bytes = encode(&instructions, TapeSize::Finite(300));
+
+    let (decoded, header) = decode(&bytes).unwrap();
+
+    assert_eq!(decoded, instructions);
+    assert_eq!(header, Header::new(TapeSize::Finite(300)));
+}
+
+#[test]
+fn optimized_examples_round_trip() {
+    for example in ["hello_world", "fib", "life", "mandelbrot"] {
+        let path = format!("{}/examples/{}.bf", env!("CARGO_MANIFEST_DIR"), example);
+        let Program {
+            mut instructions,
+            mut spans,
+            ..
+        } = parser::parse_file(&path).unwrap();
+        optimizer::optimize(&mut instructions, &mut spans, &OptimizeOptions::default()).unwrap();
+
+        let bytes = encode(&instructions, TapeSize::Infinite);
+        let (decoded, header) = decode(&bytes).unwrap();
+
+        assert_eq!(decoded, instructions, "{} didn't round-trip", example);
+        assert_eq!(header.tape_size, TapeSize::Infinite);
+    }
+}
+
+#[test]
+fn rejects_foreign_files() {
+    assert!(matches!(
+        decode(b"MBOC\x01"),
+        Err(BytecodeError::InvalidMagic)
+    ));
+}
+
+#[test]
+fn rejects_unknown_versions() {
+    let mut bytes = encode(&[], TapeSize::Infinite);
+    bytes[3] = 0xff;
+
+    assert!(matches!(
+        decode(&bytes),
+        Err(BytecodeError::UnsupportedVersion { version: 0xff })
+    ));
+}
+
+#[test]
+fn rejects_truncated_files() {
+    let bytes = encode(&every_instruction(), TapeSize::Infinite);
+
+    assert!(matches!(
+        decode(&bytes[..bytes.len() - 1]),
+        Err(BytecodeError::Io(_))
+    ));
+}
+
+#[test]
+fn rejects_invalid_opcodes() {
+    let mut bytes = encode_v2(&[Instruction::Add(1)]);
+    bytes[V2_HEADER_LENGTH] = 0xee;
+
+    assert!(matches!(
+        decode(&bytes),
+        Err(BytecodeError::InvalidOpcode {
+            index: 0,
+            opcode: 0xee
+        })
+    ));
+}
+
+#[test]
+fn rejects_impossible_io_amounts() {
+    for instruction in [
+        Instruction::Write(usize::MAX),
+        Instruction::Read(usize::MAX),
+    ] {
+        let bytes = encode(&[Instruction::Add(1), instruction], TapeSize::Infinite);
+
+        assert!(matches!(
+            decode(&bytes),
+            Err(BytecodeError::InvalidAmount {
+                index: 1,
+                amount: u64::MAX
+            })
+        ));
+    }
+}
+
+#[test]
+fn rejects_mismatched_jumps() {
+    let unclosed = [Instruction::JumpIfZero { location: 1 }, Instruction::Add(1)];
+    let crossed = [
+        Instruction::JumpIfZero { location: 3 },
+        Instruction::JumpIfZero { location: 2 },
+        Instruction::JumpIfNotZero { location: 0 },
+        Instruction::JumpIfNotZero { location: 1 },
+    ];
+
+    for instructions in [&unclosed[..], &crossed[..]] {
+        assert!(matches!(
+            decode(&encode(instructions, TapeSize::Infinite)),
+            Err(BytecodeError::InvalidJumpTarget { .. })
+        ));
+    }
+}
+
+#[test]
+fn loader_tells_bytecode_from_source() {
+    let registry = frontend::Registry::builtin();
+    let frontend = registry.get("brainfuck").unwrap();
+    let source = parser::parse_string("+[->+<]>.").unwrap();
+
+    let mut header = Header::new(TapeSize::Infinite);
+    header.metadata.insert(
+        bytecode::SOURCE_KEY.to_owned(),
+        "examples/add.bf".to_owned(),
+    );
+    let bytes = encode_with(&source.instructions, &header);
+
+    let (program, loaded) = loader::load_reader(bytes.as_slice(), frontend).unwrap();
+    assert_eq!(program.instructions, source.instructions);
+    assert_eq!(program.source_name.as_deref(), Some("examples/add.bf"));
+    assert_eq!(loaded, Some(header));
+
+    let (program, loaded) = loader::load_reader(&b"+[->+<]>."[..], frontend).unwrap();
+    assert_eq!(program.instructions, source.instructions);
+    assert_eq!(loaded, None);
+
+    // Source shorter than the magic is still source.
+    let (program, _) = loader::load_reader(&b"+"[..], frontend).unwrap();
+    assert_eq!(program.instructions, [Instruction::Add(1)]);
+}
@@ -0,0 +1,147 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::env;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::process::{self, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use membrane::compilers::{c, CompileOptions, ProgramInfo};
+use membrane::interpreter::{Interpreter, TapeSize};
+use membrane::optimizer::{self, OptimizeOptions};
+use membrane::parser;
+
+// Programs that between them use every kind of instruction once optimized, with their input.
+const PROGRAMS: &[(&str, &[u8])] = &[
+    (",[->+>++<<]>>.<.", b"\x05"),
+    ("++++++++[>++++++++<-]>+.+.>>+>+<<<[>]<.", b""),
+    (",>,[-<+>]<.>>>+<<<[<]>.", b"ab"),
+    (",[.,]", b"hello\0"),
+];
+
+fn compile(source: &str, options: &CompileOptions) -> String {
+    let mut program = parser::parse_string(source).unwrap();
+    let optimize_options = OptimizeOptions {
+        tape_size: options.tape_size,
+        ..OptimizeOptions::default()
+    };
+    optimizer::optimize_program(&mut program, &optimize_options).unwrap();
+
+    let mut code = Vec::new();
+    c::compile(
+        &program.instructions,
+        options,
+        &ProgramInfo::default(),
+        &mut code,
+    )
+    .unwrap();
+    String::from_utf8(code).unwrap()
+}
+
+fn interpret(source: &str, input: &[u8], tape_size: TapeSize) -> Vec<u8> {
+    let program = parser::parse_string(source).unwrap();
+    let mut input = input.iter().copied();
+    let mut output = Vec::new();
+
+    Interpreter::builder()
+        .tape(tape_size)
+        .build()
+        .run_with(
+            &program.instructions,
+            || input.next(),
+            |byte| output.push(byte),
+        )
+        .unwrap();
+
+    output
+}
+
+// Builds the program with the system's C compiler and returns what it prints, or None if
+// there's no C compiler to build it with.
+fn run_compiled(source: &str, input: &[u8], options: &CompileOptions) -> Option<Vec<u8>> {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+    let directory = env::temp_dir().join(format!(
+        "membrane-c-{}-{}",
+        process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::create_dir_all(&directory).unwrap();
+
+    let code = directory.join("main.c");
+    let binary = directory.join("main");
+    fs::write(&code, compile(source, options)).unwrap();
+
+    let compiler = env::var("CC").unwrap_or_else(|_| "cc".to_owned());
+    let status = match Command::new(compiler)
+        .arg("-o")
+        .arg(&binary)
+        .arg(&code)
+        .status()
+    {
+        Ok(status) => status,
+        Err(err) if err.kind() == ErrorKind::NotFound => return None,
+        Err(err) => panic!("failed to run the C compiler: {}", err),
+    };
+
+    assert!(status.success(), "the C compiler rejected {}", source);
+
+    let mut child = Command::new(&binary)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{} failed", source);
+
+    fs::remove_dir_all(&directory).unwrap();
+    Some(output.stdout)
+}
+
+#[test]
+fn programs_compile_to_calls_into_the_prelude() {
+    let code = compile(",[->+>++<<]>>.<.", &CompileOptions::default());
+    let lines = code.lines().map(str::trim).collect::<Vec<_>>();
+
+    for line in [
+        "typedef uint8_t cell;",
+        "#define RUNTIME_CHECKS 1",
+        "input(1u);",
+        "*at_right(2u) += (cell) (tape[head] * 2u);",
+        "tape[head] = 0u;",
+        "move_right(2u);",
+        "output(1u);",
+        "move_left(1u);",
+    ] {
+        assert!(lines.contains(&line), "missing {:?}", line);
+    }
+}
+
+#[test]
+fn compiled_programs_match_the_interpreter() {
+    let tape_size = TapeSize::Finite(30_000);
+    let options = CompileOptions::new(tape_size);
+
+    for (source, input) in PROGRAMS {
+        let output = match run_compiled(source, input, &options) {
+            Some(output) => output,
+            None => return,
+        };
+
+        assert_eq!(output, interpret(source, input, tape_size), "{}", source);
+    }
+
+    // Finite tapes wrap around at both ends.
+    let tape_size = TapeSize::Finite(5);
+    let source = "+<+<+<.>.>.>+[<++>-]<<<<.";
+
+    if let Some(output) = run_compiled(source, b"", &CompileOptions::new(tape_size)) {
+        assert_eq!(output, interpret(source, b"", tape_size));
+    }
+}
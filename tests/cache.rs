@@ -0,0 +1,35 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::{env, fs, process};
+
+use membrane::cache::{Cache, CacheKey};
+use membrane::optimizer::{self, OptimizeOptions};
+use membrane::parser;
+
+#[test]
+fn entries_round_trip_with_their_spans() {
+    let directory = env::temp_dir().join(format!("membrane-cache-{}", process::id()));
+    let cache = Cache::new(&directory);
+
+    let source = "++++++++[>++++++++<-]>+.\n[-]>[>+>+<<-]";
+    let options = OptimizeOptions::default();
+    let mut program = parser::parse_string(source).unwrap();
+    optimizer::optimize_program(&mut program, &options).unwrap();
+
+    let key = CacheKey::new(source.as_bytes(), &options).unwrap();
+    let other = CacheKey::new(&b"+"[..], &options).unwrap();
+    cache
+        .store(key, &program.instructions, &program.spans)
+        .unwrap();
+
+    let loaded = cache.load(key);
+    let missed = cache.load(other);
+    fs::remove_dir_all(&directory).unwrap();
+
+    assert_eq!(loaded, Some((program.instructions, program.spans)));
+    assert_eq!(missed, None);
+}
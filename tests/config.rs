@@ -0,0 +1,43 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use membrane::config::{Config, ConfigError};
+use membrane::interpreter::{CellWidth, EofMode};
+
+#[test]
+fn configs_are_read_from_flat_toml() {
+    let config =
+        "# defaults\ntape = 30_000\ncell-width = 16\neof = \"zero\" # on EOF\noptimize = true\n"
+            .parse::<Config>()
+            .unwrap();
+
+    assert_eq!(
+        config,
+        Config {
+            tape_size: Some(30000),
+            cell_width: Some(CellWidth::U16),
+            optimize: Some(true),
+            eof_mode: Some(EofMode::Zero),
+            ..Config::default()
+        }
+    );
+
+    let errors = [
+        ("[run]", "line 1: expected 'key = value'"),
+        ("\ntape = 1\ntape = 2", "line 3: 'tape' is already set"),
+        ("colour = true", "line 1: unknown key 'colour'"),
+        (
+            "buffer-read = 1",
+            "line 1: invalid value for 'buffer-read': expected true or false",
+        ),
+    ];
+
+    for (text, message) in errors {
+        let err = text.parse::<Config>().unwrap_err();
+        assert!(!matches!(err, ConfigError::Io(_)));
+        assert_eq!(err.to_string(), message);
+    }
+}
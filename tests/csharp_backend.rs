@@ -0,0 +1,90 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use membrane::compilers::{csharp, CompileOptions, ProgramInfo};
+use membrane::interpreter::CellWidth;
+use membrane::optimizer::{self, OptimizeOptions};
+use membrane::parser;
+
+fn compile(source: &str, options: &CompileOptions) -> Vec<String> {
+    let mut program = parser::parse_string(source).unwrap();
+    optimizer::optimize_program(&mut program, &OptimizeOptions::default()).unwrap();
+
+    let mut code = Vec::new();
+    csharp::compile(
+        &program.instructions,
+        options,
+        &ProgramInfo::default(),
+        &mut code,
+    )
+    .unwrap();
+
+    String::from_utf8(code)
+        .unwrap()
+        .lines()
+        .map(|line| line.trim().to_owned())
+        .collect()
+}
+
+fn assert_contains(lines: &[String], expected: &[&str]) {
+    for line in expected {
+        assert!(
+            lines.iter().any(|other| other == line),
+            "missing {:?}",
+            line
+        );
+    }
+}
+
+#[test]
+fn programs_compile_to_a_single_class() {
+    let lines = compile(",[->+>++<<]>>.<.", &CompileOptions::default());
+
+    assert_contains(
+        &lines,
+        &[
+            "using Cell = System.Byte;",
+            "public static class Program",
+            "static readonly Stream input = Console.OpenStandardInput();",
+            "public static void Main()",
+            "unchecked",
+            "Input(1);",
+            "cell = Right(2);",
+            "tape[cell] += (Cell)(tape[head] * 2);",
+            "tape[head] = 0;",
+            "head = Right(2);",
+            "Output(1);",
+            "head = Left(1);",
+            "output.Flush();",
+        ],
+    );
+}
+
+#[test]
+fn loops_become_while_statements() {
+    let lines = compile("+[>,.<-]>>+<<[>]", &CompileOptions::default());
+
+    assert_contains(
+        &lines,
+        &[
+            "tape[head] = 1;",
+            "while (tape[head] != 0)",
+            "tape[cell] += 255;",
+            "tape[cell] += 1;",
+        ],
+    );
+}
+
+#[test]
+fn wider_cells_change_the_cell_type() {
+    let options = CompileOptions {
+        cell_width: CellWidth::U16,
+        ..CompileOptions::default()
+    };
+    let lines = compile("-.", &options);
+
+    assert_contains(&lines, &["using Cell = System.UInt16;"]);
+}
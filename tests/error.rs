@@ -0,0 +1,71 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use membrane::compilers::ast::AstError;
+use membrane::compilers::{CompileError, CompileOptions, ProgramInfo, Registry};
+use membrane::interpreter::{self, CellWidth, EofMode, TapeSizeError};
+use membrane::parser::{self, ParseOptions};
+use membrane::Error;
+
+#[test]
+fn errors_from_every_stage_convert_into_the_crate_error() {
+    let err = Error::from(parser::parse_string("[[]").unwrap_err());
+    assert!(matches!(&err, Error::Parse(errors) if errors.len() == 1));
+    assert!(!err.is_io());
+
+    // Backends tell programs they can't compile apart from failing to write them.
+    let options = ParseOptions {
+        procedures: true,
+        ..ParseOptions::default()
+    };
+    let program = parser::parse_bytes_with(b"(+):", &options).unwrap();
+    let registry = Registry::builtin();
+    let err = registry
+        .get("c")
+        .unwrap()
+        .compile(
+            &program,
+            &CompileOptions::default(),
+            &ProgramInfo::default(),
+            &mut Vec::new(),
+        )
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        CompileError::Ast(AstError::UnsupportedProcedure { .. })
+    ));
+    assert!(!Error::from(err).is_io());
+
+    let options = CompileOptions {
+        cell_width: CellWidth::U16,
+        ..CompileOptions::default()
+    };
+    let program = parser::parse_string("+.").unwrap();
+    let err = registry
+        .get("x86_64")
+        .unwrap()
+        .compile(&program, &options, &ProgramInfo::default(), &mut Vec::new())
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "x86-64 assembly doesn't support 16-bit cells"
+    );
+    assert!(matches!(
+        Error::from(err),
+        Error::Compile(CompileError::Unsupported(_))
+    ));
+
+    assert_eq!(
+        interpreter::parse_tape_size("30q"),
+        Err(TapeSizeError::UnknownSuffix {
+            suffix: "q".to_owned()
+        })
+    );
+
+    let err = "never".parse::<EofMode>().unwrap_err();
+    assert_eq!(err.value, "never");
+    assert_eq!(err.expected, ["unchanged", "zero", "negative-one"]);
+}
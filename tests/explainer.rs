@@ -0,0 +1,45 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use membrane::explainer;
+use membrane::optimizer::{self, OptimizeOptions};
+use membrane::parser;
+
+#[test]
+fn regions_explain_the_loops_the_optimizer_recognized() {
+    let mut program = parser::parse_string(",[->+>++<<]>[-]>[>>]\n+[,.]").unwrap();
+    optimizer::optimize_program(&mut program, &OptimizeOptions::default()).unwrap();
+
+    let regions = explainer::explain(&program);
+    let explanations = regions
+        .iter()
+        .map(|region| (region.depth, region.explanation.as_str()))
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        explanations,
+        [
+            (0, "reads a byte into the current cell"),
+            (
+                0,
+                "adds the current cell to the next cell, adds 2 times the current cell to the cell 2 to the right, then zeroes it"
+            ),
+            (0, "moves 1 cell right"),
+            (0, "zeroes the current cell"),
+            (0, "moves 1 cell right"),
+            // The scan leaves the cell at zero, so the add after it is a store.
+            (
+                0,
+                "scans right 2 cells at a time to the next zero cell, then sets the current cell to 1"
+            ),
+            (0, "loops over what follows until the current cell is zero"),
+            (1, "reads a byte into the current cell"),
+            (1, "writes the current cell"),
+            (0, "ends the loop from line 2, column 2"),
+        ]
+    );
+    assert_eq!(regions[1].span.range(), 1..11);
+}
@@ -0,0 +1,70 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Calls the C interface through include/membrane.h the way a C program would, for
+ * tests/ffi.rs to build against the shared library. Exits with 1 after printing what went
+ * wrong.
+ */
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "membrane.h"
+
+struct streams {
+    const char *input;
+    char output[16];
+    size_t written;
+};
+
+static int read_byte(void *context) {
+    struct streams *streams = context;
+
+    if (*streams->input == '\0') {
+        return -1;
+    }
+
+    return (unsigned char) *streams->input++;
+}
+
+static void write_byte(void *context, uint8_t byte) {
+    struct streams *streams = context;
+
+    if (streams->written < sizeof streams->output - 1) {
+        streams->output[streams->written++] = (char) byte;
+    }
+}
+
+static void check(int condition, const char *what) {
+    if (!condition) {
+        fprintf(stderr, "failed: %s\n", what);
+        exit(1);
+    }
+}
+
+int main(void) {
+    const char *source = ",[+.,]";
+    MembraneProgram *program = membrane_parse((const uint8_t *) source, strlen(source));
+    check(program != NULL, "parsing a program");
+    check(membrane_optimize(program) == MEMBRANE_OK, "optimizing it");
+
+    struct streams streams = { "HAL", { 0 }, 0 };
+    int status = membrane_run(program, 30000, read_byte, write_byte, &streams);
+    check(status == MEMBRANE_END_OF_INPUT, "running until the input ran out");
+    check(strcmp(streams.output, "IBM") == 0, "writing each byte read plus one");
+
+    check(membrane_run(program, 0, NULL, NULL, NULL) == MEMBRANE_END_OF_INPUT,
+          "running without callbacks");
+    membrane_free(program);
+
+    check(membrane_parse((const uint8_t *) "+]", 2) == NULL, "refusing unmatched brackets");
+    check(membrane_optimize(NULL) == MEMBRANE_NULL_PROGRAM, "refusing null programs");
+    membrane_free(NULL);
+
+    return 0;
+}
@@ -0,0 +1,103 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+#![cfg(feature = "ffi")]
+
+use std::env;
+use std::ffi::c_void;
+use std::fs;
+use std::io::ErrorKind;
+use std::os::raw::c_int;
+use std::path::Path;
+use std::process::{self, Command};
+use std::ptr;
+
+use membrane::ffi::*;
+
+unsafe extern "C" fn read(context: *mut c_void) -> c_int {
+    let input = &mut *(context as *mut (&[u8], Vec<u8>));
+
+    match input.0.split_first() {
+        Some((&byte, rest)) => {
+            input.0 = rest;
+            byte as c_int
+        }
+        None => -1,
+    }
+}
+
+unsafe extern "C" fn write(context: *mut c_void, byte: u8) {
+    let output = &mut *(context as *mut (&[u8], Vec<u8>));
+    output.1.push(byte);
+}
+
+#[test]
+fn c_callers_parse_optimize_and_run_programs() {
+    unsafe {
+        let source = b",[+.,]";
+        let program = membrane_parse(source.as_ptr(), source.len());
+        assert!(!program.is_null());
+        assert_eq!(membrane_optimize(program), MEMBRANE_OK);
+
+        let mut streams: (&[u8], Vec<u8>) = (b"HAL", Vec::new());
+        let context = &mut streams as *mut _ as *mut c_void;
+        let status = membrane_run(program, 0, Some(read), Some(write), context);
+
+        assert_eq!(status, MEMBRANE_END_OF_INPUT);
+        assert_eq!(streams.1, b"IBM");
+
+        assert_eq!(
+            membrane_run(program, 0, None, None, ptr::null_mut()),
+            MEMBRANE_END_OF_INPUT
+        );
+        membrane_free(program);
+
+        assert!(membrane_parse(b"+]".as_ptr(), 2).is_null());
+        assert_eq!(membrane_optimize(ptr::null_mut()), MEMBRANE_NULL_PROGRAM);
+        membrane_free(ptr::null_mut());
+    }
+}
+
+// Builds tests/ffi.c against include/membrane.h and the shared library cargo built next to
+// this test, and runs it. Skipped without a C compiler.
+#[test]
+fn c_programs_link_against_the_shared_library() {
+    if !cfg!(target_os = "linux") {
+        return;
+    }
+
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let library = env::current_exe().unwrap().parent().unwrap().to_owned();
+    let binary = env::temp_dir().join(format!("membrane-ffi-{}", process::id()));
+
+    let compiler = env::var("CC").unwrap_or_else(|_| "cc".to_owned());
+    let status = match Command::new(compiler)
+        .arg("-o")
+        .arg(&binary)
+        .arg("-I")
+        .arg(root.join("include"))
+        .arg(root.join("tests").join("ffi.c"))
+        .arg("-L")
+        .arg(&library)
+        .arg(format!("-Wl,-rpath,{}", library.display()))
+        .arg("-lmembrane")
+        .status()
+    {
+        Ok(status) => status,
+        Err(err) if err.kind() == ErrorKind::NotFound => return,
+        Err(err) => panic!("failed to run the C compiler: {}", err),
+    };
+
+    assert!(status.success(), "tests/ffi.c didn't build");
+
+    let output = Command::new(&binary).output().unwrap();
+    fs::remove_file(&binary).unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
@@ -0,0 +1,54 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::fs;
+
+use membrane::formatter::{self, FormatOptions};
+use membrane::parser::ParseOptions;
+
+fn format(source: &str, options: &FormatOptions) -> String {
+    let formatted = formatter::format(source.as_bytes(), &ParseOptions::default(), options);
+    String::from_utf8(formatted.unwrap()).unwrap()
+}
+
+fn commands(source: &str) -> String {
+    source.chars().filter(|c| "+-<>.,[]".contains(*c)).collect()
+}
+
+#[test]
+fn loops_too_wide_for_a_line_become_blocks() {
+    let options = FormatOptions {
+        width: 16,
+        ..FormatOptions::default()
+    };
+
+    let source = "set up +++++ +++++\n\n\n[>+++++ +++++ [->+<] <- ] done\n";
+    assert_eq!(
+        format(source, &options),
+        "set up\n++++++++++\n\n[\n    >++++++++++\n    [->+<]<-\n] done\n"
+    );
+
+    let options = FormatOptions {
+        strip_comments: true,
+        ..options
+    };
+
+    assert_eq!(
+        format(source, &options),
+        "++++++++++\n\n[\n    >++++++++++\n    [->+<]<-\n]\n"
+    );
+}
+
+#[test]
+fn formatting_is_idempotent_and_keeps_the_program() {
+    for entry in fs::read_dir("examples").unwrap() {
+        let source = fs::read_to_string(entry.unwrap().path()).unwrap();
+        let formatted = format(&source, &FormatOptions::default());
+
+        assert_eq!(format(&formatted, &FormatOptions::default()), formatted);
+        assert_eq!(commands(&formatted), commands(&source));
+    }
+}
@@ -0,0 +1,46 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::env;
+use std::fs::{self, File};
+use std::io::Cursor;
+use std::process;
+
+use membrane::generator;
+use membrane::instruction::Instruction;
+use membrane::interpreter::{self, InputSource, OutputSource, TapeSize};
+use membrane::lowering;
+
+fn output_of(instructions: &[Instruction]) -> Vec<u8> {
+    let path = env::temp_dir().join(format!("membrane-generator-{}", process::id()));
+    let output = OutputSource::File(File::create(&path).unwrap());
+    let input = InputSource::File(Cursor::new(Vec::new()));
+
+    interpreter::interpret(instructions, input, output, TapeSize::Infinite).unwrap();
+
+    let written = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    written
+}
+
+#[test]
+fn generated_programs_write_their_text() {
+    let texts: [&[u8]; 4] = [
+        b"Hello, World!\n",
+        b"aaaa",
+        "caf\u{e9} \u{2603}".as_bytes(),
+        b"",
+    ];
+
+    for text in texts {
+        let plain = generator::text(text, false);
+        let small = generator::text(text, true);
+
+        assert_eq!(output_of(&plain), text);
+        assert_eq!(output_of(&small), text);
+        assert!(lowering::to_brainfuck(&small).len() <= lowering::to_brainfuck(&plain).len());
+    }
+}
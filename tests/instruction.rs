@@ -0,0 +1,80 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use membrane::instruction::{Instruction, ParseInstructionError};
+
+const EVERY_VARIANT: &[Instruction] = &[
+    Instruction::Add(-128),
+    Instruction::Add(5),
+    Instruction::Move(-3),
+    Instruction::Move(7),
+    Instruction::Write(1),
+    Instruction::Read(4),
+    Instruction::JumpIfZero { location: 12 },
+    Instruction::JumpIfNotZero { location: 3 },
+    Instruction::SetValue(-1),
+    Instruction::AddRelative {
+        offset: -2,
+        amount: 9,
+    },
+    Instruction::AddVector {
+        vector: [1, -2, 0, 127],
+    },
+    Instruction::MulAdd {
+        offset: 3,
+        factor: -4,
+    },
+    Instruction::MoveRightToZero {
+        increment: 0,
+        stride: 2,
+    },
+    Instruction::MoveLeftToZero {
+        increment: -1,
+        stride: 1,
+    },
+    Instruction::DefineProc { location: 8 },
+    Instruction::EndProc,
+    Instruction::CallProc,
+];
+
+#[test]
+fn every_instruction_displays_its_name_and_parses_back() {
+    for instruction in EVERY_VARIANT {
+        let text = instruction.to_string();
+
+        assert_eq!(text.split_whitespace().next(), Some(instruction.name()));
+        assert_eq!(
+            text.parse::<Instruction>().as_ref(),
+            Ok(instruction),
+            "{}",
+            text
+        );
+    }
+}
+
+#[test]
+fn malformed_instructions_are_rejected() {
+    assert_eq!(
+        "SetAbsolute 0".parse::<Instruction>(),
+        Err(ParseInstructionError::UnknownMnemonic(
+            "SetAbsolute".to_owned()
+        ))
+    );
+    assert_eq!(
+        "Move 3".parse::<Instruction>(),
+        Err(ParseInstructionError::InvalidOperand("3".to_owned()))
+    );
+    assert_eq!(
+        "AddVector [1, 2, 3]".parse::<Instruction>(),
+        Err(ParseInstructionError::InvalidOperand(
+            "[1, 2, 3]".to_owned()
+        ))
+    );
+    assert_eq!(
+        "  ".parse::<Instruction>(),
+        Err(ParseInstructionError::Empty)
+    );
+}
@@ -0,0 +1,213 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::io::{self, Cursor, Read};
+use std::thread;
+
+use membrane::instruction::Instruction;
+use membrane::interpreter::{
+    self, parse_tape_size, CellWidth, EofMode, InputSource, Interpreter, OutputSource,
+    RuntimeError, TapeSize,
+};
+use membrane::parser;
+
+#[test]
+fn tape_sizes_take_suffixes() {
+    assert_eq!(parse_tape_size("30000"), Ok(30000));
+    assert_eq!(parse_tape_size("30k"), Ok(30000));
+    assert_eq!(parse_tape_size("30K"), Ok(30000));
+    assert_eq!(parse_tape_size("1M"), Ok(1_000_000));
+    assert_eq!(parse_tape_size("64Ki"), Ok(65536));
+    assert_eq!(parse_tape_size("0"), Ok(0));
+
+    for invalid in [
+        "",
+        "k",
+        "-1",
+        "1.5k",
+        "30x",
+        "99999999999999999999",
+        "18446744073709551615G",
+    ] {
+        assert!(parse_tape_size(invalid).is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn runtime_errors_stop_the_program() {
+    let run = |source: &str, input: &[u8]| {
+        let program = parser::parse_string(source).unwrap();
+        interpreter::interpret(
+            &program.instructions,
+            InputSource::File(Cursor::new(input.to_vec())),
+            OutputSource::Sink(io::sink()),
+            TapeSize::Infinite,
+        )
+    };
+
+    assert_eq!(run("+>,.", b"a").unwrap(), 4);
+    assert!(matches!(
+        run("><<", b""),
+        Err(RuntimeError::MovedOffTape { index: 1 })
+    ));
+    assert!(matches!(
+        run(",.,", b"a"),
+        Err(RuntimeError::EndOfInput { index: 2 })
+    ));
+}
+
+#[test]
+fn final_states_keep_the_tape() {
+    let program = parser::parse_string("+++>++>+<").unwrap();
+    let state = interpreter::interpret_with_state(
+        &program.instructions,
+        InputSource::File(Cursor::new(Vec::new())),
+        OutputSource::Sink(io::sink()),
+        TapeSize::Infinite,
+    )
+    .unwrap();
+
+    assert_eq!(state.head, 1);
+    assert_eq!(state.current_cell(), 2);
+    assert_eq!(
+        (state.cell(0), state.cell(2), state.cell(1 << 20)),
+        (3, 1, 0)
+    );
+}
+
+#[test]
+fn builders_configure_cells_and_reads_past_the_end() {
+    let run = |source: &str, cell_width: CellWidth, eof_mode: EofMode| {
+        let program = parser::parse_string(source).unwrap();
+        Interpreter::builder()
+            .tape(TapeSize::Finite(16))
+            .cell_width(cell_width)
+            .eof(eof_mode)
+            .input(InputSource::File(Cursor::new(b"a".to_vec())))
+            .output(OutputSource::Sink(io::sink()))
+            .build()
+            .run(&program.instructions)
+            .unwrap()
+    };
+
+    // 256 is zero in a byte but not in anything wider.
+    let carry = "++++++++++++++++[>++++++++++++++++<-]>";
+    assert_eq!(run(carry, CellWidth::U8, EofMode::Zero).current_cell(), 0);
+    assert_eq!(
+        run(carry, CellWidth::U16, EofMode::Zero).current_cell(),
+        256
+    );
+    assert_eq!(
+        run("-", CellWidth::U16, EofMode::Zero).current_cell(),
+        0xffff
+    );
+
+    assert_eq!(run(",", CellWidth::U8, EofMode::Zero).current_cell(), 97);
+    assert_eq!(run(",,", CellWidth::U8, EofMode::Zero).current_cell(), 0);
+    assert_eq!(
+        run(",,", CellWidth::U8, EofMode::Unchanged).current_cell(),
+        97
+    );
+    assert_eq!(
+        run(",,", CellWidth::U32, EofMode::NegativeOne).current_cell(),
+        u32::MAX
+    );
+}
+
+#[test]
+fn callbacks_stand_in_for_input_and_output() {
+    let program = parser::parse_string(",[.,]+.").unwrap();
+    let mut input = b"hi".iter().copied();
+    let mut output = Vec::new();
+
+    let state = Interpreter::builder()
+        .eof(EofMode::Zero)
+        .build()
+        .run_with(
+            &program.instructions,
+            || input.next(),
+            |byte| output.push(byte),
+        )
+        .unwrap();
+
+    assert_eq!(output, b"hi\x01");
+    assert_eq!(state.current_cell(), 1);
+}
+
+#[test]
+fn large_io_amounts_are_done_in_chunks() {
+    let instructions = [
+        Instruction::Add(7),
+        Instruction::Write(100_000),
+        Instruction::Read(usize::MAX),
+    ];
+    let mut input = b"hi".iter().copied();
+    let mut output = Vec::new();
+
+    let state = Interpreter::builder()
+        .eof(EofMode::Unchanged)
+        .build()
+        .run_with(&instructions, || input.next(), |byte| output.push(byte))
+        .unwrap();
+
+    assert_eq!(output.len(), 100_000);
+    assert!(output.iter().all(|&byte| byte == 7));
+    assert_eq!(state.current_cell(), u32::from(b'i'));
+}
+
+#[test]
+fn step_limits_stop_programs_that_never_end() {
+    let program = parser::parse_string("+[>+<]").unwrap();
+    let result = Interpreter::builder().max_steps(50).build().run_with(
+        &program.instructions,
+        || None,
+        |_| {},
+    );
+
+    assert!(matches!(
+        result,
+        Err(RuntimeError::StepLimit {
+            index: 5,
+            limit: 50
+        })
+    ));
+}
+
+#[test]
+fn pipes_connect_programs_running_at_once() {
+    let run = |source: &str, input: InputSource, output: OutputSource| {
+        let program = parser::parse_string(source).unwrap();
+        interpreter::interpret(&program.instructions, input, output, TapeSize::Infinite)
+    };
+
+    let (writer, reader) = interpreter::pipe();
+    let (last_writer, mut last_reader) = interpreter::pipe();
+
+    thread::scope(|scope| {
+        // Writes forever, until the program after it stops reading.
+        let first = scope.spawn(|| {
+            run(
+                "++++++++[>++++++++<-]>+[.+]",
+                InputSource::File(Cursor::new(Vec::new())),
+                OutputSource::Pipe(writer),
+            )
+        });
+        let second = scope.spawn(|| {
+            run(
+                ",+.,+.,+.",
+                InputSource::Pipe(reader),
+                OutputSource::Pipe(last_writer),
+            )
+        });
+
+        assert!(second.join().unwrap().is_ok());
+        assert!(first.join().unwrap().unwrap_err().is_broken_pipe());
+    });
+
+    let mut output = Vec::new();
+    last_reader.read_to_end(&mut output).unwrap();
+    assert_eq!(output, b"BCD");
+}
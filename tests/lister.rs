@@ -0,0 +1,218 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::io::{self, Cursor};
+
+use membrane::instruction::Instruction;
+use membrane::interpreter::{self, InputSource, OutputSource, TapeSize};
+use membrane::json::Value;
+use membrane::lister::{self, ListingFormat, ListingOptions};
+use membrane::optimizer::{self, OptimizeOptions};
+use membrane::parser::{self, ParseOptions};
+use membrane::profile::Profile;
+
+#[test]
+fn listings_indent_loops_and_point_jumps_at_their_partners() {
+    let program = parser::parse_string("+[>+<-]+++++.").unwrap();
+    let mut listing = Vec::new();
+    lister::create_listing(&program.instructions, &mut listing).unwrap();
+
+    let listing = String::from_utf8(listing).unwrap();
+    let lines = listing.lines().collect::<Vec<_>>();
+
+    assert_eq!(lines.len(), program.instructions.len());
+    assert_eq!(lines[0], format!("0  {}", program.instructions[0]));
+    assert!(lines[1].starts_with("1  JumpIfZero"));
+    assert!(lines[1].ends_with("[ → 6"));
+    assert!(lines[2].starts_with("2    Move"));
+    assert!(lines[6].ends_with("] → 1"));
+
+    let mut empty = Vec::new();
+    lister::create_listing(&[], &mut empty).unwrap();
+    assert!(empty.is_empty());
+}
+
+#[test]
+fn json_and_csv_listings_have_a_record_per_instruction() {
+    let mut program = parser::parse_string(">>>+++<<<.").unwrap();
+    optimizer::optimize_program(&mut program, &OptimizeOptions::default()).unwrap();
+
+    let mut json = Vec::new();
+    let options = ListingOptions {
+        format: ListingFormat::Json,
+        ..ListingOptions::default()
+    };
+    lister::write_listing(&program, &options, &mut json).unwrap();
+
+    let records = String::from_utf8(json).unwrap().parse::<Value>().unwrap();
+    let record = &records.as_array().unwrap()[0];
+
+    assert_eq!(
+        record.get("opcode").and_then(Value::as_str),
+        Some("AddRelative")
+    );
+    assert_eq!(
+        record
+            .get("operands")
+            .and_then(|operands| operands.get("offset")?.as_u64()),
+        Some(3)
+    );
+    assert_eq!(
+        record
+            .get("span")
+            .and_then(|span| span.get("end")?.as_u64()),
+        Some(9)
+    );
+
+    let mut csv = Vec::new();
+    let options = ListingOptions {
+        format: ListingFormat::Csv,
+        ..ListingOptions::default()
+    };
+    lister::write_listing(&program, &options, &mut csv).unwrap();
+
+    assert_eq!(
+        String::from_utf8(csv).unwrap(),
+        "index,opcode,operands,start,end,line,column\n\
+         0,AddRelative,offset=3 amount=3,0,9,1,1\n\
+         1,Write,count=1,9,10,1,10\n"
+    );
+}
+
+#[test]
+fn listings_show_the_source_of_each_instruction() {
+    let source = ">>>+++<<< .";
+    let mut program = parser::parse_string(source).unwrap();
+    optimizer::optimize_program(&mut program, &OptimizeOptions::default()).unwrap();
+
+    let mut listing = Vec::new();
+    lister::create_listing_with_source(&program, source.as_bytes(), &mut listing).unwrap();
+
+    let listing = String::from_utf8(listing).unwrap();
+    let lines = listing.lines().collect::<Vec<_>>();
+
+    assert!(lines[0].starts_with("0  AddRelative"));
+    assert!(lines[0].ends_with("  ; >>>+++<<<"));
+    assert!(lines[1].ends_with("  ; ."));
+    assert_eq!(lines[0].find(';'), lines[1].find(';'));
+}
+
+#[test]
+fn profiles_add_hit_counts_and_mark_the_hottest_instructions() {
+    let program = parser::parse_string("+++[>++<-]").unwrap();
+    let mut profile = Profile::new(program.len());
+
+    interpreter::interpret_with_profile(
+        &program.instructions,
+        InputSource::File(Cursor::new(Vec::new())),
+        OutputSource::Sink(io::sink()),
+        TapeSize::Infinite,
+        &mut profile.hits,
+    )
+    .unwrap();
+
+    // The start of the loop runs again with each jump back to it.
+    assert_eq!(profile.hits, [1, 3, 3, 3, 3, 3, 3]);
+
+    let mut json = Vec::new();
+    profile.write_json(&mut json).unwrap();
+    let profile = String::from_utf8(json).unwrap().parse::<Profile>().unwrap();
+    assert_eq!(profile.total(), 19);
+
+    let options = ListingOptions {
+        profile: Some(&profile),
+        ..ListingOptions::default()
+    };
+
+    let mut listing = Vec::new();
+    lister::write_listing(&program, &options, &mut listing).unwrap();
+
+    let listing = String::from_utf8(listing).unwrap();
+    let lines = listing.lines().collect::<Vec<_>>();
+
+    assert!(lines[0].starts_with("0  1   5.26%   Add"));
+    assert!(lines[1].starts_with("1  3  15.79% * JumpIfZero"));
+    assert_eq!(lines.iter().filter(|line| line.contains('*')).count(), 4);
+}
+
+#[test]
+fn listings_parse_back_into_the_instructions_they_list() {
+    let options = ParseOptions {
+        procedures: true,
+        ..ParseOptions::default()
+    };
+    let source = b"++[->+++<]>(.[-]>+<):,[>>]+[<<+>>-]";
+    let mut program = parser::parse_bytes_with(source, &options).unwrap();
+    optimizer::optimize_program(&mut program, &OptimizeOptions::default()).unwrap();
+
+    let mut listing = Vec::new();
+    lister::create_listing(&program.instructions, &mut listing).unwrap();
+
+    let listing = String::from_utf8(listing).unwrap();
+    let parsed = lister::parse_listing(&listing).unwrap();
+    assert_eq!(parsed.instructions, program.instructions);
+    assert_eq!(parsed.spans[1].line, 2);
+
+    // Jump targets are worked out again, so lines can be added without renumbering.
+    let edited = "# Edited\nJumpIfZero [ → 9\n  Add  -1 ; decrement\nJumpIfNotZero ]\n";
+    let parsed = lister::parse_listing(edited).unwrap();
+    assert_eq!(
+        parsed.instructions[0],
+        Instruction::JumpIfZero { location: 2 }
+    );
+
+    let err = lister::parse_listing("0  Add +1\n1  Jump [").unwrap_err();
+    assert_eq!(err.location().line, 2);
+}
+
+#[test]
+fn summaries_total_the_instructions_and_compare_sizes() {
+    let mut program = parser::parse_string("++[->+++<]>.[-]").unwrap();
+    let unoptimized = program.len();
+    optimizer::optimize_program(&mut program, &OptimizeOptions::default()).unwrap();
+
+    let options = ListingOptions {
+        summary: true,
+        unoptimized: Some(unoptimized),
+        ..ListingOptions::default()
+    };
+
+    let mut listing = Vec::new();
+    lister::write_listing(&program, &options, &mut listing).unwrap();
+
+    let listing = String::from_utf8(listing).unwrap();
+    assert!(listing.contains(&format!("# {} instruction(s), 0 loop(s)", program.len())));
+    assert!(listing.contains(&format!("# {} instruction(s) before", unoptimized)));
+
+    // The summary is comments, so the listing still reads back.
+    let parsed = lister::parse_listing(&listing).unwrap();
+    assert_eq!(parsed.instructions, program.instructions);
+
+    let options = ListingOptions {
+        format: ListingFormat::Json,
+        ..options
+    };
+
+    let mut listing = Vec::new();
+    lister::write_listing(&program, &options, &mut listing).unwrap();
+
+    let listing = String::from_utf8(listing)
+        .unwrap()
+        .parse::<Value>()
+        .unwrap();
+    let summary = listing.get("summary").unwrap();
+    assert_eq!(
+        summary.get("unoptimized").and_then(Value::as_u64),
+        Some(unoptimized as u64)
+    );
+    assert_eq!(
+        listing
+            .get("instructions")
+            .and_then(Value::as_array)
+            .map(<[_]>::len),
+        Some(program.len())
+    );
+}
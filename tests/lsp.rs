@@ -0,0 +1,102 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use membrane::json::Value;
+use membrane::lsp;
+
+fn frame(messages: &[&str]) -> Vec<u8> {
+    messages
+        .iter()
+        .flat_map(|message| {
+            format!("Content-Length: {}\r\n\r\n{}", message.len(), message).into_bytes()
+        })
+        .collect()
+}
+
+fn unframe(output: &[u8]) -> Vec<Value> {
+    String::from_utf8_lossy(output)
+        .split("Content-Length: ")
+        .skip(1)
+        .map(|message| message.split_once("\r\n\r\n").unwrap().1.parse().unwrap())
+        .collect()
+}
+
+#[test]
+fn servers_report_errors_and_format_documents() {
+    let input = frame(&[
+        r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#,
+        r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///a.b","text":"+[>+<-]]"}}}"#,
+        r#"{"jsonrpc":"2.0","method":"textDocument/didChange","params":{"textDocument":{"uri":"file:///a.b"},"contentChanges":[{"text":"+[>+<-]\n"}]}}"#,
+        r#"{"jsonrpc":"2.0","id":2,"method":"textDocument/hover","params":{"textDocument":{"uri":"file:///a.b"},"position":{"line":0,"character":3}}}"#,
+        r#"{"jsonrpc":"2.0","id":3,"method":"textDocument/documentSymbol","params":{"textDocument":{"uri":"file:///a.b"}}}"#,
+        r#"{"jsonrpc":"2.0","id":4,"method":"textDocument/formatting","params":{"textDocument":{"uri":"file:///a.b"},"options":{"tabSize":4}}}"#,
+        r#"{"jsonrpc":"2.0","id":5,"method":"shutdown"}"#,
+        r#"{"jsonrpc":"2.0","method":"exit"}"#,
+    ]);
+
+    let mut output = Vec::new();
+    assert!(lsp::serve(input.as_slice(), &mut output).unwrap());
+
+    let messages = unframe(&output);
+    assert_eq!(messages.len(), 7);
+
+    let diagnostics = |message: &Value| {
+        message
+            .get("params")
+            .and_then(|params| params.get("diagnostics"))
+            .and_then(Value::as_array)
+            .map(<[Value]>::len)
+    };
+    assert_eq!(diagnostics(&messages[1]), Some(1));
+    assert_eq!(diagnostics(&messages[2]), Some(0));
+
+    let hover = messages[3]
+        .get("result")
+        .and_then(|result| result.get("contents"))
+        .and_then(|contents| contents.get("value"))
+        .and_then(Value::as_str)
+        .unwrap();
+    assert!(hover.contains("[>+<-]"), "{}", hover);
+
+    let symbols = messages[4].get("result").and_then(Value::as_array).unwrap();
+    assert_eq!(symbols.len(), 1);
+    assert_eq!(
+        symbols[0].get("name").and_then(Value::as_str),
+        Some("loop at line 1")
+    );
+
+    // The document is already formatted.
+    assert_eq!(messages[5].get("result"), Some(&Value::Array(Vec::new())));
+    assert_eq!(messages[6].get("result"), Some(&Value::Null));
+}
+
+#[test]
+fn json_survives_a_round_trip() {
+    let text = r#"{"a":[1,-2.5,true,null],"b":"\"\\\né😀"}"#;
+    let value = text.parse::<Value>().unwrap();
+
+    assert_eq!(
+        value.get("b").and_then(Value::as_str),
+        Some("\"\\\né\u{1f600}")
+    );
+    assert_eq!(value.to_string().parse::<Value>().unwrap(), value);
+    assert_eq!(
+        r#""\ud83d\ude00""#.parse::<Value>(),
+        Ok(Value::from("\u{1f600}"))
+    );
+    assert!("[1,]".parse::<Value>().is_err());
+    assert!("{\"a\" 1}".parse::<Value>().is_err());
+}
+
+#[test]
+fn oversized_messages_are_rejected() {
+    let input = format!("Content-Length: {}\r\n\r\n", lsp::MAX_MESSAGE_LENGTH + 1);
+    let mut output = Vec::new();
+
+    let err = lsp::serve(input.as_bytes(), &mut output).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert!(output.is_empty());
+}
@@ -0,0 +1,154 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::env;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::process::{self, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use membrane::compilers::{lua, CompileOptions, ProgramInfo};
+use membrane::interpreter::{Interpreter, TapeSize};
+use membrane::optimizer::{self, OptimizeOptions};
+use membrane::parser;
+
+// Programs that between them use every kind of instruction once optimized, with their input.
+const PROGRAMS: &[(&str, &[u8])] = &[
+    (",[->+>++<<]>>.<.", b"\x05"),
+    ("++++++++[>++++++++<-]>+.+.>>+>+<<<[>]<.", b""),
+    (",>,[-<+>]<.>>>+<<<[<]>.", b"ab"),
+    (",[.,]", b"hello\0"),
+];
+
+fn compile(source: &str, options: &CompileOptions) -> String {
+    let mut program = parser::parse_string(source).unwrap();
+    let optimize_options = OptimizeOptions {
+        tape_size: options.tape_size,
+        ..OptimizeOptions::default()
+    };
+    optimizer::optimize_program(&mut program, &optimize_options).unwrap();
+
+    let mut code = Vec::new();
+    lua::compile(
+        &program.instructions,
+        options,
+        &ProgramInfo::default(),
+        &mut code,
+    )
+    .unwrap();
+    String::from_utf8(code).unwrap()
+}
+
+fn interpret(source: &str, input: &[u8], tape_size: TapeSize) -> Vec<u8> {
+    let program = parser::parse_string(source).unwrap();
+    let mut input = input.iter().copied();
+    let mut output = Vec::new();
+
+    Interpreter::builder()
+        .tape(tape_size)
+        .build()
+        .run_with(
+            &program.instructions,
+            || input.next(),
+            |byte| output.push(byte),
+        )
+        .unwrap();
+
+    output
+}
+
+// Runs the program with Lua and returns what it prints, or None if Lua isn't installed.
+fn run_compiled(source: &str, input: &[u8], options: &CompileOptions) -> Option<Vec<u8>> {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+    let directory = env::temp_dir().join(format!(
+        "membrane-lua-{}-{}",
+        process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::create_dir_all(&directory).unwrap();
+
+    let code = directory.join("main.lua");
+    fs::write(&code, compile(source, options)).unwrap();
+
+    let lua = env::var("LUA").unwrap_or_else(|_| "lua".to_owned());
+    let mut child = match Command::new(lua)
+        .arg(&code)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(err) if err.kind() == ErrorKind::NotFound => return None,
+        Err(err) => panic!("failed to run lua: {}", err),
+    };
+
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{} failed", source);
+
+    fs::remove_dir_all(&directory).unwrap();
+    Some(output.stdout)
+}
+
+#[test]
+fn programs_compile_to_a_table_backed_tape() {
+    let code = compile(",[->+>++<<]>>.<.", &CompileOptions::default());
+    let lines = code.lines().map(str::trim).collect::<Vec<_>>();
+
+    for line in [
+        "local CELL_MASK = 255",
+        "local tape = setmetatable({}, { __index = function() return 0 end })",
+        "input(1)",
+        "if tape[head] ~= 0 then",
+        "tape[head + 2] = (tape[head + 2] + tape[head] * 2) & CELL_MASK",
+        "tape[head] = 0",
+        "head = head + 2",
+        "output(1)",
+        "head = left(1)",
+    ] {
+        assert!(lines.contains(&line), "missing {:?}", line);
+    }
+}
+
+#[test]
+fn finite_tapes_wrap_with_the_remainder() {
+    let code = compile(
+        "+<+<+<.>.>.>+[<++>-]<<<<.",
+        &CompileOptions::new(TapeSize::Finite(5)),
+    );
+    let lines = code.lines().map(str::trim).collect::<Vec<_>>();
+
+    for line in [
+        "local TAPE_LENGTH = 5",
+        "head = (head + 1) % TAPE_LENGTH",
+        "tape[(head - 1) % TAPE_LENGTH] = (tape[(head - 1) % TAPE_LENGTH] + tape[head] * 2) & CELL_MASK",
+    ] {
+        assert!(lines.contains(&line), "missing {:?}", line);
+    }
+}
+
+#[test]
+fn compiled_programs_match_the_interpreter() {
+    let tape_size = TapeSize::Finite(30_000);
+    let options = CompileOptions::new(tape_size);
+
+    for (source, input) in PROGRAMS {
+        let output = match run_compiled(source, input, &options) {
+            Some(output) => output,
+            None => return,
+        };
+
+        assert_eq!(output, interpret(source, input, tape_size), "{}", source);
+    }
+
+    let tape_size = TapeSize::Finite(5);
+    let source = "+<+<+<.>.>.>+[<++>-]<<<<.";
+
+    if let Some(output) = run_compiled(source, b"", &CompileOptions::new(tape_size)) {
+        assert_eq!(output, interpret(source, b"", tape_size));
+    }
+}
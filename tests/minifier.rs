@@ -0,0 +1,28 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use membrane::minifier;
+use membrane::optimizer::OptimizeOptions;
+use membrane::parser::{self, ParseOptions};
+
+#[test]
+fn minifying_leaves_only_commands_that_do_something() {
+    let options = ParseOptions {
+        inline_input: true,
+        ..ParseOptions::default()
+    };
+
+    // The adds and moves cancel out, which leaves the copy loop at the start of the
+    // program, where it never runs.
+    let program =
+        parser::parse_string_with("copy: ++--\n>< [->+<]\necho: ,[.,]!input", &options).unwrap();
+
+    assert_eq!(minifier::minify(&program, None).unwrap(), b",[.,]!input");
+    assert_eq!(
+        minifier::minify(&program, Some(&OptimizeOptions::default())).unwrap(),
+        b",[.,]!input"
+    );
+}
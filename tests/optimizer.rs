@@ -0,0 +1,656 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::sync::{Arc, Mutex};
+use std::{env, fs, process};
+
+use membrane::cache::{Cache, CacheKey};
+use membrane::canonicalizer;
+use membrane::instruction::Instruction;
+use membrane::interpreter::{EofMode, Interpreter, TapeSize};
+use membrane::lowering;
+use membrane::optimizer::{self, OptimizeError, OptimizeOptions, OptimizeReport};
+use membrane::parser;
+use membrane::program::Program;
+use membrane::span::Span;
+use membrane::testgen::{self, GenerateOptions};
+use proptest::prelude::*;
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+#[test]
+fn reports_follow_every_pass() {
+    let mut program = parser::parse_string("++++++++[>++++++++<-]>+.").unwrap();
+    let mut report = OptimizeReport::default();
+
+    optimizer::optimize_program_with_report(&mut program, &OptimizeOptions::default(), &mut report)
+        .unwrap();
+
+    assert_eq!(report.initial_instructions, 10);
+    assert_eq!(report.passes.last(), Some(&program.instructions.len()));
+    assert!(!report.fuel_exhausted);
+
+    let mut json = Vec::new();
+    report.write_json(&mut json, "").unwrap();
+    let json = String::from_utf8(json).unwrap();
+
+    assert!(json.contains("\"initial_instructions\": 10,"));
+    assert!(json.contains("\"segment_instructions\": null,"));
+    assert!(json.ends_with('}'));
+}
+
+// Keeps the name of every span that's opened, for checking what embedders are told.
+#[derive(Default)]
+struct Spans(Mutex<Vec<&'static str>>);
+
+impl Subscriber for Spans {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let mut names = self.0.lock().unwrap();
+        names.push(span.metadata().name());
+        Id::from_u64(names.len() as u64)
+    }
+
+    fn record(&self, _: &Id, _: &Record<'_>) {}
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, _: &Event<'_>) {}
+
+    fn enter(&self, _: &Id) {}
+
+    fn exit(&self, _: &Id) {}
+}
+
+#[test]
+fn passes_are_traced_to_subscribers() {
+    let mut program = parser::parse_string("++++++++[>++++++++<-]>+.").unwrap();
+    let mut report = OptimizeReport::default();
+    let spans = Arc::new(Spans::default());
+
+    tracing::subscriber::with_default(spans.clone(), || {
+        optimizer::optimize_program_with_report(
+            &mut program,
+            &OptimizeOptions::default(),
+            &mut report,
+        )
+        .unwrap()
+    });
+
+    let names = spans.0.lock().unwrap();
+    assert_eq!(names.first(), Some(&"optimize"));
+    assert_eq!(names.len(), 1 + report.passes.len());
+    assert!(names[1..].iter().all(|&name| name == "pass"));
+}
+
+#[test]
+fn spans_point_back_at_what_each_instruction_came_from() {
+    let source = "++++++++[>++++++++<-]>+.\n>[-]++.";
+    let optimized = optimize_and_compare(source, b"", &OptimizeOptions::default());
+
+    assert_eq!(optimized.spans.len(), optimized.instructions.len());
+
+    let texts = optimized
+        .instructions
+        .iter()
+        .zip(&optimized.spans)
+        .map(|(instruction, span)| (*instruction, &source[span.start..span.end]))
+        .collect::<Vec<_>>();
+
+    assert!(texts.contains(&(Instruction::Write(1), ".")));
+    assert!(texts.iter().all(|(_, text)| !text.is_empty()));
+    assert_eq!(optimized.spans.last().unwrap().line, 2);
+}
+
+#[test]
+fn unmatched_jumps_are_reported_with_their_index() {
+    let mut instructions = vec![
+        Instruction::Add(1),
+        Instruction::JumpIfZero { location: 0 },
+        Instruction::Move(1),
+    ];
+    let mut spans = vec![Span::default(); instructions.len()];
+
+    let result = optimizer::optimize(&mut instructions, &mut spans, &OptimizeOptions::default());
+
+    assert_eq!(result, Err(OptimizeError::UnmatchedJumpIfZero { index: 1 }));
+    assert!(result.unwrap_err().is_fatal());
+    // The broken IR is still handed back, so it can be dumped.
+    assert_eq!(instructions.len(), spans.len());
+    assert!(instructions.contains(&Instruction::JumpIfZero { location: 0 }));
+}
+
+#[test]
+fn nested_loops_still_jump_to_their_partners() {
+    let optimized = optimize_and_compare(
+        "++[>+++[>++[>+<-]<-]>>>[-<<<+>>>]<<<<-]>>>.,[.,]",
+        b"ab",
+        &OptimizeOptions::default(),
+    );
+
+    for (index, instruction) in optimized.instructions.iter().enumerate() {
+        match *instruction {
+            Instruction::JumpIfZero { location } => assert_eq!(
+                optimized.instructions[location],
+                Instruction::JumpIfNotZero { location: index }
+            ),
+            Instruction::JumpIfNotZero { location } => assert_eq!(
+                optimized.instructions[location],
+                Instruction::JumpIfZero { location: index }
+            ),
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn every_amount_of_fuel_leaves_a_working_program() {
+    let source = "+++++[>+++++<-]>[->+>+<<]>>[-<<+>>]<.[-]<.>>>,[.,]";
+    let unoptimized = parser::parse_string(source).unwrap();
+    let unlimited = optimize_and_compare(source, b"xy", &OptimizeOptions::default());
+
+    for fuel in 0..64 {
+        let options = OptimizeOptions {
+            fuel: Some(fuel),
+            ..OptimizeOptions::default()
+        };
+        let optimized = optimize_and_compare(source, b"xy", &options);
+
+        if fuel == 0 {
+            assert_eq!(optimized.instructions, unoptimized.instructions);
+        }
+    }
+
+    let mut report = OptimizeReport::default();
+    let mut optimized = unoptimized.clone();
+    let options = OptimizeOptions {
+        fuel: Some(1),
+        ..OptimizeOptions::default()
+    };
+    optimizer::optimize_program_with_report(&mut optimized, &options, &mut report).unwrap();
+    assert!(report.fuel_exhausted);
+
+    let options = OptimizeOptions {
+        fuel: Some(usize::MAX),
+        ..OptimizeOptions::default()
+    };
+    let optimized = optimize_and_compare(source, b"xy", &options);
+    assert_eq!(optimized.instructions, unlimited.instructions);
+}
+
+#[test]
+fn lowered_programs_do_what_the_optimized_ones_did() {
+    let options = OptimizeOptions::default();
+
+    for source in [
+        "++++++++[>++++++++<-]>+.>[-]++++[>+++<-]>.",
+        "+++[>+>++>+++<<<-]>[-]>>[<+>-]<.>,[.,]",
+        ">+>+>+[<]>.+(+++.):<.",
+        "+[->>>>+<<<<]>>>>[>]+.<<<[<]>.",
+    ] {
+        let optimized = optimize_and_compare(source, b"hi", &options);
+        let lowered = lowering::lower(&optimized.instructions);
+        let brainfuck = lowering::to_brainfuck(&optimized.instructions);
+
+        assert!(brainfuck.bytes().all(|byte| b"+-<>.,[]():".contains(&byte)));
+
+        assert!(lowered.iter().all(|instruction| matches!(
+            instruction,
+            Instruction::Add(1 | -1)
+                | Instruction::Move(1 | -1)
+                | Instruction::Write(1)
+                | Instruction::Read(1)
+                | Instruction::JumpIfZero { .. }
+                | Instruction::JumpIfNotZero { .. }
+                | Instruction::DefineProc { .. }
+                | Instruction::EndProc
+                | Instruction::CallProc
+        )));
+
+        let reparsed = parser::parse_string(&brainfuck).unwrap();
+        assert_eq!(
+            run(&reparsed, b"hi", TapeSize::Infinite),
+            run(&optimized, b"hi", TapeSize::Infinite),
+            "{}",
+            source
+        );
+    }
+}
+
+#[test]
+fn refactored_programs_share_a_normal_form() {
+    let canonical = |source: &str| {
+        let program = parser::parse_string(source).unwrap();
+        canonicalizer::canonicalize(&program.instructions)
+    };
+
+    for (original, refactored) in [
+        ("+>++<>>+++<<.", ">>+++<<+>++<."),
+        ("+++[>++<-]>.", "+++[->++<]>."),
+        ("+++[>+>++<<-]>.", "+++[>>++<+<-]>."),
+        ("[>+<-]+.", "+."),
+    ] {
+        assert_eq!(canonical(original), canonical(refactored));
+
+        let original = parser::parse_string(original).unwrap();
+        let refactored = parser::parse_string(refactored).unwrap();
+        assert_eq!(
+            run(&original, b"", TapeSize::Infinite).map(|(output, ..)| output),
+            run(&refactored, b"", TapeSize::Infinite).map(|(output, ..)| output)
+        );
+    }
+
+    assert_ne!(canonical("+>++<."), canonical("++>+<."));
+    assert_ne!(canonical("+[>+<-]>."), canonical("+[>++<-]>."));
+
+    // Optimizing doesn't change what a program normalizes to.
+    let source = "++++++++[>++++++++<-]>+.>[-]++++[>+++<-]>.";
+    let optimized = optimize_and_compare(source, b"", &OptimizeOptions::default());
+    assert_eq!(
+        canonicalizer::canonicalize(&optimized.instructions),
+        canonical(source)
+    );
+}
+
+#[test]
+fn untouched_cells_are_known_to_be_zero() {
+    let options = OptimizeOptions::default();
+
+    // Loops and scans over cells that were never written can't run.
+    let optimized = optimize_and_compare("[-]>[>+<-]>>[>]<[<]+++.", b"", &options);
+    assert_eq!(
+        optimized.instructions,
+        vec![
+            Instruction::Move(2),
+            Instruction::SetValue(3),
+            Instruction::Write(1),
+        ]
+    );
+
+    // Once a cell has been read it could be anything.
+    let optimized = optimize_and_compare(",[-]+.", b"x", &options);
+    assert_eq!(optimized.instructions[0], Instruction::Read(1));
+    optimize_and_compare(",[>+<-]>[<+>-]<.", b"x", &options);
+
+    // The same holds on a finite tape, where moving far enough comes back around.
+    let options = OptimizeOptions {
+        tape_size: TapeSize::Finite(4),
+        ..OptimizeOptions::default()
+    };
+    optimize_and_compare("+>>>>[-]>[>]+.", b"", &options);
+    optimize_and_compare("++<<<<[.-]", b"", &options);
+}
+
+#[test]
+fn adds_to_other_cells_fuse_across_io() {
+    let options = OptimizeOptions::default();
+
+    let optimized = optimize_and_compare(">+<,>+<.", b"x", &options);
+    assert_eq!(
+        optimized.instructions,
+        vec![
+            Instruction::AddRelative {
+                offset: 1,
+                amount: 2
+            },
+            Instruction::Read(1),
+            Instruction::Write(1),
+        ]
+    );
+
+    let optimized = optimize_and_compare(",>+<.>+<+.", b"x", &options);
+    assert_eq!(
+        optimized.instructions,
+        vec![
+            Instruction::Read(1),
+            Instruction::Write(1),
+            Instruction::AddVector {
+                vector: [1, 2, 0, 0]
+            },
+            Instruction::Write(1),
+        ]
+    );
+}
+
+#[test]
+fn segments_optimized_in_parallel_behave_the_same() {
+    // Far too long for `run`'s step limit, but every loop ends quickly.
+    let source = (0..8192)
+        .map(|index| {
+            format!(
+                "{}[->+<]>[-<++>]<.>{}<[-]",
+                "+".repeat(index % 7 + 1),
+                ">".repeat(index % 3)
+            )
+        })
+        .collect::<String>();
+    let program = parser::parse_string(&source).unwrap();
+    assert!(program.instructions.len() >= 1 << 16);
+
+    let output = |program: &Program| {
+        let mut output = Vec::new();
+        let state = Interpreter::builder()
+            .build()
+            .run_with(&program.instructions, || None, |byte| output.push(byte))
+            .unwrap();
+        (output, state.head, state.tape)
+    };
+
+    let optimize = |parallel| {
+        let mut optimized = program.clone();
+        let mut report = OptimizeReport::default();
+        let options = OptimizeOptions {
+            parallel,
+            ..OptimizeOptions::default()
+        };
+        optimizer::optimize_program_with_report(&mut optimized, &options, &mut report).unwrap();
+        (optimized, report)
+    };
+
+    let (in_parallel, report) = optimize(true);
+    assert_eq!(
+        report.segment_instructions.is_some(),
+        cfg!(feature = "parallel")
+    );
+    let (serially, report) = optimize(false);
+    assert!(report.segment_instructions.is_none());
+
+    let expected = output(&program);
+    assert_eq!(output(&in_parallel), expected);
+    assert_eq!(output(&serially), expected);
+    assert_eq!(in_parallel.spans.len(), in_parallel.instructions.len());
+}
+
+#[test]
+fn balanced_loops_become_multiplications() {
+    let options = OptimizeOptions::default();
+
+    let optimized = optimize_and_compare(",[->++>+++<<]>.>.", b"\x05", &options);
+    assert_eq!(
+        optimized.instructions[..4],
+        [
+            Instruction::Read(1),
+            Instruction::MulAdd {
+                offset: 1,
+                factor: 2
+            },
+            Instruction::MulAdd {
+                offset: 2,
+                factor: 3
+            },
+            Instruction::SetValue(0),
+        ]
+    );
+
+    // Only the copy loops inside a nested multiplication can be reduced.
+    let optimized = optimize_and_compare(",>+++[>[->+>+<<]>>[-<<+>>]<<<-]>>.", b"\x07", &options);
+    assert!(optimized
+        .instructions
+        .iter()
+        .any(|instruction| matches!(instruction, Instruction::MulAdd { .. })));
+    assert!(optimized
+        .instructions
+        .iter()
+        .any(|instruction| matches!(instruction, Instruction::JumpIfZero { .. })));
+}
+
+#[test]
+fn balanced_loops_that_wrap_onto_their_counter_stay_loops() {
+    for (source, tape_size) in [
+        ("++[->>>++<<<>+<]>.", 3),
+        ("++++[-<<->>]<<.", 2),
+        ("++++[->+<>>>>-<<<<]>.", 4),
+        ("++++++[->+<>>>>>--<<<<<]>.", 5),
+    ] {
+        let options = OptimizeOptions {
+            tape_size: TapeSize::Finite(tape_size),
+            ..OptimizeOptions::default()
+        };
+        optimize_and_compare(source, b"", &options);
+    }
+
+    // Offsets that land on other cells are still fine.
+    let options = OptimizeOptions {
+        tape_size: TapeSize::Finite(3),
+        ..OptimizeOptions::default()
+    };
+    let optimized = optimize_and_compare(",[->>>>++<<<<]>.", b"\x02", &options);
+    assert!(optimized
+        .instructions
+        .iter()
+        .any(|instruction| matches!(instruction, Instruction::MulAdd { .. })));
+}
+
+#[test]
+fn cached_programs_behave_like_the_originals() {
+    let directory = env::temp_dir().join(format!("membrane-optimizer-cache-{}", process::id()));
+    let cache = Cache::new(&directory);
+    let options = OptimizeOptions {
+        tape_size: TapeSize::Finite(8),
+        ..OptimizeOptions::default()
+    };
+
+    let sources = [
+        "++++++++[>++++++++<-]>+.<<[-]+.",
+        ",[>+>+<<-]>>[-<<+>>]<.<.,[.,]",
+    ];
+    let programs = sources.map(|source| {
+        let optimized = optimize_and_compare(source, b"ab", &options);
+        let key = CacheKey::new(source.as_bytes(), &options).unwrap();
+        cache
+            .store(key, &optimized.instructions, &optimized.spans)
+            .unwrap();
+        (key, optimized)
+    });
+
+    for (source, (key, optimized)) in sources.iter().zip(programs) {
+        let (instructions, spans) = cache.load(key).unwrap();
+        let cached = Program {
+            instructions,
+            spans,
+            ..parser::parse_string(source).unwrap()
+        };
+
+        assert_eq!(cached.instructions, optimized.instructions);
+        assert_eq!(
+            run(&cached, b"ab", options.tape_size),
+            run(
+                &parser::parse_string(source).unwrap(),
+                b"ab",
+                options.tape_size
+            )
+        );
+    }
+
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn running_out_of_passes_leaves_a_working_program() {
+    let source = "++++[>++++<-]>[<+>>+<-]<[>+<-]>.>.,[.,]";
+    let program = parser::parse_string(source).unwrap();
+    let expected = run(&program, b"ab", TapeSize::Infinite);
+
+    for max_passes in 0..4 {
+        let mut optimized = program.clone();
+        let mut report = OptimizeReport::default();
+        let options = OptimizeOptions {
+            max_passes,
+            ..OptimizeOptions::default()
+        };
+        let result = optimizer::optimize_program_with_report(&mut optimized, &options, &mut report);
+
+        assert!(report.passes.len() <= max_passes);
+        if let Err(err) = result {
+            assert_eq!(
+                err,
+                OptimizeError::PassBudgetExhausted { passes: max_passes }
+            );
+            assert!(!err.is_fatal());
+        }
+        assert_eq!(run(&optimized, b"ab", TapeSize::Infinite), expected);
+    }
+
+    let mut optimized = program.clone();
+    let options = OptimizeOptions {
+        max_passes: 0,
+        ..OptimizeOptions::default()
+    };
+    assert_eq!(
+        optimizer::optimize_program(&mut optimized, &options),
+        Err(OptimizeError::PassBudgetExhausted { passes: 0 })
+    );
+}
+
+#[test]
+fn interleaved_adds_are_scheduled_into_vectors() {
+    let options = OptimizeOptions::default();
+
+    let optimized = optimize_and_compare(",>>+<<+>>>+<<<>+<.", b"x", &options);
+    assert_eq!(
+        optimized.instructions,
+        vec![
+            Instruction::Read(1),
+            Instruction::AddVector {
+                vector: [1, 1, 1, 1]
+            },
+            Instruction::Write(1),
+        ]
+    );
+
+    for source in [
+        ",>>>+<<<>+<>>>>+<<<<+>>-<<.>>>.",
+        ",[>>+<<->+<>>>-<<<]>>.<.>>>.",
+        ",>>+<,<+>>.<<.",
+    ] {
+        optimize_and_compare(source, b"\x03", &options);
+    }
+}
+
+#[test]
+fn stores_fold_into_what_follows_them() {
+    let options = OptimizeOptions::default();
+
+    let optimized = optimize_and_compare(",[-]+>>+<<>+<.", b"x", &options);
+    assert_eq!(
+        optimized.instructions,
+        vec![
+            Instruction::Read(1),
+            Instruction::SetValue(1),
+            Instruction::AddVector {
+                vector: [0, 1, 1, 0]
+            },
+            Instruction::Write(1),
+        ]
+    );
+
+    // A multiplication by a known value is just an add.
+    let optimized = optimize_and_compare(",[-]+++[->+<]>.", b"x", &options);
+    assert_eq!(
+        optimized.instructions[..3],
+        [
+            Instruction::Read(1),
+            Instruction::AddRelative {
+                offset: 1,
+                amount: 3
+            },
+            Instruction::SetValue(0),
+        ]
+    );
+
+    for source in [
+        ",[-]++>+<-->-<[-]>.",
+        ",>,<[-]+++++>[-]<[->++<]>.<.",
+        ",[-]->+<++>>[-]+<<.>.>.",
+    ] {
+        optimize_and_compare(source, b"ab", &options);
+    }
+}
+
+// On a tape of one cell every offset is the current cell, so the adds here can't be moved
+// past the writes and stores between them.
+#[test]
+fn adds_stay_behind_io_that_wraps_onto_their_cell() {
+    let options = OptimizeOptions {
+        tape_size: TapeSize::Finite(1),
+        ..OptimizeOptions::default()
+    };
+
+    for source in [">->><<<.+[+-+>>[,-+++]]", "+>+-><-.<++>.---<>>-<<+>>[-]"] {
+        optimize_and_compare(source, b"", &options);
+    }
+}
+
+// What a program wrote, where the head ended up, and the tape up to its last nonzero cell,
+// or None if it didn't end within its steps.
+fn run(program: &Program, input: &[u8], tape_size: TapeSize) -> Option<(Vec<u8>, usize, Vec<u32>)> {
+    let mut input = input.iter().copied();
+    let mut output = Vec::new();
+
+    let state = Interpreter::builder()
+        .tape(tape_size)
+        .eof(EofMode::Zero)
+        .max_steps(10_000)
+        .build()
+        .run_with(
+            &program.instructions,
+            || input.next(),
+            |byte| output.push(byte),
+        )
+        .ok()?;
+
+    let length = state
+        .tape
+        .iter()
+        .rposition(|&cell| cell != 0)
+        .map_or(0, |index| index + 1);
+    Some((output, state.head, state.tape[..length].to_vec()))
+}
+
+// Checks that the program does the same before and after it's optimized, and hands back the
+// optimized program.
+fn optimize_and_compare(source: &str, input: &[u8], options: &OptimizeOptions) -> Program {
+    let program = parser::parse_string(source).unwrap();
+    let mut optimized = program.clone();
+    optimizer::optimize_program(&mut optimized, options).unwrap();
+
+    let expected = run(&program, input, options.tape_size);
+    assert!(expected.is_some(), "{} didn't end", source);
+    assert_eq!(
+        run(&optimized, input, options.tape_size),
+        expected,
+        "{}",
+        source
+    );
+
+    optimized
+}
+
+proptest! {
+    #[test]
+    fn optimizing_never_changes_what_programs_do(seed: u64, input: Vec<u8>) {
+        let program = testgen::program(seed, &GenerateOptions::default());
+        let expected = match run(&program, &input, TapeSize::Infinite) {
+            Some(expected) => expected,
+            None => return Ok(()),
+        };
+
+        let mut optimized = program.clone();
+        let options = OptimizeOptions {
+            parallel: false,
+            ..OptimizeOptions::default()
+        };
+
+        if let Err(err) = optimizer::optimize_program(&mut optimized, &options) {
+            prop_assert!(!err.is_fatal(), "{}", err);
+        }
+
+        prop_assert_eq!(run(&optimized, &input, TapeSize::Infinite), Some(expected));
+    }
+}
@@ -0,0 +1,467 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::io::{self, Read};
+
+use membrane::dialect::{Dialect, DialectError, DialectMap};
+use membrane::frontend::{self, ParserFrontend};
+use membrane::instruction::Instruction;
+use membrane::lowering;
+use membrane::optimizer::{self, OptimizeOptions};
+use membrane::parser::{self, ParseError, ParseOptions};
+use membrane::program::{Program, ProgramStats};
+use membrane::span::Location;
+
+fn at(offset: usize, line: usize, column: usize) -> Location {
+    Location {
+        offset,
+        line,
+        column,
+    }
+}
+
+fn errors(source: &str) -> Vec<ParseError> {
+    match parser::parse_string(source) {
+        Err(errors) => errors,
+        Ok(program) => panic!("expected errors in {:?}, got {:?}", source, program),
+    }
+}
+
+// The bracket and its offset for every error in the source.
+fn brackets(source: &str) -> Vec<(char, usize)> {
+    errors(source)
+        .iter()
+        .map(|err| match err {
+            ParseError::UnmatchedClose { location, .. } => (']', location.offset),
+            ParseError::UnclosedOpen { location, .. } => ('[', location.offset),
+            err => panic!("expected a bracket error, got {:?}", err),
+        })
+        .collect()
+}
+
+#[test]
+fn unmatched_close_points_at_the_last_loop_closed() {
+    match errors("+[-]\n  ]").as_slice() {
+        [ParseError::UnmatchedClose { location, partner }] => {
+            assert_eq!(*location, at(7, 2, 3));
+            assert_eq!(*partner, Some(at(1, 1, 2)));
+        }
+        errors => panic!("expected an unmatched ']', got {:?}", errors),
+    }
+
+    match errors("]").as_slice() {
+        [ParseError::UnmatchedClose { location, partner }] => {
+            assert_eq!(*location, at(0, 1, 1));
+            assert_eq!(*partner, None);
+        }
+        errors => panic!("expected an unmatched ']', got {:?}", errors),
+    }
+}
+
+#[test]
+fn unclosed_open_points_at_the_end_of_the_source() {
+    // Columns count characters, while offsets count bytes.
+    match errors("é[\n[-]").as_slice() {
+        [ParseError::UnclosedOpen { location, partner }] => {
+            assert_eq!(*location, at(2, 1, 2));
+            assert_eq!(*partner, at(7, 2, 4));
+        }
+        errors => panic!("expected an unclosed '[', got {:?}", errors),
+    }
+}
+
+#[test]
+fn every_unclosed_open_is_reported() {
+    assert_eq!(brackets("[[["), [('[', 0), ('[', 1), ('[', 2)]);
+    assert_eq!(brackets("[[]"), [('[', 0)]);
+    assert_eq!(brackets("[]["), [('[', 2)]);
+    assert_eq!(brackets("[+[-[]>\n[<]"), [('[', 0), ('[', 2)]);
+}
+
+#[test]
+fn every_bracket_error_is_reported_in_order() {
+    assert_eq!(brackets("]["), [(']', 0), ('[', 1)]);
+    assert_eq!(brackets("[]][["), [(']', 2), ('[', 3), ('[', 4)]);
+    assert_eq!(brackets("[[]]]["), [(']', 4), ('[', 5)]);
+    assert_eq!(brackets("]]+[-]]"), [(']', 0), (']', 1), (']', 6)]);
+
+    // Unmatched closes are skipped, so they don't close loops opened after them.
+    assert_eq!(brackets("[]]["), [(']', 2), ('[', 3)]);
+}
+
+#[test]
+fn nested_loops_jump_to_their_partners() {
+    let program = parser::parse_string("[[]][]").unwrap();
+
+    assert_eq!(
+        program.instructions,
+        [
+            Instruction::JumpIfZero { location: 3 },
+            Instruction::JumpIfZero { location: 2 },
+            Instruction::JumpIfNotZero { location: 1 },
+            Instruction::JumpIfNotZero { location: 0 },
+            Instruction::JumpIfZero { location: 5 },
+            Instruction::JumpIfNotZero { location: 4 },
+        ]
+    );
+}
+
+#[test]
+fn spans_keep_their_position_through_optimization() {
+    let Program {
+        mut instructions,
+        mut spans,
+        ..
+    } = parser::parse_string(",\n  [-]>.").unwrap();
+
+    assert_eq!(spans[1].location(), Some(at(4, 2, 3)));
+
+    optimizer::optimize(&mut instructions, &mut spans, &OptimizeOptions::default()).unwrap();
+
+    let clear = instructions
+        .iter()
+        .position(|instruction| *instruction == Instruction::SetValue(0))
+        .unwrap();
+
+    assert_eq!(spans[clear].location(), Some(at(4, 2, 3)));
+    assert_eq!(spans[clear].range(), 4..7);
+}
+
+// Hands out one byte per read, so characters and lines are split across reads.
+struct Trickle<'a>(&'a [u8]);
+
+impl Read for Trickle<'_> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        match (self.0.split_first(), buffer.first_mut()) {
+            (Some((&byte, rest)), Some(first)) => {
+                *first = byte;
+                self.0 = rest;
+                Ok(1)
+            }
+            _ => Ok(0),
+        }
+    }
+}
+
+#[test]
+fn readers_are_parsed_like_strings() {
+    let source = "héllo +[\n  ->+<\u{1f600}]\n.";
+    let expected = parser::parse_string(source).unwrap();
+
+    assert_eq!(
+        parser::parse_reader(Trickle(source.as_bytes())).unwrap(),
+        expected
+    );
+    assert_eq!(expected.spans[1].location(), Some(at(8, 1, 8)));
+    assert_eq!(expected.spans[6].location(), Some(at(20, 2, 8)));
+
+    // Bytes that aren't UTF-8 are comments like any other.
+    let program = parser::parse_reader(Trickle(b"\xff\xfe+\n\x80.")).unwrap();
+    assert_eq!(program.spans[0].location(), Some(at(2, 1, 3)));
+    assert_eq!(program.spans[1].location(), Some(at(5, 2, 1)));
+}
+
+#[test]
+fn runs_of_a_command_become_one_instruction() {
+    let source = format!("+++>>--<<<..,, ++ +{}", "+".repeat(200));
+    let expected = [
+        Instruction::Add(3),
+        Instruction::Move(2),
+        Instruction::Add(-2),
+        Instruction::Move(-3),
+        Instruction::Write(2),
+        Instruction::Read(2),
+        // Anything between two commands ends the run, and so does running out of room.
+        Instruction::Add(2),
+        Instruction::Add(127),
+        Instruction::Add(74),
+    ];
+
+    let program = parser::parse_string(&source).unwrap();
+    assert_eq!(program.instructions, expected);
+    assert_eq!(program.spans[0].range(), 0..3);
+    assert_eq!(program.spans[7].range(), 18..145);
+    assert_eq!(program.spans[8].location(), Some(at(145, 1, 146)));
+
+    // Runs carry on across reads.
+    let program = parser::parse_reader(Trickle(source.as_bytes())).unwrap();
+    assert_eq!(program.instructions, expected);
+}
+
+#[test]
+fn inline_input_follows_the_first_bang_outside_a_loop() {
+    let options = ParseOptions {
+        inline_input: true,
+        ..ParseOptions::default()
+    };
+    let source = ",[!.,]!ab!c\n[";
+
+    let program = parser::parse_string_with(source, &options).unwrap();
+    assert_eq!(program.instructions.len(), 5);
+    assert_eq!(program.input.as_deref(), Some(&b"ab!c\n["[..]));
+
+    assert_eq!(
+        parser::parse_reader_with(Trickle(source.as_bytes()), &options).unwrap(),
+        program
+    );
+
+    // Without the option, a `!` is a comment like any other.
+    assert_eq!(parser::parse_string(",.!").unwrap().input, None);
+}
+
+#[test]
+fn dialect_maps_replace_the_commands() {
+    let map = "# Tokens can share a start.\n+ a\n- ab\n[ (\n] )\n. !\n";
+    let options = ParseOptions {
+        dialect: Some(map.parse::<DialectMap>().unwrap()),
+        ..ParseOptions::default()
+    };
+
+    // The longest token wins, and the usual commands are comments.
+    let source = "aa+(ab)a ! a";
+    let expected = [
+        Instruction::Add(2),
+        Instruction::JumpIfZero { location: 3 },
+        Instruction::Add(-1),
+        Instruction::JumpIfNotZero { location: 1 },
+        Instruction::Add(1),
+        Instruction::Write(1),
+        Instruction::Add(1),
+    ];
+
+    let program = parser::parse_string_with(source, &options).unwrap();
+    assert_eq!(program.instructions, expected);
+    assert_eq!(program.spans[0].range(), 0..2);
+    assert_eq!(program.spans[2].range(), 4..6);
+
+    // Tokens carry on across reads, even when the source ends partway through one.
+    assert_eq!(
+        parser::parse_reader_with(Trickle(source.as_bytes()), &options).unwrap(),
+        program
+    );
+
+    assert!(matches!(
+        "a inc".parse::<DialectMap>(),
+        Err(DialectError::UnknownCommand { line: 1, .. })
+    ));
+    assert!(matches!(
+        "+ a\n\n- a".parse::<DialectMap>(),
+        Err(DialectError::DuplicateToken { line: 3, .. })
+    ));
+}
+
+#[test]
+fn ook_pairs_words_across_any_whitespace() {
+    let options = ParseOptions {
+        dialect: Dialect::Ook.map(),
+        ..ParseOptions::default()
+    };
+
+    let source =
+        "Ook. Ook.\nOok! Ook?Ook. Ook?  Ook!\n\tOok! Ook? Ook!\nOok? Ook. Ook. Ook! Ook. Ook.";
+    let expected = parser::parse_string("+[>-]<,+").unwrap().instructions;
+
+    let program = parser::parse_string_with(source, &options).unwrap();
+    assert_eq!(program.instructions, expected);
+    assert_eq!(program.spans[3].location(), Some(at(30, 2, 21)));
+    assert_eq!(program.spans[3].range(), 30..40);
+
+    assert_eq!(
+        parser::parse_reader_with(Trickle(source.as_bytes()), &options).unwrap(),
+        program
+    );
+
+    assert_eq!(Dialect::for_extension("OOK"), Some(Dialect::Ook));
+}
+
+#[test]
+fn dialects_write_code_that_reads_back_the_same() {
+    let options = ParseOptions {
+        dialect: Dialect::Ook.map(),
+        ..ParseOptions::default()
+    };
+
+    let code = "++[>+.<-],";
+    let ook = Dialect::Ook
+        .map()
+        .unwrap()
+        .write_code(code.as_bytes(), 20)
+        .unwrap();
+
+    assert!(ook
+        .split(|&byte| byte == b'\n')
+        .all(|line| line.len() <= 20));
+    let program = parser::parse_bytes_with(&ook, &options).unwrap();
+    assert_eq!(lowering::to_brainfuck(&program.instructions), code);
+
+    let map = "+ x\n- x x".parse::<DialectMap>().unwrap();
+    assert_eq!(map.spelling(b'-'), Some(&b"x x"[..]));
+    assert_eq!(map.write_code(b"++", 80), Err(DialectError::Ambiguous));
+    assert_eq!(
+        map.write_code(b"+>", 80),
+        Err(DialectError::NoToken { command: b'>' })
+    );
+}
+
+#[test]
+fn pbrain_procedures_pair_up_with_their_ends() {
+    let options = ParseOptions {
+        procedures: true,
+        ..ParseOptions::default()
+    };
+
+    let program = parser::parse_string_with("+(>[:])<:", &options).unwrap();
+    assert_eq!(
+        program.instructions,
+        [
+            Instruction::Add(1),
+            Instruction::DefineProc { location: 6 },
+            Instruction::Move(1),
+            Instruction::JumpIfZero { location: 5 },
+            Instruction::CallProc,
+            Instruction::JumpIfNotZero { location: 3 },
+            Instruction::EndProc,
+            Instruction::Move(-1),
+            Instruction::CallProc,
+        ]
+    );
+
+    // Loops and procedures can't end inside each other.
+    let errors = parser::parse_string_with("([)]", &options).unwrap_err();
+    assert!(matches!(
+        errors.as_slice(),
+        [
+            ParseError::UnmatchedEndProc { .. },
+            ParseError::UnclosedProc { .. },
+        ]
+    ));
+
+    // Without the option, they're comments like any other.
+    assert!(parser::parse_string("(:)").unwrap().instructions.is_empty());
+}
+
+#[test]
+fn nesting_past_the_limit_stops_the_parse() {
+    let options = ParseOptions {
+        max_depth: Some(2),
+        ..ParseOptions::default()
+    };
+
+    assert!(parser::parse_string_with("[[]][[-]]", &options).is_ok());
+
+    // Only the error for going too deep is reported, and the rest of the source is skipped.
+    match parser::parse_string_with("[[\n [[]]]]]]", &options)
+        .unwrap_err()
+        .as_slice()
+    {
+        [ParseError::TooDeep { location, limit }] => {
+            assert_eq!(*location, at(4, 2, 2));
+            assert_eq!(*limit, 2);
+        }
+        errors => panic!("expected the source to be too deep, got {:?}", errors),
+    }
+}
+
+#[test]
+fn stats_count_loops_and_how_deep_they_nest() {
+    let options = ParseOptions {
+        procedures: true,
+        ..ParseOptions::default()
+    };
+
+    let program = parser::parse_string_with("+[>[-]<(:)]>[.]", &options).unwrap();
+    assert_eq!(
+        program.stats(),
+        ProgramStats {
+            instructions: 15,
+            loops: 3,
+            procedures: 1,
+            max_depth: 2,
+        }
+    );
+}
+
+#[test]
+fn frontends_are_found_by_name_and_extension() {
+    let mut registry = frontend::Registry::builtin();
+    assert_eq!(registry.names(), ["brainfuck", "ook", "pbrain", "listing"]);
+    assert_eq!(registry.for_extension("BF").unwrap().name(), "brainfuck");
+
+    let program = registry.get("pbrain").unwrap().parse(b"(:)").unwrap();
+    assert_eq!(program.len(), 3);
+    assert_eq!(program.dialect.as_deref(), Some("pbrain"));
+
+    // Registering a frontend under a name that's taken replaces the one there.
+    let shouting = ParserFrontend::new(
+        "ook",
+        ParseOptions {
+            dialect: Some("+ OOK".parse().unwrap()),
+            ..ParseOptions::default()
+        },
+    );
+    assert!(registry.register(Box::new(shouting)).is_some());
+    assert_eq!(
+        registry
+            .get("ook")
+            .unwrap()
+            .parse(b"OOK OOK")
+            .unwrap()
+            .instructions,
+        [Instruction::Add(1), Instruction::Add(1)]
+    );
+}
+
+#[test]
+fn trivia_keeps_what_comes_between_instructions() {
+    let options = ParseOptions {
+        trivia: true,
+        ..ParseOptions::default()
+    };
+
+    let program = parser::parse_string_with("set: ++ then\n[-] done.\n", &options).unwrap();
+    let trivia = program.trivia.as_ref().unwrap();
+
+    assert_eq!(program.len(), 5);
+    assert_eq!(trivia.leading(0), b"set: ");
+    assert_eq!(trivia.leading(1), b" then\n");
+    assert_eq!(trivia.leading(3), b"");
+    assert_eq!(trivia.leading(4), b" done");
+    assert_eq!(trivia.trailing(), b"\n");
+
+    // Optimizing changes the instructions, so their trivia is dropped.
+    let mut program = program;
+    optimizer::optimize_program(&mut program, &OptimizeOptions::default()).unwrap();
+    assert_eq!(program.trivia, None);
+
+    assert_eq!(parser::parse_string("+ +").unwrap().trivia, None);
+}
+
+#[test]
+fn parallel_parses_match_sequential_ones() {
+    // Long runs and multibyte comments land on every chunk boundary sooner or later.
+    let piece = format!(
+        "{}\n{}é[->+<]\n",
+        include_str!("../examples/mandelbrot.bf"),
+        "+".repeat(300)
+    );
+    let source = piece.repeat(parser::PARALLEL_THRESHOLD / piece.len() + 1);
+    let options = ParseOptions::default();
+
+    let parallel = parser::parse_bytes_with(source.as_bytes(), &options).unwrap();
+    let sequential = parser::parse_reader_with(source.as_bytes(), &options).unwrap();
+    assert_eq!(parallel.instructions, sequential.instructions);
+    assert_eq!(parallel.spans, sequential.spans);
+
+    // Bracket errors are left to the sequential parser, so they're reported the same.
+    let unbalanced = format!("{}]", source);
+    let describe =
+        |errors: Vec<ParseError>| errors.iter().map(ToString::to_string).collect::<Vec<_>>();
+
+    assert_eq!(
+        describe(parser::parse_bytes_with(unbalanced.as_bytes(), &options).unwrap_err()),
+        describe(parser::parse_reader_with(unbalanced.as_bytes(), &options).unwrap_err())
+    );
+}
@@ -0,0 +1,24 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use membrane::instruction::Instruction;
+use membrane::optimizer::{self, OptimizeOptions};
+use membrane::parser;
+
+// The `[-]` matches well before the end, which used to skip the windows holding the rest
+// of the program and drop the `+.` after it.
+#[test]
+fn instructions_after_a_match_are_kept() {
+    let mut program = parser::parse_string(">[-]+.").unwrap();
+    optimizer::optimize(
+        &mut program.instructions,
+        &mut program.spans,
+        &OptimizeOptions::default(),
+    )
+    .unwrap();
+
+    assert_eq!(program.instructions.last(), Some(&Instruction::Write(1)));
+}
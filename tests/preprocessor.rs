@@ -0,0 +1,72 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::env;
+use std::fs;
+use std::process;
+
+use membrane::parser;
+use membrane::preprocessor::{self, PreprocessErrorKind};
+use membrane::span::Location;
+
+fn at(offset: usize, line: usize, column: usize) -> Location {
+    Location {
+        offset,
+        line,
+        column,
+    }
+}
+
+#[test]
+fn expanded_spans_point_at_their_directive() {
+    let directory = env::temp_dir().join(format!("membrane-preprocessor-{}", process::id()));
+    fs::create_dir_all(directory.join("lib")).unwrap();
+    fs::write(
+        directory.join("lib/clear.bf"),
+        "@define clear { [-] }\n@define twice { @clear@clear }\n",
+    )
+    .unwrap();
+
+    let path = directory.join("main.bf");
+    let source = "@include \"lib/clear.bf\"\n+ @twice\n.";
+    fs::write(&path, source).unwrap();
+
+    let expansion = preprocessor::preprocess_file(&path).unwrap();
+    fs::remove_dir_all(&directory).unwrap();
+
+    let mut program = parser::parse_reader(expansion.source.as_slice()).unwrap();
+    assert_eq!(
+        program.instructions,
+        parser::parse_string("+[-][-].").unwrap().instructions
+    );
+
+    expansion.remap(&mut program.spans);
+    assert_eq!(program.spans[0].location(), Some(at(24, 2, 1)));
+
+    // Both clears came from the one `@twice`.
+    for span in &program.spans[1..7] {
+        assert_eq!(span.location(), Some(at(26, 2, 3)));
+        assert_eq!(span.range(), 26..32);
+    }
+
+    assert_eq!(program.spans[7].location(), Some(at(33, 3, 1)));
+}
+
+#[test]
+fn macros_cant_expand_to_themselves() {
+    let err =
+        preprocessor::preprocess(b"@define a { +@b }\n@define b { @a }\n@a", None).unwrap_err();
+
+    assert!(matches!(
+        err.kind,
+        PreprocessErrorKind::RecursiveMacro { .. }
+    ));
+    assert_eq!(err.location, at(30, 2, 13));
+
+    let err = preprocessor::preprocess(b"+\n @missing", None).unwrap_err();
+    assert!(matches!(err.kind, PreprocessErrorKind::Undefined { .. }));
+    assert_eq!(err.location, at(3, 2, 2));
+}
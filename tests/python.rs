@@ -0,0 +1,40 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+#![cfg(feature = "python")]
+
+use membrane::python::membrane;
+use pyo3::prelude::*;
+
+#[test]
+fn python_runs_programs_from_source_or_parsed() {
+    pyo3::append_to_inittab!(membrane);
+    pyo3::prepare_freethreaded_python();
+
+    Python::with_gil(|py| {
+        py.run(
+            cr#"
+import membrane
+
+program = membrane.optimize(membrane.parse(",[+.,]"))
+result = membrane.run(program, input="HAL")
+assert (result.output, result.error) == ("IBM", None), result.error
+
+result = membrane.run("+[]", max_steps=100)
+assert result.steps is None and "100 instructions" in result.error
+
+try:
+    membrane.parse("[")
+    assert False
+except ValueError:
+    pass
+"#,
+            None,
+            None,
+        )
+    })
+    .unwrap();
+}
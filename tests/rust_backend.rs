@@ -0,0 +1,263 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{Cursor, Write};
+use std::path::PathBuf;
+use std::process::{self, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use membrane::compilers::{bundle, rust, CompileError, CompileOptions, ProgramInfo};
+use membrane::instruction::Instruction;
+use membrane::interpreter::{self, InputSource, OutputSource, TapeSize};
+use membrane::optimizer::{self, OptimizeOptions};
+use membrane::parser::{self, ParseOptions};
+use membrane::program::Program;
+
+// Programs that move the head past either end of a small tape, through plain moves, scans,
+// relative adds, and multiplications.
+const WRAPPING_PROGRAMS: &[(&str, usize)] = &[
+    ("+<+<+<.>.>.>.", 5),
+    ("+++[<++>-]<<<[-]+>>>[<+<<+>>>-]<.<.>>.", 5),
+    ("+>+>+>+<<<[<]+.<.", 5),
+    (">>>+>+[>]+.<.", 5),
+    (">>>>>>>>+<<<<<<<<<<<<.>>>>.", 3),
+    ("+[-<+++>]<[->>+<<]>>.", 4),
+    ("++++[>++++<-]>[<<+>>-]<<.", 3),
+];
+
+// Tests run in parallel, so every caller gets a directory of its own.
+fn scratch_directory(name: &str) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+    let directory = env::temp_dir().join(format!(
+        "membrane-{}-{}-{}",
+        name,
+        process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+fn interpret(instructions: &[Instruction], input: &[u8], tape_size: TapeSize) -> Vec<u8> {
+    let directory = scratch_directory("interpret");
+    let path = directory.join("output");
+
+    interpreter::interpret(
+        instructions,
+        InputSource::File(Cursor::new(input.to_vec())),
+        OutputSource::File(File::create(&path).unwrap()),
+        tape_size,
+    )
+    .unwrap();
+
+    let output = fs::read(&path).unwrap();
+    fs::remove_dir_all(&directory).unwrap();
+    output
+}
+
+// A backend that writes Rust, either the Rust backend itself or bundles.
+type Compile =
+    fn(&[Instruction], &CompileOptions, &ProgramInfo, &mut Vec<u8>) -> Result<(), CompileError>;
+
+// Compiles the instructions with rustc, and returns what the executable prints.
+fn run_compiled(
+    compile: Compile,
+    instructions: &[Instruction],
+    input: &[u8],
+    tape_size: TapeSize,
+) -> Vec<u8> {
+    let directory = scratch_directory("rust");
+    let source = directory.join("main.rs");
+    let binary = directory.join("main");
+
+    let mut code = Vec::new();
+    compile(
+        instructions,
+        &CompileOptions::new(tape_size),
+        &ProgramInfo::default(),
+        &mut code,
+    )
+    .unwrap();
+    fs::write(&source, code).unwrap();
+
+    // Without an edition flag rustc builds the 2015 edition, which generated code has to
+    // build under.
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_owned());
+    let status = Command::new(rustc)
+        .arg("-o")
+        .arg(&binary)
+        .arg(&source)
+        .status()
+        .unwrap();
+
+    assert!(status.success(), "rustc rejected the generated program");
+
+    let mut child = Command::new(&binary)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(output.status.success(), "the compiled program failed");
+
+    fs::remove_dir_all(&directory).unwrap();
+    output.stdout
+}
+
+fn assert_matches_interpreter(source: &str, input: &[u8], tape_size: TapeSize) {
+    assert_compiled_matches_interpreter(rust::compile, source, input, tape_size);
+}
+
+fn assert_compiled_matches_interpreter(
+    compile: Compile,
+    source: &str,
+    input: &[u8],
+    tape_size: TapeSize,
+) {
+    let Program {
+        mut instructions,
+        mut spans,
+        ..
+    } = parser::parse_string(source).unwrap();
+
+    assert_eq!(
+        run_compiled(compile, &instructions, input, tape_size),
+        interpret(&instructions, input, tape_size),
+        "{} differs on {:?}",
+        source,
+        tape_size
+    );
+
+    optimizer::optimize(&mut instructions, &mut spans, &OptimizeOptions::default()).unwrap();
+
+    assert_eq!(
+        run_compiled(compile, &instructions, input, tape_size),
+        interpret(&instructions, input, tape_size),
+        "{} differs on {:?} once optimized",
+        source,
+        tape_size
+    );
+}
+
+#[test]
+fn finite_tapes_wrap_like_the_interpreter() {
+    for (source, length) in WRAPPING_PROGRAMS {
+        assert_matches_interpreter(source, b"", TapeSize::Finite(*length));
+    }
+}
+
+#[test]
+fn examples_match_the_interpreter_on_finite_tapes() {
+    let path = format!("{}/examples/hello_world.bf", env!("CARGO_MANIFEST_DIR"));
+    let source = fs::read_to_string(path).unwrap();
+
+    for length in [7, 30_000] {
+        assert_matches_interpreter(&source, b"", TapeSize::Finite(length));
+    }
+}
+
+#[test]
+fn bundles_match_the_interpreter() {
+    for (source, length) in WRAPPING_PROGRAMS {
+        assert_compiled_matches_interpreter(
+            bundle::compile,
+            source,
+            b"",
+            TapeSize::Finite(*length),
+        );
+    }
+
+    assert_compiled_matches_interpreter(
+        bundle::compile,
+        ",<,<,<,>.>.>.",
+        b"abcd",
+        TapeSize::Finite(3),
+    );
+}
+
+#[test]
+fn reads_match_the_interpreter() {
+    assert_matches_interpreter(",<,<,<,>.>.>.", b"abcd", TapeSize::Finite(3));
+}
+
+#[test]
+fn repeated_io_matches_the_interpreter() {
+    // Longer than the buffer repeated writes and reads go through.
+    let source = format!("{}{}{}.", "+".repeat(65), ".".repeat(300), ",".repeat(300));
+    let input = (0..300).map(|byte| byte as u8).collect::<Vec<_>>();
+
+    assert_matches_interpreter(&source, &input, TapeSize::Finite(30_000));
+}
+
+#[test]
+fn procedures_match_the_interpreter() {
+    let options = ParseOptions {
+        procedures: true,
+        ..ParseOptions::default()
+    };
+
+    // Procedure 1 writes the cell to its right and calls itself until that reaches zero.
+    let Program {
+        mut instructions,
+        mut spans,
+        ..
+    } = parser::parse_string_with("+(>.-[<:>]<)>+++<:", &options).unwrap();
+    let tape_size = TapeSize::Finite(30_000);
+
+    assert_eq!(interpret(&instructions, b"", tape_size), [3, 2, 1]);
+    assert_eq!(
+        run_compiled(rust::compile, &instructions, b"", tape_size),
+        [3, 2, 1]
+    );
+
+    optimizer::optimize(&mut instructions, &mut spans, &OptimizeOptions::default()).unwrap();
+
+    assert_eq!(interpret(&instructions, b"", tape_size), [3, 2, 1]);
+    assert_eq!(
+        run_compiled(rust::compile, &instructions, b"", tape_size),
+        [3, 2, 1]
+    );
+}
+
+#[test]
+fn output_is_written_as_raw_bytes() {
+    assert_matches_interpreter(
+        "-.+.>++++++++[<++++++++++++++++>-]<.+.",
+        b"",
+        TapeSize::Finite(30_000),
+    );
+}
+
+#[test]
+fn projects_are_named_after_their_directory() {
+    let directory = scratch_directory("project").join("2 Fast");
+    let instructions = parser::parse_string("+.").unwrap().instructions;
+
+    rust::write_project(
+        &instructions,
+        &CompileOptions::default(),
+        &ProgramInfo::default(),
+        &directory,
+        Some("+. ```"),
+    )
+    .unwrap();
+
+    let manifest = fs::read_to_string(directory.join("Cargo.toml")).unwrap();
+    let readme = fs::read_to_string(directory.join("README.md")).unwrap();
+
+    assert!(manifest.contains("name = \"bf-2-fast\""));
+    assert!(readme.contains("````brainfuck\n+. ```\n````\n"));
+    assert!(directory.join("src").join("main.rs").is_file());
+
+    fs::remove_dir_all(directory.parent().unwrap()).unwrap();
+}
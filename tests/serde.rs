@@ -0,0 +1,45 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+#![cfg(feature = "serde")]
+
+use membrane::instruction::Instruction;
+use membrane::interpreter::TapeSize;
+use membrane::optimizer::{self, OptimizeOptions};
+use membrane::parser::{self, ParseOptions};
+use membrane::program::Program;
+
+fn round_trip<T: serde::Serialize + serde::de::DeserializeOwned>(value: &T) -> T {
+    serde_json::from_str(&serde_json::to_string(value).unwrap()).unwrap()
+}
+
+#[test]
+fn programs_survive_serialization() {
+    let options = ParseOptions {
+        inline_input: true,
+        trivia: true,
+        ..ParseOptions::default()
+    };
+    let parsed = parser::parse_string_with("+++ copy [->+<] >.!input", &options).unwrap();
+    assert_eq!(round_trip(&parsed), parsed);
+
+    let mut optimized = parsed.clone();
+    optimizer::optimize_program(&mut optimized, &OptimizeOptions::default()).unwrap();
+    assert_eq!(round_trip::<Program>(&optimized), optimized);
+
+    assert_eq!(
+        serde_json::to_string(&Instruction::AddRelative {
+            offset: -2,
+            amount: 3
+        })
+        .unwrap(),
+        r#"{"AddRelative":{"offset":-2,"amount":3}}"#
+    );
+
+    for size in [TapeSize::Finite(30_000), TapeSize::Infinite] {
+        assert_eq!(round_trip(&size), size);
+    }
+}
@@ -0,0 +1,34 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use membrane::testgen::{self, GenerateOptions};
+
+#[test]
+fn generated_programs_follow_their_options() {
+    let options = GenerateOptions {
+        size: 500,
+        max_depth: 2,
+        io_density: 0,
+    };
+
+    for seed in 0..20 {
+        let source = testgen::source(seed, &options);
+        assert_eq!(source, testgen::source(seed, &options));
+        assert_eq!(source.len(), 500);
+        assert!(!source.contains(['.', ',']));
+
+        let depths = source.bytes().scan(0, |depth, byte| {
+            *depth += i32::from(byte == b'[') - i32::from(byte == b']');
+            Some(*depth)
+        });
+        assert!(depths.clone().all(|depth| (0..=2).contains(&depth)));
+        assert_eq!(depths.last(), Some(0));
+    }
+
+    // Each command takes two bytes, and the loops still open are closed once they run out.
+    let source = testgen::source_from_bytes(&[0; 7], &options);
+    assert_eq!(source, "[[+]]");
+}
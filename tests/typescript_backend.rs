@@ -0,0 +1,168 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::process::{self, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use membrane::compilers::{typescript, CompileOptions, ProgramInfo};
+use membrane::interpreter::{Interpreter, TapeSize};
+use membrane::optimizer::{self, OptimizeOptions};
+use membrane::parser;
+
+// Programs that between them use every kind of instruction once optimized, with their input.
+const PROGRAMS: &[(&str, &[u8])] = &[
+    (",[->+>++<<]>>.<.", b"\x05"),
+    ("++++++++[>++++++++<-]>+.+.>>+>+<<<[>]<.", b""),
+    (",>,[-<+>]<.>>>+<<<[<]>.", b"ab"),
+    (",[.,]", b"hello\0"),
+];
+
+fn compile(source: &str, options: &CompileOptions) -> String {
+    let mut program = parser::parse_string(source).unwrap();
+    let optimize_options = OptimizeOptions {
+        tape_size: options.tape_size,
+        ..OptimizeOptions::default()
+    };
+    optimizer::optimize_program(&mut program, &optimize_options).unwrap();
+
+    let mut code = Vec::new();
+    typescript::compile(
+        &program.instructions,
+        options,
+        &ProgramInfo::default(),
+        &mut code,
+    )
+    .unwrap();
+    String::from_utf8(code).unwrap()
+}
+
+fn interpret(source: &str, input: &[u8], tape_size: TapeSize) -> Vec<u8> {
+    let program = parser::parse_string(source).unwrap();
+    let mut input = input.iter().copied();
+    let mut output = Vec::new();
+
+    Interpreter::builder()
+        .tape(tape_size)
+        .build()
+        .run_with(
+            &program.instructions,
+            || input.next(),
+            |byte| output.push(byte),
+        )
+        .unwrap();
+
+    output
+}
+
+// Runs the module with stdin and stdout standing in for its I/O.
+const HARNESS: &str = r#"
+import { readFileSync, writeSync } from "node:fs";
+import { run } from "./program.mts";
+
+const input = readFileSync(0);
+const output = [];
+let position = 0;
+
+run({
+  read: () => (position < input.length ? input[position++] : -1),
+  write: (byte) => output.push(byte),
+});
+
+writeSync(1, Uint8Array.from(output));
+"#;
+
+// Node can run TypeScript itself, from version 22.6 on, when asked to strip its types.
+fn node_strips_types() -> bool {
+    Command::new("node")
+        .args(["--no-warnings", "--experimental-strip-types", "-e", ""])
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+// Runs the module with Node and returns what it writes, or None if Node can't run
+// TypeScript.
+fn run_compiled(source: &str, input: &[u8], options: &CompileOptions) -> Option<Vec<u8>> {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+    if !node_strips_types() {
+        return None;
+    }
+
+    let directory = env::temp_dir().join(format!(
+        "membrane-typescript-{}-{}",
+        process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::create_dir_all(&directory).unwrap();
+
+    let harness = directory.join("main.mjs");
+    fs::write(directory.join("program.mts"), compile(source, options)).unwrap();
+    fs::write(&harness, HARNESS).unwrap();
+
+    let mut child = Command::new("node")
+        .args(["--no-warnings", "--experimental-strip-types"])
+        .arg(&harness)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{} failed", source);
+
+    fs::remove_dir_all(&directory).unwrap();
+    Some(output.stdout)
+}
+
+#[test]
+fn programs_compile_to_a_module_exporting_run() {
+    let code = compile(",[->+>++<<]>>.<.", &CompileOptions::default());
+    let lines = code.lines().map(str::trim).collect::<Vec<_>>();
+
+    for line in [
+        "export interface IO {",
+        "read(): number;",
+        "write(byte: number): void;",
+        "export function run(io: IO): void {",
+        "input(1);",
+        "if (tape[head] !== 0) {",
+        "add(right(2), Math.imul(tape[head], 2));",
+        "tape[head] = 0;",
+        "head = right(2);",
+        "output(1);",
+        "head = left(1);",
+    ] {
+        assert!(lines.contains(&line), "missing {:?}", line);
+    }
+}
+
+#[test]
+fn compiled_programs_match_the_interpreter() {
+    let tape_size = TapeSize::Finite(30_000);
+    let options = CompileOptions::new(tape_size);
+
+    for (source, input) in PROGRAMS {
+        let output = match run_compiled(source, input, &options) {
+            Some(output) => output,
+            None => return,
+        };
+
+        assert_eq!(output, interpret(source, input, tape_size), "{}", source);
+    }
+
+    // Finite tapes wrap around at both ends.
+    let tape_size = TapeSize::Finite(5);
+    let source = "+<+<+<.>.>.>+[<++>-]<<<<.";
+
+    if let Some(output) = run_compiled(source, b"", &CompileOptions::new(tape_size)) {
+        assert_eq!(output, interpret(source, b"", tape_size));
+    }
+}
@@ -0,0 +1,31 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+// Throwing needs JavaScript, so only what doesn't throw can run outside of it.
+#![cfg(feature = "wasm")]
+
+use membrane::wasm;
+
+#[test]
+fn playground_runs_are_limited_to_a_number_of_steps() {
+    let mut program = wasm::parse("++++++++[>++++++++<-]>+.,[.,]").unwrap();
+    let length = program.length();
+    wasm::optimize(&mut program).unwrap();
+    assert!(program.length() < length);
+
+    let result = wasm::run(&program, "bc", 1000);
+    assert_eq!(result.output(), "Abc");
+    assert!(result.steps().is_some());
+    assert_eq!(result.error(), None);
+
+    let endless = wasm::parse("+[]").unwrap();
+    let result = wasm::run(&endless, "", 100);
+    assert_eq!(result.steps(), None);
+    assert!(result
+        .error()
+        .unwrap()
+        .contains("after running 100 instructions"));
+}
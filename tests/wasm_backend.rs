@@ -0,0 +1,146 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::env;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::process::{self, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use membrane::compilers::{wasm, CompileOptions};
+use membrane::interpreter::{Interpreter, TapeSize};
+use membrane::optimizer::{self, OptimizeOptions};
+use membrane::parser;
+
+// Programs that between them use every kind of instruction once optimized, with their input.
+const PROGRAMS: &[(&str, &[u8])] = &[
+    (",[->+>++<<]>>.<.", b"\x05"),
+    ("++++++++[>++++++++<-]>+.+.>>+>+<<<[>]<.", b""),
+    (",>,[-<+>]<.>>>+<<<[<]>.", b"ab"),
+    (",[.,]", b"hello\0"),
+];
+
+fn compile(source: &str, options: &CompileOptions) -> Vec<u8> {
+    let mut program = parser::parse_string(source).unwrap();
+    let optimize_options = OptimizeOptions {
+        tape_size: options.tape_size,
+        ..OptimizeOptions::default()
+    };
+    optimizer::optimize_program(&mut program, &optimize_options).unwrap();
+
+    let mut code = Vec::new();
+    wasm::compile(&program.instructions, options, &mut code).unwrap();
+    code
+}
+
+fn interpret(source: &str, input: &[u8], tape_size: TapeSize) -> Vec<u8> {
+    let program = parser::parse_string(source).unwrap();
+    let mut input = input.iter().copied();
+    let mut output = Vec::new();
+
+    Interpreter::builder()
+        .tape(tape_size)
+        .build()
+        .run_with(
+            &program.instructions,
+            || input.next(),
+            |byte| output.push(byte),
+        )
+        .unwrap();
+
+    output
+}
+
+// Loads the module with Node's WASI support.
+const RUN_WITH_NODE: &str = r#"
+const { readFileSync } = require("fs");
+const { WASI } = require("wasi");
+const wasi = new WASI({ version: "preview1" });
+const wasm = new WebAssembly.Module(readFileSync(process.argv[1]));
+wasi.start(new WebAssembly.Instance(wasm, wasi.getImportObject()));
+"#;
+
+// Runs the module with Node and returns what it prints, or None if Node isn't installed.
+fn run_compiled(source: &str, input: &[u8], options: &CompileOptions) -> Option<Vec<u8>> {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+    let directory = env::temp_dir().join(format!(
+        "membrane-wasm-{}-{}",
+        process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::create_dir_all(&directory).unwrap();
+
+    let module = directory.join("program.wasm");
+    fs::write(&module, compile(source, options)).unwrap();
+
+    let mut child = match Command::new("node")
+        .arg("--no-warnings")
+        .arg("-e")
+        .arg(RUN_WITH_NODE)
+        .arg(&module)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(err) if err.kind() == ErrorKind::NotFound => return None,
+        Err(err) => panic!("failed to run node: {}", err),
+    };
+
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{} failed", source);
+
+    fs::remove_dir_all(&directory).unwrap();
+    Some(output.stdout)
+}
+
+#[test]
+fn modules_are_wasi_commands() {
+    let module = compile(",[->+>++<<]>>.<.", &CompileOptions::default());
+
+    assert_eq!(module[..8], *b"\0asm\x01\0\0\0");
+
+    let contains = |name: &[u8]| module.windows(name.len()).any(|window| window == name);
+    for name in [
+        &b"wasi_snapshot_preview1"[..],
+        b"fd_read",
+        b"fd_write",
+        b"proc_exit",
+        b"_start",
+        b"memory",
+    ] {
+        assert!(
+            contains(name),
+            "missing {:?}",
+            String::from_utf8_lossy(name)
+        );
+    }
+}
+
+#[test]
+fn modules_match_the_interpreter() {
+    let tape_size = TapeSize::Finite(30_000);
+    let options = CompileOptions::new(tape_size);
+
+    for (source, input) in PROGRAMS {
+        let output = match run_compiled(source, input, &options) {
+            Some(output) => output,
+            None => return,
+        };
+
+        assert_eq!(output, interpret(source, input, tape_size), "{}", source);
+    }
+
+    // Finite tapes wrap around at both ends.
+    let tape_size = TapeSize::Finite(5);
+    let source = "+<+<+<.>.>.>+[<++>-]<<<<.";
+
+    if let Some(output) = run_compiled(source, b"", &CompileOptions::new(tape_size)) {
+        assert_eq!(output, interpret(source, b"", tape_size));
+    }
+}
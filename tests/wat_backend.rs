@@ -0,0 +1,104 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::process::{self, Command};
+
+use membrane::compilers::{wat, CompileOptions, ProgramInfo};
+use membrane::optimizer::{self, OptimizeOptions};
+use membrane::parser;
+
+fn compile(source: &str, options: &CompileOptions) -> String {
+    let mut program = parser::parse_string(source).unwrap();
+    optimizer::optimize_program(&mut program, &OptimizeOptions::default()).unwrap();
+
+    let mut code = Vec::new();
+    wat::compile(
+        &program.instructions,
+        options,
+        &ProgramInfo::default(),
+        &mut code,
+    )
+    .unwrap();
+    String::from_utf8(code).unwrap()
+}
+
+#[test]
+fn loops_become_blocks_around_wasm_loops() {
+    let code = compile("+[>,.<-]", &CompileOptions::default());
+    let lines = code.lines().map(str::trim).collect::<Vec<_>>();
+
+    for line in [
+        r#"(import "env" "read_byte" (func $read_byte (result i32)))"#,
+        r#"(import "env" "write_byte" (func $write_byte (param i32)))"#,
+        r#"(memory (export "memory") 1)"#,
+        r#"(func (export "run")"#,
+        "(block $end_1",
+        "(br_if $end_1 (i32.eqz (i32.load8_u (global.get $head))))",
+        "(loop $loop_1",
+        "(call $input (i32.const 1))",
+        "(call $output (i32.const 1))",
+        "(br_if $loop_1 (i32.load8_u (global.get $head)))))",
+    ] {
+        assert!(lines.contains(&line), "missing {:?}", line);
+    }
+
+    // Comments aside, every expression is closed.
+    let depth = lines
+        .iter()
+        .filter(|line| !line.starts_with(";;"))
+        .flat_map(|line| line.chars())
+        .try_fold(0usize, |depth, char| match char {
+            '(' => Some(depth + 1),
+            ')' => depth.checked_sub(1),
+            _ => Some(depth),
+        });
+    assert_eq!(depth, Some(0));
+}
+
+#[test]
+fn multiplications_reserve_the_cells_they_add_to() {
+    let code = compile(",[->+>++<<]>>.<.", &CompileOptions::default());
+    let lines = code.lines().map(str::trim).collect::<Vec<_>>();
+
+    for line in [
+        "(local.set $address (i32.add (global.get $head) (i32.const 2)))",
+        "(call $reserve (local.get $address))",
+        "(i32.store8 (global.get $head) (i32.const 0))",
+        "(if (i32.lt_s (global.get $head) (i32.const 0)) (then unreachable))",
+    ] {
+        assert!(lines.contains(&line), "missing {:?}", line);
+    }
+}
+
+// Checks that the module assembles when wabt is installed.
+#[test]
+fn modules_assemble_with_wabt() {
+    let directory = env::temp_dir().join(format!("membrane-wat-{}", process::id()));
+    fs::create_dir_all(&directory).unwrap();
+
+    let code = directory.join("program.wat");
+    fs::write(
+        &code,
+        compile(",[->+>++<<]>>.<.[>,.<-]", &CompileOptions::default()),
+    )
+    .unwrap();
+
+    let status = Command::new("wat2wasm")
+        .arg(&code)
+        .arg("-o")
+        .arg(directory.join("program.wasm"))
+        .status();
+    fs::remove_dir_all(&directory).unwrap();
+
+    match status {
+        Ok(status) => assert!(status.success(), "wat2wasm rejected the module"),
+        Err(err) if err.kind() == ErrorKind::NotFound => {}
+        Err(err) => panic!("failed to run wat2wasm: {}", err),
+    }
+}
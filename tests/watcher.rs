@@ -0,0 +1,40 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::{env, fs, process};
+
+use membrane::watcher::Watcher;
+
+#[test]
+fn watchers_see_files_change() {
+    let path = env::temp_dir().join(format!("membrane-watcher-{}.b", process::id()));
+    fs::write(&path, "+.").unwrap();
+
+    let mut watcher = Watcher::new([&path]);
+    assert!(watcher.poll().is_empty());
+
+    fs::write(&path, "++.").unwrap();
+    assert_eq!(watcher.poll(), vec![path.clone()]);
+    assert!(watcher.poll().is_empty());
+
+    fs::remove_file(&path).unwrap();
+    assert_eq!(watcher.poll(), vec![path]);
+}
+
+#[test]
+fn settling_returns_nothing_when_files_are_unchanged() {
+    let path = env::temp_dir().join(format!("membrane-settle-{}.b", process::id()));
+    fs::write(&path, "+.").unwrap();
+
+    let mut watcher = Watcher::new([&path]);
+    assert!(watcher.settle().is_empty());
+
+    fs::write(&path, "++.").unwrap();
+    assert_eq!(watcher.settle(), vec![path.clone()]);
+    assert!(watcher.settle().is_empty());
+
+    fs::remove_file(&path).unwrap();
+}